@@ -1,2 +1,2 @@
 #!/bin/sh
-echo ":telda:M::álvur2\\n::$(realpath target/release/t):" | sudo tee /proc/sys/fs/binfmt_misc/register
\ No newline at end of file
+echo ":telda:M::álvur::$(realpath target/release/t):" | sudo tee /proc/sys/fs/binfmt_misc/register
\ No newline at end of file
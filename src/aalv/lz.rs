@@ -0,0 +1,112 @@
+//! A small LZ77 scheme for compressing section payloads
+//!
+//! The compressed data is a sequence of tokens. A token byte below `0x80` is followed by
+//! that many plus one literal bytes. Otherwise it is a back reference of length
+//! `(token & 0x7f) + MIN_MATCH` followed by the distance back as a little-endian `u16`.
+
+use std::io::{self, ErrorKind};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0x7f + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn hash(bytes: &[u8]) -> usize {
+    let n = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (n.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i + MIN_MATCH <= input.len() {
+        let h = hash(&input[i..]);
+        let candidate = table[h];
+        table[h] = i;
+
+        let match_len = if candidate != usize::MAX && i - candidate <= MAX_DISTANCE {
+            input[candidate..]
+                .iter()
+                .zip(&input[i..])
+                .take(MAX_MATCH)
+                .take_while(|(a, b)| a == b)
+                .count()
+        } else {
+            0
+        };
+
+        if match_len >= MIN_MATCH {
+            flush_literals(&mut out, &input[literal_start..i]);
+            out.push(0x80 | (match_len - MIN_MATCH) as u8);
+            out.extend_from_slice(&((i - candidate) as u16).to_le_bytes());
+            i += match_len;
+            literal_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    flush_literals(&mut out, &input[literal_start..]);
+
+    out
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.push(chunk.len() as u8 - 1);
+        out.extend_from_slice(chunk);
+    }
+}
+
+pub fn decompress(input: &[u8]) -> io::Result<Vec<u8>> {
+    let corrupt = |msg| io::Error::new(ErrorKind::InvalidData, msg);
+    let mut out = Vec::with_capacity(input.len() * 2);
+    let mut i = 0;
+
+    while let Some(&token) = input.get(i) {
+        i += 1;
+        if token < 0x80 {
+            let len = token as usize + 1;
+            let literals = input
+                .get(i..i + len)
+                .ok_or_else(|| corrupt("compressed literals are cut off"))?;
+            out.extend_from_slice(literals);
+            i += len;
+        } else {
+            let len = (token & 0x7f) as usize + MIN_MATCH;
+            let &[dl, dh] = input
+                .get(i..i + 2)
+                .ok_or_else(|| corrupt("compressed back reference is cut off"))?
+            else {
+                unreachable!()
+            };
+            i += 2;
+            let distance = u16::from_le_bytes([dl, dh]) as usize;
+            if distance == 0 || distance > out.len() {
+                return Err(corrupt("compressed back reference points outside the data"));
+            }
+            // Byte by byte since the reference may overlap what it produces
+            let start = out.len() - distance;
+            for j in start..start + len {
+                out.push(out[j]);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[test]
+fn round_trip() {
+    let mut data = b"telda telda telda telda, aaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec();
+    data.extend((0..1000u32).map(|n| (n % 7) as u8));
+    data.extend((0..300u32).map(|n| (n * 31 % 251) as u8));
+
+    let compressed = compress(&data);
+    assert!(compressed.len() < data.len());
+    assert_eq!(decompress(&compressed).unwrap(), data);
+    assert_eq!(decompress(&compress(&[])).unwrap(), b"");
+}
@@ -4,7 +4,27 @@ use std::{
     path::Path,
 };
 
-const AALV_MAGIC: &str = "álvur2\n";
+/// Followed by the version in decimal and a newline
+const AALV_MAGIC_PREFIX: &str = "álvur";
+/// Version written by [`AalvWriter`] and the newest one that can be read
+///
+/// Version 2 had no section flags, version 3 had no feature word after the magic.
+pub const AALV_VERSION: u32 = 4;
+
+/// Some sections are compressed
+pub const FEATURE_COMPRESSION: u32 = 0x0000_0001;
+/// The upper half of the feature word holds the CPU features the code needs, see [`crate::isa::feature`]
+///
+/// Whoever runs the code checks them, readers only have to pass them on.
+pub const CPU_FEATURES_SHIFT: u32 = 16;
+pub const CPU_FEATURES_MASK: u32 = 0xffff << CPU_FEATURES_SHIFT;
+/// Features a reader has to understand to read the file correctly, older readers refuse files with other bits set
+pub const KNOWN_FEATURES: u32 = FEATURE_COMPRESSION | CPU_FEATURES_MASK;
+
+/// The section payload is compressed with the LZ scheme of the `lz` module
+pub const SECTION_COMPRESSED: u8 = 0x01;
+/// Sections smaller than this are never compressed
+const COMPRESSION_THRESHOLD: usize = 64;
 
 pub fn read_aalv_file<P: AsRef<Path>>(path: P) -> Result<AalvReader<BufReader<File>>> {
     let f = BufReader::new(File::open(path)?);
@@ -19,21 +39,33 @@ pub fn write_aalv_file_with_offset<P: AsRef<Path>>(
     path: P,
     file_offset: u64,
 ) -> Result<AalvWriter<BufWriter<File>>> {
-    let f = File::options().create(true).write(true).open(path)?;
+    let f = File::options()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
     f.set_len(file_offset)?;
     AalvWriter::new(BufWriter::new(f), file_offset)
 }
 
 pub struct AalvReader<F> {
     pub file_offset: u64,
+    /// Position right after the terminating zero byte of the container
+    pub end_offset: u64,
+    pub version: u32,
+    pub features: u32,
     file: F,
-    sections: Vec<(Box<str>, u64, u16)>,
+    /// Name, position, stored size and flags
+    sections: Vec<(Box<str>, u64, u16, u8)>,
 }
 
 impl<F: BufRead + Seek> AalvReader<F> {
     pub fn new(mut file: F) -> Result<Self> {
         let mut new = Self {
             file_offset: file.stream_position()?,
+            end_offset: 0,
+            version: 0,
+            features: 0,
             file,
             sections: Vec::new(),
         };
@@ -49,33 +81,101 @@ impl<F: BufRead + Seek> AalvReader<F> {
         Ok(new)
     }
 
+    /// Reads the first section with the name of `S` and removes it, so the next call reads the next one
     pub fn read_section<S: Section>(&mut self) -> Option<Result<S>> {
-        let id = self
-            .sections
+        let id = self.position(S::NAME)?;
+
+        let section = self.read_section_at(id);
+        self.sections.remove(id);
+        Some(section)
+    }
+    /// Reads the section at the index (in file order) without removing it
+    pub fn read_section_at<S: Section>(&mut self, index: usize) -> Result<S> {
+        let (payload, _) = self.read_payload(index, usize::MAX)?;
+        S::read(&*payload)
+    }
+    /// Index of the first section with the name
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.positions(name).next()
+    }
+    /// Indices of all sections with the name
+    pub fn positions<'a>(&'a self, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.sections
             .iter()
-            .position(|(s, _pos, _size)| &**s == S::NAME)?;
+            .enumerate()
+            .filter(move |(_, (s, _, _, _))| &**s == name)
+            .map(|(i, _)| i)
+    }
+    /// Reads at most `limit` bytes from the start of the payload of the section at the index,
+    /// also giving the length of the whole payload
+    ///
+    /// Compressed sections still have to be read and decompressed whole.
+    pub fn read_payload(&mut self, index: usize, limit: usize) -> Result<(Vec<u8>, usize)> {
+        let (ref name, pos, size, flags) = self.sections[index];
+        let compressed = flags & SECTION_COMPRESSED != 0;
+        let len = if compressed {
+            size as usize
+        } else {
+            limit.min(size as usize)
+        };
+
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(&mut buf).map_err(|e| {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("section {name} is truncated"),
+                )
+            } else {
+                e
+            }
+        })?;
 
-        let (_, pos, size) = self.sections.remove(id);
-        match self.file.seek(SeekFrom::Start(pos)) {
-            Ok(_) => (),
-            Err(e) => return Some(Err(e)),
+        if compressed {
+            let mut buf = lz::decompress(&buf)?;
+            let len = buf.len();
+            buf.truncate(limit);
+            Ok((buf, len))
+        } else {
+            Ok((buf, size as usize))
+        }
+    }
+    /// CRC32 of the (uncompressed) payloads of all sections except the ones named `skip`, in file order
+    ///
+    /// Fails if the file ends before a section does.
+    pub fn payload_checksum(&mut self, skip: &str) -> Result<u32> {
+        let mut crc = Crc32::new();
+
+        for i in 0..self.sections.len() {
+            if &*self.sections[i].0 == skip {
+                continue;
+            }
+            crc.update(&self.read_payload(i, usize::MAX)?.0);
         }
 
-        let read_window = ReadWindow {
-            reader: &mut self.file,
-            length: size as usize,
-        };
-        Some(S::read(read_window))
+        Ok(crc.finish())
+    }
+    /// Name, file position, stored size and flags of the sections not read with [`Self::read_section`], in file order
+    pub fn section_headers(&self) -> impl Iterator<Item = (&str, u64, u16, u8)> {
+        self.sections
+            .iter()
+            .map(|&(ref name, pos, size, flags)| (&**name, pos, size, flags))
     }
     pub fn remaing_sections(&self) -> impl Iterator<Item = &str> {
-        self.sections.iter().map(|(s, _, _)| &**s)
+        self.sections.iter().map(|(s, _, _, _)| &**s)
+    }
+    /// Moves the underlying reader to right after this container
+    pub fn seek_to_end(&mut self) -> Result<()> {
+        self.file.seek(SeekFrom::Start(self.end_offset))?;
+        Ok(())
     }
 
     fn read_magic(&mut self) -> Result<()> {
         // Find magic
         loop {
-            let first_magic_byte = AALV_MAGIC.as_bytes()[0];
-            let mut magic_buf = [0; AALV_MAGIC.len()];
+            let first_magic_byte = AALV_MAGIC_PREFIX.as_bytes()[0];
+            let mut magic_buf = [0; AALV_MAGIC_PREFIX.len()];
 
             while first_magic_byte != magic_buf[0] {
                 self.file.read_exact(&mut magic_buf[..1])?;
@@ -83,13 +183,60 @@ impl<F: BufRead + Seek> AalvReader<F> {
             self.file_offset = self.file.stream_position()? - 1;
 
             self.file.read_exact(&mut magic_buf[1..])?;
-            if magic_buf == AALV_MAGIC.as_bytes() {
-                break;
+            if magic_buf == AALV_MAGIC_PREFIX.as_bytes() {
+                if let Some(version) = self.read_version()? {
+                    self.version = version;
+                    break;
+                }
+            }
+            // Not the magic, continue searching right after where this started
+            self.file.seek(SeekFrom::Start(self.file_offset + 1))?;
+        }
+
+        if self.version > AALV_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "file requires newer telda2 (álvur version {} but only up to {AALV_VERSION} is supported)",
+                    self.version
+                ),
+            ));
+        }
+        if self.version >= 4 {
+            let mut buf = [0; 4];
+            self.file.read_exact(&mut buf)?;
+            self.features = u32::from_le_bytes(buf);
+
+            let unknown = self.features & !KNOWN_FEATURES;
+            if unknown != 0 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("file requires newer telda2 (unknown álvur features {unknown:#010x})"),
+                ));
             }
         }
 
         Ok(())
     }
+    /// Reads the decimal version and newline after the magic prefix
+    fn read_version(&mut self) -> Result<Option<u32>> {
+        let mut version = 0u32;
+        let mut digits = 0;
+        loop {
+            let mut buf = [0];
+            self.file.read_exact(&mut buf)?;
+            match buf[0] {
+                b'\n' if digits > 0 => break,
+                d @ b'0'..=b'9' if digits < 9 => {
+                    version = version * 10 + (d - b'0') as u32;
+                    digits += 1;
+                }
+                _ => return Ok(None),
+            }
+        }
+
+        Ok(Some(version).filter(|&v| v >= 2))
+    }
 
     fn read_section_headers(&mut self) -> Result<()> {
         let mut name_buf = Vec::new();
@@ -106,10 +253,27 @@ impl<F: BufRead + Seek> AalvReader<F> {
 
             let name: Box<str> = String::from_utf8_lossy(&name_buf).into();
 
-            if name.len() == 0 {
+            if name.is_empty() {
+                self.end_offset = self.file.stream_position()?;
                 break;
             }
 
+            let flags = if self.version >= 3 {
+                let mut flag_buf = [0];
+                self.file.read_exact(&mut flag_buf)?;
+                flag_buf[0]
+            } else {
+                0
+            };
+            if flags & !SECTION_COMPRESSED != 0 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "file requires newer telda2 (section {name} has unknown flags {flags:02x})"
+                    ),
+                ));
+            }
+
             let mut num_buf = [0; 2];
             self.file.read_exact(&mut num_buf)?;
 
@@ -119,49 +283,30 @@ impl<F: BufRead + Seek> AalvReader<F> {
 
             self.file.seek(SeekFrom::Current(len as i64))?;
 
-            self.sections.push((name, pos, len));
-        }
-
-        Ok(())
-    }
-}
-
-struct ReadWindow<R: Read> {
-    reader: R,
-    length: usize,
-}
-
-impl<R: Read> Read for ReadWindow<R> {
-    fn read(&mut self, mut buf: &mut [u8]) -> Result<usize> {
-        if buf.len() > self.length {
-            buf = &mut buf[..self.length];
+            self.sections.push((name, pos, len, flags));
         }
 
-        let n = self.reader.read(buf)?;
-        self.length -= n;
-        Ok(n)
-    }
-    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
-        if buf.len() > self.length {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "read window is smaller",
-            ));
-        }
-
-        self.reader.read_exact(buf)?;
-        self.length -= buf.len();
         Ok(())
     }
 }
 
 pub struct AalvWriter<F: Write> {
     file: F,
+    crc: Crc32,
+    compress: bool,
+    features: u32,
+    features_pos: u64,
 }
 
 impl<F: Write + Seek> AalvWriter<F> {
     pub fn new(file: F, file_offset: u64) -> Result<Self> {
-        let mut new = AalvWriter { file };
+        let mut new = AalvWriter {
+            file,
+            crc: Crc32::new(),
+            compress: false,
+            features: 0,
+            features_pos: 0,
+        };
 
         new.file.seek(SeekFrom::Start(file_offset))?;
         new.write_magic()?;
@@ -169,7 +314,31 @@ impl<F: Write + Seek> AalvWriter<F> {
     }
 
     fn write_magic(&mut self) -> Result<()> {
-        self.file.write_all(AALV_MAGIC.as_bytes())
+        writeln!(self.file, "{AALV_MAGIC_PREFIX}{AALV_VERSION}")?;
+        self.features_pos = self.file.stream_position()?;
+        self.file.write_all(&self.features.to_le_bytes())
+    }
+    /// Marks the file as needing readers that understand these features
+    pub fn require_features(&mut self, features: u32) -> Result<()> {
+        if self.features & features == features {
+            return Ok(());
+        }
+        self.features |= features;
+
+        let pos = self.file.stream_position()?;
+        self.file.seek(SeekFrom::Start(self.features_pos))?;
+        self.file.write_all(&self.features.to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+    /// Whether to compress the sections written after this, if it makes them smaller
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    /// CRC32 of the payloads of all sections written so far
+    pub fn payload_checksum(&self) -> u32 {
+        self.crc.finish()
     }
 
     pub fn write_section<S: Section>(&mut self, segment: &S) -> Result<()> {
@@ -179,19 +348,32 @@ impl<F: Write + Seek> AalvWriter<F> {
         );
         assert_ne!(!S::NAME.len(), 0, "section name may not be empty");
 
-        write!(self.file, "{}\0\0\0", S::NAME)?;
-
-        let mut w = CountedWriter {
-            writer: &mut self.file,
-            length: 0,
-        };
+        let mut payload = Vec::new();
+        segment.write(&mut payload)?;
+        self.crc.update(&payload);
+
+        let mut flags = 0;
+        if self.compress && payload.len() >= COMPRESSION_THRESHOLD {
+            let compressed = lz::compress(&payload);
+            if compressed.len() < payload.len() {
+                payload = compressed;
+                flags |= SECTION_COMPRESSED;
+                self.require_features(FEATURE_COMPRESSION)?;
+            }
+        }
 
-        segment.write(&mut w)?;
-        let CountedWriter { writer: _, length } = w;
+        let length = payload.len();
+        if length > u16::MAX as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("section {} is too big ({length} bytes)", S::NAME),
+            ));
+        }
 
-        self.file.seek(SeekFrom::Current(-(length as i64) - 2))?;
+        write!(self.file, "{}\0", S::NAME)?;
+        self.file.write_all(&[flags])?;
         self.file.write_all(&(length as u16).to_le_bytes())?;
-        self.file.seek(SeekFrom::Current(length as i64))?;
+        self.file.write_all(&payload)?;
 
         Ok(())
     }
@@ -205,23 +387,31 @@ impl<F: Write> Drop for AalvWriter<F> {
     }
 }
 
-struct CountedWriter<W: Write> {
-    writer: W,
-    length: usize,
-}
+/// CRC-32 (IEEE) as used by zip and png
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
 
-impl<R: Write> Write for CountedWriter<R> {
-    fn flush(&mut self) -> Result<()> {
-        self.writer.flush()
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32(!0)
     }
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let n = self.writer.write(buf)?;
-        self.length += n;
-        Ok(n)
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
     }
-    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
-        self.length += buf.len();
-        self.writer.write_all(buf)
+    pub const fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -231,6 +421,14 @@ pub trait Section: Sized {
     fn write<W: Write>(&self, writer: W) -> Result<()>;
 }
 
+#[test]
+fn crc32_check_value() {
+    let mut crc = Crc32::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.finish(), 0xcbf4_3926);
+}
+
+mod lz;
 pub mod obj;
 pub mod sample {
     use super::Section;
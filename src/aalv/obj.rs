@@ -1,63 +1,81 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     fmt::{self, Display},
-    io::{self, BufRead, BufReader, Read, Write},
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, Write},
     path::Path,
+    str::FromStr,
 };
 
-use super::{read_aalv_file, write_aalv_file_with_offset, Section};
+use super::{
+    write_aalv_file_with_offset, AalvReader, Crc32, Section, CPU_FEATURES_MASK, CPU_FEATURES_SHIFT,
+};
+// They are what memory protection works with as well
+pub use crate::mem::{Region, SegmentFlags};
+
+mod builder;
+pub use self::builder::{BuildError, ObjectBuilder};
 
 pub const AALV_OBJECT_EXT: &str = "to";
+/// Extension of telda archives, which are just object files concatenated together
+pub const AALV_ARCHIVE_EXT: &str = "ta";
+
+/// Reads all objects in an archive file
+pub fn read_archive_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<Object>> {
+    let mut f = BufReader::new(File::open(path)?);
+    let mut objects = Vec::new();
+
+    while !f.fill_buf()?.is_empty() {
+        let obj = Object::from_reader(&mut f)?;
+        objects.push(obj);
+    }
+
+    Ok(objects)
+}
 
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Object {
     pub file_offset: u64,
     pub entry: Option<Entry>,
     pub segs: BTreeMap<SegmentType, (u16, Vec<u8>)>,
+    /// Segments without flags here use the default flags of their type
+    pub seg_flags: SegmentFlagsTable,
     pub symbols: SymbolTable,
     pub relocation_table: RelocationTable,
+    pub lines: LineTable,
+    pub notes: Notes,
+    /// Contents of the banks the banking device maps into its window, by bank number
+    pub banks: BTreeMap<u8, Vec<u8>>,
+    /// The CPU features the code needs, see [`crate::isa::feature`]
+    pub cpu_features: u32,
 }
 
 impl Object {
     pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let mut aalvur = read_aalv_file(path)?;
-
-        let mut segs = BTreeMap::new();
-
-        while let Some(seg) = aalvur.read_section() {
-            let BinarySegment {
-                offset,
-                stype,
-                bytes,
-            } = seg?;
-
-            if segs.insert(stype, (offset, bytes)).is_some() {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "duplicate segment type",
-                ));
-            }
-        }
-
-        let obj = Object {
-            file_offset: aalvur.file_offset,
-            entry: aalvur.read_section().transpose()?,
-            segs,
-            symbols: aalvur
-                .read_section()
-                .transpose()?
-                .unwrap_or_else(|| SymbolTable(Vec::new())),
-            relocation_table: aalvur
-                .read_section()
-                .transpose()?
-                .unwrap_or_else(|| RelocationTable(Vec::new())),
-        };
-
-        if aalvur.remaing_sections().any(|s| s.starts_with('_')) {
-            unimplemented!("error unexpected sections")
-        } else {
-            Ok(obj)
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+    /// Like [`Object::from_file`] but does not check the checksum
+    pub fn from_file_unverified<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?), false)
+    }
+    /// Reads an object from the current position of the reader
+    ///
+    /// Leaves the reader positioned right after the object.
+    /// If the object has a checksum, it is verified.
+    pub fn from_reader<R: BufRead + Seek>(reader: R) -> io::Result<Self> {
+        Self::read(reader, true)
+    }
+    /// Like [`Object::from_reader`] but does not check the checksum
+    pub fn from_reader_unverified<R: BufRead + Seek>(reader: R) -> io::Result<Self> {
+        Self::read(reader, false)
+    }
+    fn read<R: BufRead + Seek>(reader: R, verify: bool) -> io::Result<Self> {
+        let mut reader = ObjectReader::new(reader)?;
+        if verify {
+            reader.verify()?;
         }
+        reader.read_object()
     }
     pub fn zero_offset(self) -> Self {
         Self {
@@ -66,15 +84,34 @@ impl Object {
         }
     }
     pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to_file_with(path, false)
+    }
+    /// Writes the object, compressing the sections that get smaller from it if `compress` is set
+    pub fn write_to_file_with<P: AsRef<Path>>(&self, path: P, compress: bool) -> io::Result<()> {
         let Object {
             file_offset,
             entry,
             segs,
+            seg_flags,
             symbols,
             relocation_table,
+            lines,
+            notes,
+            banks,
+            cpu_features,
         } = self;
 
+        let cpu_bits = cpu_features << CPU_FEATURES_SHIFT;
+        if cpu_bits >> CPU_FEATURES_SHIFT != *cpu_features {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cpu features {cpu_features:#x} do not fit in the álvur header"),
+            ));
+        }
+
         let mut aalvur = write_aalv_file_with_offset(path, *file_offset)?;
+        aalvur.set_compression(compress);
+        aalvur.require_features(cpu_bits)?;
 
         if let Some(entry) = entry {
             aalvur.write_section(entry)?;
@@ -86,13 +123,110 @@ impl Object {
                 bytes: bytes.clone(),
             })?;
         }
+        if !seg_flags.0.is_empty() {
+            aalvur.write_section(seg_flags)?;
+        }
         if !symbols.0.is_empty() {
-            aalvur.write_section(symbols)?;
+            let mut strtab = StringTable::default();
+            let packed = PackedSymbolTable::from_symbols(symbols, &mut strtab);
+            let sources = SymbolSources::from_symbols(symbols, &mut strtab);
+            aalvur.write_section(&strtab)?;
+            aalvur.write_section(&packed)?;
+            if !sources.0.is_empty() {
+                aalvur.write_section(&sources)?;
+            }
+
+            let attrs = SymbolAttributes::from_symbols(symbols);
+            if !attrs.0.is_empty() {
+                aalvur.write_section(&attrs)?;
+            }
         }
         if !relocation_table.0.is_empty() {
             aalvur.write_section(relocation_table)?;
         }
+        if !lines.entries.is_empty() {
+            aalvur.write_section(lines)?;
+        }
+        if !notes.0.is_empty() {
+            aalvur.write_section(notes)?;
+        }
+        for (&number, bytes) in banks {
+            aalvur.write_section(&Bank {
+                number,
+                bytes: bytes.clone(),
+            })?;
+        }
+        aalvur.write_section(&Checksum(aalvur.payload_checksum()))?;
+
+        Ok(())
+    }
+
+    /// Flags of the segment, falling back to the default for its type
+    pub fn segment_flags(&self, stype: SegmentType) -> SegmentFlags {
+        self.seg_flags
+            .0
+            .get(&stype)
+            .copied()
+            .unwrap_or_else(|| SegmentFlags::default_for(stype))
+    }
+    /// The address ranges of all segments with their flags
+    pub fn memory_regions(&self) -> Vec<Region> {
+        self.segs
+            .iter()
+            .map(|(&stype, &(start, ref bytes))| Region {
+                start,
+                end: start as u32 + bytes.len() as u32,
+                flags: self.segment_flags(stype),
+            })
+            .collect()
+    }
 
+    /// CRC32 of where the segments are and what is in them, which stays the same when symbols are stripped
+    pub fn segments_checksum(&self) -> u32 {
+        let mut crc = Crc32::new();
+        for (&stype, (start, bytes)) in &self.segs {
+            crc.update(&[stype as u8]);
+            crc.update(&start.to_le_bytes());
+            crc.update(bytes);
+        }
+        crc.finish()
+    }
+    /// Moves the symbols and line table out into an object of their own, so the rest can be shipped without them
+    ///
+    /// The debug object has a note with the [`Object::segments_checksum`] of this one as its only source, under the
+    /// name given, to check that it is loaded for the right binary. The relocations go too, like `tstrip -a` does.
+    pub fn split_debug(&mut self, binary_name: &str, producer: &str) -> Object {
+        self.relocation_table = RelocationTable::default();
+        Object {
+            symbols: std::mem::take(&mut self.symbols),
+            lines: std::mem::take(&mut self.lines),
+            notes: Notes(vec![Note {
+                producer: format!("{producer} {}", env!("CARGO_PKG_VERSION")).into(),
+                flags: "split debug info".into(),
+                sources: vec![(binary_name.into(), self.segments_checksum())],
+            }]),
+            ..Object::default()
+        }
+    }
+    /// Takes the symbols and line table of a debug object made by [`Object::split_debug`]
+    ///
+    /// Errs if it was split from a binary with other segments.
+    pub fn attach_debug(&mut self, debug: Object) -> io::Result<()> {
+        let checksum = self.segments_checksum();
+        let matches = debug
+            .notes
+            .0
+            .iter()
+            .flat_map(|note| &note.sources)
+            .any(|&(_, crc)| crc == checksum);
+        if !matches {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the debug info is of another binary",
+            ));
+        }
+        self.symbols = debug.symbols;
+        self.lines = debug.lines;
         Ok(())
     }
 
@@ -113,7 +247,248 @@ impl Object {
     }
 }
 
+/// Names of the sections this version understands, other sections starting with `_` need a newer version
+pub const KNOWN_SECTIONS: &[&str] = &[
+    Entry::NAME,
+    BinarySegment::NAME,
+    SegmentFlagsTable::NAME,
+    SymbolTable::NAME,
+    StringTable::NAME,
+    PackedSymbolTable::NAME,
+    SymbolAttributes::NAME,
+    SymbolSources::NAME,
+    RelocationTable::NAME,
+    LEGACY_RELOCATION_TABLE,
+    LineTable::NAME,
+    Notes::NAME,
+    Bank::NAME,
+    Checksum::NAME,
+];
+
+/// Size and place of a segment, known without reading its bytes
 #[derive(Debug, Clone, Copy)]
+pub struct SegmentInfo {
+    pub stype: SegmentType,
+    pub start: u16,
+    pub size: u16,
+}
+
+/// Reads the sections of an object only when they are asked for
+///
+/// Opening only reads the section directory and the headers of the segments,
+/// so tools that only need e.g. the symbols don't read all segment data.
+/// Unlike [`Object::from_file`] the checksum is only checked by [`ObjectReader::verify`].
+pub struct ObjectReader<R> {
+    aalvur: AalvReader<R>,
+    /// With the index of their section
+    segments: Vec<(SegmentInfo, usize)>,
+}
+
+impl ObjectReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead + Seek> ObjectReader<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut aalvur = AalvReader::new(reader)?;
+
+        let unknown = aalvur
+            .remaing_sections()
+            .find(|s| s.starts_with('_') && !KNOWN_SECTIONS.contains(s));
+        if let Some(s) = unknown {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file requires newer telda2 (unknown section {s})"),
+            ));
+        }
+
+        let mut segments: Vec<(SegmentInfo, usize)> = Vec::new();
+        for index in aalvur.positions(BinarySegment::NAME).collect::<Vec<_>>() {
+            let (header, len) = aalvur.read_payload(index, 3)?;
+            let &[sl, sh, stype] = &*header else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "segment section too small",
+                ));
+            };
+            let info = SegmentInfo {
+                stype: segment_type_from_u8(stype)?,
+                start: u16::from_le_bytes([sl, sh]),
+                size: (len - 3) as u16,
+            };
+            if segments.iter().any(|(s, _)| s.stype == info.stype) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "duplicate segment type",
+                ));
+            }
+            segments.push((info, index));
+        }
+
+        Ok(ObjectReader { aalvur, segments })
+    }
+    /// Checks the checksum if the object has one
+    pub fn verify(&mut self) -> io::Result<()> {
+        let Some(Checksum(expected)) = self.read(Checksum::NAME)? else {
+            return Ok(());
+        };
+        let actual = self.aalvur.payload_checksum(Checksum::NAME)?;
+        if actual != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("checksum mismatch (expected {expected:08x}, got {actual:08x}), the object file is corrupted"),
+            ));
+        }
+        Ok(())
+    }
+    fn read<S: Section>(&mut self, name: &str) -> io::Result<Option<S>> {
+        self.aalvur
+            .position(name)
+            .map(|i| self.aalvur.read_section_at(i))
+            .transpose()
+    }
+
+    pub fn file_offset(&self) -> u64 {
+        self.aalvur.file_offset
+    }
+    /// Version of the álvur format the object was written with
+    pub fn version(&self) -> u32 {
+        self.aalvur.version
+    }
+    /// Features a reader needs to understand the object, see [`crate::aalv::KNOWN_FEATURES`]
+    pub fn features(&self) -> u32 {
+        self.aalvur.features
+    }
+    /// Name, file position, stored size and flags of every section, in file order
+    pub fn sections(&self) -> impl Iterator<Item = (&str, u64, u16, u8)> {
+        self.aalvur.section_headers()
+    }
+    pub fn segments(&self) -> impl Iterator<Item = SegmentInfo> + '_ {
+        self.segments.iter().map(|&(info, _)| info)
+    }
+    /// Reads the bytes of the segment, if it exists
+    pub fn segment(&mut self, stype: SegmentType) -> io::Result<Option<(u16, Vec<u8>)>> {
+        let Some(&(_, index)) = self.segments.iter().find(|(s, _)| s.stype == stype) else {
+            return Ok(None);
+        };
+        let BinarySegment { offset, bytes, .. } = self.aalvur.read_section_at(index)?;
+        Ok(Some((offset, bytes)))
+    }
+    pub fn entry(&mut self) -> io::Result<Option<Entry>> {
+        self.read(Entry::NAME)
+    }
+    pub fn segment_flags(&mut self) -> io::Result<SegmentFlagsTable> {
+        Ok(self.read(SegmentFlagsTable::NAME)?.unwrap_or_default())
+    }
+    /// Reads the symbols with their attributes
+    ///
+    /// Reads the string table and symbol table, falling back to the older `_syms` section with inline names.
+    pub fn symbols(&mut self) -> io::Result<SymbolTable> {
+        let mut symbols = match self.read::<PackedSymbolTable>(PackedSymbolTable::NAME)? {
+            Some(packed) => {
+                let strtab: StringTable = self.read(StringTable::NAME)?.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "symbol table without a string table",
+                    )
+                })?;
+                let mut symbols = packed.resolve(&strtab)?;
+                if let Some(sources) = self.read::<SymbolSources>(SymbolSources::NAME)? {
+                    sources.apply(&mut symbols, &strtab)?;
+                }
+                symbols
+            }
+            None => self
+                .read(SymbolTable::NAME)?
+                .unwrap_or_else(|| SymbolTable(Vec::new())),
+        };
+        if let Some(attrs) = self.read::<SymbolAttributes>(SymbolAttributes::NAME)? {
+            attrs.apply(&mut symbols)?;
+        }
+        Ok(symbols)
+    }
+    /// Reads the relocations, falling back to the older `_reloc` section without kinds
+    pub fn relocation_table(&mut self) -> io::Result<RelocationTable> {
+        if let Some(table) = self.read(RelocationTable::NAME)? {
+            return Ok(table);
+        }
+        match self.aalvur.position(LEGACY_RELOCATION_TABLE) {
+            Some(i) => {
+                let (payload, _) = self.aalvur.read_payload(i, usize::MAX)?;
+                read_legacy_relocations(&*payload)
+            }
+            None => Ok(RelocationTable::default()),
+        }
+    }
+    pub fn lines(&mut self) -> io::Result<LineTable> {
+        Ok(self.read(LineTable::NAME)?.unwrap_or_default())
+    }
+    pub fn notes(&mut self) -> io::Result<Notes> {
+        Ok(self.read(Notes::NAME)?.unwrap_or_default())
+    }
+    pub fn banks(&mut self) -> io::Result<BTreeMap<u8, Vec<u8>>> {
+        let mut banks = BTreeMap::new();
+        for index in self.aalvur.positions(Bank::NAME).collect::<Vec<_>>() {
+            let Bank { number, bytes } = self.aalvur.read_section_at(index)?;
+            if banks.insert(number, bytes).is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("duplicate bank {number}"),
+                ));
+            }
+        }
+        Ok(banks)
+    }
+    /// The CPU features the code needs, from the upper half of the header's feature word
+    pub fn cpu_features(&self) -> u32 {
+        (self.aalvur.features & CPU_FEATURES_MASK) >> CPU_FEATURES_SHIFT
+    }
+    /// Reads everything into an [`Object`], leaving the reader right after the object
+    pub fn read_object(mut self) -> io::Result<Object> {
+        let mut segs = BTreeMap::new();
+        for (info, _) in self.segments.clone() {
+            let seg = self.segment(info.stype)?.expect("segment was found before");
+            segs.insert(info.stype, seg);
+        }
+
+        let obj = Object {
+            file_offset: self.file_offset(),
+            entry: self.entry()?,
+            segs,
+            seg_flags: self.segment_flags()?,
+            symbols: self.symbols()?,
+            relocation_table: self.relocation_table()?,
+            lines: self.lines()?,
+            notes: self.notes()?,
+            banks: self.banks()?,
+            cpu_features: self.cpu_features(),
+        };
+        self.aalvur.seek_to_end()?;
+        Ok(obj)
+    }
+}
+
+/// CRC32 of the payloads of all other sections, see [`AalvReader::payload_checksum`]
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum(pub u32);
+
+impl Section for Checksum {
+    const NAME: &'static str = "_crc";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf)?;
+        Ok(Checksum(u32::from_le_bytes(buf)))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.0.to_le_bytes())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entry(pub SegmentType, pub u16);
 
 impl Section for Entry {
@@ -132,6 +507,7 @@ impl Section for Entry {
 
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SegmentType {
     Unknown = 0xff,
     Zero = 0,
@@ -154,6 +530,23 @@ impl Display for SegmentType {
     }
 }
 
+impl FromStr for SegmentType {
+    type Err = String;
+
+    /// Parses the names of segments that can hold data, as written in `.seg`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "data" => Ok(SegmentType::Data),
+            "rodata" => Ok(SegmentType::RoData),
+            "text" => Ok(SegmentType::Text),
+            "heap" => Ok(SegmentType::Heap),
+            s => Err(format!(
+                "unknown segment {s}, expected data, rodata, text or heap"
+            )),
+        }
+    }
+}
+
 impl TryFrom<u8> for SegmentType {
     type Error = ();
 
@@ -171,6 +564,103 @@ impl TryFrom<u8> for SegmentType {
     }
 }
 
+impl SegmentFlags {
+    pub const fn default_for(stype: SegmentType) -> Self {
+        use self::SegmentType::*;
+        match stype {
+            Unknown => Self::NONE,
+            Zero => Self::READ.union(Self::WRITE).union(Self::EXECUTE),
+            Data | Heap => Self::READ.union(Self::WRITE),
+            RoData => Self::READ,
+            Text => Self::READ.union(Self::EXECUTE),
+        }
+    }
+    /// Parses flags written like `rwx`, `rx` or `r`
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut flags = Self::NONE;
+        for c in s.chars() {
+            let flag = match c {
+                'r' => Self::READ,
+                'w' => Self::WRITE,
+                'x' => Self::EXECUTE,
+                _ => return None,
+            };
+            flags = flags.union(flag);
+        }
+        Some(flags)
+    }
+}
+
+impl Display for SegmentFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (flag, c) in [(Self::READ, 'r'), (Self::WRITE, 'w'), (Self::EXECUTE, 'x')] {
+            if self.contains(flag) {
+                write!(f, "{c}")?;
+            } else {
+                write!(f, "-")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentFlagsTable(pub BTreeMap<SegmentType, SegmentFlags>);
+
+impl Section for SegmentFlagsTable {
+    const NAME: &'static str = "_segflags";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut flags = BTreeMap::new();
+
+        loop {
+            let mut buf = [0; 2];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let [stype, f] = buf;
+            flags.insert(segment_type_from_u8(stype)?, SegmentFlags(f));
+        }
+
+        Ok(SegmentFlagsTable(flags))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (&stype, &SegmentFlags(f)) in &self.0 {
+            writer.write_all(&[stype as u8, f])?;
+        }
+        Ok(())
+    }
+}
+
+/// The contents of a bank, starting at the bank window
+#[derive(Debug, Clone)]
+pub struct Bank {
+    pub number: u8,
+    pub bytes: Vec<u8>,
+}
+
+impl Section for Bank {
+    const NAME: &'static str = "_bank";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut number = [0];
+        reader.read_exact(&mut number)?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Bank {
+            number: number[0],
+            bytes,
+        })
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&[self.number])?;
+        writer.write_all(&self.bytes)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BinarySegment {
     pub offset: u16,
@@ -204,15 +694,214 @@ impl Section for BinarySegment {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SymbolDefinition {
     // No nulls, no initial whitespace
     pub name: Box<str>,
     pub is_global: bool,
     pub segment_type: SegmentType,
     pub location: u16,
+    pub kind: SymbolKind,
+    /// Size in bytes of what the symbol refers to, if known
+    pub size: Option<u16>,
+    /// The source file that defined the symbol followed by the files that included it, if known
+    pub defined_in: Vec<Box<str>>,
+}
+
+impl SymbolDefinition {
+    /// Describes where the symbol was defined, like `lib/math.telda included from main.telda`
+    pub fn source_description(&self) -> Option<String> {
+        let (file, includers) = self.defined_in.split_first()?;
+        let mut s = file.to_string();
+        for includer in includers {
+            s.push_str(" included from ");
+            s.push_str(includer);
+        }
+        Some(s)
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SymbolKind {
+    #[default]
+    NoType = 0,
+    Function = 1,
+    Object = 2,
+    Section = 3,
+}
+
+impl Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolKind::NoType => write!(f, "notype"),
+            SymbolKind::Function => write!(f, "function"),
+            SymbolKind::Object => write!(f, "object"),
+            SymbolKind::Section => write!(f, "section"),
+        }
+    }
 }
 
+impl TryFrom<u8> for SymbolKind {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        use self::SymbolKind::*;
+        match value {
+            0 => Ok(NoType),
+            1 => Ok(Function),
+            2 => Ok(Object),
+            3 => Ok(Section),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Kinds and sizes of symbols that have them, by index in the symbol table
 #[derive(Debug, Clone, Default)]
+struct SymbolAttributes(Vec<(u16, SymbolKind, Option<u16>)>);
+
+impl SymbolAttributes {
+    fn from_symbols(symbols: &SymbolTable) -> Self {
+        SymbolAttributes(
+            symbols
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.kind != SymbolKind::NoType || s.size.is_some())
+                .map(|(i, s)| (i as u16, s.kind, s.size))
+                .collect(),
+        )
+    }
+    fn apply(self, symbols: &mut SymbolTable) -> io::Result<()> {
+        for (i, kind, size) in self.0 {
+            let sym = symbols.0.get_mut(i as usize).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "symbol attributes for a symbol that does not exist",
+                )
+            })?;
+            sym.kind = kind;
+            sym.size = size;
+        }
+        Ok(())
+    }
+}
+
+impl Section for SymbolAttributes {
+    const NAME: &'static str = "_symattrs";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut attrs = Vec::new();
+
+        loop {
+            let mut buf = [0; 6];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let [il, ih, kind, has_size, sl, sh] = buf;
+
+            let kind = SymbolKind::try_from(kind).map_err(|()| {
+                io::Error::new(io::ErrorKind::InvalidData, "unrecognised symbol kind")
+            })?;
+            let size = (has_size != 0).then(|| u16::from_le_bytes([sl, sh]));
+            attrs.push((u16::from_le_bytes([il, ih]), kind, size));
+        }
+
+        Ok(SymbolAttributes(attrs))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for &(i, kind, size) in &self.0 {
+            writer.write_all(&i.to_le_bytes())?;
+            writer.write_all(&[kind as u8, size.is_some() as u8])?;
+            writer.write_all(&size.unwrap_or(0).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Where symbols were defined, by index in the symbol table
+///
+/// Each file is an offset into the [`StringTable`] of the symbols.
+struct SymbolSources(Vec<(u16, Vec<u16>)>);
+
+impl SymbolSources {
+    fn from_symbols(symbols: &SymbolTable, strtab: &mut StringTable) -> Self {
+        SymbolSources(
+            symbols
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| !s.defined_in.is_empty())
+                .map(|(i, s)| {
+                    let files = s.defined_in.iter().map(|f| strtab.insert(f)).collect();
+                    (i as u16, files)
+                })
+                .collect(),
+        )
+    }
+    fn apply(self, symbols: &mut SymbolTable, strtab: &StringTable) -> io::Result<()> {
+        for (i, files) in self.0 {
+            let sym = symbols.0.get_mut(i as usize).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "symbol source for a symbol that does not exist",
+                )
+            })?;
+            sym.defined_in = files
+                .into_iter()
+                .map(|f| strtab.get(f).map(Box::from))
+                .collect::<io::Result<_>>()?;
+        }
+        Ok(())
+    }
+}
+
+impl Section for SymbolSources {
+    const NAME: &'static str = "_symsrc";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut sources = Vec::new();
+
+        loop {
+            let mut buf = [0; 3];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let [il, ih, count] = buf;
+
+            let mut files = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let mut buf = [0; 2];
+                reader.read_exact(&mut buf)?;
+                files.push(u16::from_le_bytes(buf));
+            }
+            sources.push((u16::from_le_bytes([il, ih]), files));
+        }
+
+        Ok(SymbolSources(sources))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (i, files) in &self.0 {
+            writer.write_all(&i.to_le_bytes())?;
+            // Include chains longer than this are cut off
+            let files = &files[..files.len().min(u8::MAX as usize)];
+            writer.write_all(&[files.len() as u8])?;
+            for f in files {
+                writer.write_all(&f.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SymbolTable(pub Vec<SymbolDefinition>);
 
 impl SymbolTable {
@@ -225,6 +914,7 @@ impl SymbolTable {
             is_global,
             segment_type,
             location,
+            ..
         } in &mut self.0
         {
             f(name, is_global, segment_type, location);
@@ -274,6 +964,9 @@ impl Section for SymbolTable {
                 is_global,
                 segment_type,
                 location: u16::from_le_bytes([ol, oh]),
+                kind: SymbolKind::NoType,
+                size: None,
+                defined_in: Vec::new(),
             };
             symbols.push(def);
         }
@@ -286,6 +979,7 @@ impl Section for SymbolTable {
             is_global,
             segment_type,
             location,
+            ..
         } in &self.0
         {
             write!(writer, "{}{name}\0", if is_global { "" } else { " " })?;
@@ -297,24 +991,222 @@ impl Section for SymbolTable {
     }
 }
 
+/// Zero-terminated strings referred to by their offset into the table
+///
+/// Inserting a string that is already in the table gives back the same offset.
+#[derive(Debug, Clone, Default)]
+pub struct StringTable {
+    bytes: Vec<u8>,
+    offsets: HashMap<Box<str>, u16>,
+}
+
+impl StringTable {
+    pub fn insert(&mut self, s: &str) -> u16 {
+        if let Some(&offset) = self.offsets.get(s) {
+            return offset;
+        }
+        let offset = self.bytes.len() as u16;
+        self.bytes.extend_from_slice(s.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(s.into(), offset);
+
+        offset
+    }
+    pub fn get(&self, offset: u16) -> io::Result<&str> {
+        let bytes = self.bytes.get(offset as usize..).unwrap_or_default();
+        let end = bytes.iter().position(|&b| b == 0).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "string table offset does not point at a string",
+            )
+        })?;
+
+        std::str::from_utf8(&bytes[..end])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Section for StringTable {
+    const NAME: &'static str = "_strtab";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(StringTable {
+            bytes,
+            offsets: HashMap::new(),
+        })
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.bytes)
+    }
+}
+
+const SYMBOL_GLOBAL: u8 = 1;
+
+/// Symbol table with names as offsets into a [`StringTable`]
+///
+/// Each entry is the name offset, flags, segment type and location.
+struct PackedSymbolTable(Vec<(u16, u8, SegmentType, u16)>);
+
+impl PackedSymbolTable {
+    fn from_symbols(symbols: &SymbolTable, strtab: &mut StringTable) -> Self {
+        PackedSymbolTable(
+            symbols
+                .0
+                .iter()
+                .map(|def| {
+                    let flags = if def.is_global { SYMBOL_GLOBAL } else { 0 };
+                    (
+                        strtab.insert(&def.name),
+                        flags,
+                        def.segment_type,
+                        def.location,
+                    )
+                })
+                .collect(),
+        )
+    }
+    fn resolve(self, strtab: &StringTable) -> io::Result<SymbolTable> {
+        self.0
+            .into_iter()
+            .map(|(name, flags, segment_type, location)| {
+                Ok(SymbolDefinition {
+                    name: strtab.get(name)?.into(),
+                    is_global: flags & SYMBOL_GLOBAL != 0,
+                    segment_type,
+                    location,
+                    kind: SymbolKind::NoType,
+                    size: None,
+                    defined_in: Vec::new(),
+                })
+            })
+            .collect::<io::Result<_>>()
+            .map(SymbolTable)
+    }
+}
+
+impl Section for PackedSymbolTable {
+    const NAME: &'static str = "_symtab";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut entries = Vec::new();
+
+        loop {
+            let mut buf = [0; 6];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let [nl, nh, flags, stype, ol, oh] = buf;
+
+            entries.push((
+                u16::from_le_bytes([nl, nh]),
+                flags,
+                segment_type_from_u8(stype)?,
+                u16::from_le_bytes([ol, oh]),
+            ));
+        }
+
+        Ok(PackedSymbolTable(entries))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for &(name, flags, segment_type, location) in &self.0 {
+            writer.write_all(&name.to_le_bytes())?;
+            writer.write_all(&[flags, segment_type as u8])?;
+            writer.write_all(&location.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// How the address of a symbol is patched in at a relocation
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RelocationKind {
+    /// The whole address as a little-endian wide
+    #[default]
+    Wide = 0,
+    /// Only the lower byte of the address, from `lo(label)`
+    ByteLow = 1,
+    /// Only the upper byte of the address, from `hi(label)`
+    ByteHigh = 2,
+    /// How far the address is from the end of the byte, as a signed byte, for short jumps
+    Relative = 3,
+    /// Like [`RelocationKind::Wide`] as the target of a jump, which `tl --relax` may make short
+    Jump = 4,
+}
+
+impl RelocationKind {
+    /// Amount of bytes that get patched
+    pub const fn size(self) -> u16 {
+        match self {
+            RelocationKind::Wide | RelocationKind::Jump => 2,
+            RelocationKind::ByteLow | RelocationKind::ByteHigh | RelocationKind::Relative => 1,
+        }
+    }
+    /// Patches the address into the start of `bytes`, which are at `location`
+    pub fn apply(self, bytes: &mut [u8], address: u16, location: u16) {
+        let [lo, hi] = address.to_le_bytes();
+        match self {
+            RelocationKind::Wide | RelocationKind::Jump => bytes[..2].copy_from_slice(&[lo, hi]),
+            RelocationKind::ByteLow => bytes[0] = lo,
+            RelocationKind::ByteHigh => bytes[0] = hi,
+            RelocationKind::Relative => {
+                bytes[0] = address.wrapping_sub(location.wrapping_add(1)) as u8
+            }
+        }
+    }
+}
+
+impl Display for RelocationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelocationKind::Wide => write!(f, "wide"),
+            RelocationKind::ByteLow => write!(f, "lo"),
+            RelocationKind::ByteHigh => write!(f, "hi"),
+            RelocationKind::Relative => write!(f, "rel"),
+            RelocationKind::Jump => write!(f, "jump"),
+        }
+    }
+}
+
+impl TryFrom<u8> for RelocationKind {
+    type Error = ();
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(RelocationKind::Wide),
+            1 => Ok(RelocationKind::ByteLow),
+            2 => Ok(RelocationKind::ByteHigh),
+            3 => Ok(RelocationKind::Relative),
+            4 => Ok(RelocationKind::Jump),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RelocationEntry {
     pub reference_segment: SegmentType,
     pub reference_location: u16,
     pub symbol_index: u16,
-    // Future perhaps a format field again
+    pub kind: RelocationKind,
 }
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RelocationTable(pub Vec<RelocationEntry>);
 
 impl Section for RelocationTable {
-    const NAME: &'static str = "_reloc";
+    const NAME: &'static str = "_relocs";
 
     fn read<R: Read>(mut reader: R) -> io::Result<Self> {
         let mut entries = Vec::new();
 
         loop {
-            let mut buf = [0; 5];
+            let mut buf = [0; 6];
             match reader.read_exact(&mut buf) {
                 Ok(()) => (),
                 Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
@@ -322,18 +1214,21 @@ impl Section for RelocationTable {
                 }
                 Err(e) => return Err(e),
             }
-            let [stype, ol1, oh1, ol2, oh2] = buf;
+            let [kind, stype, ol1, oh1, ol2, oh2] = buf;
 
-            let reference_segment = segment_type_from_u8(stype)?;
-            let reference_location = u16::from_le_bytes([ol1, oh1]);
-            let symbol_index = u16::from_le_bytes([ol2, oh2]);
+            let kind = RelocationKind::try_from(kind).map_err(|()| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "file requires newer telda2 (unknown relocation kind)",
+                )
+            })?;
 
-            let entry = RelocationEntry {
-                reference_segment,
-                reference_location,
-                symbol_index,
-            };
-            entries.push(entry)
+            entries.push(RelocationEntry {
+                reference_segment: segment_type_from_u8(stype)?,
+                reference_location: u16::from_le_bytes([ol1, oh1]),
+                symbol_index: u16::from_le_bytes([ol2, oh2]),
+                kind,
+            });
         }
 
         Ok(RelocationTable(entries))
@@ -343,9 +1238,10 @@ impl Section for RelocationTable {
             reference_segment,
             reference_location,
             symbol_index,
+            kind,
         } in &self.0
         {
-            writer.write_all(&[reference_segment as u8])?;
+            writer.write_all(&[kind as u8, reference_segment as u8])?;
             writer.write_all(&reference_location.to_le_bytes())?;
             writer.write_all(&symbol_index.to_le_bytes())?;
         }
@@ -353,7 +1249,422 @@ impl Section for RelocationTable {
     }
 }
 
+/// The name of the relocation table from before relocations had kinds, which is only ever read
+const LEGACY_RELOCATION_TABLE: &str = "_reloc";
+
+/// Reads the relocation table from before relocations had kinds, they are all [`RelocationKind::Wide`]
+fn read_legacy_relocations<R: Read>(mut reader: R) -> io::Result<RelocationTable> {
+    let mut entries = Vec::new();
+
+    loop {
+        let mut buf = [0; 5];
+        match reader.read_exact(&mut buf) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+        let [stype, ol1, oh1, ol2, oh2] = buf;
+
+        let reference_segment = segment_type_from_u8(stype)?;
+        let reference_location = u16::from_le_bytes([ol1, oh1]);
+        let symbol_index = u16::from_le_bytes([ol2, oh2]);
+
+        let entry = RelocationEntry {
+            reference_segment,
+            reference_location,
+            symbol_index,
+            kind: RelocationKind::Wide,
+        };
+        entries.push(entry)
+    }
+
+    Ok(RelocationTable(entries))
+}
+
+/// Marks the start of code or data that was produced by a line of source code
+///
+/// It lasts until the next entry.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineEntry {
+    pub segment: SegmentType,
+    pub location: u16,
+    /// Index into the file names of the line table
+    pub file: u16,
+    pub line: u32,
+}
+
+/// Debug information mapping locations to source lines
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineTable {
+    pub files: Vec<Box<str>>,
+    /// Sorted by location
+    pub entries: Vec<LineEntry>,
+}
+
+impl LineTable {
+    /// Index of the file name, adding it if it is not in the table yet
+    pub fn file_id(&mut self, file: &str) -> u16 {
+        match self.files.iter().position(|f| &**f == file) {
+            Some(i) => i as u16,
+            None => {
+                self.files.push(file.into());
+                self.files.len() as u16 - 1
+            }
+        }
+    }
+    /// Finds the source file and line that produced the location
+    pub fn lookup(&self, location: u16) -> Option<(&str, u32)> {
+        let i = self.entries.partition_point(|e| e.location <= location);
+        let entry = self.entries[..i].last()?;
+
+        Some((&self.files[entry.file as usize], entry.line))
+    }
+    pub fn sort(&mut self) {
+        self.entries.sort_by_key(|e| e.location);
+    }
+}
+
+/// Names locations by the closest symbol at or before them, like `0x001f <loop+0x8>`
+///
+/// Only symbols with a name count, and only for locations in their own segment and within their size when it is
+/// known. Global symbols win over local ones at the same location.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolLookup {
+    /// The name and size of the symbol at each location
+    by_location: BTreeMap<u16, (Box<str>, Option<u16>)>,
+    /// Where the segments start and end
+    segments: Vec<(u16, usize)>,
+}
+
+impl SymbolLookup {
+    pub fn new(obj: &Object) -> Self {
+        let mut lookup = SymbolLookup {
+            by_location: BTreeMap::new(),
+            segments: obj
+                .segs
+                .values()
+                .map(|(start, bytes)| (*start, *start as usize + bytes.len()))
+                .collect(),
+        };
+        for s in obj.symbols.0.iter().filter(|s| !s.name.is_empty()) {
+            let symbol = (s.name.clone(), s.size);
+            if s.is_global {
+                lookup.by_location.insert(s.location, symbol);
+            } else {
+                lookup.by_location.entry(s.location).or_insert(symbol);
+            }
+        }
+        lookup
+    }
+    /// The symbol exactly at the location
+    pub fn at(&self, location: u16) -> Option<&str> {
+        self.by_location.get(&location).map(|(name, _)| &**name)
+    }
+    /// The closest symbol at or before the location and how far after it the location is
+    pub fn nearest(&self, location: u16) -> Option<(&str, u16)> {
+        let &(start, _) = self
+            .segments
+            .iter()
+            .find(|&&(start, end)| start <= location && (location as usize) < end)?;
+        self.by_location
+            .range(start..=location)
+            .rev()
+            .map(|(&l, (name, size))| (&**name, location - l, *size))
+            .find(|&(_, offset, size)| size.is_none_or(|size| offset < size))
+            .map(|(name, offset, _)| (name, offset))
+    }
+    /// Like `<loop+0x8>`, or `<loop>` at the symbol itself
+    pub fn label(&self, location: u16) -> Option<String> {
+        Some(match self.nearest(location)? {
+            (name, 0) => format!("<{name}>"),
+            (name, offset) => format!("<{name}+0x{offset:x}>"),
+        })
+    }
+    /// Like `0x001f <loop+0x8>`, or just `0x001f` without a symbol before it
+    pub fn describe(&self, location: u16) -> String {
+        match self.label(location) {
+            Some(label) => format!("{location:#06x} {label}"),
+            None => format!("{location:#06x}"),
+        }
+    }
+}
+
+impl Section for LineTable {
+    const NAME: &'static str = "_lines";
+
+    fn read<R: Read>(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf)?;
+        let file_count = u16::from_le_bytes(buf);
+
+        let mut files = Vec::with_capacity(file_count as usize);
+        for _ in 0..file_count {
+            let mut namebuf = Vec::new();
+            reader.read_until(0, &mut namebuf)?;
+            if namebuf.pop() != Some(0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "file name did not end in a zero byte",
+                ));
+            }
+            files.push(String::from_utf8_lossy(&namebuf).into());
+        }
+
+        let mut entries = Vec::new();
+        loop {
+            let mut buf = [0; 9];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let [stype, ol, oh, fl, fh, l1, l2, l3, l4] = buf;
+
+            let file = u16::from_le_bytes([fl, fh]);
+            if file as usize >= files.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "line entry refers to a file that does not exist",
+                ));
+            }
+
+            entries.push(LineEntry {
+                segment: segment_type_from_u8(stype)?,
+                location: u16::from_le_bytes([ol, oh]),
+                file,
+                line: u32::from_le_bytes([l1, l2, l3, l4]),
+            });
+        }
+
+        Ok(LineTable { files, entries })
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&(self.files.len() as u16).to_le_bytes())?;
+        for file in &self.files {
+            write!(writer, "{file}\0")?;
+        }
+        for &LineEntry {
+            segment,
+            location,
+            file,
+            line,
+        } in &self.entries
+        {
+            writer.write_all(&[segment as u8])?;
+            writer.write_all(&location.to_le_bytes())?;
+            writer.write_all(&file.to_le_bytes())?;
+            writer.write_all(&line.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Records which tool made an object and from what
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Note {
+    /// Name and version of the tool
+    pub producer: Box<str>,
+    /// Options the tool was run with
+    pub flags: Box<str>,
+    /// Input files with the CRC32 of their contents
+    pub sources: Vec<(Box<str>, u32)>,
+}
+
+impl Note {
+    /// A note from this version of a telda2 tool with the hashes of the source files
+    ///
+    /// Source files that cannot be read are left out.
+    pub fn new<S: AsRef<str>>(tool: &str, flags: &str, sources: &[S]) -> Self {
+        let sources = sources
+            .iter()
+            .filter_map(|src| {
+                let src = src.as_ref();
+                let contents = std::fs::read(src).ok()?;
+                let mut crc = Crc32::new();
+                crc.update(&contents);
+                Some((src.into(), crc.finish()))
+            })
+            .collect();
+
+        Note {
+            producer: format!("{tool} {}", env!("CARGO_PKG_VERSION")).into(),
+            flags: flags.into(),
+            sources,
+        }
+    }
+}
+
+/// Notes of all tools that were involved in making the object, kept through linking
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Notes(pub Vec<Note>);
+
+impl Section for Notes {
+    const NAME: &'static str = "_notes";
+
+    fn read<R: Read>(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut notes = Vec::new();
+
+        let read_str = |reader: &mut BufReader<R>| -> io::Result<Option<Box<str>>> {
+            let mut buf = Vec::new();
+            if reader.read_until(0, &mut buf)? == 0 {
+                return Ok(None);
+            }
+            if buf.pop() != Some(0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "note string did not end in a zero byte",
+                ));
+            }
+            Ok(Some(String::from_utf8_lossy(&buf).into()))
+        };
+        let cut_off = || io::Error::new(io::ErrorKind::InvalidData, "note is cut off");
+
+        while let Some(producer) = read_str(&mut reader)? {
+            let flags = read_str(&mut reader)?.ok_or_else(cut_off)?;
+
+            let mut buf = [0; 2];
+            reader.read_exact(&mut buf)?;
+            let count = u16::from_le_bytes(buf);
+
+            let mut sources = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let name = read_str(&mut reader)?.ok_or_else(cut_off)?;
+                let mut buf = [0; 4];
+                reader.read_exact(&mut buf)?;
+                sources.push((name, u32::from_le_bytes(buf)));
+            }
+
+            notes.push(Note {
+                producer,
+                flags,
+                sources,
+            });
+        }
+
+        Ok(Notes(notes))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for Note {
+            producer,
+            flags,
+            sources,
+        } in &self.0
+        {
+            write!(writer, "{producer}\0{flags}\0")?;
+            writer.write_all(&(sources.len() as u16).to_le_bytes())?;
+            for (name, crc) in sources {
+                write!(writer, "{name}\0")?;
+                writer.write_all(&crc.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
 fn segment_type_from_u8(n: u8) -> io::Result<SegmentType> {
     SegmentType::try_from(n)
         .map_err(|()| io::Error::new(io::ErrorKind::InvalidData, "unrecognised segment type"))
 }
+
+#[test]
+fn string_table_dedup() {
+    let mut strtab = StringTable::default();
+    let a = strtab.insert("main");
+    let b = strtab.insert("loop");
+    assert_eq!(strtab.insert("main"), a);
+    assert_eq!(strtab.get(b).unwrap(), "loop");
+    assert_eq!(strtab.bytes.len(), 10);
+}
+
+#[test]
+fn split_debug() {
+    let mut obj = Object::default();
+    obj.segs.insert(SegmentType::Text, (0x10, vec![1, 2, 3]));
+    obj.lines.entries.push(LineEntry {
+        segment: SegmentType::Text,
+        location: 0x10,
+        file: 0,
+        line: 1,
+    });
+    let debug = obj.split_debug("a.to", "tl");
+    assert!(obj.lines.entries.is_empty());
+
+    let mut other = Object::default();
+    other.segs.insert(SegmentType::Text, (0x10, vec![1, 2, 4]));
+    assert_ne!(other.segments_checksum(), obj.segments_checksum());
+    assert!(other.attach_debug(Object::default()).is_err());
+    obj.attach_debug(debug).unwrap();
+    assert_eq!(obj.lines.entries.len(), 1);
+}
+
+#[test]
+fn symbol_lookup() {
+    let symbol = |name: &str, is_global, location, size| SymbolDefinition {
+        name: name.into(),
+        is_global,
+        segment_type: SegmentType::Text,
+        location,
+        kind: SymbolKind::NoType,
+        size,
+        defined_in: Vec::new(),
+    };
+    let mut obj = Object::default();
+    obj.segs.insert(SegmentType::Text, (0x10, vec![0; 0x10]));
+    obj.symbols.0.push(symbol("start", false, 0x10, None));
+    obj.symbols.0.push(symbol("local", false, 0x14, None));
+    obj.symbols.0.push(symbol("main", true, 0x14, Some(2)));
+    obj.symbols.0.push(symbol("", false, 0x18, None));
+    let symbols = SymbolLookup::new(&obj);
+
+    assert_eq!(symbols.at(0x14), Some("main"));
+    assert_eq!(symbols.at(0x18), None);
+    assert_eq!(symbols.describe(0x10), "0x0010 <start>");
+    assert_eq!(symbols.describe(0x15), "0x0015 <main+0x1>");
+    // Past the end of main, so named by the symbol before it
+    assert_eq!(symbols.describe(0x1a), "0x001a <start+0xa>");
+    assert_eq!(symbols.describe(0x20), "0x0020");
+}
+
+#[test]
+fn segment_at_the_top() {
+    let mut obj = Object::default();
+    obj.segs.insert(SegmentType::Data, (0xfff0, vec![0; 0x10]));
+    let regions = obj.memory_regions();
+    assert_eq!(regions[0].end, 0x10000);
+    assert!(regions[0].contains(0xffff));
+    assert!(!regions[0].contains(0xffef));
+}
+
+#[test]
+fn cpu_features_in_header() {
+    let path = std::env::temp_dir().join(format!("telda-cpu-{}.to", std::process::id()));
+    let obj = Object {
+        cpu_features: crate::isa::FEATURE_STACK | crate::isa::FEATURE_ATOMIC,
+        ..Object::default()
+    };
+    obj.write_to_file(&path).unwrap();
+
+    let reader = ObjectReader::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+    assert_eq!(reader.features() & !CPU_FEATURES_MASK, 0);
+    assert_eq!(reader.cpu_features(), obj.cpu_features);
+    assert_eq!(
+        Object::from_file(&path).unwrap().cpu_features,
+        obj.cpu_features
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    let too_wide = Object {
+        cpu_features: 0x1_0000,
+        ..Object::default()
+    };
+    assert!(too_wide.write_to_file(&path).is_err());
+}
@@ -0,0 +1,234 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use super::{
+    Entry, Object, RelocationEntry, RelocationKind, SegmentFlags, SegmentType, SymbolDefinition,
+    SymbolKind, SymbolTable,
+};
+
+/// Constructs an [`Object`], checking on [`ObjectBuilder::build`] that it makes sense
+///
+/// Locations are absolute addresses like in [`Object`] itself.
+#[derive(Debug, Default)]
+pub struct ObjectBuilder {
+    entry: Vec<Entry>,
+    segs: Vec<(SegmentType, u16, Vec<u8>)>,
+    seg_flags: Vec<(SegmentType, SegmentFlags)>,
+    symbols: Vec<SymbolDefinition>,
+    relocs: Vec<(SegmentType, u16, Box<str>, RelocationKind)>,
+}
+
+impl ObjectBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn entry(mut self, stype: SegmentType, location: u16) -> Self {
+        self.entry.push(Entry(stype, location));
+        self
+    }
+    pub fn segment(mut self, stype: SegmentType, start: u16, bytes: impl Into<Vec<u8>>) -> Self {
+        self.segs.push((stype, start, bytes.into()));
+        self
+    }
+    pub fn segment_flags(mut self, stype: SegmentType, flags: SegmentFlags) -> Self {
+        self.seg_flags.push((stype, flags));
+        self
+    }
+    /// Defines a symbol at a location in a segment
+    pub fn symbol(
+        self,
+        name: &str,
+        is_global: bool,
+        segment_type: SegmentType,
+        location: u16,
+    ) -> Self {
+        self.symbol_definition(SymbolDefinition {
+            name: name.into(),
+            is_global,
+            segment_type,
+            location,
+            kind: SymbolKind::NoType,
+            size: None,
+            defined_in: Vec::new(),
+        })
+    }
+    /// Adds an undefined global symbol to be resolved by the linker
+    pub fn reference(self, name: &str) -> Self {
+        self.symbol(name, true, SegmentType::Unknown, 0)
+    }
+    pub fn symbol_definition(mut self, def: SymbolDefinition) -> Self {
+        self.symbols.push(def);
+        self
+    }
+    /// Relocates the wide at the location with the address of the named symbol
+    pub fn reloc(self, stype: SegmentType, location: u16, symbol: &str) -> Self {
+        self.reloc_kind(stype, location, symbol, RelocationKind::Wide)
+    }
+    /// Relocates the location with the address of the named symbol as given by the kind
+    pub fn reloc_kind(
+        mut self,
+        stype: SegmentType,
+        location: u16,
+        symbol: &str,
+        kind: RelocationKind,
+    ) -> Self {
+        self.relocs.push((stype, location, symbol.into(), kind));
+        self
+    }
+
+    pub fn build(self) -> Result<Object, BuildError> {
+        let ObjectBuilder {
+            entry,
+            segs,
+            seg_flags,
+            symbols,
+            relocs,
+        } = self;
+        let mut obj = Object::default();
+
+        for (stype, start, bytes) in segs {
+            if stype == SegmentType::Unknown {
+                return Err(BuildError::UnknownSegment(stype));
+            }
+            if start as usize + bytes.len() > 0x10000 {
+                return Err(BuildError::SegmentTooBig(stype));
+            }
+            let end = start + bytes.len() as u16;
+            for (&other, &(other_start, ref other_bytes)) in &obj.segs {
+                if other == stype {
+                    return Err(BuildError::DuplicateSegment(stype));
+                }
+                let other_end = other_start + other_bytes.len() as u16;
+                if start < other_end && other_start < end {
+                    return Err(BuildError::OverlappingSegments(other, stype));
+                }
+            }
+            obj.segs.insert(stype, (start, bytes));
+        }
+
+        let check_location = |stype: SegmentType, location: u16, size: u16| {
+            let &(start, ref bytes) = obj
+                .segs
+                .get(&stype)
+                .ok_or(BuildError::UnknownSegment(stype))?;
+            if location < start || location as usize + size as usize > start as usize + bytes.len()
+            {
+                Err(BuildError::OutsideSegment(stype, location))
+            } else {
+                Ok(())
+            }
+        };
+
+        match *entry {
+            [] => (),
+            [Entry(stype, location)] => {
+                check_location(stype, location, 1)?;
+                obj.entry = Some(Entry(stype, location));
+            }
+            [_, _, ..] => return Err(BuildError::DuplicateEntry),
+        }
+
+        for (stype, flags) in seg_flags {
+            if !obj.segs.contains_key(&stype) {
+                return Err(BuildError::UnknownSegment(stype));
+            }
+            if obj.seg_flags.0.insert(stype, flags).is_some() {
+                return Err(BuildError::DuplicateSegment(stype));
+            }
+        }
+
+        for (i, def) in symbols.iter().enumerate() {
+            if def.name.is_empty() || def.name.contains('\0') || def.name.starts_with(' ') {
+                return Err(BuildError::InvalidSymbolName(def.name.clone()));
+            }
+            if symbols[..i].iter().any(|d| d.name == def.name) {
+                return Err(BuildError::DuplicateSymbol(def.name.clone()));
+            }
+            if def.segment_type == SegmentType::Unknown {
+                if !def.is_global {
+                    return Err(BuildError::UndefinedLocalSymbol(def.name.clone()));
+                }
+            } else {
+                check_location(def.segment_type, def.location, 0)?;
+            }
+        }
+
+        for (stype, location, symbol, kind) in relocs {
+            check_location(stype, location, kind.size())?;
+            let symbol_index = symbols
+                .iter()
+                .position(|d| d.name == symbol)
+                .ok_or(BuildError::UnknownSymbol(symbol))?;
+
+            obj.relocation_table.0.push(RelocationEntry {
+                reference_segment: stype,
+                reference_location: location,
+                symbol_index: symbol_index as u16,
+                kind,
+            });
+        }
+        obj.symbols = SymbolTable(symbols);
+
+        Ok(obj)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    DuplicateEntry,
+    DuplicateSegment(SegmentType),
+    UnknownSegment(SegmentType),
+    SegmentTooBig(SegmentType),
+    OverlappingSegments(SegmentType, SegmentType),
+    OutsideSegment(SegmentType, u16),
+    InvalidSymbolName(Box<str>),
+    DuplicateSymbol(Box<str>),
+    UndefinedLocalSymbol(Box<str>),
+    UnknownSymbol(Box<str>),
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::BuildError::*;
+        match self {
+            DuplicateEntry => write!(f, "more than one entry point"),
+            DuplicateSegment(st) => write!(f, "segment {st} was given more than once"),
+            UnknownSegment(st) => write!(f, "segment {st} does not exist"),
+            SegmentTooBig(st) => write!(f, "segment {st} goes past the end of memory"),
+            OverlappingSegments(st1, st2) => write!(f, "segments {st1} and {st2} overlap"),
+            OutsideSegment(st, loc) => write!(f, "location {loc:04x} is outside segment {st}"),
+            InvalidSymbolName(name) => write!(f, "invalid symbol name `{name}'"),
+            DuplicateSymbol(name) => write!(f, "symbol `{name}' is defined more than once"),
+            UndefinedLocalSymbol(name) => write!(f, "local symbol `{name}' is not defined"),
+            UnknownSymbol(name) => write!(f, "relocation refers to unknown symbol `{name}'"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+#[test]
+fn build_object() {
+    let obj = ObjectBuilder::new()
+        .segment(SegmentType::Text, 0x10, [0x40, 0x10, 0x00, 0x00, 0x0a])
+        .segment(SegmentType::Data, 0x04, b"hi\0".to_vec())
+        .symbol("main", true, SegmentType::Text, 0x10)
+        .symbol("str", false, SegmentType::Data, 0x04)
+        .reloc(SegmentType::Text, 0x12, "str")
+        .entry(SegmentType::Text, 0x10)
+        .build()
+        .unwrap();
+    assert_eq!(obj.relocation_table.0[0].symbol_index, 1);
+
+    let err = ObjectBuilder::new()
+        .segment(SegmentType::Text, 0x10, [0; 4])
+        .segment(SegmentType::Data, 0x12, [0; 4])
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        BuildError::OverlappingSegments(SegmentType::Text, SegmentType::Data)
+    );
+}
@@ -0,0 +1,91 @@
+//! What `t` tells about a program that trapped, to find out where it was and how it got there
+
+use telda2::{
+    aalv::obj::SymbolLookup,
+    cpu::TrapMode,
+    disassemble::Disassembler,
+    machine::{Fault, Machine},
+    mem::Io,
+};
+
+use crate::dump_registers;
+
+/// How many instructions are shown before and after the one that trapped
+const CONTEXT: usize = 3;
+/// How far before the trap disassembling may start from the closest symbol
+const MAX_LEAD: u16 = 0x40;
+const MAX_FRAMES: usize = 16;
+
+/// Writes the trap, the code around it, the registers and a backtrace to stderr
+pub fn report<I: Io>(machine: &Machine<I>, symbols: &SymbolLookup, tm: TrapMode) {
+    let at = match machine.last_fault() {
+        Some(Fault { trap, addr, pc }) if trap == tm => {
+            eprintln!(
+                "trapped with {tm:?} accessing {} at {}",
+                symbols.describe(addr),
+                symbols.describe(pc)
+            );
+            pc
+        }
+        _ => {
+            let at = machine.last_instruction();
+            eprintln!("trapped with {tm:?} at {}", symbols.describe(at));
+            at
+        }
+    };
+
+    eprintln!();
+    for line in code_around(machine.memory(), symbols, at) {
+        eprintln!("{line}");
+    }
+    eprintln!();
+    // The instruction that trapped, like the lines above, rather than the one after it
+    dump_registers(machine, symbols, at);
+    eprintln!();
+    eprintln!("backtrace:");
+    eprintln!("  #0 {}", symbols.describe(at));
+    for (n, addr) in machine.return_addresses(MAX_FRAMES).into_iter().enumerate() {
+        eprintln!("  #{} {}", n + 1, symbols.describe(addr));
+    }
+}
+
+/// The disassembly of the instructions around the location, with it marked by `=>`
+///
+/// Instructions have different sizes, so it starts at the closest symbol before it if there is one near enough,
+/// or at the location itself if disassembling from there does not end up at it.
+fn code_around(memory: &[u8], symbols: &SymbolLookup, at: u16) -> Vec<String> {
+    let disassembler = Disassembler::new(memory)
+        .symbolizer(|p| symbols.at(p).map(|s| s.to_owned()))
+        .linear(true);
+
+    let start = match symbols.nearest(at) {
+        Some((_, offset)) if offset <= MAX_LEAD => at - offset,
+        _ => at,
+    };
+    let mut before: Vec<_> = disassembler
+        .instructions(start)
+        .take_while(|&(location, _)| location < at)
+        .collect();
+    let lands = before
+        .last()
+        .is_none_or(|(_, ins)| ins.next_instruction_location == at);
+    if !lands {
+        before.clear();
+    }
+    let from = before.len().saturating_sub(CONTEXT);
+
+    before[from..]
+        .iter()
+        .map(|(_, ins)| ins.annotated_source.clone())
+        .chain(
+            disassembler
+                .instructions(at)
+                .take(CONTEXT + 1)
+                .enumerate()
+                .map(|(n, (_, ins))| match n {
+                    0 => format!("=>{}", &ins.annotated_source[2..]),
+                    _ => ins.annotated_source,
+                }),
+        )
+        .collect()
+}
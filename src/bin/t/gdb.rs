@@ -0,0 +1,346 @@
+//! A stub for GDB's remote serial protocol, enough for registers, memory, breakpoints, stepping and continuing
+//!
+//! See [`telda2::rsp`] for the registers.
+
+use std::{
+    collections::HashSet,
+    io::{self, BufReader, ErrorKind, Read},
+    net::{TcpListener, TcpStream},
+};
+
+use telda2::{
+    cpu::{TrapMode, WideRegister},
+    machine::Machine,
+    mem::Io,
+    rsp::{
+        flags, parse_bytes, parse_wides, read_packet, set_flags, wide_hex, write_packet, FLAGS, PC,
+        REGISTERS,
+    },
+    U4,
+};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.telda.core">
+    <reg name="r0" bitsize="16" type="int"/>
+    <reg name="r1" bitsize="16" type="int"/>
+    <reg name="r2" bitsize="16" type="int"/>
+    <reg name="r3" bitsize="16" type="int"/>
+    <reg name="r4" bitsize="16" type="int"/>
+    <reg name="r5" bitsize="16" type="int"/>
+    <reg name="r6" bitsize="16" type="int"/>
+    <reg name="r7" bitsize="16" type="int"/>
+    <reg name="r8" bitsize="16" type="int"/>
+    <reg name="r9" bitsize="16" type="int"/>
+    <reg name="r10" bitsize="16" type="int"/>
+    <reg name="rs" bitsize="16" type="data_ptr"/>
+    <reg name="rl" bitsize="16" type="code_ptr"/>
+    <reg name="rf" bitsize="16" type="data_ptr"/>
+    <reg name="rp" bitsize="16" type="int"/>
+    <reg name="rh" bitsize="16" type="code_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="flags" bitsize="16" type="int"/>
+  </feature>
+</target>
+"#;
+
+/// How the session ended
+pub enum Outcome {
+    /// The program trapped without a trap handler, like by halting
+    Ended(TrapMode),
+    /// The debugger killed the program
+    Killed,
+}
+
+struct Stub<'a, I> {
+    machine: &'a mut Machine<I>,
+    breakpoints: HashSet<u16>,
+    ended: Option<TrapMode>,
+}
+
+/// Waits for a debugger to connect on the port and lets it control the machine
+pub fn serve<I: Io>(machine: &mut Machine<I>, port: u16) -> io::Result<Outcome> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for gdb on 127.0.0.1:{port}");
+    let (stream, addr) = listener.accept()?;
+    eprintln!("gdb connected from {addr}");
+    match session(machine, stream)? {
+        Some(outcome) => Ok(outcome),
+        // Detached, the program goes on by itself
+        None => Ok(Outcome::Ended(machine.run())),
+    }
+}
+
+/// Lets debuggers attach to the program while it runs, one at a time
+pub struct Listener {
+    listener: TcpListener,
+    steps: u32,
+}
+
+impl Listener {
+    /// Listens on `127.0.0.1:PORT`, or any address like `0.0.0.0:PORT` to be debugged from other machines
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = match addr.parse::<u16>() {
+            Ok(port) => TcpListener::bind(("127.0.0.1", port))?,
+            Err(_) => TcpListener::bind(addr)?,
+        };
+        listener.set_nonblocking(true)?;
+        eprintln!("debuggers can attach on {}", listener.local_addr()?);
+        Ok(Listener { listener, steps: 0 })
+    }
+    /// Lets a debugger that connected control the machine until it detaches, looking for one every so many steps
+    ///
+    /// Gives how the program ended if it did while attached, or the debugger killed it.
+    pub fn poll<I: Io>(&mut self, machine: &mut Machine<I>) -> io::Result<Option<Outcome>> {
+        self.steps = self.steps.wrapping_add(1);
+        if !self.steps.is_multiple_of(4096) {
+            return Ok(None);
+        }
+        let stream = match self.listener.accept() {
+            Ok((stream, addr)) => {
+                eprintln!("debugger attached from {addr}");
+                stream
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        stream.set_nonblocking(false)?;
+        let outcome = session(machine, stream)?;
+        if outcome.is_none() {
+            eprintln!("debugger detached");
+        }
+        Ok(outcome)
+    }
+}
+
+/// Lets the debugger on the stream control the machine, until it detaches (giving none) or the program is over
+fn session<I: Io>(machine: &mut Machine<I>, stream: TcpStream) -> io::Result<Option<Outcome>> {
+    // Acknowledgements and replies are small, waiting to send them together only slows stepping down
+    stream.set_nodelay(true)?;
+    let mut stub = Stub {
+        machine,
+        breakpoints: HashSet::new(),
+        ended: None,
+    };
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    loop {
+        let packet = match read_packet(&mut reader, &mut writer)? {
+            Some(packet) if packet == "D" => {
+                write_packet(&mut writer, "OK")?;
+                None
+            }
+            packet => packet,
+        };
+        let Some(packet) = packet else {
+            return Ok(stub.ended.map(Outcome::Ended));
+        };
+        if packet == "k" {
+            return Ok(Some(Outcome::Killed));
+        }
+        let reply = stub.handle(&packet, &writer);
+        write_packet(&mut writer, &reply)?;
+    }
+}
+
+impl<I: Io> Stub<'_, I> {
+    fn handle(&mut self, packet: &str, stream: &TcpStream) -> String {
+        if packet.is_empty() {
+            return String::new();
+        }
+        let (cmd, args) = packet.split_at(1);
+        match cmd {
+            "?" => self.stop_reply(),
+            "g" => (0..REGISTERS).map(|i| wide_hex(self.register(i))).collect(),
+            "G" => {
+                let Some(values) = parse_wides(args) else {
+                    return "E01".to_owned();
+                };
+                for (i, val) in values.into_iter().enumerate().take(REGISTERS) {
+                    self.set_register(i, val);
+                }
+                "OK".to_owned()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(i) if i < REGISTERS => wide_hex(self.register(i)),
+                _ => "E01".to_owned(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(i, val)| {
+                    let i = usize::from_str_radix(i, 16).ok()?;
+                    Some((i, *parse_wides(val)?.first()?))
+                });
+                match parsed {
+                    Some((i, val)) if i < REGISTERS => {
+                        self.set_register(i, val);
+                        "OK".to_owned()
+                    }
+                    _ => "E01".to_owned(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => (0..len)
+                    .map(|i| format!("{:02x}", self.machine.peek(addr.wrapping_add(i))))
+                    .collect(),
+                None => "E01".to_owned(),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, parse_bytes(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (i, b) in (0..).zip(data) {
+                            self.machine.poke(addr.wrapping_add(i), b);
+                        }
+                        "OK".to_owned()
+                    }
+                    _ => "E01".to_owned(),
+                }
+            }
+            "Z" | "z" => {
+                // Software and hardware breakpoints are the same here
+                let parsed = args.split(',').collect::<Vec<_>>();
+                let addr = match parsed[..] {
+                    ["0" | "1", addr, _] => u16::from_str_radix(addr, 16).ok(),
+                    _ => return String::new(),
+                };
+                match (cmd, addr) {
+                    ("Z", Some(addr)) => self.breakpoints.insert(addr),
+                    ("z", Some(addr)) => self.breakpoints.remove(&addr),
+                    _ => return "E01".to_owned(),
+                };
+                "OK".to_owned()
+            }
+            "s" | "c" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    self.machine.set_pc(addr);
+                }
+                if cmd == "s" {
+                    self.step();
+                } else {
+                    self.resume(stream);
+                }
+                self.stop_reply()
+            }
+            "H" => "OK".to_owned(),
+            "q" => self.query(args),
+            _ => String::new(),
+        }
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+".to_owned()
+        } else if query == "Telda.Trap" {
+            // Which trap ended the program, more exactly than the signal, and the instruction that trapped
+            self.ended.map_or(String::new(), |tm| {
+                format!("{:02x},{:04x}", tm as u8, self.machine.last_instruction())
+            })
+        } else if query == "Attached" {
+            "1".to_owned()
+        } else if query == "C" {
+            "QC1".to_owned()
+        } else if query == "fThreadInfo" {
+            "m1".to_owned()
+        } else if query == "sThreadInfo" {
+            "l".to_owned()
+        } else if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, len)) = range.split_once(',').and_then(|(o, l)| {
+                Some((
+                    usize::from_str_radix(o, 16).ok()?,
+                    usize::from_str_radix(l, 16).ok()?,
+                ))
+            }) else {
+                return "E01".to_owned();
+            };
+            let rest = TARGET_XML.get(offset..).unwrap_or("");
+            if rest.len() > len {
+                format!("m{}", &rest[..len])
+            } else {
+                format!("l{rest}")
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    fn step(&mut self) {
+        if self.ended.is_none() {
+            self.ended = self.machine.step().err();
+        }
+    }
+    /// Runs until a breakpoint, the end or the debugger interrupting
+    fn resume(&mut self, stream: &TcpStream) {
+        let mut steps = 0u32;
+        loop {
+            self.step();
+            if self.ended.is_some() || self.breakpoints.contains(&self.machine.pc()) {
+                return;
+            }
+            steps = steps.wrapping_add(1);
+            if steps.is_multiple_of(4096) && interrupted(stream) {
+                return;
+            }
+        }
+    }
+    fn stop_reply(&self) -> String {
+        match self.ended {
+            None => "S05".to_owned(),
+            Some(TrapMode::Halt) => format!("W{:02x}", self.machine.registers().exit_code),
+            Some(tm) => format!("X{:02x}", signal(tm)),
+        }
+    }
+
+    fn register(&self, i: usize) -> u16 {
+        let r = self.machine.registers();
+        match i {
+            PC => r.program_counter,
+            FLAGS => flags(r),
+            i => r.read_wide(WideRegister(U4::new(i as u8))),
+        }
+    }
+    fn set_register(&mut self, i: usize, val: u16) {
+        let r = self.machine.registers_mut();
+        match i {
+            PC => r.program_counter = val,
+            FLAGS => set_flags(r, val),
+            i => r.write_wide(WideRegister(U4::new(i as u8)), val),
+        }
+    }
+}
+
+/// The POSIX signal GDB knows that is closest to the trap
+fn signal(tm: TrapMode) -> u8 {
+    match tm {
+        TrapMode::Halt => 0,
+        TrapMode::ZeroDiv => 8,
+        TrapMode::IllegalRead | TrapMode::IllegalWrite | TrapMode::IllegalExecute => 11,
+        _ => 4,
+    }
+}
+
+/// Checks without blocking whether the debugger sent an interrupt (^C)
+fn interrupted(stream: &TcpStream) -> bool {
+    let mut buf = [0];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let got = matches!(stream.peek(&mut buf), Ok(1)) && buf[0] == 0x03;
+    if got {
+        let _ = (&*stream).read_exact(&mut buf);
+    }
+    let _ = stream.set_nonblocking(false);
+    got
+}
+
+/// Parses `addr,length`
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((
+        u16::from_str_radix(addr, 16).ok()?,
+        u16::from_str_radix(len, 16).ok()?,
+    ))
+}
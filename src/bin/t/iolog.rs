@@ -0,0 +1,36 @@
+//! Logging every access the program makes to its I/O, to find out afterwards what a driver did to a device
+
+use std::{cell::Cell, io::Write, rc::Rc};
+
+use telda2::{
+    aalv::obj::SymbolLookup,
+    machine::{Access, Machine},
+    mem::Io,
+};
+
+/// Writes a line to `out` for every byte the program reads from or writes to the I/O mapping, with the number of the
+/// instruction that did it (from 1) and where that instruction is
+///
+/// It sees the instructions of the machine, so nothing is compiled to native code while it logs.
+pub fn attach<I: Io>(machine: &mut Machine<I>, mut out: Box<dyn Write>, symbols: SymbolLookup) {
+    let at = Rc::new(Cell::new((0u64, 0u16)));
+    let counted = at.clone();
+    machine.on_instruction(move |location, _| {
+        let (count, _) = counted.get();
+        counted.set((count + 1, location));
+    });
+    machine.on_io(move |access, port, value| {
+        let (count, location) = at.get();
+        let access = match access {
+            Access::Read => "read ",
+            Access::Write => "write",
+        };
+        writeln!(
+            out,
+            "{count:>10} {access} 0x{:04x} 0x{value:02x} at {}",
+            0xff00 | port as u16,
+            symbols.describe(location)
+        )
+        .expect("I/O log failed");
+    });
+}
@@ -0,0 +1,719 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::mpsc::channel,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+
+mod crash;
+mod gdb;
+mod iolog;
+mod serial;
+mod terminal;
+mod trace;
+use telda2::{
+    aalv::obj::{Object, SymbolLookup},
+    cpu::{TrapMode, WideRegister},
+    devices::{self, Sandbox},
+    isa,
+    machine::Machine,
+    mem::{Io, MemoryMap, StdIo, IO_MAPPING_CUTOFF},
+    replay::{self, Recorder, Replayer},
+    snapshot::Snapshot,
+    U4,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Binary file
+    binary: PathBuf,
+
+    /// Arguments for the program, which gets them after the path of the binary
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+
+    /// Gives the program an environment variable, can be given more than once
+    #[arg(long, value_name = "NAME=VALUE")]
+    env: Vec<String>,
+
+    /// Takes the symbols and lines from a debug object that `tl --split-debug` wrote for the binary
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+
+    /// The features of the CPU to emulate, like `base,stack` or `all`; binaries that need others are refused
+    #[arg(long, value_name = "FEATURES", default_value = "all", value_parser = isa::parse_features)]
+    cpu: u32,
+
+    /// Whether the termination point should be displayed
+    #[arg(short, long)]
+    termination_point: bool,
+
+    /// Prints how many cycles the program took to stderr when it ends
+    #[arg(long)]
+    report_cycles: bool,
+
+    /// How much RAM the program has from address 0, like 0x8000 or 32K, at most up to the I/O mapping at 0xffe0
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    mem_size: Option<u16>,
+
+    /// Lays out memory like the file says, with lines like `ram 0x0..0x8000`, `rom 0x8000..0xa000 boot.bin` and
+    /// `device 0xffe0..0x10000`; accesses outside of its areas trap
+    #[arg(long, value_name = "FILE", conflicts_with = "mem_size")]
+    memory_map: Option<PathBuf>,
+
+    /// Gives the program a keyboard device at 0xfff4 that does not block, with the terminal sending keys as they are typed
+    #[arg(long)]
+    keyboard: bool,
+
+    /// Gives the program a gamepad device at 0xffed with the arrows, z, x and enter of the terminal as its buttons
+    #[arg(long)]
+    gamepad: bool,
+
+    /// Gives the program a 40x25 text display at 0xfff6, drawn to the terminal whenever the program shows it, with
+    /// 60 frames a second of --hz
+    #[arg(long)]
+    display: bool,
+
+    /// Gives the program a disk at 0xfff9 backed by the file, which is created if it does not exist
+    #[arg(long, value_name = "FILE")]
+    disk: Option<PathBuf>,
+
+    /// Gives the program at least this many banks of 16K at 0xffe5, mapped into 0x8000..0xc000 one at a time;
+    /// binaries linked with banks get them without this
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..=256))]
+    banks: Option<u16>,
+
+    /// Gives the program a serial port at 0xffe0 that TCP connections on this port talk to, one at a time
+    #[arg(long, value_name = "PORT")]
+    serial: Option<u16>,
+
+    /// Only lets the program open files in the directory through the file device at 0xffe7, or none without one
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    sandbox: Option<Option<PathBuf>>,
+
+    /// Shows a host file read-only in a window of memory like 0xc000..0xe000=tables.bin, a window-sized page at a
+    /// time selected through the device at 0xffef
+    #[arg(long, value_name = "RANGE=FILE", value_parser = parse_mapping)]
+    map_file: Option<(u16, u16, PathBuf)>,
+
+    /// Like --map-file, but the program can write the window, with the writes kept with their page and never
+    /// reaching the file
+    #[arg(long, value_name = "RANGE=FILE", value_parser = parse_mapping, conflicts_with = "map_file")]
+    map_file_cow: Option<(u16, u16, PathBuf)>,
+
+    /// Saves a snapshot of the machine to the file when the program stops
+    #[arg(long, value_name = "FILE")]
+    save_on_exit: Option<PathBuf>,
+
+    /// Starts from a snapshot saved with --save-on-exit of the same binary, run with the same devices
+    #[arg(long, value_name = "FILE")]
+    restore: Option<PathBuf>,
+
+    /// Records what the program reads from its I/O and when it gets interrupted to the file, to replay the run
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// Logs every byte the program reads from or writes to its I/O to the file, with the number and location of the
+    /// instruction that did it
+    #[arg(long, value_name = "FILE")]
+    io_log: Option<PathBuf>,
+
+    /// Replays a run recorded with --record of the same binary, with the reads and interrupts from the recording
+    #[arg(long, value_name = "FILE", conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// Stops the program after this many instructions if it has not halted by then
+    #[arg(long, value_name = "N", conflicts_with = "gdb")]
+    max_steps: Option<u64>,
+
+    /// Stops the program after this many seconds if it has not halted by then
+    #[arg(long, value_name = "SECS", conflicts_with = "gdb", value_parser = parse_timeout)]
+    timeout: Option<Duration>,
+
+    /// Runs about this many cycles a second, instead of as fast as it can, so that games keep a steady speed
+    #[arg(long, value_name = "N", conflicts_with_all = ["gdb", "trace"], value_parser = clap::value_parser!(u64).range(1..))]
+    hz: Option<u64>,
+
+    /// Waits for GDB to connect on this port over TCP and lets it control the program
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+
+    /// Lets `tdb --attach` connect on this port, or an address like 0.0.0.0:PORT, while the program runs
+    #[arg(long, value_name = "[HOST:]PORT", conflicts_with_all = ["gdb", "trace"])]
+    debug_listen: Option<String>,
+
+    /// Logs every executed instruction with the registers it changed, to the file or stderr
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true)]
+    trace: Option<Option<PathBuf>>,
+
+    /// Only traces instructions in a symbol or address range like 0x10..0x40, can be given more than once
+    #[arg(long = "trace-range", value_name = "RANGE", requires = "trace")]
+    trace_ranges: Vec<String>,
+
+    /// How to write the trace, csv and json have every register and the memory each instruction accessed
+    #[arg(long = "trace-format", value_enum, default_value_t = trace::Format::Text, requires = "trace")]
+    trace_format: trace::Format,
+
+    /// Compiles code the program runs often to native code; memory accesses, I/O and traps still go through the
+    /// interpreter
+    #[cfg(feature = "jit")]
+    #[arg(long, conflicts_with_all = ["gdb", "debug_listen", "trace", "hz"])]
+    jit: bool,
+}
+
+#[allow(clippy::enum_variant_names)]
+enum Error {
+    NoEntry,
+    /// The program trapped, which has been reported already
+    Trapped,
+    IoError(io::Error),
+    Trace(String),
+    Snapshot(io::Error),
+    #[cfg(feature = "jit")]
+    Jit(String),
+    Replay(io::Error),
+    Symbols(io::Error),
+    MemoryMap(String),
+    MapFile(String),
+    Arguments(String),
+    Cpu(String),
+    /// The program was stopped by a limit, which has been reported already
+    Limit,
+}
+
+/// What `t` exits with when a program is stopped by `--max-steps` or `--timeout`, like `timeout` does
+const LIMIT_EXIT_CODE: u8 = 124;
+
+pub fn main() -> ExitCode {
+    match t_main() {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            match e {
+                Error::NoEntry => eprintln!("no entry point in binary"),
+                Error::IoError(e) => eprintln!("unexpected io error occured: {e}"),
+                Error::Trace(e) => eprintln!("cannot trace: {e}"),
+                Error::Snapshot(e) => eprintln!("cannot restore snapshot: {e}"),
+                Error::Replay(e) => eprintln!("cannot read recording: {e}"),
+                #[cfg(feature = "jit")]
+                Error::Jit(e) => eprintln!("cannot compile to native code: {e}"),
+                Error::Symbols(e) => eprintln!("cannot load symbols: {e}"),
+                Error::MemoryMap(e) => eprintln!("invalid memory map: {e}"),
+                Error::MapFile(e) => eprintln!("cannot map file: {e}"),
+                Error::Arguments(e) => eprintln!("cannot pass arguments: {e}"),
+                Error::Cpu(e) => eprintln!("cannot run the binary: {e}"),
+                Error::Limit => return ExitCode::from(LIMIT_EXIT_CODE),
+                Error::Trapped => (),
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Gives the exit code the program halted with
+fn t_main() -> Result<u8, Error> {
+    let Cli {
+        binary,
+        args,
+        env,
+        symbols,
+        cpu,
+        termination_point,
+        report_cycles,
+        max_steps,
+        timeout,
+        hz,
+        mem_size,
+        memory_map,
+        keyboard,
+        gamepad,
+        display,
+        disk,
+        banks,
+        serial,
+        sandbox,
+        map_file,
+        map_file_cow,
+        save_on_exit,
+        restore,
+        record,
+        io_log,
+        replay,
+        gdb,
+        debug_listen,
+        trace,
+        trace_ranges,
+        trace_format,
+        #[cfg(feature = "jit")]
+        jit,
+    } = Cli::parse();
+
+    let args: Vec<String> = std::iter::once(binary.display().to_string())
+        .chain(args)
+        .collect();
+    let mut obj = Object::from_file(binary).map_err(Error::IoError)?;
+    if obj.entry.is_none() {
+        return Err(Error::NoEntry);
+    }
+    let unknown = obj.cpu_features & !isa::ALL_FEATURES;
+    if unknown != 0 {
+        eprintln!(
+            "warning: the binary needs cpu features {} that this version does not know",
+            isa::feature_names(unknown).join(", ")
+        );
+    }
+    let missing = obj.cpu_features & isa::ALL_FEATURES & !cpu;
+    if missing != 0 {
+        return Err(Error::Cpu(format!(
+            "it needs the cpu features {}",
+            isa::feature_names(missing).join(", ")
+        )));
+    }
+    if let Some(path) = symbols {
+        let debug = Object::from_file(path).map_err(Error::Symbols)?;
+        obj.attach_debug(debug).map_err(Error::Symbols)?;
+    }
+    let tracer = match trace {
+        Some(path) => {
+            let out: Box<dyn Write> = match path {
+                Some(path) => Box::new(BufWriter::new(File::create(path).map_err(Error::IoError)?)),
+                None => Box::new(io::stderr()),
+            };
+            Some(trace::Tracer::new(out, trace_format, &obj, &trace_ranges).map_err(Error::Trace)?)
+        }
+        None => None,
+    };
+    let symbols = SymbolLookup::new(&obj);
+    let mut map = match (mem_size, memory_map) {
+        (Some(size), _) => MemoryMap::with_size(size).map_err(Error::MemoryMap)?,
+        (None, Some(path)) => read_memory_map(&path)?,
+        (None, None) => MemoryMap::default(),
+    };
+    for (stype, (start, data)) in &obj.segs {
+        let end = *start as u32 + data.len() as u32;
+        if !data.is_empty() && !map.holds(*start, end) {
+            return Err(Error::MemoryMap(format!(
+                "the {stype} segment at 0x{start:04x}..0x{end:04x} is not in RAM or ROM"
+            )));
+        }
+    }
+    let mapped = match (map_file, map_file_cow) {
+        (Some((start, end, path)), _) => {
+            map.protect(start, end as u32).map_err(Error::MemoryMap)?;
+            Some((start, end, path, false))
+        }
+        (None, Some((start, end, path))) => Some((start, end, path, true)),
+        (None, None) => None,
+    };
+
+    let (buttons, received) = channel();
+    let io: Box<dyn Io> = if keyboard || gamepad {
+        let buttons = gamepad.then_some(buttons);
+        Box::new(terminal::Console::new(buttons).map_err(Error::IoError)?)
+    } else {
+        Box::new(StdIo)
+    };
+    let mut bus = devices::standard(io);
+    if gamepad {
+        bus.attach(devices::GAMEPAD_PORT, 2, devices::Gamepad::new(received));
+    }
+    let sandbox = match sandbox {
+        None => Sandbox::None,
+        Some(Some(dir)) => Sandbox::Directory(dir),
+        Some(None) => Sandbox::Closed,
+    };
+    bus.attach(devices::FILES_PORT, 6, devices::Files::new(sandbox));
+    if display {
+        let mut display = devices::Display::new(io::stdout());
+        if let Some(hz) = hz {
+            display.set_frame_cycles(hz / 60);
+        }
+        bus.attach_on_line(devices::DISPLAY_PORT, 3, devices::DISPLAY_LINE, display);
+    }
+    if let Some(path) = disk {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(Error::IoError)?;
+        bus.attach(devices::DISK_PORT, 5, devices::Disk::new(file));
+    }
+    let linked_banks = std::mem::take(&mut obj.banks);
+    if banks.is_some() || !linked_banks.is_empty() {
+        let linked = linked_banks.keys().last().map_or(0, |&n| n as usize + 1);
+        let mut contents = vec![Vec::new(); linked.max(banks.unwrap_or(0) as usize)];
+        for (n, bytes) in linked_banks {
+            contents[n as usize] = bytes;
+        }
+        bus.attach(devices::BANK_PORT, 2, devices::Banks::new(contents));
+    }
+    if let Some((start, end, path, copy_on_write)) = mapped {
+        if let Some((stype, _)) = obj.segs.iter().find(|(_, (at, data))| {
+            !data.is_empty() && *at < end && (start as usize) < *at as usize + data.len()
+        }) {
+            return Err(Error::MapFile(format!(
+                "the {stype} segment overlaps the window 0x{start:04x}..0x{end:04x}"
+            )));
+        }
+        let contents =
+            fs::read(&path).map_err(|e| Error::MapFile(format!("{}: {e}", path.display())))?;
+        let file =
+            devices::MappedFile::new(contents, start..end, copy_on_write).ok_or_else(|| {
+                Error::MapFile(format!(
+                    "{} is more than 255 pages of 0x{:x} bytes",
+                    path.display(),
+                    end - start
+                ))
+            })?;
+        bus.attach(devices::MAPPED_FILE_PORT, 1, file);
+    }
+    if let Some(port) = serial {
+        bus.attach_on_line(
+            devices::SERIAL_PORT,
+            2,
+            devices::SERIAL_LINE,
+            serial::listen(port).map_err(Error::IoError)?,
+        );
+    }
+    let listener = match debug_listen {
+        Some(addr) => Some(gdb::Listener::bind(&addr).map_err(Error::IoError)?),
+        None => None,
+    };
+    let io_log = match io_log {
+        Some(path) => {
+            let out: Box<dyn Write> =
+                Box::new(BufWriter::new(File::create(path).map_err(Error::IoError)?));
+            Some(out)
+        }
+        None => None,
+    };
+    let session = Session {
+        args,
+        env,
+        limits: Limits::new(max_steps, timeout),
+        throttle: hz.map(Throttle::new),
+        restore,
+        save_on_exit,
+        gdb,
+        listener,
+        tracer,
+        io_log,
+        report_cycles,
+        termination_point,
+        symbols,
+        #[cfg(feature = "jit")]
+        jit,
+    };
+    if let Some(path) = record {
+        let out = BufWriter::new(File::create(path).map_err(Error::IoError)?);
+        let recorder = Recorder::new(bus, out).map_err(Error::IoError)?;
+        run(&mut Machine::with_map(obj, recorder, map), session)
+    } else if let Some(path) = replay {
+        let events = replay::read_recording(path).map_err(Error::Replay)?;
+        let mut machine = Machine::with_map(obj, Replayer::new(bus, events), map);
+        let result = run(&mut machine, session);
+        if let Some(at) = machine.io().diverged() {
+            eprintln!("the run diverged from the recording at cycle {at}");
+        } else if !machine.io().finished() {
+            eprintln!("the run ended before the recording did");
+        }
+        result
+    } else {
+        run(&mut Machine::with_map(obj, bus, map), session)
+    }
+}
+
+/// Reads a memory map file, with the files of ROMs relative to it
+fn read_memory_map(path: &Path) -> Result<MemoryMap, Error> {
+    let text = fs::read_to_string(path).map_err(Error::IoError)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    MemoryMap::parse(&text, |rom| fs::read(dir.join(rom))).map_err(Error::MemoryMap)
+}
+
+/// Parses a window and the file to show in it, like `0xc000..0xe000=tables.bin`
+fn parse_mapping(s: &str) -> Result<(u16, u16, PathBuf), String> {
+    let (range, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{s} is not like 0xc000..0xe000=FILE"))?;
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("{range} is not an address range like 0xc000..0xe000"))?;
+    let (start, end) = (parse_size(start)?, parse_size(end)?);
+    if start >= end || end > IO_MAPPING_CUTOFF {
+        return Err(format!(
+            "{range} is not a window below the I/O mapping at 0x{IO_MAPPING_CUTOFF:04x}"
+        ));
+    }
+    Ok((start, end, PathBuf::from(path)))
+}
+
+/// Parses seconds like `2` or `0.5`
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("{s} is not a number of seconds"))
+}
+
+/// Parses a size like `4096`, `0x1000` or `4K`
+fn parse_size(s: &str) -> Result<u16, String> {
+    let (number, scale) = match s.strip_suffix(['K', 'k']) {
+        Some(number) => (number, 1024),
+        None => (s, 1),
+    };
+    let n = match number.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .map_err(|e| e.to_string())?;
+    n.checked_mul(scale)
+        .and_then(|n| u16::try_from(n).ok())
+        .ok_or_else(|| format!("{s} is more than the 64K there are"))
+}
+
+/// Which limit stopped a program
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    Steps(u64),
+    Timeout(Duration),
+}
+
+/// How long a program may run for
+pub struct Limits {
+    max_steps: Option<u64>,
+    timeout: Option<(Duration, Instant)>,
+    steps: u64,
+    /// The step to look at the clock again at
+    next_check: u64,
+    reached: Option<Limit>,
+}
+
+impl Limits {
+    fn new(max_steps: Option<u64>, timeout: Option<Duration>) -> Self {
+        Limits {
+            max_steps,
+            timeout: timeout.map(|t| (t, Instant::now() + t)),
+            steps: 0,
+            next_check: 0,
+            reached: None,
+        }
+    }
+    /// Whether a limit has been reached and the program should not run another step, counting the step if not
+    pub fn reached(&mut self) -> bool {
+        if let Some(max) = self.max_steps.filter(|&max| self.steps >= max) {
+            self.reached = Some(Limit::Steps(max));
+        }
+        // Looking at the clock every step would slow things down
+        if let Some((timeout, deadline)) = self.timeout {
+            if self.steps >= self.next_check {
+                self.next_check = self.steps + 1024;
+                if Instant::now() >= deadline {
+                    self.reached = Some(Limit::Timeout(timeout));
+                }
+            }
+        }
+        self.steps += 1;
+        self.reached.is_some()
+    }
+    /// How many steps may run now, including the one `reached` just counted
+    #[cfg(feature = "jit")]
+    fn left(&self) -> u64 {
+        self.max_steps.map_or(u64::MAX, |max| max + 1 - self.steps)
+    }
+    /// Counts the steps run after `reached` was asked, which counted only one of them
+    #[cfg(feature = "jit")]
+    fn ran(&mut self, steps: u64) {
+        self.steps += steps - 1;
+    }
+}
+
+/// Keeps a program from running more cycles than it would on a machine with the clock speed
+struct Throttle {
+    hz: u64,
+    /// When the cycles since `start_cycles` started
+    start: Instant,
+    start_cycles: u64,
+    /// The cycle to look at the clock again at, about every 10ms
+    next_check: u64,
+}
+
+impl Throttle {
+    /// How far behind the program may fall, like while waiting for input, before it stops catching up
+    const MAX_LAG: Duration = Duration::from_millis(100);
+
+    fn new(hz: u64) -> Self {
+        Throttle {
+            hz,
+            start: Instant::now(),
+            start_cycles: 0,
+            next_check: 0,
+        }
+    }
+    /// Sleeps until the clock catches up with the cycles the program has run
+    fn pace(&mut self, cycles: u64) {
+        if cycles < self.next_check {
+            return;
+        }
+        self.next_check = cycles + (self.hz / 100).max(1);
+        let due = Duration::from_secs_f64((cycles - self.start_cycles) as f64 / self.hz as f64);
+        let elapsed = self.start.elapsed();
+        if let Some(ahead) = due.checked_sub(elapsed) {
+            thread::sleep(ahead);
+        } else if elapsed - due > Self::MAX_LAG {
+            self.start = Instant::now();
+            self.start_cycles = cycles;
+        }
+    }
+}
+
+/// What to do with a machine once it is set up
+struct Session {
+    args: Vec<String>,
+    env: Vec<String>,
+    limits: Limits,
+    throttle: Option<Throttle>,
+    restore: Option<PathBuf>,
+    save_on_exit: Option<PathBuf>,
+    gdb: Option<u16>,
+    listener: Option<gdb::Listener>,
+    tracer: Option<trace::Tracer<Box<dyn Write>>>,
+    io_log: Option<Box<dyn Write>>,
+    report_cycles: bool,
+    termination_point: bool,
+    symbols: SymbolLookup,
+    #[cfg(feature = "jit")]
+    jit: bool,
+}
+
+fn run<I: Io>(machine: &mut Machine<I>, session: Session) -> Result<u8, Error> {
+    let Session {
+        args,
+        env,
+        mut limits,
+        mut throttle,
+        restore,
+        save_on_exit,
+        gdb,
+        mut listener,
+        tracer,
+        io_log,
+        report_cycles,
+        termination_point,
+        symbols,
+        #[cfg(feature = "jit")]
+        jit,
+    } = session;
+    if let Some(out) = io_log {
+        iolog::attach(machine, out, symbols.clone());
+    }
+    if let Some(path) = restore {
+        let snapshot = Snapshot::read_file(path).map_err(Error::Snapshot)?;
+        machine.restore(&snapshot).map_err(Error::Snapshot)?;
+    } else {
+        machine
+            .pass_arguments(&args, &env)
+            .map_err(Error::Arguments)?;
+    }
+    if let Some(throttle) = &mut throttle {
+        // A restored program keeps the cycles it had
+        throttle.start_cycles = machine.cycles();
+        throttle.next_check = machine.cycles();
+    }
+    let tm = match (gdb, tracer) {
+        (Some(port), _) => match gdb::serve(machine, port).map_err(Error::IoError)? {
+            gdb::Outcome::Ended(tm) => Some(tm),
+            gdb::Outcome::Killed => return Ok(0),
+        },
+        (None, Some(mut tracer)) => tracer.run(machine, &mut limits).map_err(Error::IoError)?,
+        #[cfg(feature = "jit")]
+        (None, None) if jit => {
+            let mut jit = telda2::jit::Jit::new().map_err(Error::Jit)?;
+            loop {
+                if limits.reached() {
+                    break None;
+                }
+                let (ran, result) = machine.step_native(&mut jit, limits.left());
+                limits.ran(ran);
+                if let Err(tm) = result {
+                    break Some(tm);
+                }
+            }
+        }
+        (None, None) => loop {
+            if limits.reached() {
+                break None;
+            }
+            if let Err(tm) = machine.step() {
+                break Some(tm);
+            }
+            if let Some(throttle) = &mut throttle {
+                throttle.pace(machine.cycles());
+            }
+            if let Some(listener) = &mut listener {
+                match listener.poll(machine).map_err(Error::IoError)? {
+                    Some(gdb::Outcome::Ended(tm)) => break Some(tm),
+                    Some(gdb::Outcome::Killed) => return Ok(0),
+                    None => (),
+                }
+            }
+        },
+    };
+
+    if let Some(path) = save_on_exit {
+        machine
+            .snapshot()
+            .write_file(path)
+            .map_err(Error::IoError)?;
+    }
+    if report_cycles {
+        eprintln!("{} cycles", machine.cycles());
+    }
+    let Some(tm) = tm else {
+        let limit = limits.reached.expect("only limits stop early");
+        match limit {
+            Limit::Steps(n) => eprintln!("stopped after {n} steps"),
+            Limit::Timeout(t) => eprintln!("timed out after {t:?}"),
+        }
+        dump_registers(machine, &symbols, machine.pc());
+        return Err(Error::Limit);
+    };
+    if termination_point {
+        let (closest, diff) = symbols.nearest(machine.pc()).unwrap_or(("", machine.pc()));
+        println!("Ended with {tm:?} at <{closest}+{diff:02X}>");
+    } else if tm != TrapMode::Halt {
+        crash::report(machine, &symbols, tm);
+        return Err(Error::Trapped);
+    }
+
+    Ok(match tm {
+        TrapMode::Halt => machine.registers().exit_code,
+        _ => 0,
+    })
+}
+
+/// Writes the registers to stderr, with `pc` as the program counter
+fn dump_registers<I: Io>(machine: &Machine<I>, symbols: &SymbolLookup, pc: u16) {
+    let r = machine.registers();
+    for n in 0..16 {
+        let reg = WideRegister(U4::new(n));
+        eprint!("{:>4} = 0x{:04x}", reg.to_string(), r.read_wide(reg));
+        if n % 4 == 3 {
+            eprintln!();
+        }
+    }
+    eprintln!("  pc = {}", symbols.describe(pc));
+    eprintln!(
+        "flags: {}{}{}{}",
+        if r.carry { "C" } else { "-" },
+        if r.overflow { "O" } else { "-" },
+        if r.sign { "S" } else { "-" },
+        if r.zero { "Z" } else { "-" },
+    );
+}
@@ -0,0 +1,54 @@
+//! Bridging a serial device to TCP connections, one at a time
+//!
+//! Bytes the program sends while nothing is connected are lost.
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc::channel, Arc, Mutex},
+    thread,
+};
+
+use telda2::devices::Serial;
+
+/// The connection bytes from the program go to, if there is one
+#[derive(Debug, Clone, Default)]
+pub struct Connection(Arc<Mutex<Option<TcpStream>>>);
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self.0.lock().unwrap();
+        if let Some(stream) = &mut *current {
+            if stream.write_all(buf).is_err() {
+                *current = None;
+            }
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Listens on the port for connections to a serial device, without waiting for one
+pub fn listen(port: u16) -> io::Result<Serial<Connection>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("serial port on 127.0.0.1:{port}");
+    let (send, received) = channel();
+    let connection = Connection::default();
+    let current = connection.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            *current.0.lock().unwrap() = stream.try_clone().ok();
+            let mut stream = stream;
+            let mut buf = [0; 256];
+            while let Ok(n @ 1..) = stream.read(&mut buf) {
+                if buf[..n].iter().any(|&b| send.send(b).is_err()) {
+                    return;
+                }
+            }
+            *current.0.lock().unwrap() = None;
+        }
+    });
+    Ok(Serial::new(received, connection))
+}
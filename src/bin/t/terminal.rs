@@ -0,0 +1,170 @@
+//! Typing into the program as it runs, with the keys going to a keyboard device instead of waiting for lines
+
+use std::{
+    io::{self, stdin, stdout, IsTerminal, Read, Write},
+    process::{self, Command, Stdio},
+    sync::mpsc::{channel, Sender},
+    thread,
+};
+
+use telda2::{
+    devices::{Buttons, Gamepad, Keyboard, KEYBOARD_PORT},
+    mem::Io,
+};
+
+/// The terminal settings from before, put back when dropped
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    /// Turns off echoing, line buffering and signals of the terminal on stdin, if it is one
+    fn enter() -> io::Result<Option<Self>> {
+        if !stdin().is_terminal() {
+            return Ok(None);
+        }
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Ok(Some(RawMode {
+            saved: saved.trim().to_owned(),
+        }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Turns keys into gamepad buttons: the arrows, z for A, x for B and enter for start
+#[derive(Default)]
+struct ButtonMap {
+    /// How far into an arrow's escape sequence (`ESC [ A` or `ESC O A`) the keys are
+    escape: u8,
+}
+
+impl ButtonMap {
+    /// The buttons the key presses, if any
+    fn press(&mut self, key: u8) -> u8 {
+        match (self.escape, key) {
+            (0, 0x1b) => {
+                self.escape = 1;
+                0
+            }
+            (1, b'[' | b'O') => {
+                self.escape = 2;
+                0
+            }
+            (2, key) => {
+                self.escape = 0;
+                match key {
+                    b'A' => Gamepad::UP,
+                    b'B' => Gamepad::DOWN,
+                    b'C' => Gamepad::RIGHT,
+                    b'D' => Gamepad::LEFT,
+                    _ => 0,
+                }
+            }
+            (_, key) => {
+                self.escape = 0;
+                match key {
+                    b'z' | b'Z' => Gamepad::A,
+                    b'x' | b'X' => Gamepad::B,
+                    b'\r' | b'\n' => Gamepad::START,
+                    _ => 0,
+                }
+            }
+        }
+    }
+}
+
+/// Stdout and a keyboard on stdin at `KEYBOARD_PORT`
+///
+/// Reading the other ports waits for the next key like reading stdin would.
+pub struct Console {
+    keyboard: Keyboard,
+    _raw: Option<RawMode>,
+}
+
+impl Console {
+    /// Also taps the buttons of a gamepad for keys that map to them, when there is one
+    pub fn new(gamepad: Option<Sender<Buttons>>) -> io::Result<Self> {
+        let raw = RawMode::enter()?;
+        let restore = raw.as_ref().map(|r| r.saved.clone());
+
+        let (send, keys) = channel();
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            let mut map = ButtonMap::default();
+            while let Ok(n @ 1..) = stdin().read(&mut buf) {
+                for &b in &buf[..n] {
+                    // With signals off, ^C comes as a key, but it should still stop `t`
+                    if b == 0x03 {
+                        if let Some(saved) = &restore {
+                            let _ = stty(&[saved]);
+                        }
+                        process::exit(130);
+                    }
+                    if let Some(gamepad) = &gamepad {
+                        let pressed = map.press(b);
+                        if pressed != 0 {
+                            let _ = gamepad.send(Buttons::Tapped(pressed));
+                        }
+                    }
+                    if send.send(b).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Console {
+            keyboard: Keyboard::new(keys),
+            _raw: raw,
+        })
+    }
+}
+
+impl Io for Console {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr.wrapping_sub(KEYBOARD_PORT) {
+            port @ 0..=1 => self.keyboard.read(port),
+            _ => self.keyboard.wait().unwrap_or(0),
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        match addr.wrapping_sub(KEYBOARD_PORT) {
+            port @ 0..=1 => self.keyboard.write(port, val),
+            _ => {
+                let mut stdout = stdout();
+                stdout.write_all(&[val]).expect("stdout failed");
+                stdout.flush().expect("stdout failed");
+            }
+        }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.keyboard.tick(cycles);
+    }
+    fn interrupt_pending(&self) -> bool {
+        self.keyboard.interrupt_pending()
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.keyboard.save_state(state)
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        self.keyboard.restore_state(state)
+    }
+}
@@ -0,0 +1,351 @@
+//! Logging every executed instruction with the registers it changed
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use clap::ValueEnum;
+
+use crate::Limits;
+use telda2::{
+    aalv::obj::{Object, SymbolLookup},
+    cpu::{Registers, TrapMode, WideRegister},
+    disassemble::Disassembler,
+    machine::{Access, Machine},
+    mem::Io,
+    U4,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Aligned lines with the registers and flags each instruction changed
+    Text,
+    /// A header and then one row per instruction with every register and the memory it accessed
+    Csv,
+    /// One JSON object per line and instruction with every register and the memory it accessed
+    Json,
+}
+
+/// A byte an instruction read or wrote, I/O accesses have their full address
+#[derive(Debug, Clone, Copy)]
+struct Effect {
+    access: Access,
+    addr: u16,
+    value: u8,
+}
+
+/// An executed instruction and what it did
+struct Step<'a> {
+    location: u16,
+    opcode: u8,
+    text: &'a str,
+    before: &'a Registers,
+    after: &'a Registers,
+    effects: &'a [Effect],
+    trap: Option<TrapMode>,
+}
+
+pub struct Tracer<W> {
+    out: W,
+    format: Format,
+    /// Only instructions in these ranges are traced, all if there are none
+    ranges: Vec<(u16, u16)>,
+    symbols: SymbolLookup,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Makes a tracer for the object, limited to the ranges if any are given
+    ///
+    /// A range is either a symbol, covering its size or up to the next symbol, or addresses like `0x10..0x40`.
+    pub fn new(out: W, format: Format, obj: &Object, ranges: &[String]) -> Result<Self, String> {
+        // Symbols by location in each segment, as start and end of the segment, for where symbols end
+        let mut segments: Vec<_> = obj
+            .segs
+            .values()
+            .map(|(start, bytes)| (*start, *start as usize + bytes.len(), BTreeMap::new()))
+            .collect();
+        for s in obj.symbols.0.iter().filter(|s| !s.name.is_empty()) {
+            if let Some((_, _, symbols)) = segments
+                .iter_mut()
+                .find(|(start, end, _)| *start <= s.location && (s.location as usize) < *end)
+            {
+                symbols.entry(s.location).or_insert_with(|| s.name.clone());
+            }
+        }
+
+        let ranges = ranges
+            .iter()
+            .map(|range| {
+                if let Some((start, end)) = range.split_once("..") {
+                    return match (parse_num(start), parse_num(end)) {
+                        (Some(start), Some(end)) => Ok((start, end)),
+                        _ => Err(format!("invalid address range {range}")),
+                    };
+                }
+                let symbol = obj
+                    .symbols
+                    .0
+                    .iter()
+                    .find(|s| *s.name == **range)
+                    .ok_or_else(|| format!("no symbol named {range}"))?;
+                let end = match symbol.size {
+                    Some(size) => symbol.location.saturating_add(size),
+                    None => segments
+                        .iter()
+                        .find(|(start, end, _)| {
+                            *start <= symbol.location && (symbol.location as usize) < *end
+                        })
+                        .map_or(symbol.location.saturating_add(1), |(_, end, symbols)| {
+                            symbols
+                                .range(symbol.location + 1..)
+                                .next()
+                                .map_or(*end as u16, |(&l, _)| l)
+                        }),
+                };
+                Ok((symbol.location, end))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Tracer {
+            out,
+            format,
+            ranges,
+            symbols: SymbolLookup::new(obj),
+        })
+    }
+
+    fn wants(&self, location: u16) -> bool {
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| start <= location && location < end)
+    }
+
+    fn record(&mut self, step: Step) -> io::Result<()> {
+        match self.format {
+            Format::Text => self.record_text(step),
+            Format::Csv => self.record_csv(step),
+            Format::Json => self.record_json(step),
+        }
+    }
+
+    fn record_text(&mut self, step: Step) -> io::Result<()> {
+        let Step {
+            location,
+            text,
+            before,
+            after,
+            ..
+        } = step;
+        let described = self.symbols.describe(location);
+        let mut line = format!("{described:<24} {text:<24}");
+        for n in 1..16 {
+            let r = WideRegister(U4::new(n));
+            let (old, new) = (before.read_wide(r), after.read_wide(r));
+            if old != new {
+                line.push_str(&format!(" {r}=0x{new:04x}"));
+            }
+        }
+        if flags(before) != flags(after) {
+            line.push_str(" flags=");
+            line.extend(flags(after));
+        }
+        writeln!(self.out, "{}", line.trim_end())
+    }
+
+    fn record_csv(&mut self, step: Step) -> io::Result<()> {
+        let mut line = format!(
+            "{},{},{},{}",
+            step.location,
+            csv_field(&self.symbol(step.location)),
+            step.opcode,
+            csv_field(step.text)
+        );
+        for n in 1..16 {
+            let _ = write!(line, ",{}", step.after.read_wide(WideRegister(U4::new(n))));
+        }
+        line.push(',');
+        line.extend(flags(step.after));
+        let effects: Vec<_> = step
+            .effects
+            .iter()
+            .map(|e| {
+                let access = match e.access {
+                    Access::Read => 'r',
+                    Access::Write => 'w',
+                };
+                format!("{access}:{}={}", e.addr, e.value)
+            })
+            .collect();
+        let trap = step.trap.map(|tm| format!("{tm:?}")).unwrap_or_default();
+        writeln!(self.out, "{line},{},{trap}", effects.join(" "))
+    }
+
+    fn record_json(&mut self, step: Step) -> io::Result<()> {
+        let mut line = format!(
+            "{{\"pc\":{},\"symbol\":{},\"opcode\":{},\"instruction\":{},\"registers\":{{",
+            step.location,
+            json_string(&self.symbol(step.location)),
+            step.opcode,
+            json_string(step.text)
+        );
+        for n in 1..16 {
+            let r = WideRegister(U4::new(n));
+            let comma = if n > 1 { "," } else { "" };
+            let _ = write!(line, "{comma}\"{r}\":{}", step.after.read_wide(r));
+        }
+        let flags: String = flags(step.after).iter().collect();
+        let _ = write!(line, "}},\"flags\":\"{flags}\",\"memory\":[");
+        for (i, e) in step.effects.iter().enumerate() {
+            let access = match e.access {
+                Access::Read => "read",
+                Access::Write => "write",
+            };
+            let comma = if i > 0 { "," } else { "" };
+            let _ = write!(
+                line,
+                "{comma}{{\"access\":\"{access}\",\"addr\":{},\"value\":{}}}",
+                e.addr, e.value
+            );
+        }
+        line.push_str("],\"trap\":");
+        match step.trap {
+            Some(tm) => line.push_str(&json_string(&format!("{tm:?}"))),
+            None => line.push_str("null"),
+        }
+        writeln!(self.out, "{line}}}")
+    }
+
+    /// The symbol at exactly the location, or an empty string
+    fn symbol(&self, location: u16) -> String {
+        self.symbols.at(location).unwrap_or_default().to_owned()
+    }
+
+    /// Runs the machine until it traps without a trap handler, tracing each instruction
+    ///
+    /// Gives `None` if a limit stopped it first.
+    pub fn run<I: Io>(
+        &mut self,
+        machine: &mut Machine<I>,
+        limits: &mut Limits,
+    ) -> io::Result<Option<TrapMode>> {
+        let effects = Rc::new(RefCell::new(Vec::new()));
+        if self.format != Format::Text {
+            let record = |access| {
+                let effects = effects.clone();
+                move |addr, value| {
+                    effects.borrow_mut().push(Effect {
+                        access,
+                        addr,
+                        value,
+                    })
+                }
+            };
+            machine.on_mem_read(record(Access::Read));
+            machine.on_mem_write(record(Access::Write));
+            let effects = effects.clone();
+            machine.on_io(move |access, port, value| {
+                let addr = 0xff00 | port as u16;
+                effects.borrow_mut().push(Effect {
+                    access,
+                    addr,
+                    value,
+                })
+            });
+            if self.format == Format::Csv {
+                let registers: Vec<_> = (1..16)
+                    .map(|n| WideRegister(U4::new(n)).to_string())
+                    .collect();
+                writeln!(
+                    self.out,
+                    "pc,symbol,opcode,instruction,{},flags,memory,trap",
+                    registers.join(",")
+                )?;
+            }
+        }
+
+        let tm = loop {
+            if limits.reached() {
+                break None;
+            }
+            let location = machine.pc();
+            if !self.wants(location) {
+                if let Err(tm) = machine.step() {
+                    break Some(tm);
+                }
+                continue;
+            }
+
+            let text = Disassembler::new(machine.memory())
+                .symbolizer(|p| self.symbols.at(p).map(|s| s.to_owned()))
+                .decode(location)
+                .text;
+            let opcode = machine.peek(location);
+            let before = machine.registers().clone();
+            effects.borrow_mut().clear();
+            let result = machine.step();
+            self.record(Step {
+                location,
+                opcode,
+                text: &text,
+                before: &before,
+                after: machine.registers(),
+                effects: &effects.borrow(),
+                trap: result.err(),
+            })?;
+            if let Err(tm) = result {
+                break Some(tm);
+            }
+        };
+        self.out.flush()?;
+        Ok(tm)
+    }
+}
+
+/// The carry, overflow, sign and zero flags as letters, or `-` when clear
+fn flags(r: &Registers) -> [char; 4] {
+    [
+        (r.carry, 'C'),
+        (r.overflow, 'O'),
+        (r.sign, 'S'),
+        (r.zero, 'Z'),
+    ]
+    .map(|(set, c)| if set { c } else { '-' })
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn parse_num(s: &str) -> Option<u16> {
+    let s = s.trim();
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
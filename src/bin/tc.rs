@@ -1,130 +1,206 @@
-use std::{collections::BTreeMap, env::args, path::Path, process::ExitCode};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    process::ExitCode,
+    thread,
+    time::{Duration, SystemTime},
+};
 
+use clap::Parser;
 use telda2::{
-    aalv::obj::{
-        Object, RelocationEntry, RelocationTable, SegmentType, SymbolDefinition, SymbolTable,
-        AALV_OBJECT_EXT,
-    },
-    source::{
-        process, write_data_operand, DataLine, Error as TeldaError, LabelRead, ProcessedSource,
-        SourceLines, SymbolType, Wide,
-    },
+    aalv::obj::{Object, AALV_OBJECT_EXT},
+    link::{link, write_executable, Error as LinkError, LinkOptions},
+    source::{assemble, Error as TeldaError},
 };
 
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+/// Assembles source files and links them with any object files into an executable
+struct Cli {
+    /// Source files to assemble and .to object files to link with them
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Only assemble, each source into an object file next to it (or to -o with one source)
+    #[arg(short = 'c')]
+    compile_only: bool,
+
+    /// Sets the output path, otherwise a.to is used for the executable
+    #[arg(short, long, value_name = "FILE")]
+    out: Option<PathBuf>,
+
+    /// Sets the entry point of the executable, a hexadecimal address prefixed by 0x or a global symbol
+    #[arg(
+        short = 'E',
+        long,
+        value_name = "ENTRY",
+        conflicts_with = "compile_only"
+    )]
+    set_entry: Option<String>,
+
+    /// Emit debug information mapping code back to source lines
+    #[arg(short = 'g')]
+    debug_info: bool,
+
+    /// Adds a directory to search for libraries in
+    #[arg(short = 'L', value_name = "DIR", conflicts_with = "compile_only")]
+    library_dirs: Vec<PathBuf>,
+
+    /// Links with the library archive lib<NAME>.ta found in the library search path
+    #[arg(short = 'l', value_name = "NAME", conflicts_with = "compile_only")]
+    libraries: Vec<String>,
+
+    /// Do not link with the standard library (libtstd.ta)
+    #[arg(long, conflicts_with = "compile_only")]
+    no_std: bool,
+
+    /// Keeps running, building again whenever one of the files or a file they include changes
+    #[arg(long)]
+    watch: bool,
+}
+
+/// How often to check if the watched files changed
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+
 fn main() -> ExitCode {
-    let mut ret = ExitCode::SUCCESS;
-    for arg in args().skip(1) {
-        let p = Path::new(&arg);
-        let ProcessedSource { labels, dls, entry } = match SourceLines::new(p).and_then(process) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("{}", e);
-                ret = ExitCode::FAILURE;
-                continue;
-            }
+    let cli = Cli::parse();
+    if !cli.watch {
+        return build(&cli, &mut Vec::new());
+    }
+
+    // Files stay watched after failed builds, where the included files are not known
+    let mut watched = cli.files.clone();
+    loop {
+        let status = if build(&cli, &mut watched) == ExitCode::SUCCESS {
+            "done"
+        } else {
+            "failed"
         };
-        let mut label_reads: Vec<Vec<LabelRead>> = Vec::new();
-        label_reads.resize_with(labels.len(), Vec::new);
+        eprintln!("[{status}, watching {} files]", watched.len());
+
+        let changed = wait_for_change(&watched);
+        eprintln!();
+        eprintln!("[{} changed, building again]", changed.display());
+    }
+}
 
-        let mut segs = BTreeMap::new();
-        let mut lines = Vec::with_capacity(dls.len());
+fn modification_times(files: &[PathBuf]) -> BTreeMap<&PathBuf, Option<SystemTime>> {
+    files
+        .iter()
+        .map(|p| (p, fs::metadata(p).and_then(|m| m.modified()).ok()))
+        .collect()
+}
 
-        for (stype, dls) in dls {
-            segs.insert(stype, (dls.start, Vec::with_capacity(dls.size as usize)));
-            lines.push(dls.lines);
+/// Polls the files until one of them is changed, created or removed, giving which one
+fn wait_for_change(files: &[PathBuf]) -> PathBuf {
+    let before = modification_times(files);
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let now = modification_times(files);
+        if let Some((&p, _)) = now.iter().find(|&(p, time)| before[p] != *time) {
+            return p.clone();
         }
+    }
+}
 
-        for ((&st, &mut (segment_start, ref mut mem)), lines) in segs.iter_mut().zip(lines) {
-            for data_line in lines {
-                match data_line {
-                    DataLine::Raw(mut bytes) => {
-                        mem.append(&mut bytes);
-                    }
-                    DataLine::Wide(Wide::Number(w)) => mem.extend_from_slice(&w.to_le_bytes()),
-                    DataLine::Wide(Wide::Label(id)) => {
-                        let lr = LabelRead {
-                            segment: st,
-                            position: mem.len() as u16 + segment_start,
-                        };
-                        label_reads[id].push(lr);
-                        let w = labels[id].3;
-                        mem.extend_from_slice(&w.to_le_bytes());
-                    }
-                    DataLine::Ins(opcode, dat_op) => {
-                        mem.push(opcode);
+/// Assembles and links like the options say, adding every file it read to `watched`
+fn build(cli: &Cli, watched: &mut Vec<PathBuf>) -> ExitCode {
+    let Cli {
+        files,
+        compile_only,
+        out,
+        set_entry,
+        debug_info,
+        library_dirs,
+        libraries,
+        no_std,
+        watch: _,
+    } = cli;
+    let (compile_only, debug_info, no_std) = (*compile_only, *debug_info, *no_std);
 
-                        let read_label = |id: usize, lr| {
-                            label_reads[id].push(lr);
-                            labels[id].3
-                        };
+    let (objects, sources): (Vec<_>, Vec<_>) = files
+        .iter()
+        .cloned()
+        .partition(|p| p.extension().is_some_and(|ext| ext == AALV_OBJECT_EXT));
+    if compile_only {
+        if out.is_some() && sources.len() > 1 {
+            eprintln!("-o cannot be used with -c and several source files");
+            return ExitCode::FAILURE;
+        }
+        for p in &objects {
+            eprintln!(
+                "warning: {} is not used, since -c does not link",
+                p.display()
+            );
+        }
+    }
 
-                        write_data_operand(st, mem, read_label, dat_op);
+    let mut ret = ExitCode::SUCCESS;
+    let mut assembled = Vec::with_capacity(sources.len() + objects.len());
+    for p in sources {
+        let obj = match assemble(&p, debug_info) {
+            Ok(obj) => {
+                let included = obj.notes.0.iter().flat_map(|n| &n.sources);
+                for (src, _) in included {
+                    let src = PathBuf::from(&**src);
+                    if !watched.contains(&src) {
+                        watched.push(src);
                     }
                 }
+                obj
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ret = ExitCode::FAILURE;
+                continue;
             }
-        }
-
-        let mut aalvur = Object {
-            segs,
-            entry,
-            ..Object::default()
         };
 
-        let mut symbol_table = Vec::new();
-        {
-            for &(ref lbl, st, segment_type, location) in labels.iter() {
-                let is_global = match st {
-                    SymbolType::Global => true,
-                    SymbolType::Internal => false,
-                    SymbolType::Reference => {
-                        assert_eq!(
-                            segment_type,
-                            SegmentType::Unknown,
-                            "reference symbols should have unknown segment type"
-                        );
-                        true
-                    }
-                };
-
-                symbol_table.push(SymbolDefinition {
-                    name: lbl.clone(),
-                    is_global,
-                    segment_type,
-                    location,
-                })
+        if compile_only {
+            let out = out
+                .clone()
+                .unwrap_or_else(|| p.with_extension(AALV_OBJECT_EXT));
+            if let Err(e) = obj.write_to_file(out) {
+                eprintln!("{}", TeldaError::from(e));
+                ret = ExitCode::FAILURE;
             }
+        } else {
+            assembled.push((p, obj));
         }
-        aalvur.symbols = SymbolTable(symbol_table);
-
-        let reloc_table;
-        {
-            let mut reloc_t = Vec::new();
-
-            for (i, label_reads) in label_reads.into_iter().enumerate() {
-                let symbol_index = i as u16;
-
-                for LabelRead { segment, position } in label_reads {
-                    let entry = RelocationEntry {
-                        reference_location: aalvur.segs[&segment].0 + position,
-                        reference_segment: segment,
-                        symbol_index,
-                    };
+    }
+    if compile_only || ret == ExitCode::FAILURE {
+        return ret;
+    }
 
-                    reloc_t.push(entry);
-                }
+    for p in objects {
+        match Object::from_file(&p) {
+            Ok(obj) => assembled.push((p, obj)),
+            Err(e) => {
+                eprintln!("could not read object file {}: {e}", p.display());
+                return ExitCode::FAILURE;
             }
-            reloc_table = RelocationTable(reloc_t);
         }
-        aalvur.relocation_table = reloc_table;
+    }
 
-        match aalvur.write_to_file(p.with_extension(AALV_OBJECT_EXT)) {
-            Ok(()) => (),
-            Err(e) => {
-                eprintln!("{}", TeldaError::from(e));
-                ret = ExitCode::FAILURE;
-                continue;
-            }
+    let options = LinkOptions {
+        set_entry: set_entry.clone(),
+        executable: true,
+        library_dirs: library_dirs.clone(),
+        libraries: libraries.clone(),
+        no_std,
+        ..LinkOptions::default()
+    };
+    let args: Vec<String> = env::args().skip(1).collect();
+    let out = out.clone().unwrap_or_else(|| PathBuf::from("a.to"));
+    let linked = link(assembled, &options, "tc", &args.join(" "))
+        .and_then(|obj| write_executable(obj, &out, false));
+    match linked {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(LinkError::ObjectFailure) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
         }
     }
-    ret
 }
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use telda2::{
+    machine::{Access, Machine, Watch, WatchHit},
+    mem::Io,
+};
+
+use crate::expr::Expr;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    At(u16),
+    /// Stops after instructions that access the `len` bytes from `start`
+    Watch {
+        start: u16,
+        len: u16,
+        watch: Watch,
+    },
+}
+
+/// An expression with the text it was written as
+pub struct Condition {
+    pub text: String,
+    pub expr: Expr,
+}
+
+pub struct Breakpoint {
+    pub kind: Kind,
+    /// Only stops when this is not zero
+    pub condition: Option<Condition>,
+    /// How many more times to not stop when it is hit
+    pub ignore: u32,
+    /// How many times it was reached with its condition holding
+    pub hits: u32,
+}
+
+impl Breakpoint {
+    fn catches(&self, hit: WatchHit) -> bool {
+        match self.kind {
+            Kind::Watch { start, len, watch } => {
+                hit.addr.wrapping_sub(start) < len
+                    && matches!(
+                        (watch, hit.access),
+                        (Watch::Any, _)
+                            | (Watch::Read, Access::Read)
+                            | (Watch::Write, Access::Write)
+                    )
+            }
+            Kind::At(_) => false,
+        }
+    }
+    fn holds<I: Io>(&self, machine: &Machine<I>) -> bool {
+        self.condition
+            .as_ref()
+            .is_none_or(|cond| cond.expr.eval(machine) != 0)
+    }
+    /// Counts a hit if the condition holds, telling whether to stop
+    fn hit<I: Io>(&mut self, machine: &Machine<I>) -> bool {
+        if !self.holds(machine) {
+            return false;
+        }
+        self.hits += 1;
+        if self.ignore > 0 {
+            self.ignore -= 1;
+            return false;
+        }
+        true
+    }
+}
+
+/// Breakpoints and watchpoints by their number
+pub struct Breakpoints {
+    breakpoints: BTreeMap<u32, Breakpoint>,
+    next: u32,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Breakpoints {
+            breakpoints: BTreeMap::new(),
+            next: 1,
+        }
+    }
+    pub fn add(&mut self, kind: Kind, condition: Option<Condition>) -> u32 {
+        let n = self.next;
+        self.next += 1;
+        self.breakpoints.insert(
+            n,
+            Breakpoint {
+                kind,
+                condition,
+                ignore: 0,
+                hits: 0,
+            },
+        );
+        n
+    }
+    pub fn remove(&mut self, n: u32) -> bool {
+        self.breakpoints.remove(&n).is_some()
+    }
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+    pub fn get_mut(&mut self, n: u32) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&n)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Breakpoint)> {
+        self.breakpoints.iter().map(|(&n, b)| (n, b))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    /// Whether there is a breakpoint at the location, whatever its condition
+    pub fn at(&self, location: u16) -> bool {
+        self.breakpoints
+            .values()
+            .any(|b| matches!(b.kind, Kind::At(l) if l == location))
+    }
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u16, u16, Watch)> + '_ {
+        self.breakpoints.values().filter_map(|b| match b.kind {
+            Kind::Watch { start, len, watch } => Some((start, len, watch)),
+            Kind::At(_) => None,
+        })
+    }
+
+    /// Counts the hits of the breakpoints at the location, giving the first that stops
+    pub fn reached<I: Io>(&mut self, location: u16, machine: &Machine<I>) -> Option<u32> {
+        let mut stop = None;
+        for (&n, b) in &mut self.breakpoints {
+            if matches!(b.kind, Kind::At(l) if l == location) && b.hit(machine) {
+                stop = stop.or(Some(n));
+            }
+        }
+        stop
+    }
+    /// The first breakpoint at the location with its condition holding, without counting hits or ignoring any
+    ///
+    /// For going backwards, where the hits were already counted.
+    pub fn would_stop_at<I: Io>(&self, location: u16, machine: &Machine<I>) -> Option<u32> {
+        self.breakpoints
+            .iter()
+            .find(|(_, b)| matches!(b.kind, Kind::At(l) if l == location) && b.holds(machine))
+            .map(|(&n, _)| n)
+    }
+    /// The first watchpoint catching the access with its condition holding, like [`Self::would_stop_at`]
+    pub fn would_catch<I: Io>(&self, hit: WatchHit, machine: &Machine<I>) -> Option<u32> {
+        self.breakpoints
+            .iter()
+            .find(|(_, b)| b.catches(hit) && b.holds(machine))
+            .map(|(&n, _)| n)
+    }
+    /// Counts the hits of the watchpoints that catch the access, giving the first that stops
+    pub fn watched<I: Io>(&mut self, hit: WatchHit, machine: &Machine<I>) -> Option<u32> {
+        let mut stop = None;
+        for (&n, b) in &mut self.breakpoints {
+            if b.catches(hit) && b.hit(machine) {
+                stop = stop.or(Some(n));
+            }
+        }
+        stop
+    }
+}
@@ -0,0 +1,136 @@
+//! How `x/NFU LOC` shows memory, like `x/16xb buf` for 16 bytes in hex or `x/4dw rs` for 4 signed wides
+//!
+//! The format and unit stay as they were last given, the count is 1 unless given.
+
+use crate::Symbols;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    Signed,
+    Unsigned,
+    Octal,
+    Binary,
+    Char,
+    /// Described with the symbol it points into, for pointers
+    Address,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Byte,
+    Wide,
+}
+
+impl Unit {
+    pub fn size(self) -> u16 {
+        match self {
+            Unit::Byte => 1,
+            Unit::Wide => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+    pub count: u16,
+    pub format: Format,
+    pub unit: Unit,
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Spec {
+            count: 1,
+            format: Format::Hex,
+            unit: Unit::Byte,
+        }
+    }
+}
+
+impl Spec {
+    /// Parses what follows the slash, like `16xb`, keeping the format and unit of `self` if they are left out
+    pub fn parse(self, s: &str) -> Result<Self, String> {
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let count = match &s[..digits] {
+            "" => 1,
+            n => n.parse().map_err(|_| format!("invalid count {n}"))?,
+        };
+        let mut spec = Spec { count, ..self };
+        for c in s[digits..].chars() {
+            match c {
+                'x' => spec.format = Format::Hex,
+                'd' => spec.format = Format::Signed,
+                'u' => spec.format = Format::Unsigned,
+                'o' => spec.format = Format::Octal,
+                't' => spec.format = Format::Binary,
+                'c' => spec.format = Format::Char,
+                'a' => spec.format = Format::Address,
+                'b' => spec.unit = Unit::Byte,
+                'w' => spec.unit = Unit::Wide,
+                c => {
+                    return Err(format!(
+                        "unknown format or unit {c:?}, expected one of x d u o t c a and b or w"
+                    ))
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    /// How many values are shown on each line
+    pub fn per_row(self) -> u16 {
+        match (self.format, self.unit) {
+            (Format::Address, _) => 2,
+            (Format::Binary, _) => 4,
+            (Format::Char, _) => 16,
+            (_, unit) => 16 / unit.size(),
+        }
+    }
+
+    pub fn show(self, val: u16, symbols: &Symbols) -> String {
+        match (self.format, self.unit) {
+            (Format::Hex, Unit::Byte) => format!("0x{val:02x}"),
+            (Format::Hex, Unit::Wide) => format!("0x{val:04x}"),
+            (Format::Signed, Unit::Byte) => format!("{:>4}", val as u8 as i8),
+            (Format::Signed, Unit::Wide) => format!("{:>6}", val as i16),
+            (Format::Unsigned, Unit::Byte) => format!("{val:>3}"),
+            (Format::Unsigned, Unit::Wide) => format!("{val:>5}"),
+            (Format::Octal, _) => format!("0o{val:o}"),
+            (Format::Binary, Unit::Byte) => format!("0b{val:08b}"),
+            (Format::Binary, Unit::Wide) => format!("0b{val:016b}"),
+            (Format::Char, _) => match u8::try_from(val) {
+                Ok(b) => format!("{:<4}", format!("'{}'", b.escape_ascii())),
+                Err(_) => format!("0x{val:04x}"),
+            },
+            (Format::Address, _) => symbols.describe(val),
+        }
+    }
+}
+
+#[test]
+fn specs() {
+    let spec = Spec::default().parse("16xb").unwrap();
+    assert_eq!(
+        spec,
+        Spec {
+            count: 16,
+            format: Format::Hex,
+            unit: Unit::Byte
+        }
+    );
+    // The format and unit are kept, the count is not
+    let spec = spec.parse("w").unwrap();
+    assert_eq!(
+        (spec.count, spec.format, spec.unit),
+        (1, Format::Hex, Unit::Wide)
+    );
+    assert_eq!(spec.parse("2d").unwrap().format, Format::Signed);
+    assert!(spec.parse("4q").is_err());
+
+    let symbols = Symbols::default();
+    let signed = spec.parse("d").unwrap();
+    assert_eq!(signed.show(0xfffe, &symbols).trim(), "-2");
+    let chars = signed.parse("cb").unwrap();
+    assert_eq!(chars.show(b'\n' as u16, &symbols).trim(), "'\\n'");
+}
@@ -0,0 +1,252 @@
+//! Expressions over registers and memory, like `r1 == 10 && *counter > 3`
+//!
+//! Everything is a wide value that wraps around, comparisons and logic give 1 or 0.
+//! `*x` and `wide(x)` read the wide at `x`, `byte(x)` the byte, and symbols are their address.
+
+use std::{iter::Peekable, str::CharIndices};
+
+use telda2::{machine::Machine, mem::Io};
+
+use crate::{parse_num, Register, Symbols};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl Op {
+    /// How tightly it binds, operators with the same precedence go from left to right
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+            Op::BitOr => 4,
+            Op::BitAnd => 5,
+            Op::Add | Op::Sub => 6,
+        }
+    }
+    fn apply(self, a: u16, b: u16) -> u16 {
+        match self {
+            Op::Or => (a != 0 || b != 0) as u16,
+            Op::And => (a != 0 && b != 0) as u16,
+            Op::Eq => (a == b) as u16,
+            Op::Ne => (a != b) as u16,
+            Op::Lt => (a < b) as u16,
+            Op::Le => (a <= b) as u16,
+            Op::Gt => (a > b) as u16,
+            Op::Ge => (a >= b) as u16,
+            Op::BitOr => a | b,
+            Op::BitAnd => a & b,
+            Op::Add => a.wrapping_add(b),
+            Op::Sub => a.wrapping_sub(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(u16),
+    Ident(String),
+    Op(Op),
+    Star,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = s.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let mut followed_by = |next: char| chars.next_if(|&(_, c)| c == next).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '*' => Token::Star,
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '=' if followed_by('=') => Token::Op(Op::Eq),
+            '!' if followed_by('=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if followed_by('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if followed_by('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '&' if followed_by('&') => Token::Op(Op::And),
+            '&' => Token::Op(Op::BitAnd),
+            '|' if followed_by('|') => Token::Op(Op::Or),
+            '|' => Token::Op(Op::BitOr),
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) =
+                    chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_' || c == '.')
+                {
+                    end = j + c.len_utf8();
+                }
+                let word = &s[i..end];
+                if c.is_ascii_digit() {
+                    Token::Num(parse_num(word)?)
+                } else {
+                    Token::Ident(word.to_owned())
+                }
+            }
+            c => return Err(format!("unexpected {c:?} in expression")),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Num(u16),
+    Register(Register),
+    Byte(Box<Expr>),
+    Wide(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn expect_close(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => Err("expected `)`".to_owned()),
+        }
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(&Token::Op(op)) = self.peek() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        Ok(match self.next() {
+            Some(Token::Num(n)) => Expr::Num(n),
+            Some(Token::Star) => Expr::Wide(Box::new(self.unary()?)),
+            Some(Token::Not) => Expr::Not(Box::new(self.unary()?)),
+            Some(Token::Op(Op::Sub)) => Expr::Neg(Box::new(self.unary()?)),
+            Some(Token::Open) => {
+                let e = self.binary(0)?;
+                self.expect_close()?;
+                e
+            }
+            Some(Token::Ident(name)) => {
+                if let (Some(Token::Open), "byte" | "wide") = (self.peek(), &*name) {
+                    self.pos += 1;
+                    let e = Box::new(self.binary(0)?);
+                    self.expect_close()?;
+                    return Ok(match &*name {
+                        "byte" => Expr::Byte(e),
+                        _ => Expr::Wide(e),
+                    });
+                }
+                match Register::parse(&name) {
+                    Some(r) => Expr::Register(r),
+                    None => match self.symbols.by_name.get(&*name) {
+                        Some(&l) => Expr::Num(l),
+                        None => return Err(format!("no register or symbol named {name}")),
+                    },
+                }
+            }
+            Some(t) => return Err(format!("unexpected {t:?} in expression")),
+            None => return Err("expression ends early".to_owned()),
+        })
+    }
+}
+
+impl Expr {
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            symbols,
+        };
+        let e = parser.binary(0)?;
+        match parser.next() {
+            None => Ok(e),
+            Some(t) => Err(format!("unexpected {t:?} after expression")),
+        }
+    }
+
+    pub fn eval<I: Io>(&self, m: &Machine<I>) -> u16 {
+        match self {
+            &Expr::Num(n) => n,
+            &Expr::Register(r) => r.read(m.registers()),
+            Expr::Byte(e) => m.peek(e.eval(m)) as u16,
+            Expr::Wide(e) => m.peek_wide(e.eval(m)),
+            Expr::Not(e) => (e.eval(m) == 0) as u16,
+            Expr::Neg(e) => e.eval(m).wrapping_neg(),
+            Expr::Binary(op, a, b) => op.apply(a.eval(m), b.eval(m)),
+        }
+    }
+    /// Whether evaluating it looks at memory, not just registers
+    pub fn reads_memory(&self) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Register(_) => false,
+            Expr::Byte(_) | Expr::Wide(_) => true,
+            Expr::Not(e) | Expr::Neg(e) => e.reads_memory(),
+            Expr::Binary(_, a, b) => a.reads_memory() || b.reads_memory(),
+        }
+    }
+}
+
+#[test]
+fn expressions() {
+    use telda2::{aalv::obj::Object, cpu::R1, mem::PanickingIO};
+
+    let mut symbols = Symbols::default();
+    symbols.by_name.insert("counter".into(), 0x100);
+    let mut m = Machine::with_io(Object::default(), PanickingIO);
+    m.registers_mut().write_wide(R1, 10);
+    m.poke_wide(0x100, 0x1234);
+
+    let eval = |s: &str| Expr::parse(s, &symbols).map(|e| e.eval(&m));
+    assert_eq!(eval("r1 == 10"), Ok(1));
+    assert_eq!(eval("r1 == 10 && *counter > 0x1000"), Ok(1));
+    assert!(eval("r1l * 3").is_err());
+    assert_eq!(eval("byte(counter + 1)"), Ok(0x12));
+    assert_eq!(eval("1 + 2 == 3 || r1 < 1"), Ok(1));
+    assert_eq!(eval("!(r1 != 10) & 1"), Ok(1));
+    assert_eq!(eval("-1"), Ok(0xffff));
+    assert!(eval("nowhere").is_err());
+    assert!(eval("(1").is_err());
+}
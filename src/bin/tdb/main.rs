@@ -0,0 +1,1360 @@
+#[cfg(feature = "tui")]
+use std::{cell::RefCell, rc::Rc};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::{self, stdin, stdout, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use self::{
+    breakpoints::{Breakpoints, Condition, Kind},
+    examine::{Spec, Unit},
+    expr::Expr,
+    remote::{Remote, Stopped},
+};
+use clap::Parser;
+
+mod breakpoints;
+mod examine;
+mod expr;
+mod remote;
+#[cfg(feature = "tui")]
+mod tui;
+use telda2::{
+    aalv::obj::{LineTable, Object, SymbolLookup},
+    cpu::{ByteRegister, Event, Registers, TrapMode, WideRegister},
+    disassemble::{Disassembler, Flow},
+    machine::{Access, Machine, Watch, WatchHit},
+    mem::Io,
+    snapshot::Snapshot,
+    U4,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Executable to debug
+    binary: PathBuf,
+
+    /// Sets custom entry point for this to start execution at
+    ///
+    /// Can be either an address or a symbol
+    #[arg(short = 'E', long)]
+    entry: Option<String>,
+
+    /// Takes the symbols and lines from a debug object that `tl --split-debug` wrote for the binary
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+
+    /// Runs the commands in the file first, one on each line with `#` starting a comment, can be given more than once
+    #[arg(short = 'x', long, value_name = "FILE")]
+    command: Vec<PathBuf>,
+
+    /// Runs the command after the command files, can be given more than once and also as `-ex`
+    #[arg(long = "ex", value_name = "COMMAND")]
+    ex: Vec<String>,
+
+    /// Quits after running the commands instead of prompting for more, exiting with 1 if one of them failed
+    #[arg(long)]
+    batch: bool,
+
+    /// Debugs the program running in `t --debug-listen` at the address, which has to be the same binary
+    #[arg(long, value_name = "HOST:PORT")]
+    attach: Option<String>,
+
+    /// Shows the code, registers, stack and watched memory in panes around the command line
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "batch")]
+    tui: bool,
+}
+
+/// Where commands and the program write to and where the program's input comes from: the terminal, or the TUI
+///
+/// `write!` and `writeln!` work on it like on stdout.
+#[derive(Clone, Default)]
+struct Console {
+    /// What the TUI shows in its output pane, which is written to instead of stdout
+    #[cfg(feature = "tui")]
+    pane: Option<Rc<RefCell<String>>>,
+}
+
+impl Console {
+    fn write_fmt(&self, args: fmt::Arguments) {
+        #[cfg(feature = "tui")]
+        if let Some(pane) = &self.pane {
+            let _ = fmt::Write::write_fmt(&mut *pane.borrow_mut(), args);
+            return;
+        }
+        let mut stdout = stdout();
+        stdout.write_fmt(args).expect("stdout failed");
+        stdout.flush().expect("stdout failed");
+    }
+    /// Writes what the program output, bytes that are not ASCII show as `�` in the TUI
+    fn write_bytes(&self, bytes: &[u8]) {
+        #[cfg(feature = "tui")]
+        if let Some(pane) = &self.pane {
+            let mut pane = pane.borrow_mut();
+            pane.extend(
+                bytes
+                    .iter()
+                    .map(|&b| if b.is_ascii() { b as char } else { '\u{fffd}' }),
+            );
+            return;
+        }
+        let mut stdout = stdout();
+        stdout.write_all(bytes).expect("stdout failed");
+        stdout.flush().expect("stdout failed");
+    }
+    /// Reads a line of input for the program
+    fn read_line(&self) -> String {
+        #[cfg(feature = "tui")]
+        if self.pane.is_some() {
+            return tui::read_line(self);
+        }
+        write!(self, "(program input) ");
+        let mut buf = String::new();
+        stdin().read_line(&mut buf).expect("stdin failed");
+        buf
+    }
+}
+
+/// Program output goes to the console as it comes, input is read a line at a time when the program needs it
+///
+/// What the program read is kept, so that running it again after going back reads the same, and output is only
+/// shown the first time. How far the program got in both is its state in snapshots.
+#[derive(Default)]
+struct DbgIo {
+    console: Console,
+    in_buf: VecDeque<u8>,
+    /// Every byte the program has read
+    read: Vec<u8>,
+    /// How many bytes of `read` the program read so far this time
+    reads: u64,
+    /// How many bytes were shown
+    shown: u64,
+    /// How many bytes the program wrote so far this time
+    writes: u64,
+}
+
+impl Io for DbgIo {
+    fn read(&mut self, _addr: u8) -> u8 {
+        if let Some(&b) = self.read.get(self.reads as usize) {
+            self.reads += 1;
+            return b;
+        }
+        if self.in_buf.is_empty() {
+            let line = self.console.read_line();
+            self.in_buf.extend(line.into_bytes());
+        }
+        let b = self.in_buf.pop_front().unwrap_or(0);
+        self.read.push(b);
+        self.reads += 1;
+        b
+    }
+    fn write(&mut self, _addr: u8, val: u8) {
+        self.writes += 1;
+        if self.writes <= self.shown {
+            return;
+        }
+        self.shown = self.writes;
+        self.console.write_bytes(&[val]);
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend(self.reads.to_le_bytes());
+        state.extend(self.writes.to_le_bytes());
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 16];
+        state.read_exact(&mut buf)?;
+        let (reads, writes) = buf.split_at(8);
+        self.reads = u64::from_le_bytes(reads.try_into().unwrap());
+        self.writes = u64::from_le_bytes(writes.try_into().unwrap());
+        Ok(())
+    }
+}
+
+/// Snapshots are taken this many steps apart at first
+const CHECKPOINT_INTERVAL: u64 = 1000;
+/// With more snapshots than this, every other one is dropped and they are taken twice as far apart
+const MAX_CHECKPOINTS: usize = 256;
+
+/// Symbols by name and by location
+///
+/// Global symbols win over local ones of the same name or location.
+#[derive(Default)]
+struct Symbols {
+    by_name: HashMap<Box<str>, u16>,
+    by_location: SymbolLookup,
+}
+
+impl Symbols {
+    fn new(obj: &Object) -> Self {
+        let mut by_name = HashMap::new();
+        for s in obj.symbols.0.iter().filter(|s| !s.name.is_empty()) {
+            if s.is_global {
+                by_name.insert(s.name.clone(), s.location);
+            } else {
+                by_name.entry(s.name.clone()).or_insert(s.location);
+            }
+        }
+        Symbols {
+            by_name,
+            by_location: SymbolLookup::new(obj),
+        }
+    }
+    fn at(&self, location: u16) -> Option<&str> {
+        self.by_location.at(location)
+    }
+    fn nearest(&self, location: u16) -> Option<(&str, u16)> {
+        self.by_location.nearest(location)
+    }
+    fn describe(&self, location: u16) -> String {
+        self.by_location.describe(location)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Byte(ByteRegister),
+    Wide(WideRegister),
+    ProgramCounter,
+}
+
+impl Register {
+    fn parse(name: &str) -> Option<Self> {
+        if name == "pc" || name == "rpc" {
+            return Some(Register::ProgramCounter);
+        }
+        (0..16).map(U4::new).find_map(|n| {
+            if WideRegister(n).to_string() == name {
+                Some(Register::Wide(WideRegister(n)))
+            } else if ByteRegister(n).to_string() == name {
+                Some(Register::Byte(ByteRegister(n)))
+            } else {
+                None
+            }
+        })
+    }
+    fn read(self, r: &Registers) -> u16 {
+        match self {
+            Register::Byte(b) => r.read_byte(b) as u16,
+            Register::Wide(w) => r.read_wide(w),
+            Register::ProgramCounter => r.program_counter,
+        }
+    }
+}
+
+/// Why execution stopped
+enum Stop {
+    Stepped,
+    Breakpoint(u32),
+    /// The instruction at the location accessed an address watched by the watchpoint
+    Watchpoint(u32, WatchHit, u16),
+    /// Went to the trap handler
+    Trap(TrapMode),
+    /// Trapped without a trap handler at the instruction, so the program is over
+    Ended(TrapMode, u16),
+}
+
+struct Debugger {
+    out: Console,
+    binary: PathBuf,
+    entry: Option<String>,
+    /// Where the symbols and lines are if not in the binary
+    debug_file: Option<PathBuf>,
+    machine: Machine<DbgIo>,
+    symbols: Symbols,
+    lines: LineTable,
+    breakpoints: Breakpoints,
+    ended: Option<(TrapMode, u16)>,
+    /// The cycle count when the program was last run or stepped
+    resumed_at: u64,
+    /// How many instructions ran since the start, counting ones that trapped
+    steps: u64,
+    /// Snapshots by the step they were taken at, to go back from
+    checkpoints: Vec<(u64, Snapshot)>,
+    checkpoint_interval: u64,
+    /// How `x` showed memory last
+    examine: Spec,
+    /// Where `x` without an address goes on from
+    examined: u16,
+    /// The program this is attached to, which the machine is a copy of
+    remote: Option<Remote>,
+}
+
+impl Debugger {
+    fn load(
+        binary: PathBuf,
+        entry: Option<String>,
+        debug_file: Option<PathBuf>,
+        out: Console,
+    ) -> Result<Self, String> {
+        let mut obj = Object::from_file(&binary)
+            .map_err(|e| format!("could not read {}: {e}", binary.display()))?;
+        if let Some(path) = &debug_file {
+            let e = |e: io::Error| format!("could not load symbols from {}: {e}", path.display());
+            let debug = Object::from_file(path).map_err(e)?;
+            obj.attach_debug(debug).map_err(e)?;
+        }
+        let symbols = Symbols::new(&obj);
+        let lines = std::mem::take(&mut obj.lines);
+
+        let start = match &entry {
+            Some(entry) => parse_location(entry, &symbols)?,
+            None => match obj.entry {
+                Some(e) => e.1,
+                None => {
+                    return Err("no entry point in binary, set one with -E".to_owned());
+                }
+            },
+        };
+        let io = DbgIo {
+            console: out.clone(),
+            ..DbgIo::default()
+        };
+        let mut machine = Machine::with_io(obj, io);
+        *machine.registers_mut() = Registers::new(start);
+        let checkpoints = vec![(0, machine.snapshot())];
+
+        Ok(Debugger {
+            out,
+            binary,
+            entry,
+            debug_file,
+            machine,
+            symbols,
+            lines,
+            breakpoints: Breakpoints::new(),
+            ended: None,
+            resumed_at: 0,
+            steps: 0,
+            checkpoints,
+            checkpoint_interval: CHECKPOINT_INTERVAL,
+            examine: Spec::default(),
+            examined: 0,
+            remote: None,
+        })
+    }
+
+    /// Loads the binary again and starts over, keeping the breakpoints
+    fn restart(&mut self) -> Result<(), String> {
+        let Debugger {
+            machine,
+            symbols,
+            lines,
+            checkpoints,
+            ..
+        } = Debugger::load(
+            self.binary.clone(),
+            self.entry.clone(),
+            self.debug_file.clone(),
+            self.out.clone(),
+        )?;
+        self.machine = machine;
+        self.symbols = symbols;
+        self.lines = lines;
+        self.ended = None;
+        self.resumed_at = 0;
+        self.steps = 0;
+        self.checkpoints = checkpoints;
+        self.checkpoint_interval = CHECKPOINT_INTERVAL;
+        self.sync_watchpoints();
+        Ok(())
+    }
+    /// Gives the machine the watchpoints again after they changed
+    fn sync_watchpoints(&mut self) {
+        self.machine.clear_watchpoints();
+        for (start, len, watch) in self.breakpoints.watchpoints() {
+            self.machine.watch(start, len, watch);
+        }
+    }
+
+    fn step(&mut self) -> Option<Stop> {
+        if let Some((tm, location)) = self.ended {
+            return Some(Stop::Ended(tm, location));
+        }
+        let location = self.machine.pc();
+        let result = self.machine.step();
+        self.steps += 1;
+        self.checkpoint();
+        let hit = self.machine.watch_hit();
+        match result {
+            Ok(Event::Executed) => {
+                let hit = hit?;
+                let n = self.breakpoints.watched(hit, &self.machine)?;
+                Some(Stop::Watchpoint(n, hit, location))
+            }
+            Ok(Event::TrapHandled(tm)) => Some(Stop::Trap(tm)),
+            Err(tm) => {
+                self.ended = Some((tm, location));
+                Some(Stop::Ended(tm, location))
+            }
+        }
+    }
+    /// Takes a snapshot if it is time for one and there is none yet from going this far before
+    fn checkpoint(&mut self) {
+        let last = self.checkpoints.last().map_or(0, |&(step, _)| step);
+        if !self.steps.is_multiple_of(self.checkpoint_interval) || self.steps <= last {
+            return;
+        }
+        self.checkpoints.push((self.steps, self.machine.snapshot()));
+        if self.checkpoints.len() > MAX_CHECKPOINTS {
+            self.checkpoint_interval *= 2;
+            let interval = self.checkpoint_interval;
+            self.checkpoints
+                .retain(|&(step, _)| step.is_multiple_of(interval));
+        }
+    }
+    /// Forgets the snapshots after the current step, for when the state was changed by hand
+    ///
+    /// The state from now on is then a snapshot, so going back to after this keeps the change.
+    fn changed(&mut self) -> Result<(), String> {
+        if let Some(remote) = &mut self.remote {
+            return remote.push(&self.machine).map_err(|e| e.to_string());
+        }
+        let steps = self.steps;
+        self.checkpoints.retain(|&(step, _)| step < steps);
+        self.checkpoints.push((steps, self.machine.snapshot()));
+        Ok(())
+    }
+    /// Goes back to the state after `target` steps by restoring the closest snapshot before it and running from there
+    fn travel_to(&mut self, target: u64) {
+        let (step, snapshot) = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|&&(step, _)| step <= target)
+            .expect("there is always a snapshot of the start");
+        self.machine
+            .restore(snapshot)
+            .expect("snapshot of the same machine");
+        self.steps = *step;
+        self.ended = None;
+        while self.steps < target {
+            let location = self.machine.pc();
+            if let Err(tm) = self.machine.step() {
+                self.ended = Some((tm, location));
+            }
+            self.steps += 1;
+        }
+        self.machine.watch_hit();
+    }
+    /// Goes back `count` instructions
+    fn reverse_step(&mut self, count: u64) -> Result<Stop, String> {
+        if self.steps == 0 {
+            return Err("already at the start of the program".to_owned());
+        }
+        self.travel_to(self.steps.saturating_sub(count));
+        Ok(Stop::Stepped)
+    }
+    /// Goes back to the last time a breakpoint or watchpoint would have stopped, or to the start
+    ///
+    /// Runs from the snapshots before the current step, latest first, to find it.
+    fn reverse_continue(&mut self) -> Result<Stop, String> {
+        let current = self.steps;
+        if current == 0 {
+            return Err("already at the start of the program".to_owned());
+        }
+        let starts: Vec<u64> = self
+            .checkpoints
+            .iter()
+            .map(|&(step, _)| step)
+            .filter(|&step| step < current)
+            .collect();
+        let mut end = current;
+        for &start in starts.iter().rev() {
+            self.travel_to(start);
+            let mut last = None;
+            while self.steps < end {
+                if let Some(n) = self
+                    .breakpoints
+                    .would_stop_at(self.machine.pc(), &self.machine)
+                {
+                    last = Some((self.steps, Stop::Breakpoint(n)));
+                }
+                let location = self.machine.pc();
+                let result = self.machine.step();
+                self.steps += 1;
+                if let Some(hit) = self.machine.watch_hit() {
+                    match self.breakpoints.would_catch(hit, &self.machine) {
+                        Some(n) if self.steps < current => {
+                            last = Some((self.steps, Stop::Watchpoint(n, hit, location)))
+                        }
+                        _ => (),
+                    }
+                }
+                if result.is_err() {
+                    break;
+                }
+            }
+            if let Some((step, stop)) = last {
+                self.travel_to(step);
+                return Ok(stop);
+            }
+            end = start;
+        }
+        self.travel_to(0);
+        writeln!(self.out, "reached the start of the program");
+        Ok(Stop::Stepped)
+    }
+    /// Runs until a breakpoint, a trap or `until`
+    fn resume(&mut self, until: Option<u16>) -> Stop {
+        loop {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+            let pc = self.machine.pc();
+            if until == Some(pc) {
+                return Stop::Stepped;
+            }
+            if let Some(n) = self.breakpoints.reached(pc, &self.machine) {
+                return Stop::Breakpoint(n);
+            }
+        }
+    }
+    /// Steps over calls instead of into them
+    fn next(&mut self) -> Stop {
+        let ins = self.disassembler().decode(self.machine.pc());
+        match ins.flow {
+            Flow::Call(_) | Flow::IndirectCall => self.resume(Some(ins.next_instruction_location)),
+            _ => self.step().unwrap_or(Stop::Stepped),
+        }
+    }
+
+    /// Attaches to the program, making the machine a copy of it
+    fn attach(&mut self, addr: &str) -> Result<(), String> {
+        let e = |e: io::Error| format!("cannot attach to {addr}: {e}");
+        let mut remote = Remote::connect(addr).map_err(e)?;
+        let stopped = remote.stop_reason().map_err(e)?;
+        remote.fetch(&mut self.machine).map_err(e)?;
+        if let Stopped::Ended(tm, location) = stopped {
+            self.ended = Some((tm, location));
+        }
+        self.remote = Some(remote);
+        self.checkpoints.clear();
+        writeln!(self.out, "attached to {addr}");
+        Ok(())
+    }
+    /// Runs the remote program until a breakpoint, the end or `until`, checking conditions here
+    ///
+    /// Only the registers are fetched for conditions that do not look at memory.
+    fn resume_remote(&mut self, until: Option<u16>) -> io::Result<Stop> {
+        if let Some((tm, location)) = self.ended {
+            return Ok(Stop::Ended(tm, location));
+        }
+        let remote = self.remote.as_mut().expect("only when attached");
+        let mut locations: Vec<u16> = self
+            .breakpoints
+            .iter()
+            .filter_map(|(_, b)| match b.kind {
+                Kind::At(l) => Some(l),
+                Kind::Watch { .. } => None,
+            })
+            .collect();
+        locations.extend(until);
+        loop {
+            let stopped = remote.resume(&locations)?;
+            remote.fetch_registers(&mut self.machine)?;
+            let pc = self.machine.pc();
+            let reads_memory = self.breakpoints.iter().any(|(_, b)| {
+                matches!(b.kind, Kind::At(l) if l == pc)
+                    && b.condition.as_ref().is_some_and(|c| c.expr.reads_memory())
+            });
+            if reads_memory {
+                remote.fetch_memory(&mut self.machine)?;
+            }
+            let stop = match stopped {
+                Stopped::Ended(tm, location) => {
+                    self.ended = Some((tm, location));
+                    Stop::Ended(tm, location)
+                }
+                _ if until == Some(pc) || !locations.contains(&pc) => Stop::Stepped,
+                _ => match self.breakpoints.reached(pc, &self.machine) {
+                    Some(n) => Stop::Breakpoint(n),
+                    None => continue,
+                },
+            };
+            if !reads_memory {
+                remote.fetch_memory(&mut self.machine)?;
+            }
+            return Ok(stop);
+        }
+    }
+    /// Runs the commands that run the program on the remote one, none if the command is not one of them
+    fn remote_command(&mut self, cmd: &str, args: &str) -> Result<Option<bool>, String> {
+        let result = match cmd {
+            "s" | "step" | "si" => {
+                let count: u64 = if args.is_empty() {
+                    1
+                } else {
+                    args.parse().map_err(|_| "invalid step count")?
+                };
+                self.step_remote(count)
+            }
+            "n" | "next" => {
+                let ins = self.disassembler().decode(self.machine.pc());
+                match ins.flow {
+                    Flow::Call(_) | Flow::IndirectCall => {
+                        self.resume_remote(Some(ins.next_instruction_location))
+                    }
+                    _ => self.step_remote(1),
+                }
+            }
+            "c" | "continue" => self.resume_remote(None),
+            "rs" | "reverse-step" | "rc" | "reverse-continue" | "r" | "run" | "watch"
+            | "rwatch" | "awatch" => return Err(format!("{cmd} is not available when attached")),
+            "q" | "quit" | "detach" => {
+                let remote = self.remote.as_mut().expect("only when attached");
+                remote.detach().map_err(|e| e.to_string())?;
+                writeln!(self.out, "detached, the program goes on");
+                return Ok(Some(true));
+            }
+            "kill" => {
+                let remote = self.remote.as_mut().expect("only when attached");
+                remote.kill().map_err(|e| e.to_string())?;
+                return Ok(Some(true));
+            }
+            _ => return Ok(None),
+        };
+        let stop = result.map_err(|e| format!("lost the connection: {e}"))?;
+        self.report(stop);
+        Ok(Some(false))
+    }
+    fn step_remote(&mut self, count: u64) -> io::Result<Stop> {
+        if let Some((tm, location)) = self.ended {
+            return Ok(Stop::Ended(tm, location));
+        }
+        let remote = self.remote.as_mut().expect("only when attached");
+        let mut stopped = Stopped::Paused;
+        for _ in 0..count {
+            stopped = remote.step()?;
+            if matches!(stopped, Stopped::Ended(..)) {
+                break;
+            }
+        }
+        remote.fetch(&mut self.machine)?;
+        Ok(match stopped {
+            Stopped::Paused => Stop::Stepped,
+            Stopped::Ended(tm, location) => {
+                self.ended = Some((tm, location));
+                Stop::Ended(tm, location)
+            }
+        })
+    }
+
+    fn disassembler(&self) -> Disassembler<'_> {
+        Disassembler::new(self.machine.memory())
+            .symbolizer(|p| self.symbols.at(p).map(|s| s.to_owned()))
+    }
+
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Stepped => (),
+            Stop::Breakpoint(n) => writeln!(self.out, "breakpoint {n} hit"),
+            Stop::Watchpoint(n, hit, location) => {
+                let addr = self.symbols.describe(hit.addr);
+                let by = self.symbols.describe(location);
+                match hit.access {
+                    Access::Read => {
+                        writeln!(
+                            self.out,
+                            "watchpoint {n}: {addr} read as 0x{:02x} by {by}",
+                            hit.value
+                        )
+                    }
+                    Access::Write => writeln!(
+                        self.out,
+                        "watchpoint {n}: {addr} changed from 0x{:02x} to 0x{:02x} by {by}",
+                        hit.previous, hit.value
+                    ),
+                }
+            }
+            Stop::Trap(tm) => writeln!(
+                self.out,
+                "trapped with {tm:?}{}, entering the trap handler",
+                self.fault_access(tm)
+            ),
+            Stop::Ended(tm, location) => {
+                let exit_code = match tm {
+                    TrapMode::Halt => {
+                        format!(" (exit code {})", self.machine.registers().exit_code)
+                    }
+                    _ => String::new(),
+                };
+                writeln!(
+                    self.out,
+                    "program ended with {tm:?}{exit_code} at {}{}",
+                    self.symbols.describe(location),
+                    self.fault_access(tm)
+                );
+                return;
+            }
+        }
+        self.show_location();
+    }
+    /// What the access memory protection stopped was to, if that is why it trapped
+    fn fault_access(&self, tm: TrapMode) -> String {
+        match self.machine.last_fault() {
+            Some(fault) if fault.trap == tm => {
+                format!(" accessing {}", self.symbols.describe(fault.addr))
+            }
+            _ => String::new(),
+        }
+    }
+    fn show_location(&self) {
+        let pc = self.machine.pc();
+        write!(self.out, "{}", self.symbols.describe(pc));
+        match self.lines.lookup(pc) {
+            Some((file, line)) => writeln!(self.out, " at {file}:{line}"),
+            None => writeln!(self.out),
+        }
+        self.list(pc, 0, 1);
+    }
+
+    /// Disassembles `before` instructions before `location` and `after` from it
+    fn list(&self, location: u16, before: usize, after: usize) {
+        for line in self.listing(location, before, after) {
+            writeln!(self.out, "{line}");
+        }
+    }
+    /// The lines `list` shows, with labels and the current instruction and breakpoints marked
+    ///
+    /// The instructions before are found by disassembling from the closest symbol.
+    fn listing(&self, location: u16, before: usize, after: usize) -> Vec<String> {
+        let disassembler = self.disassembler();
+
+        let mut previous = VecDeque::new();
+        if let Some((_, offset)) = self.symbols.nearest(location) {
+            let mut l = location - offset;
+            while l < location && offset <= 0x400 {
+                previous.push_back(l);
+                if previous.len() > before {
+                    previous.pop_front();
+                }
+                let next = disassembler.decode(l).next_instruction_location;
+                if next <= l {
+                    break;
+                }
+                l = next;
+            }
+            if l != location {
+                // Did not line up with the instruction at the location
+                previous.clear();
+            }
+        }
+
+        let mut l = location;
+        let following = (0..after).map_while(|_| {
+            let current = l;
+            l = disassembler.decode(l).next_instruction_location;
+            (l > current).then_some(current)
+        });
+        let mut lines = Vec::new();
+        for l in previous.into_iter().chain(following.collect::<Vec<_>>()) {
+            if let Some(name) = self.symbols.at(l) {
+                lines.push(format!("<{name}>:"));
+            }
+            let ins = disassembler.decode(l);
+            let marker = if l == self.machine.pc() {
+                "=>"
+            } else if self.breakpoints.at(l) {
+                " *"
+            } else {
+                "  "
+            };
+            lines.push(format!("{marker}{}", &ins.annotated_source[2..]));
+        }
+        lines
+    }
+
+    /// Shows the current location and where it was called from, as far as `Machine::return_addresses` can tell
+    fn backtrace(&self, max: usize) {
+        let location = self
+            .ended
+            .map_or(self.machine.pc(), |(_, location)| location);
+        let frames = self.machine.return_addresses(max.saturating_sub(1));
+        // Return addresses are after the call, whose line is the one to show
+        let calls = frames.into_iter().map(|addr| (addr, addr - 3));
+        for (n, (addr, at)) in [(location, location)].into_iter().chain(calls).enumerate() {
+            write!(self.out, "#{n:<3} {}", self.symbols.describe(addr));
+            match self.lines.lookup(at) {
+                Some((file, line)) => writeln!(self.out, " at {file}:{line}"),
+                None => writeln!(self.out),
+            }
+        }
+    }
+
+    fn print_registers(&self) {
+        let r = self.machine.registers();
+        for n in 0..16 {
+            let reg = WideRegister(U4::new(n));
+            let val = r.read_wide(reg);
+            write!(self.out, "{:>4} = 0x{val:04x}", reg.to_string());
+            if n % 4 == 3 {
+                writeln!(self.out);
+            }
+        }
+        writeln!(
+            self.out,
+            "  pc = {}",
+            self.symbols.describe(r.program_counter)
+        );
+        writeln!(
+            self.out,
+            "flags: {}{}{}{}{}{}",
+            if r.carry { "C" } else { "-" },
+            if r.overflow { "O" } else { "-" },
+            if r.sign { "S" } else { "-" },
+            if r.zero { "Z" } else { "-" },
+            if r.interrupt_enable {
+                " (interrupts enabled)"
+            } else {
+                ""
+            },
+            if r.trap { " (in trap handler)" } else { "" },
+        );
+        if r.interrupt_vectors != 0 {
+            writeln!(
+                self.out,
+                "vector table: {}",
+                self.symbols.describe(r.interrupt_vectors)
+            );
+        }
+        writeln!(self.out, "cycles: {}", self.machine.cycles());
+    }
+
+    /// Shows memory from the address `args` evaluates to as the spec says, with where each line starts
+    fn examine(&mut self, args: &str, spec: &str) -> Result<(), String> {
+        let spec = self.examine.parse(spec)?;
+        let addr = match args {
+            "" => self.examined,
+            args => Expr::parse(args, &self.symbols)?.eval(&self.machine),
+        };
+        self.examine = spec;
+        let size = spec.unit.size();
+        let per_row = spec.per_row();
+        for row in (0..spec.count).step_by(per_row as usize) {
+            let start = addr.wrapping_add(row.wrapping_mul(size));
+            write!(self.out, "{}:", self.symbols.describe(start));
+            for i in 0..per_row.min(spec.count - row) {
+                let at = start.wrapping_add(i * size);
+                let val = match spec.unit {
+                    Unit::Byte => self.machine.peek(at) as u16,
+                    Unit::Wide => self.machine.peek_wide(at),
+                };
+                write!(self.out, " {}", spec.show(val, &self.symbols));
+            }
+            writeln!(self.out);
+        }
+        self.examined = addr.wrapping_add(spec.count.wrapping_mul(size));
+        Ok(())
+    }
+
+    fn condition(&self, text: &str) -> Result<Condition, String> {
+        Ok(Condition {
+            text: text.to_owned(),
+            expr: Expr::parse(text, &self.symbols)?,
+        })
+    }
+    /// Splits off a condition like in `main+4 if r1 == 3`
+    fn parse_condition<'a>(&self, args: &'a str) -> Result<(&'a str, Option<Condition>), String> {
+        match args.split_once(" if ") {
+            Some((args, cond)) => {
+                let cond = cond.trim();
+                Ok((args.trim(), Some(self.condition(cond)?)))
+            }
+            None => Ok((args, None)),
+        }
+    }
+
+    /// Runs a command, telling whether to quit
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        if let "s" | "step" | "si" | "n" | "next" | "c" | "continue" | "rs" | "reverse-step"
+        | "rc" | "reverse-continue" = cmd
+        {
+            self.resumed_at = self.machine.cycles();
+        }
+        if self.remote.is_some() {
+            if let Some(quit) = self.remote_command(cmd, args)? {
+                return Ok(quit);
+            }
+        }
+        match cmd {
+            "q" | "quit" => return Ok(true),
+            "h" | "help" => print_help(&self.out),
+            "b" | "break" => {
+                let (location, condition) = self.parse_condition(args)?;
+                let location = parse_location(location, &self.symbols)?;
+                let n = self.breakpoints.add(Kind::At(location), condition);
+                writeln!(
+                    self.out,
+                    "breakpoint {n} at {}",
+                    self.symbols.describe(location)
+                );
+            }
+            "watch" | "rwatch" | "awatch" => {
+                let (args, condition) = self.parse_condition(args)?;
+                let (start, len) = args.split_once(' ').unwrap_or((args, "1"));
+                let start = parse_location(start, &self.symbols)?;
+                let len = parse_num(len.trim())?;
+                let watch = match cmd {
+                    "watch" => Watch::Write,
+                    "rwatch" => Watch::Read,
+                    _ => Watch::Any,
+                };
+                let n = self
+                    .breakpoints
+                    .add(Kind::Watch { start, len, watch }, condition);
+                self.sync_watchpoints();
+                writeln!(
+                    self.out,
+                    "watchpoint {n} on {}",
+                    self.symbols.describe(start)
+                );
+            }
+            "condition" => {
+                let (n, condition) = args.split_once(' ').unwrap_or((args, ""));
+                let n = n.parse().map_err(|_| "invalid breakpoint number")?;
+                let condition = match condition.trim() {
+                    "" => None,
+                    c => Some(self.condition(c)?),
+                };
+                let b = self
+                    .breakpoints
+                    .get_mut(n)
+                    .ok_or_else(|| format!("no breakpoint {n}"))?;
+                b.condition = condition;
+            }
+            "ignore" => {
+                let (n, count) = args.split_once(' ').ok_or("expected `ignore N COUNT`")?;
+                let n = n.parse().map_err(|_| "invalid breakpoint number")?;
+                let count = count.trim().parse().map_err(|_| "invalid count")?;
+                let b = self
+                    .breakpoints
+                    .get_mut(n)
+                    .ok_or_else(|| format!("no breakpoint {n}"))?;
+                b.ignore = count;
+                writeln!(
+                    self.out,
+                    "will ignore the next {count} hits of breakpoint {n}"
+                );
+            }
+            "d" | "delete" if args.is_empty() => {
+                self.breakpoints.clear();
+                self.sync_watchpoints();
+            }
+            "d" | "delete" => {
+                let n = args.parse().map_err(|_| "invalid breakpoint number")?;
+                if !self.breakpoints.remove(n) {
+                    return Err(format!("no breakpoint {n}"));
+                }
+                self.sync_watchpoints();
+            }
+            "i" | "info" if args == "b" || args == "breakpoints" => {
+                if self.breakpoints.is_empty() {
+                    writeln!(self.out, "no breakpoints");
+                }
+                for (n, b) in self.breakpoints.iter() {
+                    match b.kind {
+                        Kind::At(l) => writeln!(self.out, "{n:>3}  {}", self.symbols.describe(l)),
+                        Kind::Watch { start, len, watch } => writeln!(
+                            self.out,
+                            "{n:>3}  {watch:?} watch on {len} byte{} at {}",
+                            if len == 1 { "" } else { "s" },
+                            self.symbols.describe(start)
+                        ),
+                    }
+                    if let Some(cond) = &b.condition {
+                        writeln!(self.out, "       if {}", cond.text);
+                    }
+                    if b.hits > 0 {
+                        writeln!(
+                            self.out,
+                            "       hit {} time{}",
+                            b.hits,
+                            if b.hits == 1 { "" } else { "s" }
+                        );
+                    }
+                    if b.ignore > 0 {
+                        writeln!(self.out, "       ignoring the next {} hits", b.ignore);
+                    }
+                }
+            }
+            "i" | "info" if args == "r" || args == "registers" => self.print_registers(),
+            "regs" => self.print_registers(),
+            "s" | "step" | "si" => {
+                let count = if args.is_empty() {
+                    1
+                } else {
+                    args.parse().map_err(|_| "invalid step count")?
+                };
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    if let Some(s) = self.step() {
+                        stop = s;
+                        break;
+                    }
+                }
+                self.report(stop);
+            }
+            "n" | "next" => {
+                let stop = self.next();
+                self.report(stop);
+            }
+            "c" | "continue" => {
+                let stop = self.resume(None);
+                self.report(stop);
+            }
+            "rs" | "reverse-step" => {
+                let count = if args.is_empty() {
+                    1
+                } else {
+                    args.parse().map_err(|_| "invalid step count")?
+                };
+                let stop = self.reverse_step(count)?;
+                self.report(stop);
+            }
+            "rc" | "reverse-continue" => {
+                let stop = self.reverse_continue()?;
+                self.report(stop);
+            }
+            "r" | "run" => {
+                self.restart()?;
+                let stop = match self.breakpoints.reached(self.machine.pc(), &self.machine) {
+                    Some(n) => Stop::Breakpoint(n),
+                    None => self.resume(None),
+                };
+                self.report(stop);
+            }
+            "l" | "list" | "disas" => {
+                let location = match args {
+                    "" => self.machine.pc(),
+                    _ => parse_location(args, &self.symbols)?,
+                };
+                self.list(location, 4, 8);
+            }
+            "p" | "print" => match Register::parse(args) {
+                Some(Register::Byte(r)) => {
+                    let val = self.machine.registers().read_byte(r);
+                    writeln!(self.out, "{r} = {val} 0x{val:02x}");
+                }
+                Some(Register::Wide(r)) => {
+                    let val = self.machine.registers().read_wide(r);
+                    writeln!(self.out, "{r} = {val} 0x{val:04x}");
+                }
+                Some(Register::ProgramCounter) => {
+                    writeln!(
+                        self.out,
+                        "pc = {}",
+                        self.symbols.describe(self.machine.pc())
+                    );
+                }
+                None => {
+                    let addr = parse_location(args, &self.symbols)?;
+                    let val = self.machine.peek_wide(addr);
+                    writeln!(
+                        self.out,
+                        "{} = {val} 0x{val:04x}",
+                        self.symbols.describe(addr)
+                    );
+                }
+            },
+            "m" | "mem" => {
+                let (addr, count) = args.split_once(' ').unwrap_or((args, "16"));
+                let addr = parse_location(addr, &self.symbols)?;
+                let count: u16 = count.trim().parse().map_err(|_| "invalid byte count")?;
+                for row in (0..count).step_by(16) {
+                    let start = addr.wrapping_add(row);
+                    write!(self.out, "{start:04x}:");
+                    for i in 0..16.min(count - row) {
+                        write!(
+                            self.out,
+                            " {:02x}",
+                            self.machine.peek(start.wrapping_add(i))
+                        );
+                    }
+                    writeln!(self.out);
+                }
+            }
+            "x" | "x/" => self.examine(args, "")?,
+            cmd if cmd.starts_with("x/") => self.examine(args, &cmd[2..])?,
+            "set" => {
+                let (target, value) = args
+                    .split_once('=')
+                    .ok_or("expected `set TARGET = VALUE`")?;
+                let (target, value) = (target.trim(), value.trim());
+                // Like `set wide list+2 = buf` or `set {byte} r2 = 0`
+                let sized = target.split_once(' ').and_then(|(unit, loc)| {
+                    match unit.trim_matches(['{', '}']) {
+                        "byte" => Some((Unit::Byte, loc)),
+                        "wide" => Some((Unit::Wide, loc)),
+                        _ => None,
+                    }
+                });
+                if let Some((unit, loc)) = sized {
+                    let addr = Expr::parse(loc, &self.symbols)?.eval(&self.machine);
+                    let value = Expr::parse(value, &self.symbols)?.eval(&self.machine);
+                    match unit {
+                        Unit::Byte => {
+                            let value =
+                                u8::try_from(value).map_err(|_| "value too big for a byte")?;
+                            self.machine.poke(addr, value);
+                        }
+                        Unit::Wide => self.machine.poke_wide(addr, value),
+                    }
+                    self.changed()?;
+                    return Ok(false);
+                }
+                let value = parse_location(value, &self.symbols)?;
+                match Register::parse(target) {
+                    Some(Register::Byte(r)) => {
+                        let value =
+                            u8::try_from(value).map_err(|_| "value too big for byte register")?;
+                        self.machine.registers_mut().write_byte(r, value);
+                    }
+                    Some(Register::Wide(r)) => self.machine.registers_mut().write_wide(r, value),
+                    Some(Register::ProgramCounter) => self.machine.set_pc(value),
+                    None => {
+                        let addr = parse_location(target, &self.symbols)?;
+                        let value = u8::try_from(value).map_err(|_| "value too big for a byte")?;
+                        self.machine.poke(addr, value);
+                    }
+                }
+                self.changed()?;
+            }
+            "where" => self.show_location(),
+            "bt" | "backtrace" => {
+                let max = if args.is_empty() {
+                    64
+                } else {
+                    args.parse().map_err(|_| "invalid frame count")?
+                };
+                self.backtrace(max);
+            }
+            "cycles" => {
+                let cycles = self.machine.cycles();
+                writeln!(
+                    self.out,
+                    "{cycles} cycles, {} since the last step or continue",
+                    cycles - self.resumed_at
+                );
+            }
+            _ => return Err(format!("unknown command {cmd:?}, type help for a list")),
+        }
+        Ok(false)
+    }
+}
+
+/// Parses an address or symbol, optionally with an offset like `main+4`
+fn parse_location(s: &str, symbols: &Symbols) -> Result<u16, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("expected an address or symbol".to_owned());
+    }
+    if let Ok(n) = parse_num(s) {
+        return Ok(n);
+    }
+    let (base, offset) = match s.rfind(['+', '-']) {
+        Some(i) if i > 0 => {
+            let offset = parse_num(s[i + 1..].trim())?;
+            let base = s[..i].trim();
+            match &s[i..=i] {
+                "+" => (base, offset),
+                _ => (base, offset.wrapping_neg()),
+            }
+        }
+        _ => (s, 0),
+    };
+    let base = match symbols.by_name.get(base) {
+        Some(&l) => l,
+        None => parse_num(base).map_err(|_| format!("no symbol named {base}"))?,
+    };
+    Ok(base.wrapping_add(offset))
+}
+
+fn parse_num(num: &str) -> Result<u16, &'static str> {
+    Ok(if let Some(num) = num.strip_prefix("0x") {
+        u16::from_str_radix(num, 16).map_err(|_| "invalid hex number")?
+    } else if let Some(num) = num.strip_prefix("0o") {
+        u16::from_str_radix(num, 8).map_err(|_| "invalid octal number")?
+    } else if let Some(num) = num.strip_prefix("0b") {
+        u16::from_str_radix(num, 2).map_err(|_| "invalid binary number")?
+    } else {
+        num.parse().map_err(|_| "invalid decimal number")?
+    })
+}
+
+fn print_help(out: &Console) {
+    writeln!(
+        out,
+        "\
+break LOC [if EXPR] (b)
+                    stop when reaching LOC, an address or symbol like main+4,
+                    if given only when EXPR is not zero, like `r1 == 10 && *counter > 3`
+delete [N] (d)      remove breakpoint or watchpoint N, or all of them
+watch LOC [N]       stop after writes to N bytes from LOC, 1 by default
+rwatch LOC [N]      stop after reads of them
+awatch LOC [N]      stop after reads of or writes to them
+condition N [EXPR]  only stop at breakpoint N when EXPR is not zero, or always
+ignore N COUNT      do not stop at the next COUNT hits of breakpoint N
+info breakpoints    list the breakpoints and watchpoints
+step [N] (s, si)    run N instructions, 1 by default
+next (n)            like step, but runs calls until they return
+continue (c)        run until a breakpoint or trap
+reverse-step [N] (rs)
+                    go back N instructions, 1 by default
+reverse-continue (rc)
+                    go back to the last time a breakpoint or watchpoint stopped,
+                    or to the start
+run (r)             load the binary again and run from the start
+list [LOC] (l)      disassemble around LOC, the current instruction by default
+info registers      show all registers (also regs)
+print REG|LOC (p)   show a register or the wide value at LOC
+mem LOC [N] (m)     show N bytes from LOC, 16 by default
+x/NFU [EXPR]        show N values from the address EXPR, like x/16xb buf or x/4dw rs+2,
+                    in format F (x hex, d signed, u unsigned, o octal, t binary, c char,
+                    a address) of unit U (b byte, w wide), the last ones by default,
+                    going on after the last values without EXPR
+set REG = VALUE     change a register (or pc)
+set LOC = VALUE     change the byte at LOC
+set byte|wide EXPR = VALUE
+                    change the byte or wide at the address EXPR to the expression VALUE
+kill                end the program when attached
+detach              let it go on by itself and quit, like quit does when attached
+where               show the current instruction
+backtrace [N] (bt)  show where the current function was called from, N frames at most,
+                    found from rl and return addresses pushed on the stack
+cycles              show the cycles taken so far and by the last step or continue
+quit (q)"
+    );
+}
+
+/// Runs the commands of a script, stopping at the first that fails
+///
+/// Gives whether it went fine and whether to quit.
+fn run_script(dbg: &mut Debugger, path: &Path) -> (bool, bool) {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("cannot read {}: {e}", path.display());
+            return (false, false);
+        }
+    };
+    for (n, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match dbg.command(line) {
+            Ok(quit) if quit => return (true, true),
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("{}:{}: {e}", path.display(), n + 1);
+                return (false, false);
+            }
+        }
+    }
+    (true, false)
+}
+
+fn main() -> ExitCode {
+    // Like GDB takes it
+    let args = std::env::args_os().map(|a| if a == "-ex" { "--ex".into() } else { a });
+    let Cli {
+        binary,
+        entry,
+        symbols,
+        command,
+        ex,
+        batch,
+        attach,
+        #[cfg(feature = "tui")]
+        tui,
+    } = Cli::parse_from(args);
+
+    #[cfg(not(feature = "tui"))]
+    let console = Console::default();
+    #[cfg(feature = "tui")]
+    let console = Console {
+        pane: tui.then(Rc::default),
+    };
+
+    let mut dbg = match Debugger::load(binary, entry, symbols, console.clone()) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(addr) = attach {
+        if let Err(e) = dbg.attach(&addr) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    dbg.show_location();
+
+    let mut failed = false;
+    for path in &command {
+        let (ok, quit) = run_script(&mut dbg, path);
+        failed |= !ok;
+        if quit {
+            return ExitCode::from(failed as u8);
+        }
+    }
+    for line in &ex {
+        match dbg.command(line.trim()) {
+            Ok(true) => return ExitCode::from(failed as u8),
+            Ok(false) => (),
+            Err(e) => {
+                eprintln!("{e}");
+                failed = true;
+            }
+        }
+    }
+    if batch {
+        return ExitCode::from(failed as u8);
+    }
+    #[cfg(feature = "tui")]
+    if let Some(pane) = &console.pane {
+        if let Err(e) = tui::run(&mut dbg, pane) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let stdin = stdin();
+    let mut input = String::new();
+    let mut last = String::new();
+    loop {
+        print!("(tdb) ");
+        stdout().flush().expect("stdout failed");
+
+        input.clear();
+        if stdin.read_line(&mut input).expect("stdin failed") == 0 {
+            break;
+        }
+        // An empty line repeats the last command, like stepping again
+        let line = match input.trim() {
+            "" => last.clone(),
+            line => line.to_owned(),
+        };
+        if line.is_empty() {
+            continue;
+        }
+        match dbg.command(&line) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(e) => eprintln!("{e}"),
+        }
+        last = line;
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,193 @@
+//! Debugging a program running in `t --debug-listen` on another terminal or machine
+//!
+//! The program runs over there and the local machine is a copy of it, fetched every time it stops, so the commands
+//! that look at it work the same. Changes to the copy are sent back before it runs on.
+
+use std::{
+    io::{self, BufReader},
+    net::TcpStream,
+};
+
+use telda2::{
+    cpu::{TrapMode, WideRegister},
+    machine::Machine,
+    mem::Io,
+    rsp::{
+        flags, parse_bytes, parse_wides, request, set_flags, wide_hex, write_packet, FLAGS, PC,
+        REGISTERS,
+    },
+    U4,
+};
+
+/// How many bytes are fetched at a time, well within what the stub takes
+const CHUNK: u32 = 0x800;
+
+/// How the remote program stopped
+pub enum Stopped {
+    /// At a breakpoint or after a step, or because it was already stopped
+    Paused,
+    /// Trapped without a trap handler with the instruction that did
+    Ended(TrapMode, u16),
+}
+
+pub struct Remote {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// The memory as last fetched, to only send back what changed
+    memory: Vec<u8>,
+    /// What the program exited with if it halted, which is not one of the registers the stub sends
+    exit_code: u8,
+}
+
+fn invalid(what: &str, reply: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected reply to {what}: {reply:?}"),
+    )
+}
+
+impl Remote {
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Remote {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            memory: Vec::new(),
+            exit_code: 0,
+        })
+    }
+
+    fn request(&mut self, packet: &str) -> io::Result<String> {
+        request(&mut self.reader, &mut self.writer, packet)
+    }
+    fn expect_ok(&mut self, packet: &str) -> io::Result<()> {
+        match &*self.request(packet)? {
+            "OK" => Ok(()),
+            reply => Err(invalid(packet, reply)),
+        }
+    }
+
+    /// Why the program is stopped, which attaching stops it for
+    pub fn stop_reason(&mut self) -> io::Result<Stopped> {
+        let reply = self.request("?")?;
+        self.stopped(&reply)
+    }
+    pub fn step(&mut self) -> io::Result<Stopped> {
+        let reply = self.request("s")?;
+        self.stopped(&reply)
+    }
+    /// Lets the program run until it reaches one of the locations or ends
+    pub fn resume(&mut self, breakpoints: &[u16]) -> io::Result<Stopped> {
+        for addr in breakpoints {
+            self.expect_ok(&format!("Z0,{addr:x},1"))?;
+        }
+        let reply = self.request("c")?;
+        for addr in breakpoints {
+            self.expect_ok(&format!("z0,{addr:x},1"))?;
+        }
+        self.stopped(&reply)
+    }
+    fn stopped(&mut self, reply: &str) -> io::Result<Stopped> {
+        if reply.starts_with('S') || reply.starts_with('T') {
+            return Ok(Stopped::Paused);
+        }
+        match reply.split_at_checked(1) {
+            Some(("W", code)) => {
+                self.exit_code =
+                    u8::from_str_radix(code, 16).map_err(|_| invalid("resuming", reply))?
+            }
+            Some(("X", _)) => (),
+            _ => return Err(invalid("resuming", reply)),
+        }
+        let trap = self.request("qTelda.Trap")?;
+        trap.split_once(',')
+            .and_then(|(tm, location)| {
+                let tm = TrapMode::from_u8(u8::from_str_radix(tm, 16).ok()?)?;
+                Some(Stopped::Ended(tm, u16::from_str_radix(location, 16).ok()?))
+            })
+            .ok_or_else(|| invalid("qTelda.Trap", &trap))
+    }
+
+    /// Makes the local machine a copy of the remote one
+    pub fn fetch<I: Io>(&mut self, machine: &mut Machine<I>) -> io::Result<()> {
+        self.fetch_registers(machine)?;
+        self.fetch_memory(machine)
+    }
+    /// Only fetches the registers, which is much less to send than the memory
+    pub fn fetch_registers<I: Io>(&mut self, machine: &mut Machine<I>) -> io::Result<()> {
+        let reply = self.request("g")?;
+        let values = parse_wides(&reply)
+            .filter(|v| v.len() >= REGISTERS)
+            .ok_or_else(|| invalid("g", &reply))?;
+        let r = machine.registers_mut();
+        r.exit_code = self.exit_code;
+        for (i, &val) in values.iter().enumerate().take(REGISTERS) {
+            match i {
+                PC => r.program_counter = val,
+                FLAGS => set_flags(r, val),
+                i => r.write_wide(WideRegister(U4::new(i as u8)), val),
+            }
+        }
+        Ok(())
+    }
+    pub fn fetch_memory<I: Io>(&mut self, machine: &mut Machine<I>) -> io::Result<()> {
+        let mut memory = Vec::with_capacity(0x10000);
+        for start in (0..0x10000).step_by(CHUNK as usize) {
+            let packet = format!("m{start:x},{CHUNK:x}");
+            let reply = self.request(&packet)?;
+            let bytes = parse_bytes(&reply)
+                .filter(|b| b.len() == CHUNK as usize)
+                .ok_or_else(|| invalid(&packet, &reply))?;
+            memory.extend(bytes);
+        }
+        for (addr, &b) in (0..=u16::MAX).zip(&memory) {
+            if machine.peek(addr) != b {
+                machine.poke(addr, b);
+            }
+        }
+        self.memory = memory;
+        Ok(())
+    }
+    /// Sends the registers and the memory that changed in the local machine since it was fetched
+    pub fn push<I: Io>(&mut self, machine: &Machine<I>) -> io::Result<()> {
+        let r = machine.registers();
+        let registers: String = (0..REGISTERS)
+            .map(|i| match i {
+                PC => wide_hex(r.program_counter),
+                FLAGS => wide_hex(flags(r)),
+                i => wide_hex(r.read_wide(WideRegister(U4::new(i as u8)))),
+            })
+            .collect();
+        self.expect_ok(&format!("G{registers}"))?;
+
+        let mut addr = 0;
+        while addr < self.memory.len() {
+            let differs = |a: usize| machine.peek(a as u16) != self.memory[a];
+            if !differs(addr) {
+                addr += 1;
+                continue;
+            }
+            let limit = self.memory.len().min(addr + CHUNK as usize);
+            let end = (addr..limit).find(|&a| !differs(a)).unwrap_or(limit);
+            let bytes: String = (addr..end)
+                .map(|a| format!("{:02x}", machine.peek(a as u16)))
+                .collect();
+            self.expect_ok(&format!("M{addr:x},{:x}:{bytes}", end - addr))?;
+            for a in addr..end {
+                self.memory[a] = machine.peek(a as u16);
+            }
+            addr = end;
+        }
+        Ok(())
+    }
+
+    /// Lets the program go on by itself
+    pub fn detach(&mut self) -> io::Result<()> {
+        self.expect_ok("D")
+    }
+    /// Ends the program, which gives no reply
+    pub fn kill(&mut self) -> io::Result<()> {
+        write_packet(&mut self.writer, "k")
+    }
+}
@@ -0,0 +1,322 @@
+//! A terminal UI for the debugger, with the code, registers, stack and watched memory always in sight
+//!
+//! Commands are typed on the line at the bottom like at the prompt, with what they and the program write in the
+//! output pane. When the program needs input, the UI makes way for the terminal until a line is entered.
+
+use std::{
+    cell::RefCell,
+    io::{self, stdin, stdout, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use ratatui::{
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    DefaultTerminal, Frame,
+};
+use telda2::{
+    cpu::{Registers, WideRegister},
+    U4,
+};
+
+use crate::{Console, Debugger};
+
+/// Set when the terminal was given back for input, so the whole UI has to be drawn again
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+/// The command line and what came before it
+#[derive(Default)]
+struct Prompt {
+    input: String,
+    history: Vec<String>,
+    /// The entry of the history that is being looked at with the arrow keys
+    browsing: Option<usize>,
+    /// How many lines the output pane is scrolled back
+    scroll: usize,
+}
+
+/// Runs the debugger in the UI until it quits, with `pane` being what its console writes to
+pub fn run(dbg: &mut Debugger, pane: &RefCell<String>) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = main_loop(&mut terminal, dbg, pane);
+    ratatui::restore();
+    result
+}
+
+fn main_loop(
+    terminal: &mut DefaultTerminal,
+    dbg: &mut Debugger,
+    pane: &RefCell<String>,
+) -> io::Result<()> {
+    let mut prompt = Prompt::default();
+    let mut before = dbg.machine.registers().clone();
+    loop {
+        if SUSPENDED.swap(false, Ordering::Relaxed) {
+            terminal.clear()?;
+        }
+        terminal.draw(|f| draw(f, dbg, &before, &prompt, &pane.borrow()))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let line = match key.code {
+            KeyCode::Char('c' | 'd') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(())
+            }
+            KeyCode::Char(c) => {
+                prompt.input.push(c);
+                continue;
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+                continue;
+            }
+            KeyCode::Esc => {
+                prompt.input.clear();
+                continue;
+            }
+            KeyCode::Up | KeyCode::Down => {
+                let last = prompt.history.len().checked_sub(1);
+                prompt.browsing = match (key.code, prompt.browsing) {
+                    (KeyCode::Up, None) => last,
+                    (KeyCode::Up, Some(n)) => Some(n.saturating_sub(1)),
+                    (_, Some(n)) if Some(n) < last => Some(n + 1),
+                    _ => None,
+                };
+                prompt.input = prompt
+                    .browsing
+                    .map_or_else(String::new, |n| prompt.history[n].clone());
+                continue;
+            }
+            KeyCode::PageUp => {
+                prompt.scroll += 10;
+                continue;
+            }
+            KeyCode::PageDown => {
+                prompt.scroll = prompt.scroll.saturating_sub(10);
+                continue;
+            }
+            KeyCode::F(5) => "continue".to_owned(),
+            KeyCode::F(10) => "next".to_owned(),
+            KeyCode::F(11) => "step".to_owned(),
+            // An empty line repeats the last command, like stepping again
+            KeyCode::Enter if prompt.input.trim().is_empty() => match prompt.history.last() {
+                Some(last) => last.clone(),
+                None => continue,
+            },
+            KeyCode::Enter => std::mem::take(&mut prompt.input).trim().to_owned(),
+            _ => continue,
+        };
+
+        prompt.browsing = None;
+        prompt.scroll = 0;
+        if prompt.history.last() != Some(&line) {
+            prompt.history.push(line.clone());
+        }
+        before = dbg.machine.registers().clone();
+        writeln!(dbg.out, "(tdb) {line}");
+        match dbg.command(&line) {
+            Ok(true) => return Ok(()),
+            Ok(false) => (),
+            Err(e) => writeln!(dbg.out, "{e}"),
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, dbg: &Debugger, before: &Registers, prompt: &Prompt, output: &str) {
+    let [main, command] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(main);
+    let [code, out] =
+        Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+    let [registers, stack, watched] = Layout::vertical([
+        Constraint::Length(9),
+        Constraint::Percentage(50),
+        Constraint::Percentage(50),
+    ])
+    .areas(right);
+
+    draw_code(frame, dbg, code);
+    draw_registers(frame, dbg, before, registers);
+    draw_stack(frame, dbg, stack);
+    draw_watched(frame, dbg, watched);
+
+    let height = out.height.saturating_sub(2) as usize;
+    let lines: Vec<_> = output.lines().collect();
+    let end = lines.len().saturating_sub(prompt.scroll);
+    let shown = lines[end.saturating_sub(height)..end]
+        .iter()
+        .map(|&l| Line::raw(l))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(shown).block(Block::bordered().title(" output ")),
+        out,
+    );
+
+    let text = format!("(tdb) {}", prompt.input);
+    frame.set_cursor_position((command.x + 1 + text.len() as u16, command.y + 1));
+    frame.render_widget(
+        Paragraph::new(text).block(Block::bordered().title(" F5 continue  F10 next  F11 step ")),
+        command,
+    );
+}
+
+fn draw_code(frame: &mut Frame, dbg: &Debugger, area: Rect) {
+    let pc = dbg.machine.pc();
+    let height = area.height.saturating_sub(2) as usize;
+    let lines: Vec<_> = dbg
+        .listing(pc, height / 3, height)
+        .into_iter()
+        .take(height)
+        .map(|line| {
+            let style = if line.starts_with("=>") {
+                Style::new().add_modifier(Modifier::REVERSED)
+            } else if line.starts_with(" *") {
+                Style::new().fg(Color::Red)
+            } else if line.starts_with('<') {
+                Style::new().fg(Color::Cyan)
+            } else {
+                Style::new()
+            };
+            Line::styled(line, style)
+        })
+        .collect();
+    let mut title = format!(" {} ", dbg.symbols.describe(pc));
+    if let Some((file, line)) = dbg.lines.lookup(pc) {
+        title = format!("{title}at {file}:{line} ");
+    }
+    if let Some((tm, _)) = dbg.ended {
+        title = format!("{title}(ended with {tm:?}) ");
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+/// The registers with the ones the last command changed highlighted
+fn draw_registers(frame: &mut Frame, dbg: &Debugger, before: &Registers, area: Rect) {
+    let r = dbg.machine.registers();
+    let changed = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let mut lines: Vec<_> = (0..4)
+        .map(|row| {
+            let spans = (0..4).map(|col| {
+                let reg = WideRegister(U4::new(row * 4 + col));
+                let val = r.read_wide(reg);
+                let text = format!("{:>4} {val:04x} ", reg.to_string());
+                if val == before.read_wide(reg) {
+                    Span::raw(text)
+                } else {
+                    Span::styled(text, changed)
+                }
+            });
+            Line::from(spans.collect::<Vec<_>>())
+        })
+        .collect();
+    let pc = format!("  pc {}", dbg.symbols.describe(r.program_counter));
+    lines.push(Line::raw(pc));
+    let flag = |set: bool, was: bool, name: &'static str| {
+        let text = if set { name } else { "-" };
+        if set == was {
+            Span::raw(text)
+        } else {
+            Span::styled(text, changed)
+        }
+    };
+    lines.push(Line::from(vec![
+        Span::raw("flags "),
+        flag(r.carry, before.carry, "C"),
+        flag(r.overflow, before.overflow, "O"),
+        flag(r.sign, before.sign, "S"),
+        flag(r.zero, before.zero, "Z"),
+        Span::raw(if r.interrupt_enable { " I" } else { "" }),
+        Span::raw(if r.trap { " (in trap handler)" } else { "" }),
+    ]));
+    lines.push(Line::raw(format!("cycles {}", dbg.machine.cycles())));
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" registers ")),
+        area,
+    );
+}
+
+/// The wides from the stack pointer on, with the symbol of values that point into a segment
+fn draw_stack(frame: &mut Frame, dbg: &Debugger, area: Rect) {
+    let sp = dbg.machine.registers().stack;
+    let lines: Vec<_> = (0..area.height.saturating_sub(2))
+        .map_while(|n| sp.checked_add(n * 2))
+        .map(|addr| {
+            let val = dbg.machine.peek_wide(addr);
+            let mut line = format!("{addr:04x}  {val:04x}");
+            if let Some(label) = dbg.symbols.by_location.label(val) {
+                line = format!("{line}  {label}");
+            }
+            Line::raw(line)
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" stack ")),
+        area,
+    );
+}
+
+/// The memory of the watchpoints, 8 bytes to a line
+fn draw_watched(frame: &mut Frame, dbg: &Debugger, area: Rect) {
+    let mut lines = Vec::new();
+    for (start, len, _) in dbg.breakpoints.watchpoints() {
+        for row in (0..len).step_by(8) {
+            let at = start.wrapping_add(row);
+            let bytes: Vec<_> = (0..8.min(len - row))
+                .map(|i| format!("{:02x}", dbg.machine.peek(at.wrapping_add(i))))
+                .collect();
+            lines.push(Line::raw(format!(
+                "{}: {}",
+                dbg.symbols.describe(at),
+                bytes.join(" ")
+            )));
+        }
+    }
+    if lines.is_empty() {
+        lines.push(Line::styled(
+            "watch LOC [N] to see memory here",
+            Style::new().fg(Color::DarkGray),
+        ));
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" watched ")),
+        area,
+    );
+}
+
+/// Leaves the UI for the terminal to read a line of input for the program, which is shown in the output pane after
+pub fn read_line(console: &Console) -> String {
+    let pane = console.pane.as_ref().expect("only used with the UI");
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stdout(), LeaveAlternateScreen);
+    // Whatever the program wrote on the line, like a question
+    let partial = pane
+        .borrow()
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    print!("(program input) {partial}");
+    let _ = stdout().flush();
+    let mut buf = String::new();
+    stdin().read_line(&mut buf).expect("stdin failed");
+    pane.borrow_mut().push_str(&buf);
+    let _ = execute!(stdout(), EnterAlternateScreen);
+    let _ = terminal::enable_raw_mode();
+    SUSPENDED.store(true, Ordering::Relaxed);
+    buf
+}
@@ -0,0 +1,209 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Parser, ValueEnum};
+
+use telda2::{
+    aalv::obj::{
+        Entry, LineTable, Notes, Object, RelocationTable, SegmentFlagsTable, SegmentType,
+        SymbolTable,
+    },
+    mem::IO_MAPPING_CUTOFF,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Telda object file
+    Aalv,
+    /// Raw memory contents, starting at the lowest segment
+    Binary,
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+/// Copies an object file while changing its segments and sections or converting it to and from raw binary
+struct Cli {
+    input_file: PathBuf,
+    output_file: PathBuf,
+
+    /// Format of the input, a raw binary becomes a text segment loaded at --org with its entry point there
+    #[arg(short = 'I', long, value_enum, default_value_t = Format::Aalv)]
+    input_format: Format,
+    /// Format to write the output in
+    #[arg(short = 'O', long, value_enum, default_value_t = Format::Aalv)]
+    output_format: Format,
+    /// Address a raw binary input is loaded at, in hexadecimal with 0x or decimal
+    #[arg(long, value_parser = parse_address, default_value = "0")]
+    org: u16,
+
+    /// Only writes this segment, to extract it as a raw binary with -O binary
+    #[arg(short = 'j', long, value_name = "SEGMENT")]
+    only_segment: Option<SegmentType>,
+    /// Removes a section like _lines, _symtab, _relocs or _notes (can be given several times)
+    #[arg(short = 'R', long, value_name = "SECTION")]
+    remove_section: Vec<String>,
+    /// Removes the debug information, like -R _lines
+    #[arg(short = 'g', long)]
+    strip_debug: bool,
+    /// Adds the contents of a file as a new segment, like --add-segment rodata=logo.bin@0x400
+    #[arg(long, value_name = "SEGMENT=FILE@ADDRESS", value_parser = parse_add_segment)]
+    add_segment: Vec<(SegmentType, PathBuf, u16)>,
+
+    /// Compresses the sections that get smaller from it
+    #[arg(short = 'z', long)]
+    compress: bool,
+}
+
+fn main() -> ExitCode {
+    let Cli {
+        input_file,
+        output_file,
+        input_format,
+        output_format,
+        org,
+        only_segment,
+        mut remove_section,
+        strip_debug,
+        add_segment,
+        compress,
+    } = Cli::parse();
+
+    let obj = match input_format {
+        Format::Aalv => Object::from_file(&input_file),
+        Format::Binary => read_binary(&input_file, org),
+    };
+    let mut obj = match obj {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("could not read {}: {e}", input_file.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if strip_debug {
+        remove_section.push("_lines".to_owned());
+    }
+    for section in &remove_section {
+        if let Err(e) = remove(&mut obj, section) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if obj.symbols.0.is_empty() && !obj.relocation_table.0.is_empty() {
+        eprintln!("cannot remove the symbols while relocations refer to them, remove _relocs too");
+        return ExitCode::FAILURE;
+    }
+    for (stype, path, start) in add_segment {
+        if let Err(e) = add(&mut obj, stype, &path, start) {
+            eprintln!("could not add segment {stype} from {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(stype) = only_segment {
+        if output_format != Format::Binary {
+            eprintln!("--only-segment is for extracting a segment with -O binary");
+            return ExitCode::FAILURE;
+        }
+        if !obj.segs.contains_key(&stype) {
+            eprintln!("no segment {stype} in the object");
+            return ExitCode::FAILURE;
+        }
+        obj.segs.retain(|&s, _| s == stype);
+    }
+
+    let written = match output_format {
+        Format::Aalv => obj.zero_offset().write_to_file_with(&output_file, compress),
+        Format::Binary => write_binary(&obj, &output_file),
+    };
+    match written {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("could not write {}: {e}", output_file.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn parse_add_segment(s: &str) -> Result<(SegmentType, PathBuf, u16), String> {
+    let (stype, rest) = s.split_once('=').ok_or("expected SEGMENT=FILE@ADDRESS")?;
+    let (path, address) = rest
+        .rsplit_once('@')
+        .ok_or("expected an @ADDRESS after the file")?;
+    Ok((stype.parse()?, path.into(), parse_address(address)?))
+}
+
+/// Removes what the section holds from the object, all symbol sections go together
+fn remove(obj: &mut Object, section: &str) -> Result<(), String> {
+    match section {
+        "_entry" => obj.entry = None,
+        "_segflags" => obj.seg_flags = SegmentFlagsTable::default(),
+        "_strtab" | "_symtab" | "_syms" | "_symattrs" | "_symsrc" => {
+            obj.symbols = SymbolTable::default()
+        }
+        "_relocs" | "_reloc" => obj.relocation_table = RelocationTable::default(),
+        "_lines" => obj.lines = LineTable::default(),
+        "_notes" => obj.notes = Notes::default(),
+        "_seg" => return Err("remove segments by extracting the others with -j".to_owned()),
+        section => return Err(format!("unknown section {section}")),
+    }
+    Ok(())
+}
+
+fn add(obj: &mut Object, stype: SegmentType, path: &Path, start: u16) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+    let bytes = fs::read(path)?;
+    let end = start as usize + bytes.len();
+    if end > IO_MAPPING_CUTOFF as usize {
+        return Err(invalid(format!(
+            "it goes past the end of memory at 0x{end:04x}"
+        )));
+    }
+    if obj.segs.contains_key(&stype) {
+        return Err(invalid(format!("the object already has a {stype} segment")));
+    }
+    let overlapping = obj
+        .segs
+        .iter()
+        .find(|(_, &(s, ref b))| (start as usize) < s as usize + b.len() && (s as usize) < end);
+    if let Some((other, _)) = overlapping {
+        return Err(invalid(format!("it overlaps the {other} segment")));
+    }
+
+    obj.segs.insert(stype, (start, bytes));
+    Ok(())
+}
+
+/// Makes an object with just a text segment of the file's contents at the given address
+fn read_binary(path: &Path, org: u16) -> io::Result<Object> {
+    let mut obj = Object::default();
+    add(&mut obj, SegmentType::Text, path, org)?;
+    obj.entry = Some(Entry(SegmentType::Text, org));
+    Ok(obj)
+}
+
+/// Writes the memory from the lowest segment to the end of the highest, with zeroes in between
+fn write_binary(obj: &Object, path: &Path) -> io::Result<()> {
+    let start = obj
+        .segs
+        .values()
+        .map(|&(s, _)| s as usize)
+        .min()
+        .unwrap_or(0);
+    let mem = obj.get_flattened_memory();
+    if start != 0 {
+        eprintln!("note: the binary starts at 0x{start:04x}");
+    }
+    fs::write(path, &mem[start.min(mem.len())..])
+}
@@ -0,0 +1,81 @@
+use std::{
+    env,
+    fmt::Display,
+    io::{self, IsTerminal},
+};
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Colors if printing to a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+/// Puts ANSI colors around the parts of the output if enabled
+#[derive(Debug, Clone, Copy)]
+pub struct Colors {
+    enabled: bool,
+}
+
+const MNEMONIC: &str = "33";
+const REGISTER: &str = "36";
+const IMMEDIATE: &str = "32";
+const LABEL: &str = "1;35";
+const RELOCATION: &str = "31";
+
+impl Colors {
+    pub fn new(choice: ColorChoice) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        };
+        Colors { enabled }
+    }
+
+    fn paint(self, code: &str, s: impl Display) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{s}\x1b[0m")
+        } else {
+            s.to_string()
+        }
+    }
+    pub fn label(self, s: impl Display) -> String {
+        self.paint(LABEL, s)
+    }
+    pub fn relocation(self, s: impl Display) -> String {
+        self.paint(RELOCATION, s)
+    }
+
+    /// Colors an instruction like `ldi r1, 0x010` by what each operand looks like
+    pub fn instruction(self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_owned();
+        }
+        let (mnemonic, operands) = text.split_once(' ').unwrap_or((text, ""));
+        let mut s = self.paint(MNEMONIC, mnemonic);
+        for (i, operand) in operands.split(", ").filter(|o| !o.is_empty()).enumerate() {
+            s.push_str(if i == 0 { " " } else { ", " });
+            let code = if is_register(operand) {
+                REGISTER
+            } else if operand.starts_with(|c: char| c.is_ascii_digit()) {
+                IMMEDIATE
+            } else {
+                LABEL
+            };
+            s.push_str(&self.paint(code, operand));
+        }
+        s
+    }
+}
+
+fn is_register(s: &str) -> bool {
+    match s.strip_prefix('r') {
+        Some("s" | "l" | "f" | "p" | "h") => true,
+        Some(rest) => rest.starts_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
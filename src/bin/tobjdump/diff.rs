@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use telda2::aalv::obj::{Object, SegmentType, SymbolDefinition};
+
+use crate::graph;
+
+/// Prints what changed from `old` to `new`: symbols, segments and the instructions of functions
+pub fn print_diff(old: &Object, new: &Object) {
+    let mut symbols = Vec::new();
+    let old_symbols = symbols_by_name(old);
+    let new_symbols = symbols_by_name(new);
+    for (name, sym) in &old_symbols {
+        match new_symbols.get(name) {
+            None => symbols.push(format!("- {name} = {}", place(sym))),
+            Some(new_sym) if place(sym) != place(new_sym) => symbols.push(format!(
+                "~ {name} moved from {} to {}",
+                place(sym),
+                place(new_sym)
+            )),
+            Some(_) => (),
+        }
+    }
+    for (name, sym) in &new_symbols {
+        if !old_symbols.contains_key(name) {
+            symbols.push(format!("+ {name} = {}", place(sym)));
+        }
+    }
+
+    let mut segments = Vec::new();
+    for (stype, &(start, ref bytes)) in &old.segs {
+        let Some(&(new_start, ref new_bytes)) = new.segs.get(stype) else {
+            segments.push(format!(
+                "- {stype} at 0x{start:04x} size {:#x}",
+                bytes.len()
+            ));
+            continue;
+        };
+        if start != new_start {
+            segments.push(format!(
+                "~ {stype} moved from 0x{start:04x} to 0x{new_start:04x}"
+            ));
+        }
+        if bytes.len() != new_bytes.len() {
+            segments.push(format!(
+                "~ {stype} size {:#x} -> {:#x}",
+                bytes.len(),
+                new_bytes.len()
+            ));
+        }
+    }
+    for (stype, &(start, ref bytes)) in &new.segs {
+        if !old.segs.contains_key(stype) {
+            segments.push(format!(
+                "+ {stype} at 0x{start:04x} size {:#x}",
+                bytes.len()
+            ));
+        }
+    }
+
+    let mut changed = !symbols.is_empty() || !segments.is_empty();
+    print_section("symbols", &symbols);
+    print_section("segments", &segments);
+
+    let old_functions = graph::functions(old);
+    let new_functions = graph::functions(new);
+    for (name, body) in &old_functions {
+        let Some(new_body) = new_functions.get(name) else {
+            changed = true;
+            println!("function {name} removed");
+            println!();
+            continue;
+        };
+        let old_text: Vec<_> = body.iter().map(|(_, text)| &**text).collect();
+        let new_text: Vec<_> = new_body.iter().map(|(_, text)| &**text).collect();
+        if old_text == new_text {
+            continue;
+        }
+        changed = true;
+        println!("function {name}:");
+        for change in diff_lines(&old_text, &new_text) {
+            match change {
+                Change::Same(line) => println!("      {line}"),
+                Change::Removed(line) => println!("    - {line}"),
+                Change::Added(line) => println!("    + {line}"),
+            }
+        }
+        println!();
+    }
+    for name in new_functions.keys() {
+        if !old_functions.contains_key(name) {
+            changed = true;
+            println!("function {name} added");
+            println!();
+        }
+    }
+    if !changed {
+        println!("no differences");
+    }
+}
+
+fn print_section(heading: &str, lines: &[String]) {
+    if !lines.is_empty() {
+        println!("{heading}:");
+        for line in lines {
+            println!("    {line}");
+        }
+        println!();
+    }
+}
+
+/// Named symbols, the first one of a name if there are several
+fn symbols_by_name(obj: &Object) -> BTreeMap<&str, &SymbolDefinition> {
+    let mut symbols = BTreeMap::new();
+    for s in obj.symbols.0.iter().filter(|s| !s.name.is_empty()) {
+        symbols.entry(&*s.name).or_insert(s);
+    }
+    symbols
+}
+
+fn place(sym: &SymbolDefinition) -> String {
+    match sym.segment_type {
+        SegmentType::Unknown => "UNDEFINED".to_owned(),
+        stype => format!("0x{:04x} in {stype}", sym.location),
+    }
+}
+
+enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Finds the fewest lines to remove and add to get from `old` to `new` with a longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Change<'a>> {
+    // Length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(Change::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|l| Change::Removed(l)));
+    changes.extend(new[j..].iter().map(|l| Change::Added(l)));
+    changes
+}
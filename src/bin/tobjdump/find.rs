@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use telda2::{
+    aalv::obj::{Object, SymbolLookup},
+    disassemble::Disassembler,
+};
+
+use crate::graph;
+
+/// Parses bytes written in hex like `AABBCC` or `aa bb cc`
+pub fn parse_bytes(s: &str) -> Result<Box<[u8]>, String> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err("expected an even number of hex digits".to_owned());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|e| e.to_string())?;
+            u8::from_str_radix(pair, 16).map_err(|e| format!("{pair}: {e}"))
+        })
+        .collect()
+}
+
+/// Names where the address is, like ` <loop+0x3>`, or nothing if it is before every symbol
+fn context(symbols: &SymbolLookup, address: u16) -> String {
+    symbols
+        .label(address)
+        .map(|label| format!(" {label}"))
+        .unwrap_or_default()
+}
+
+/// Prints every place in the segments where the bytes are
+pub fn find_bytes(obj: &Object, pattern: &[u8]) {
+    let symbols = SymbolLookup::new(obj);
+    println!("matches of {}:", hex(pattern));
+    for (stype, &(start, ref bytes)) in &obj.segs {
+        for (i, window) in bytes.windows(pattern.len()).enumerate() {
+            if window == pattern {
+                let address = start + i as u16;
+                println!("  {address:04x}{} in {stype}", context(&symbols, address));
+            }
+        }
+    }
+    println!();
+}
+
+/// Prints every instruction in the executable segments whose disassembly contains `pattern`
+pub fn find_instructions(obj: &Object, pattern: &str) {
+    let mem = obj.get_flattened_memory();
+    let labels: HashMap<u16, &str> = graph::code_labels(obj);
+    let symbols = SymbolLookup::new(obj);
+    let disassembler = Disassembler::new(&mem)
+        .show_bytes(false)
+        .symbolizer(|p| labels.get(&p).map(|s| s.to_string()));
+
+    println!("instructions matching {pattern}:");
+    for region in graph::executable_regions(obj) {
+        let mut location = region.start;
+        while (location as u32) < region.end {
+            let ins = disassembler.decode(location);
+            if !ins.undecodable && ins.text.contains(pattern) {
+                println!(
+                    "  {location:04x}{}: {}",
+                    context(&symbols, location),
+                    ins.text
+                );
+            }
+            if ins.next_instruction_location <= location {
+                break;
+            }
+            location = ins.next_instruction_location;
+        }
+    }
+    println!();
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
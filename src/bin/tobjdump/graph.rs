@@ -0,0 +1,342 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    io::{self, Write},
+};
+
+use telda2::{
+    aalv::obj::{Object, Region, SegmentFlags, SegmentType, SymbolKind},
+    disassemble::{DisassembledInstruction, Disassembler, Flow},
+};
+
+/// Names of the symbols in executable segments by their location
+pub fn code_labels(obj: &Object) -> HashMap<u16, &str> {
+    let mut labels = HashMap::new();
+    if let Some(entry) = obj.entry {
+        labels.insert(entry.1, ".entry");
+    }
+    for s in &obj.symbols.0 {
+        if s.segment_type != SegmentType::Unknown && !s.name.is_empty() {
+            labels.insert(s.location, &*s.name);
+        }
+    }
+    labels
+}
+
+/// Where functions start: the entry point and global or function symbols in executable segments
+pub fn function_starts(obj: &Object) -> Vec<u16> {
+    let regions = executable_regions(obj);
+    obj.entry
+        .map(|e| e.1)
+        .into_iter()
+        .chain(
+            obj.symbols
+                .0
+                .iter()
+                .filter(|s| s.segment_type != SegmentType::Unknown)
+                .filter(|s| s.is_global || s.kind == SymbolKind::Function)
+                .map(|s| s.location),
+        )
+        .filter(|&loc| is_executable(&regions, loc))
+        .collect()
+}
+
+pub fn executable_regions(obj: &Object) -> Vec<Region> {
+    obj.memory_regions()
+        .into_iter()
+        .filter(|r| r.flags.contains(SegmentFlags::EXECUTE))
+        .collect()
+}
+
+pub fn is_executable(regions: &[Region], location: u16) -> bool {
+    regions.iter().any(|r| r.contains(location))
+}
+
+pub fn function_name<'a>(labels: &HashMap<u16, &'a str>, location: u16) -> Cow<'a, str> {
+    match labels.get(&location) {
+        Some(&name) => Cow::Borrowed(name),
+        None => Cow::Owned(format!("sub_{location:04x}")),
+    }
+}
+
+fn symbolized<'a>(mem: &'a [u8], labels: &'a HashMap<u16, &str>) -> Disassembler<'a> {
+    Disassembler::new(mem).symbolizer(|p| labels.get(&p).map(|s| s.to_string()))
+}
+
+/// An instruction reachable from the start of a function
+struct Instruction {
+    text: String,
+    flow: Flow,
+    next: u16,
+    data_reference: Option<u16>,
+}
+
+/// Finds every instruction reachable from `start` without following calls
+fn explore(
+    start: u16,
+    disassembler: &Disassembler,
+    regions: &[Region],
+) -> BTreeMap<u16, Instruction> {
+    let mut instructions = BTreeMap::new();
+    let mut to_visit = vec![start];
+
+    while let Some(location) = to_visit.pop() {
+        if instructions.contains_key(&location) || !is_executable(regions, location) {
+            continue;
+        }
+        let DisassembledInstruction {
+            text,
+            flow,
+            data_reference,
+            next_instruction_location: next,
+            ..
+        } = disassembler.decode(location);
+
+        match flow {
+            Flow::Next | Flow::Call(_) | Flow::IndirectCall => to_visit.push(next),
+            Flow::Branch(target) => to_visit.extend([next, target]),
+            Flow::Jump(target) => to_visit.push(target),
+            Flow::IndirectJump | Flow::Stop => (),
+        }
+        instructions.insert(
+            location,
+            Instruction {
+                text,
+                flow,
+                next,
+                data_reference,
+            },
+        );
+    }
+
+    instructions
+}
+
+/// Writes a Graphviz graph with the basic blocks of every function
+pub fn write_cfg<W: Write>(obj: &Object, mut w: W) -> io::Result<()> {
+    let mem = obj.get_flattened_memory();
+    let regions = executable_regions(obj);
+    let labels = code_labels(obj);
+    let disassembler = symbolized(&mem, &labels);
+
+    let mut functions: VecDeque<u16> = function_starts(obj).into();
+    let mut done = BTreeSet::new();
+
+    writeln!(w, "digraph cfg {{")?;
+    writeln!(w, "    node [shape=box, fontname=\"monospace\"];")?;
+
+    while let Some(start) = functions.pop_front() {
+        if !done.insert(start) {
+            continue;
+        }
+        let instructions = explore(start, &disassembler, &regions);
+
+        let mut leaders = BTreeSet::from([start]);
+        for ins in instructions.values() {
+            match ins.flow {
+                Flow::Branch(target) => {
+                    leaders.insert(target);
+                    leaders.insert(ins.next);
+                }
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                }
+                Flow::Call(target) => functions.push_back(target),
+                _ => (),
+            }
+        }
+
+        let node = |block: u16| format!("\"{start:04x}_{block:04x}\"");
+        let name = function_name(&labels, start);
+        writeln!(w, "    subgraph \"cluster_{start:04x}\" {{")?;
+        writeln!(w, "        label = \"{}\";", escape(&name))?;
+
+        let mut edges = Vec::new();
+        for &leader in leaders.iter().filter(|l| instructions.contains_key(l)) {
+            let mut label = String::new();
+            let mut location = leader;
+            loop {
+                let ins = &instructions[&location];
+                label.push_str(&format!("{location:04x}: {}\\l", escape(&ins.text)));
+
+                let falls_through =
+                    matches!(ins.flow, Flow::Next | Flow::Call(_) | Flow::IndirectCall);
+                match ins.flow {
+                    Flow::Branch(target) => {
+                        edges.push((leader, target, "color=green"));
+                        edges.push((leader, ins.next, "color=red"));
+                    }
+                    Flow::Jump(target) => edges.push((leader, target, "")),
+                    _ => (),
+                }
+                if !falls_through || !instructions.contains_key(&ins.next) {
+                    break;
+                }
+                if leaders.contains(&ins.next) {
+                    edges.push((leader, ins.next, ""));
+                    break;
+                }
+                location = ins.next;
+            }
+            writeln!(w, "        {} [label=\"{label}\"];", node(leader))?;
+        }
+        writeln!(w, "    }}")?;
+
+        for (from, to, attrs) in edges {
+            if instructions.contains_key(&to) {
+                writeln!(w, "    {} -> {} [{attrs}];", node(from), node(to))?;
+            }
+        }
+    }
+
+    writeln!(w, "}}")
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// What a function calls
+#[derive(Debug, Default)]
+pub struct Calls {
+    pub direct: BTreeSet<u16>,
+    /// Locations of calls and jumps to addresses in registers
+    pub indirect: Vec<u16>,
+}
+
+/// Finds the calls of every function reachable from the entry point and symbols
+pub fn call_graph(obj: &Object) -> BTreeMap<u16, Calls> {
+    let mem = obj.get_flattened_memory();
+    let regions = executable_regions(obj);
+    let labels = code_labels(obj);
+    let disassembler = symbolized(&mem, &labels);
+
+    let mut functions: VecDeque<u16> = function_starts(obj).into();
+    let mut graph = BTreeMap::new();
+
+    while let Some(start) = functions.pop_front() {
+        if graph.contains_key(&start) {
+            continue;
+        }
+        let mut calls = Calls::default();
+        for (&location, ins) in &explore(start, &disassembler, &regions) {
+            match ins.flow {
+                Flow::Call(target) => {
+                    calls.direct.insert(target);
+                    functions.push_back(target);
+                }
+                Flow::IndirectJump | Flow::IndirectCall => calls.indirect.push(location),
+                _ => (),
+            }
+        }
+        graph.insert(start, calls);
+    }
+
+    graph
+}
+
+pub fn print_call_graph(obj: &Object) {
+    let labels = code_labels(obj);
+
+    println!("call graph:");
+    for (&function, calls) in &call_graph(obj) {
+        print!("    {}", function_name(&labels, function));
+        let mut sep = ": ";
+        for &target in &calls.direct {
+            print!("{sep}{}", function_name(&labels, target));
+            sep = ", ";
+        }
+        for &location in &calls.indirect {
+            print!("{sep}INDIRECT @ 0x{location:02x}");
+            sep = ", ";
+        }
+        println!();
+    }
+    println!();
+}
+
+/// Writes the call graph as a Graphviz graph, with indirect calls going to a separate node
+pub fn write_call_graph<W: Write>(obj: &Object, mut w: W) -> io::Result<()> {
+    let labels = code_labels(obj);
+    let graph = call_graph(obj);
+
+    writeln!(w, "digraph callgraph {{")?;
+    writeln!(w, "    node [shape=box, fontname=\"monospace\"];")?;
+    if graph.values().any(|c| !c.indirect.is_empty()) {
+        writeln!(w, "    indirect [label=\"(indirect)\", style=dashed];")?;
+    }
+    for (&function, calls) in &graph {
+        let name = escape(&function_name(&labels, function));
+        writeln!(w, "    \"{function:04x}\" [label=\"{name}\"];")?;
+        for &target in &calls.direct {
+            writeln!(w, "    \"{function:04x}\" -> \"{target:04x}\";")?;
+        }
+        if !calls.indirect.is_empty() {
+            writeln!(w, "    \"{function:04x}\" -> indirect [style=dashed];")?;
+        }
+    }
+    writeln!(w, "}}")
+}
+
+/// Instructions reachable from the functions and the addresses they use as data
+#[derive(Debug, Default)]
+pub struct Reachable {
+    pub code: BTreeSet<u16>,
+    pub data: BTreeSet<u16>,
+}
+
+pub fn reachable(obj: &Object) -> Reachable {
+    let mem = obj.get_flattened_memory();
+    let regions = executable_regions(obj);
+    let labels = code_labels(obj);
+    let disassembler = symbolized(&mem, &labels);
+
+    let mut functions: VecDeque<u16> = function_starts(obj).into();
+    let mut done = BTreeSet::new();
+    let mut reachable = Reachable::default();
+
+    while let Some(start) = functions.pop_front() {
+        if !done.insert(start) {
+            continue;
+        }
+        for (location, ins) in explore(start, &disassembler, &regions) {
+            if let Flow::Call(target) = ins.flow {
+                functions.push_back(target);
+            }
+            reachable.data.extend(ins.data_reference);
+            reachable.code.insert(location);
+        }
+    }
+    // Jumping somewhere makes it code, however it is used otherwise
+    reachable.data.retain(|loc| !reachable.code.contains(loc));
+
+    reachable
+}
+
+/// The instructions of every function reachable from the entry point and symbols, by function name
+pub fn functions(obj: &Object) -> BTreeMap<String, Vec<(u16, String)>> {
+    let mem = obj.get_flattened_memory();
+    let regions = executable_regions(obj);
+    let labels = code_labels(obj);
+    let disassembler = symbolized(&mem, &labels);
+
+    let mut starts: VecDeque<u16> = function_starts(obj).into();
+    let mut done = BTreeSet::new();
+    let mut functions = BTreeMap::new();
+
+    while let Some(start) = starts.pop_front() {
+        if !done.insert(start) {
+            continue;
+        }
+        let mut body = Vec::new();
+        for (location, ins) in explore(start, &disassembler, &regions) {
+            if let Flow::Call(target) = ins.flow {
+                starts.push_back(target);
+            }
+            body.push((location, ins.text));
+        }
+        functions.insert(function_name(&labels, start).into_owned(), body);
+    }
+
+    functions
+}
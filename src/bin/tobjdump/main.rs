@@ -0,0 +1,953 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{ArgAction, ArgGroup, Parser, ValueEnum};
+
+mod color;
+mod diff;
+mod find;
+mod graph;
+mod pattern;
+mod stats;
+
+use self::{
+    color::{ColorChoice, Colors},
+    pattern::Pattern,
+};
+use telda2::{
+    aalv::{
+        obj::{
+            Entry, LineTable, Object, ObjectReader, RelocationKind, SegmentFlags, SegmentType,
+            SymbolKind, SymbolLookup,
+        },
+        FEATURE_COMPRESSION, SECTION_COMPRESSED,
+    },
+    disassemble::{DisassembledInstruction, Disassembler, Flow},
+    isa::feature_names,
+    mem::IO_MAPPING_CUTOFF,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None, disable_help_flag = true)]
+#[command(group(
+            ArgGroup::new("show")
+                .required(true)
+                .multiple(true)
+                .args(["disassemble", "show_symbols", "show_notes", "file_headers", "headers", "hexdump", "find_bytes", "find_ins", "diff", "stats", "size_report", "cfg", "callgraph"]),
+        ))]
+struct Cli {
+    /// Input telda object file
+    input_file: PathBuf,
+
+    /// Reads the input as raw machine code instead of an object, like a memory dump or ROM image
+    #[arg(long, conflicts_with_all = ["show_symbols", "show_notes", "no_verify"])]
+    binary: bool,
+    /// Address the raw machine code is loaded at, in hexadecimal with 0x or decimal
+    #[arg(long, requires = "binary", value_parser = parse_address, default_value = "0")]
+    org: u16,
+
+    /// Disassemble symbols in .text. If -D is not set, disassembles from all global symbols
+    ///
+    /// With --binary, always disassembles linearly like --full
+    #[arg(short, long, group = "show")]
+    disassemble: bool,
+    /// If disassembling, sets the symbols to start disassembling from seperated by commas
+    #[arg(short = 'D', long, requires = "disassemble", value_name = "SYMBOLS")]
+    disassemble_from: Option<String>,
+    /// If disassembling, disassembles every executable segment from start to end instead of following symbols
+    #[arg(long, requires = "disassemble", conflicts_with = "disassemble_from")]
+    full: bool,
+    /// If disassembling, disassembles linearly from this address instead of following symbols
+    #[arg(long, requires = "disassemble", conflicts_with_all = ["disassemble_from", "full"], value_parser = parse_address)]
+    start: Option<u16>,
+    /// Address to stop disassembling at given --start, otherwise the end of its segment
+    #[arg(long, requires = "start", value_parser = parse_address)]
+    stop: Option<u16>,
+
+    /// Whether to show the symbol table
+    #[arg(short = 't', long = "syms", group = "show")]
+    show_symbols: bool,
+    /// Sorts the symbol table by address or name instead of listing it in file order
+    #[arg(long, value_enum, requires = "show_symbols")]
+    sort: Option<SymbolSort>,
+    /// Only shows global symbols in the symbol table
+    #[arg(long, requires = "show_symbols")]
+    globals_only: bool,
+    /// Only shows symbols whose name matches the regular expression (with ., [], *, +, ?, ^ and $)
+    #[arg(long, value_name = "REGEX", requires = "show_symbols")]
+    filter: Option<Pattern>,
+
+    /// Shows the notes on which tools made the object from what
+    #[arg(short = 'n', long = "notes")]
+    show_notes: bool,
+
+    /// Shows the format version, entry point and total size of the segments
+    #[arg(short = 'f', long, conflicts_with = "binary")]
+    file_headers: bool,
+
+    /// Shows the sections in the file and the segments with their addresses, sizes and flags
+    #[arg(short = 'h', long, conflicts_with = "binary")]
+    headers: bool,
+
+    /// Shows the contents of every segment or just the given one as hex and ASCII, like -x=data
+    #[arg(short = 'x', long, value_name = "SEGMENT", num_args = 0..=1, require_equals = true)]
+    hexdump: Option<Option<String>>,
+
+    /// Shows where the bytes, given in hex like AABBCC, are in the segments
+    #[arg(long, value_name = "HEX", value_parser = find::parse_bytes)]
+    find_bytes: Option<Box<[u8]>>,
+
+    /// Shows the instructions whose disassembly contains the text, like "call" or "ldi r1"
+    #[arg(long, value_name = "TEXT")]
+    find_ins: Option<String>,
+
+    /// Shows how the input changed from this older object: symbols, segment sizes and function instructions
+    #[arg(long, value_name = "OLD", conflicts_with = "binary")]
+    diff: Option<PathBuf>,
+
+    /// Shows how often each instruction is used by the code and how many bytes of each segment are code or data
+    #[arg(long)]
+    stats: bool,
+
+    /// Lists the functions, objects and global symbols by size, biggest first
+    #[arg(long)]
+    size_report: bool,
+
+    /// Shows relocations in disassembly
+    #[arg(short = 'R', long, requires = "disassemble")]
+    show_relocations: bool,
+
+    /// Shows the source file and line of instructions in disassembly, if the object has debug info
+    #[arg(short = 'l', long, requires = "disassemble")]
+    line_numbers: bool,
+
+    /// Shows the source lines above the instructions they became in disassembly, if the object has debug info
+    #[arg(short = 'S', long, requires = "disassemble")]
+    source: bool,
+
+    /// Writes the control-flow graph of every function in Graphviz DOT format to the file
+    #[arg(long, value_name = "OUT_DOT", group = "show")]
+    cfg: Option<PathBuf>,
+
+    /// Shows which functions call which, or writes it in Graphviz DOT format with --callgraph=OUT_DOT
+    #[arg(long, value_name = "OUT_DOT", group = "show", num_args = 0..=1, require_equals = true)]
+    callgraph: Option<Option<PathBuf>>,
+
+    /// Whether to color the disassembly
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Reads the object even if its checksum does not match
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Print help
+    #[arg(long, action = ArgAction::Help)]
+    help: Option<bool>,
+
+    /// Dumps the whole object as JSON
+    #[cfg(feature = "serde")]
+    #[arg(long, group = "show", conflicts_with_all = ["disassemble", "show_symbols", "show_notes"])]
+    json: bool,
+}
+
+fn main() -> ExitCode {
+    let Cli {
+        input_file,
+        binary,
+        org,
+        disassemble,
+        disassemble_from: dissasemble_from,
+        full,
+        start,
+        stop,
+        show_symbols,
+        sort,
+        globals_only,
+        filter,
+        show_notes,
+        file_headers,
+        headers,
+        hexdump,
+        find_bytes,
+        find_ins,
+        diff,
+        stats,
+        size_report,
+        show_relocations,
+        line_numbers,
+        source,
+        cfg,
+        callgraph,
+        color,
+        no_verify,
+        help: _,
+        #[cfg(feature = "serde")]
+        json,
+    } = Cli::parse();
+
+    let obj = if binary {
+        read_binary(&input_file, org)
+    } else if no_verify {
+        Object::from_file_unverified(&input_file)
+    } else {
+        Object::from_file(&input_file)
+    };
+    let obj = match obj {
+        Ok(o) => o,
+        Err(e) => {
+            let kind = if binary { "binary" } else { "object" };
+            eprintln!("could not read {kind} file: {e}");
+
+            return ExitCode::FAILURE;
+        }
+    };
+
+    #[cfg(feature = "serde")]
+    if json {
+        match serde_json::to_string_pretty(&obj) {
+            Ok(s) => println!("{s}"),
+            Err(e) => {
+                eprintln!("could not serialise object: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if file_headers {
+        if let Err(e) = file_header(&input_file, &obj) {
+            eprintln!("could not read file header: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if headers {
+        if let Err(e) = section_headers(&input_file, &obj) {
+            eprintln!("could not read section headers: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if show_notes {
+        notes(&obj);
+    }
+    if show_symbols {
+        symbols(&obj, sort, globals_only, filter.as_ref());
+    }
+    if let Some(segment) = hexdump {
+        if let Err(e) = hexdumps(&obj, segment.as_deref()) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(pattern) = find_bytes {
+        find::find_bytes(&obj, &pattern);
+    }
+    if let Some(pattern) = find_ins {
+        find::find_instructions(&obj, &pattern);
+    }
+    if let Some(old_file) = diff {
+        let old = if no_verify {
+            Object::from_file_unverified(&old_file)
+        } else {
+            Object::from_file(&old_file)
+        };
+        match old {
+            Ok(old) => diff::print_diff(&old, &obj),
+            Err(e) => {
+                eprintln!("could not read object file {}: {e}", old_file.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if stats {
+        stats::print_stats(&obj);
+    }
+    if size_report {
+        stats::print_size_report(&obj);
+    }
+    let colors = Colors::new(color);
+    let lines = LineDisplay {
+        numbers: line_numbers,
+        source,
+    };
+    if let Some(start) = start {
+        let stop = stop.map(usize::from).or_else(|| {
+            obj.memory_regions()
+                .into_iter()
+                .find(|r| r.contains(start))
+                .map(|r| r.end as usize)
+        });
+        let Some(stop) = stop else {
+            eprintln!("0x{start:04x} is not in a segment, give where to stop with --stop");
+            return ExitCode::FAILURE;
+        };
+        let heading = format!("0x{start:04x} to 0x{stop:04x}");
+        linear_disassembly(
+            &obj,
+            &[(heading, start, stop)],
+            show_relocations,
+            lines,
+            colors,
+        );
+    } else if disassemble && (full || binary) {
+        let ranges: Vec<_> = obj
+            .segs
+            .iter()
+            .filter(|(&stype, _)| obj.segment_flags(stype).contains(SegmentFlags::EXECUTE))
+            .map(|(stype, &(start, ref bytes))| {
+                (
+                    format!("segment {stype}"),
+                    start,
+                    start as usize + bytes.len(),
+                )
+            })
+            .collect();
+        linear_disassembly(&obj, &ranges, show_relocations, lines, colors);
+    } else if disassemble {
+        disassembly(&obj, dissasemble_from, show_relocations, lines, colors);
+    }
+
+    match callgraph {
+        None => (),
+        Some(None) => graph::print_call_graph(&obj),
+        Some(Some(path)) => {
+            let written = fs::File::create(&path)
+                .and_then(|f| graph::write_call_graph(&obj, io::BufWriter::new(f)));
+            if let Err(e) = written {
+                eprintln!("could not write call graph: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Some(path) = cfg {
+        let written =
+            fs::File::create(&path).and_then(|f| graph::write_cfg(&obj, io::BufWriter::new(f)));
+        if let Err(e) = written {
+            eprintln!("could not write control-flow graph: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Makes an object with just a text segment of the file's contents at the given address
+fn read_binary(path: &Path, org: u16) -> io::Result<Object> {
+    let bytes = fs::read(path)?;
+    if org as usize + bytes.len() > IO_MAPPING_CUTOFF as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} bytes loaded at 0x{org:04x} go past the end of memory",
+                bytes.len()
+            ),
+        ));
+    }
+
+    let mut obj = Object::default();
+    obj.segs.insert(SegmentType::Text, (org, bytes));
+    Ok(obj)
+}
+
+fn file_header(path: &Path, obj: &Object) -> io::Result<()> {
+    let reader = ObjectReader::open(path)?;
+
+    println!("{}: file format álvur{}", path.display(), reader.version());
+    let mut features = Vec::new();
+    if reader.features() & FEATURE_COMPRESSION != 0 {
+        features.push("compression");
+    }
+    if features.is_empty() {
+        println!("features: none");
+    } else {
+        println!("features: {}", features.join(", "));
+    }
+    if obj.cpu_features == 0 {
+        println!("cpu features: base");
+    } else {
+        println!(
+            "cpu features: {}",
+            feature_names(obj.cpu_features).join(", ")
+        );
+    }
+    match obj.entry {
+        Some(Entry(stype, location)) => println!("entry point: 0x{location:04x} in {stype}"),
+        None => println!("entry point: none"),
+    }
+    println!("file offset: {:#x}", reader.file_offset());
+
+    let sizes: Vec<_> = reader
+        .segments()
+        .map(|info| format!("{} {:#x}", info.stype, info.size))
+        .collect();
+    let total: usize = reader.segments().map(|info| info.size as usize).sum();
+    print!("segment size: {total:#x}");
+    if !sizes.is_empty() {
+        print!(" ({})", sizes.join(", "));
+    }
+    println!();
+    println!();
+    Ok(())
+}
+
+fn section_headers(path: &Path, obj: &Object) -> io::Result<()> {
+    let reader = ObjectReader::open(path)?;
+
+    println!("sections:");
+    println!("    {:<10} {:>6} {:>6}", "name", "offset", "size");
+    for (name, pos, size, flags) in reader.sections() {
+        print!("    {name:<10} {pos:>#6x} {size:>#6x}");
+        if flags & SECTION_COMPRESSED != 0 {
+            print!(" compressed");
+        }
+        println!();
+    }
+    println!();
+
+    println!("segments:");
+    println!("    {:<10} {:>6} {:>6} flags", "type", "start", "size");
+    for info in reader.segments() {
+        let flags = obj.segment_flags(info.stype);
+        println!(
+            "    {:<10} {:>#6x} {:>#6x} {flags}",
+            info.stype.to_string(),
+            info.start,
+            info.size
+        );
+    }
+    println!();
+    if !obj.banks.is_empty() {
+        println!("banks:");
+        println!("    {:<10} {:>6}", "bank", "size");
+        for (number, bytes) in &obj.banks {
+            println!("    {number:<10} {:>#6x}", bytes.len());
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn notes(obj: &Object) {
+    if !obj.notes.0.is_empty() {
+        println!("notes:");
+        for note in &obj.notes.0 {
+            println!("    {} {}", note.producer, note.flags);
+            for (src, crc) in &note.sources {
+                println!("        {src} (crc32 {crc:08x})");
+            }
+        }
+        println!();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SymbolSort {
+    /// By address, undefined symbols last
+    Addr,
+    Name,
+}
+
+fn symbols(obj: &Object, sort: Option<SymbolSort>, globals_only: bool, filter: Option<&Pattern>) {
+    let mut syms: Vec<_> = obj
+        .symbols
+        .0
+        .iter()
+        .filter(|s| !globals_only || s.is_global)
+        .filter(|s| filter.is_none_or(|f| f.is_match(&s.name)))
+        .collect();
+    match sort {
+        None => (),
+        Some(SymbolSort::Addr) => {
+            syms.sort_by_key(|s| (s.segment_type == SegmentType::Unknown, s.location))
+        }
+        Some(SymbolSort::Name) => syms.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+
+    if !syms.is_empty() {
+        println!("symbols:");
+        for sym_def in syms {
+            print!("    ");
+            if sym_def.is_global {
+                print!("GLOBAL ");
+            }
+            match sym_def.segment_type {
+                SegmentType::Unknown => {
+                    print!("{} = UNDEFINED ({:02x})", sym_def.name, sym_def.location)
+                }
+                stype => print!("{} = {:02x} in {:?}", sym_def.name, sym_def.location, stype),
+            }
+            if sym_def.kind != SymbolKind::NoType {
+                print!(" {}", sym_def.kind);
+            }
+            if let Some(size) = sym_def.size {
+                print!(" size {size:#x}");
+            }
+            if let Some(src) = sym_def.source_description() {
+                print!(", defined in {src}");
+            }
+            println!();
+        }
+        println!();
+    }
+}
+
+/// Dumps the contents of the segments, or just the one named `segment`
+fn hexdumps(obj: &Object, segment: Option<&str>) -> Result<(), String> {
+    if let Some(name) = segment {
+        if !obj.segs.keys().any(|stype| stype.to_string() == name) {
+            let names: Vec<_> = obj.segs.keys().map(|stype| stype.to_string()).collect();
+            return Err(format!(
+                "no segment {name} in the object, it has: {}",
+                names.join(", ")
+            ));
+        }
+    }
+
+    for (stype, &(start, ref bytes)) in &obj.segs {
+        if segment.is_some_and(|name| stype.to_string() != name) {
+            continue;
+        }
+        println!("contents of segment {stype}:");
+        hexdump(start, bytes);
+        println!();
+    }
+    Ok(())
+}
+
+/// Prints 16 bytes a line with their address, in hex and as ASCII
+fn hexdump(start: u16, bytes: &[u8]) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        print!("  {:04x}: ", start as usize + i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                print!(" ");
+            }
+            match line.get(j) {
+                Some(b) => print!(" {b:02x}"),
+                None => print!("   "),
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("  |{ascii}|");
+    }
+}
+
+fn disassembly(
+    obj: &Object,
+    start_symbol: Option<String>,
+    show_relocations: bool,
+    lines: LineDisplay,
+    colors: Colors,
+) {
+    let syms = &obj.symbols.0;
+
+    let symbols: VecDeque<usize>;
+    if let Some(start_symbol) = start_symbol.as_ref() {
+        symbols = start_symbol
+            .split(',')
+            .map(|s| s.trim())
+            .map(|name| {
+                syms.iter()
+                    .position(|s| &*s.name == name)
+                    .expect("start symbol did not exist in symbol table")
+            })
+            .collect();
+    } else {
+        let entry_id = obj.symbols.0.len();
+        symbols = obj
+            .entry
+            .map(|_| entry_id)
+            .into_iter()
+            .chain(obj.symbols.0.iter().enumerate().filter_map(|(i, s)| {
+                if s.is_global {
+                    Some(i)
+                } else {
+                    None
+                }
+            }))
+            .collect();
+    }
+
+    println!("disassembly:");
+    let mem;
+    let mut pos_to_labels = HashMap::new();
+    {
+        mem = obj.get_flattened_memory();
+
+        for (id, s) in obj.symbols.0.iter().enumerate() {
+            pos_to_labels.insert(s.location, id);
+        }
+        if let Some(ep) = obj.entry {
+            pos_to_labels.insert(ep.1, obj.symbols.0.len());
+        }
+    }
+
+    let mut relocs = BTreeMap::new();
+    if show_relocations {
+        for &re in &obj.relocation_table.0 {
+            relocs.insert(re.reference_location, (re.symbol_index as usize, re.kind));
+        }
+    }
+
+    let mut printed_labels = HashSet::new();
+    let mut labels_to_print = symbols;
+    let mut source_lines = SourceLines::new(obj, lines);
+
+    let get_name = |id: usize| {
+        if id == syms.len() {
+            return Cow::Borrowed(".entry");
+        }
+
+        let name = &*syms[id].name;
+        if name.is_empty() {
+            Cow::Owned(format!("@L{id}"))
+        } else {
+            Cow::Borrowed(name)
+        }
+    };
+
+    let nearest = SymbolLookup::new(obj);
+    let disassembler = Disassembler::new(&mem)
+        .symbolizer(|p| pos_to_labels.get(&p).map(|&id| get_name(id).into_owned()))
+        .nearest_symbolizer(|p| nearest.label(p));
+
+    while let Some(label_to_print) = labels_to_print.pop_front() {
+        // Printed labels can end up in the queue
+        if printed_labels.contains(&label_to_print) {
+            continue;
+        }
+
+        println!("<{}>:", colors.label(get_name(label_to_print)));
+        printed_labels.insert(label_to_print);
+
+        let start = if label_to_print == syms.len() {
+            obj.entry
+                .expect("this value would not happen if it is None")
+                .1
+        } else {
+            syms[label_to_print].location
+        };
+
+        for (location, ins) in disassembler.instructions(start) {
+            let DisassembledInstruction {
+                annotated_source,
+                text,
+                flow,
+                data_reference,
+                ends_block,
+                next_instruction_location,
+                ..
+            } = ins;
+            let target = match flow {
+                Flow::Branch(t) | Flow::Jump(t) | Flow::Call(t) => Some(t),
+                _ => None,
+            };
+            for p in target.into_iter().chain(data_reference) {
+                if let Some(&l) = pos_to_labels.get(&p) {
+                    if !printed_labels.contains(&l) {
+                        labels_to_print.push_back(l);
+                    }
+                }
+            }
+
+            source_lines.print(location);
+            if show_relocations {
+                print_relocations(
+                    &relocs,
+                    location..next_instruction_location,
+                    get_name,
+                    colors,
+                );
+            }
+            println!("{}", colored(&annotated_source, &text, colors));
+            if ends_block {
+                break;
+            }
+            if let Some(&lbl) = pos_to_labels.get(&next_instruction_location) {
+                let name = get_name(lbl);
+                if printed_labels.insert(lbl) {
+                    // Was not printed before
+                    println!("<{}>:", colors.label(name));
+                } else {
+                    // Was printed before => end block
+                    println!("<{}> ...", colors.label(name));
+                    break;
+                }
+            }
+        }
+        println!();
+    }
+}
+
+/// Disassembles the address ranges linearly, showing bytes that are not instructions as data
+fn linear_disassembly(
+    obj: &Object,
+    ranges: &[(String, u16, usize)],
+    show_relocations: bool,
+    lines: LineDisplay,
+    colors: Colors,
+) {
+    let syms = &obj.symbols.0;
+    let mem = obj.get_flattened_memory();
+
+    let mut pos_to_labels: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+    for (id, s) in syms.iter().enumerate() {
+        if s.segment_type != SegmentType::Unknown {
+            pos_to_labels.entry(s.location).or_default().push(id);
+        }
+    }
+    let mut relocs = BTreeMap::new();
+    if show_relocations {
+        for &re in &obj.relocation_table.0 {
+            relocs.insert(re.reference_location, (re.symbol_index as usize, re.kind));
+        }
+    }
+    let get_name = |id: usize| {
+        let name = &*syms[id].name;
+        if name.is_empty() {
+            Cow::Owned(format!("@L{id}"))
+        } else {
+            Cow::Borrowed(name)
+        }
+    };
+
+    let reachable = graph::reachable(obj);
+    let nearest = SymbolLookup::new(obj);
+    let disassembler = Disassembler::new(&mem)
+        .symbolizer(|p| {
+            let label = pos_to_labels.get(&p).and_then(|ids| ids.first());
+            label.map(|&id| get_name(id).into_owned())
+        })
+        .nearest_symbolizer(|p| nearest.label(p));
+
+    println!("disassembly:");
+    for &(ref heading, start, end) in ranges {
+        println!("{heading}:");
+
+        let mut data = Vec::new();
+        let mut source_lines = SourceLines::new(obj, lines);
+        let mut location = start as usize;
+        while location < end {
+            let labels = pos_to_labels.get(&(location as u16));
+            if labels.is_some() || data.len() == 4 {
+                print_data(&mut data);
+            }
+            for &id in labels.into_iter().flatten() {
+                println!("<{}>:", colors.label(get_name(id)));
+            }
+
+            // Only used by loads, stores and the like so show it as data until something else starts
+            if reachable.data.contains(&(location as u16)) {
+                let after = location as u16 + 1..;
+                let data_end = [
+                    reachable.code.range(after.clone()).next(),
+                    reachable.data.range(after.clone()).next(),
+                    pos_to_labels.range(after).next().map(|(p, _)| p),
+                ]
+                .into_iter()
+                .flatten()
+                .map(|&p| p as usize)
+                .fold(end, usize::min);
+
+                print_data_region(
+                    location as u16,
+                    &mem[location.min(mem.len())..data_end.min(mem.len())],
+                );
+                location = data_end;
+                continue;
+            }
+
+            let DisassembledInstruction {
+                annotated_source,
+                text,
+                undecodable,
+                next_instruction_location,
+                ..
+            } = disassembler.decode(location as u16);
+            let next = next_instruction_location as usize;
+
+            // Instructions running past the segment or into a label are not real either
+            let runs_into_label = pos_to_labels
+                .range(location as u16 + 1..)
+                .next()
+                .is_some_and(|(&p, _)| (p as usize) < next);
+            if undecodable || next > end || runs_into_label {
+                data.push((location as u16, mem.get(location).copied().unwrap_or(0)));
+                location += 1;
+                continue;
+            }
+            print_data(&mut data);
+
+            source_lines.print(location as u16);
+            if show_relocations {
+                print_relocations(
+                    &relocs,
+                    location as u16..next_instruction_location,
+                    get_name,
+                    colors,
+                );
+            }
+            println!("{}", colored(&annotated_source, &text, colors));
+            location = next;
+        }
+        print_data(&mut data);
+        println!();
+    }
+}
+
+/// What to show of where the disassembled instructions came from
+#[derive(Debug, Clone, Copy)]
+struct LineDisplay {
+    numbers: bool,
+    source: bool,
+}
+
+/// Prints where an instruction came from when it is a different line than the one before
+struct SourceLines<'a> {
+    display: LineDisplay,
+    lines: &'a LineTable,
+    last_line: Option<(&'a str, u32)>,
+    /// Lines of the source files, none if the file could not be read
+    files: HashMap<&'a str, Option<Vec<String>>>,
+}
+
+impl<'a> SourceLines<'a> {
+    fn new(obj: &'a Object, display: LineDisplay) -> Self {
+        SourceLines {
+            display,
+            lines: &obj.lines,
+            last_line: None,
+            files: HashMap::new(),
+        }
+    }
+    fn print(&mut self, location: u16) {
+        if !self.display.numbers && !self.display.source {
+            return;
+        }
+        let line = self.lines.lookup(location);
+        if let Some((file, ln)) = line.filter(|_| line != self.last_line) {
+            if self.display.numbers {
+                println!("{file}:{ln}");
+            }
+            if self.display.source {
+                let contents =
+                    self.files
+                        .entry(file)
+                        .or_insert_with(|| match fs::read_to_string(file) {
+                            Ok(s) => Some(s.lines().map(str::to_owned).collect()),
+                            Err(e) => {
+                                eprintln!("could not read source file {file}: {e}");
+                                None
+                            }
+                        });
+                let text = contents
+                    .as_ref()
+                    .zip(ln.checked_sub(1))
+                    .and_then(|(c, i)| c.get(i as usize));
+                if let Some(text) = text {
+                    println!("{}", text.trim_end());
+                }
+            }
+        }
+        self.last_line = line;
+    }
+}
+
+/// Colors the instruction at the end of the annotated source
+fn colored(annotated_source: &str, text: &str, colors: Colors) -> String {
+    let address_and_bytes = &annotated_source[..annotated_source.len() - text.len()];
+    format!("{address_and_bytes}{}", colors.instruction(text))
+}
+
+fn print_relocations<'a>(
+    relocs: &BTreeMap<u16, (usize, RelocationKind)>,
+    range: Range<u16>,
+    get_name: impl Fn(usize) -> Cow<'a, str>,
+    colors: Colors,
+) {
+    for (&loc, &(sym, kind)) in relocs.range(range) {
+        let reloc = match kind {
+            RelocationKind::Wide => format!("RELOC: {} @ 0x{loc:02x}", get_name(sym)),
+            kind => format!("RELOC: {kind}({}) @ 0x{loc:02x}", get_name(sym)),
+        };
+        println!("    {}", colors.relocation(reloc));
+    }
+}
+
+/// Prints data as a string if it looks like one and otherwise as bytes
+fn print_data_region(location: u16, bytes: &[u8]) {
+    let text = bytes.strip_suffix(b"\0").unwrap_or(bytes);
+    let is_text = |&b: &u8| b.is_ascii_graphic() || b" \t\r\n".contains(&b);
+    if text.len() < 2 || !text.iter().all(is_text) {
+        let mut data = Vec::new();
+        for (i, &b) in bytes.iter().enumerate() {
+            data.push((location + i as u16, b));
+            if data.len() == 4 {
+                print_data(&mut data);
+            }
+        }
+        print_data(&mut data);
+        return;
+    }
+
+    print!("  {location:04x}: ");
+    for b in &bytes[..bytes.len().min(4)] {
+        print!(" {b:02x}");
+    }
+    for _ in bytes.len()..4 {
+        print!("   ");
+    }
+    print!("    .string ");
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'\t' => print!("\\t"),
+            b'\r' => print!("\\r"),
+            b'\n' => print!("\\n"),
+            b'\0' => print!("\\0"),
+            b'\\' => print!("\\\\"),
+            // Would get trimmed away by the assembler
+            b' ' if i == 0 || i == bytes.len() - 1 => print!("\\x20"),
+            b if b.is_ascii_graphic() || b == b' ' => print!("{}", b as char),
+            b => print!("\\x{b:02x}"),
+        }
+    }
+    println!();
+}
+
+/// Prints a run of bytes that could not be disassembled, lined up with the instructions
+fn print_data(data: &mut Vec<(u16, u8)>) {
+    let Some(&(addr, _)) = data.first() else {
+        return;
+    };
+    print!("  {addr:04x}: ");
+    for (_, b) in data.iter() {
+        print!(" {b:02x}");
+    }
+    for _ in data.len()..4 {
+        print!("   ");
+    }
+    let bytes: Vec<_> = data.iter().map(|(_, b)| format!("0x{b:02x}")).collect();
+    println!("    .byte {}", bytes.join(", "));
+    data.clear();
+}
@@ -0,0 +1,181 @@
+use std::{fmt, str::FromStr};
+
+/// A small regular expression: literals, `.`, `[abc]`, `[^a-z]`, `*`, `+`, `?`, `^`, `$` and `\` to escape
+///
+/// Unanchored like `grep`, so `main` matches every name containing it.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    start_anchored: bool,
+    end_anchored: bool,
+    pieces: Vec<(Atom, Repeat)>,
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Any,
+    Char(char),
+    /// Ranges and whether the class is negated
+    Class(Vec<(char, char)>, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    Once,
+    /// `?`
+    Optional,
+    /// `*`
+    Any,
+    /// `+`
+    AtLeastOnce,
+}
+
+#[derive(Debug)]
+pub struct PatternError(String);
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start_anchored, s) = match s.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (end_anchored, s) = match s.strip_suffix('$') {
+            Some(rest) if !rest.ends_with('\\') => (true, rest),
+            _ => (false, s),
+        };
+
+        let mut pieces: Vec<(Atom, Repeat)> = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '.' => Atom::Any,
+                '\\' => Atom::Char(
+                    chars
+                        .next()
+                        .ok_or_else(|| PatternError("trailing \\".to_owned()))?,
+                ),
+                '[' => parse_class(&mut chars)?,
+                '*' | '+' | '?' => {
+                    let Some((_, repeat @ Repeat::Once)) = pieces.last_mut() else {
+                        return Err(PatternError(format!(
+                            "{c} does not follow something to repeat"
+                        )));
+                    };
+                    *repeat = match c {
+                        '*' => Repeat::Any,
+                        '+' => Repeat::AtLeastOnce,
+                        _ => Repeat::Optional,
+                    };
+                    continue;
+                }
+                c => Atom::Char(c),
+            };
+            pieces.push((atom, Repeat::Once));
+        }
+
+        Ok(Pattern {
+            start_anchored,
+            end_anchored,
+            pieces,
+        })
+    }
+}
+
+fn parse_class(chars: &mut std::str::Chars) -> Result<Atom, PatternError> {
+    let unclosed = || PatternError("unclosed [".to_owned());
+
+    let mut negated = false;
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = chars.next().ok_or_else(unclosed)?;
+        match c {
+            '^' if first && !negated => {
+                negated = true;
+                continue;
+            }
+            ']' if !first => break,
+            c => {
+                let c = if c == '\\' {
+                    chars.next().ok_or_else(unclosed)?
+                } else {
+                    c
+                };
+                let mut lookahead = chars.clone();
+                match (lookahead.next(), lookahead.next()) {
+                    (Some('-'), Some(end)) if end != ']' => {
+                        *chars = lookahead;
+                        ranges.push((c, end));
+                    }
+                    _ => ranges.push((c, c)),
+                }
+            }
+        }
+        first = false;
+    }
+    Ok(Atom::Class(ranges, negated))
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Any => true,
+            &Atom::Char(a) => a == c,
+            Atom::Class(ranges, negated) => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+        }
+    }
+}
+
+impl Pattern {
+    pub fn is_match(&self, s: &str) -> bool {
+        let chars: Vec<char> = s.chars().collect();
+        if self.start_anchored {
+            return self.matches_at(&self.pieces, &chars);
+        }
+        (0..=chars.len()).any(|i| self.matches_at(&self.pieces, &chars[i..]))
+    }
+
+    fn matches_at(&self, pieces: &[(Atom, Repeat)], s: &[char]) -> bool {
+        let Some(((atom, repeat), rest)) = pieces.split_first() else {
+            return !self.end_anchored || s.is_empty();
+        };
+        let (min, max) = match repeat {
+            Repeat::Once => (1, 1),
+            Repeat::Optional => (0, 1),
+            Repeat::Any => (0, usize::MAX),
+            Repeat::AtLeastOnce => (1, usize::MAX),
+        };
+        let available = s.iter().take_while(|&&c| atom.matches(c)).count();
+        if available < min {
+            return false;
+        }
+        // Greedy, backing off one at a time
+        (min..=available.min(max))
+            .rev()
+            .any(|n| self.matches_at(rest, &s[n..]))
+    }
+}
+
+#[test]
+fn patterns() {
+    let p = |s: &str| s.parse::<Pattern>().unwrap();
+    assert!(p("main").is_match("_main_loop"));
+    assert!(!p("^main$").is_match("main_loop"));
+    assert!(p("^print_.*$").is_match("print_num"));
+    assert!(p("^[a-c]+[^0-9]?$").is_match("abcx"));
+    assert!(!p("^[a-c]+[^0-9]?$").is_match("abc1"));
+    assert!(p("a\\.b").is_match("a.b"));
+    assert!(!p("a\\.b").is_match("axb"));
+    assert!("*a".parse::<Pattern>().is_err());
+}
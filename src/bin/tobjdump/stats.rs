@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use telda2::{
+    aalv::obj::{Object, SegmentType, SymbolKind},
+    disassemble::Disassembler,
+};
+
+use crate::graph;
+
+/// Longest bar in the histogram
+const BAR_WIDTH: usize = 40;
+
+/// Prints how often each opcode is used by the reachable code and how much of each segment is code
+pub fn print_stats(obj: &Object) {
+    let mem = obj.get_flattened_memory();
+    let disassembler = Disassembler::new(&mem).show_bytes(false);
+    let reachable = graph::reachable(obj);
+
+    // Opcodes like 0x40 are different instructions depending on the operands, so count by mnemonic too
+    let mut usage: BTreeMap<(u8, String), usize> = BTreeMap::new();
+    let mut code_bytes = Vec::new();
+    for &location in &reachable.code {
+        let ins = disassembler.decode(location);
+        if ins.undecodable {
+            continue;
+        }
+        let mnemonic = ins.text.split(' ').next().unwrap_or_default().to_owned();
+        *usage.entry((mem[location as usize], mnemonic)).or_default() += 1;
+        code_bytes.push((
+            location,
+            ins.next_instruction_location.wrapping_sub(location),
+        ));
+    }
+
+    let mut usage: Vec<_> = usage.into_iter().collect();
+    usage.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then(a_key.cmp(b_key)));
+    let most = usage.first().map_or(1, |&(_, n)| n);
+    let total: usize = usage.iter().map(|&(_, n)| n).sum();
+
+    println!("opcode usage ({total} instructions):");
+    for ((opcode, mnemonic), n) in &usage {
+        let bar = "#".repeat((n * BAR_WIDTH).div_ceil(most));
+        println!("    0x{opcode:02x} {mnemonic:<6} {n:>6} {bar}");
+    }
+    println!();
+
+    println!("segment sizes:");
+    println!(
+        "    {:<10} {:>6} {:>6} {:>6}",
+        "segment", "code", "data", "total"
+    );
+    for (stype, &(start, ref bytes)) in &obj.segs {
+        let end = start as usize + bytes.len();
+        let code: usize = code_bytes
+            .iter()
+            .filter(|&&(loc, _)| start <= loc && (loc as usize) < end)
+            .map(|&(_, size)| size as usize)
+            .sum();
+        // Instructions at the end can only run past it in broken objects
+        let code = code.min(bytes.len());
+        println!(
+            "    {:<10} {code:>#6x} {:>#6x} {:>#6x}",
+            stype.to_string(),
+            bytes.len() - code,
+            bytes.len()
+        );
+    }
+    println!();
+}
+
+/// Lists functions, objects and global symbols from biggest to smallest
+///
+/// Symbols without a `.size` are assumed to go until the next listed symbol or the end of their segment.
+pub fn print_size_report(obj: &Object) {
+    let mut syms: Vec<_> = obj
+        .symbols
+        .0
+        .iter()
+        .filter(|s| s.segment_type != SegmentType::Unknown && !s.name.is_empty())
+        .filter(|s| s.is_global || matches!(s.kind, SymbolKind::Function | SymbolKind::Object))
+        .collect();
+    syms.sort_by_key(|s| (s.segment_type, s.location));
+
+    let mut sizes = Vec::new();
+    for (i, s) in syms.iter().enumerate() {
+        let size = match s.size {
+            Some(size) => (size as usize, true),
+            None => {
+                let Some(&(start, ref bytes)) = obj.segs.get(&s.segment_type) else {
+                    continue;
+                };
+                let end = syms[i + 1..]
+                    .iter()
+                    .find(|next| next.segment_type == s.segment_type && next.location > s.location)
+                    .map_or(start as usize + bytes.len(), |next| next.location as usize);
+                (end.saturating_sub(s.location as usize), false)
+            }
+        };
+        sizes.push((size, s));
+    }
+    sizes.sort_by(|((a, _), a_sym), ((b, _), b_sym)| b.cmp(a).then(a_sym.name.cmp(&b_sym.name)));
+
+    println!("symbol sizes (~ when estimated from the next symbol):");
+    for ((size, exact), s) in sizes {
+        let size = if exact {
+            format!("{size:#x}")
+        } else {
+            format!("~{size:#x}")
+        };
+        let kind = match s.kind {
+            SymbolKind::NoType => String::new(),
+            kind => kind.to_string(),
+        };
+        println!(
+            "    {size:>8} {:<8} {:<8} {}",
+            s.segment_type.to_string(),
+            kind,
+            s.name
+        );
+    }
+    println!();
+}
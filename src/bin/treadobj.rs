@@ -0,0 +1,107 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use clap::Parser;
+
+use telda2::aalv::{obj::KNOWN_SECTIONS, AalvReader, SECTION_COMPRESSED};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+/// Dumps every section of the álvur containers in a file, also ones telda2 does not know
+struct Cli {
+    /// Object file or archive
+    input_file: PathBuf,
+
+    /// How many bytes of each section's payload to show
+    #[arg(short = 'n', long, default_value_t = 32)]
+    preview: usize,
+}
+
+fn main() -> ExitCode {
+    let Cli {
+        input_file,
+        preview,
+    } = Cli::parse();
+
+    match dump(&input_file, preview) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("could not read {}: {e}", input_file.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Dumps the containers one after another, as archives are just concatenated objects
+fn dump(path: &PathBuf, preview: usize) -> io::Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    while !file.fill_buf()?.is_empty() {
+        let mut aalvur = AalvReader::new(&mut file)?;
+        println!(
+            "álvur{} at {:#x} to {:#x}, features {:#010x}:",
+            aalvur.version, aalvur.file_offset, aalvur.end_offset, aalvur.features
+        );
+
+        let headers: Vec<_> = aalvur
+            .section_headers()
+            .map(|(name, pos, size, flags)| (name.to_owned(), pos, size, flags))
+            .collect();
+        for (i, (name, pos, size, flags)) in headers.into_iter().enumerate() {
+            print!("  {i:>3} {name:<10} at {pos:#06x}, {size:#x} bytes");
+            if flags & SECTION_COMPRESSED != 0 {
+                print!(", compressed");
+            }
+            if name.starts_with('_') && !KNOWN_SECTIONS.contains(&&*name) {
+                print!(" (unknown)");
+            }
+            println!();
+
+            match aalvur.read_payload(i, preview) {
+                Ok((payload, len)) => {
+                    if flags & SECTION_COMPRESSED != 0 {
+                        println!("        {len:#x} bytes uncompressed");
+                    }
+                    hex_preview(&payload);
+                    if len > payload.len() {
+                        println!("        ... {:#x} more bytes", len - payload.len());
+                    }
+                }
+                Err(e) => println!("        could not read payload: {e}"),
+            }
+        }
+        println!();
+
+        aalvur.seek_to_end()?;
+    }
+
+    Ok(())
+}
+
+/// Prints bytes 16 a line with their offset in the payload, in hex and as ASCII
+fn hex_preview(bytes: &[u8]) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        print!("        {:04x}: ", i * 16);
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => print!(" {b:02x}"),
+                None => print!("   "),
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("  |{ascii}|");
+    }
+}
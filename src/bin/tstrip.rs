@@ -2,7 +2,7 @@ use std::{path::PathBuf, process::ExitCode};
 
 use telda2::{
     self,
-    aalv::obj::{Object, RelocationTable},
+    aalv::obj::{LineTable, Object, RelocationTable},
 };
 
 use clap::Parser;
@@ -40,7 +40,9 @@ fn main() -> ExitCode {
         }
         obj.relocation_table = RelocationTable::default();
         obj.symbols.0.clear();
+        obj.lines = LineTable::default();
     } else {
+        obj.lines = LineTable::default();
         obj.symbols.mutate(|name, &mut is_global, _, _| {
             if !is_global {
                 *name = "".into()
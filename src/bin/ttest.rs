@@ -0,0 +1,316 @@
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use telda2::{aalv::obj::Object, cpu::TrapMode, devices, machine::Machine, mem::Io};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+/// Runs binaries without a terminal and checks what they output
+///
+/// Each test is a binary, like tests/hello.to, with files next to it named after it:
+/// tests/hello.in is given to the program as input, tests/hello.out is the output it has to write
+/// and tests/hello.exit says how it has to end, an exit code to halt with (0 by default) or a trap like `zerodiv`.
+/// Any of them can be left out.
+struct Cli {
+    /// Binaries to test
+    #[arg(required = true)]
+    tests: Vec<PathBuf>,
+
+    /// How to report the results
+    #[arg(long, value_enum, default_value_t = Format::Tap)]
+    format: Format,
+
+    /// Fails a test that has not ended after this many seconds
+    #[arg(long, value_name = "SECS", default_value = "10", value_parser = parse_timeout)]
+    timeout: Duration,
+
+    /// Fails a test that has not ended after this many instructions
+    #[arg(long, value_name = "N")]
+    max_steps: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// The Test Anything Protocol
+    Tap,
+    /// A JSON object on each line for each test
+    Json,
+}
+
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("{s} is not a number of seconds"))
+}
+
+/// Gives the program its scripted input and keeps what it outputs
+struct Scripted {
+    input: Vec<u8>,
+    read: usize,
+    output: Vec<u8>,
+}
+
+impl Io for Scripted {
+    fn read(&mut self, _addr: u8) -> u8 {
+        let b = self.input.get(self.read).copied().unwrap_or(0);
+        self.read += 1;
+        b
+    }
+    fn write(&mut self, _addr: u8, val: u8) {
+        self.output.push(val);
+    }
+}
+
+/// How a program stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum End {
+    /// Halted with the exit code
+    Exit(u8),
+    Trap(TrapMode),
+    Steps(u64),
+    Timeout(Duration),
+}
+
+/// What running a test found
+struct Outcome {
+    end: End,
+    output: Vec<u8>,
+    steps: u64,
+    cycles: u64,
+    failures: Vec<String>,
+}
+
+/// The file next to the test binary with the extension
+fn companion(test: &Path, extension: &str) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(test.with_extension(extension)) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// An exit code or the name of a trap mode
+fn parse_end(s: &str) -> Option<End> {
+    if let Ok(code) = s.parse() {
+        return Some(End::Exit(code));
+    }
+    match (0..=u8::MAX)
+        .filter_map(TrapMode::from_u8)
+        .find(|tm| format!("{tm:?}").eq_ignore_ascii_case(s))?
+    {
+        TrapMode::Halt => Some(End::Exit(0)),
+        tm => Some(End::Trap(tm)),
+    }
+}
+
+fn run_test(test: &Path, timeout: Duration, max_steps: Option<u64>) -> Result<Outcome, String> {
+    let mut obj = Object::from_file(test).map_err(|e| format!("cannot read binary: {e}"))?;
+    if obj.entry.is_none() {
+        return Err("no entry point in binary".to_owned());
+    }
+    let read = |extension| {
+        companion(test, extension).map_err(|e| format!("cannot read .{extension} file: {e}"))
+    };
+    let input = read("in")?.unwrap_or_default();
+    let expected = read("out")?;
+    let expected_end = match read("exit")? {
+        Some(bytes) => {
+            let s = String::from_utf8_lossy(&bytes);
+            parse_end(s.trim()).ok_or_else(|| {
+                format!("{} in the .exit file is not an exit code or trap", s.trim())
+            })?
+        }
+        None => End::Exit(0),
+    };
+
+    let mut bus = devices::standard(Scripted {
+        input,
+        read: 0,
+        output: Vec::new(),
+    });
+    let linked_banks = std::mem::take(&mut obj.banks);
+    if let Some(&last) = linked_banks.keys().last() {
+        let mut contents = vec![Vec::new(); last as usize + 1];
+        for (n, bytes) in linked_banks {
+            contents[n as usize] = bytes;
+        }
+        bus.attach(devices::BANK_PORT, 2, devices::Banks::new(contents));
+    }
+    let mut machine = Machine::with_io(obj, bus);
+
+    let deadline = Instant::now() + timeout;
+    let mut steps = 0;
+    let end = loop {
+        if max_steps.is_some_and(|max| steps >= max) {
+            break End::Steps(steps);
+        }
+        // Looking at the clock every step would slow things down
+        if steps.is_multiple_of(1024) && Instant::now() >= deadline {
+            break End::Timeout(timeout);
+        }
+        steps += 1;
+        match machine.step() {
+            Err(TrapMode::Halt) => break End::Exit(machine.registers().exit_code),
+            Err(tm) => break End::Trap(tm),
+            Ok(_) => (),
+        }
+    };
+
+    let cycles = machine.cycles();
+    let Scripted {
+        input,
+        read,
+        output,
+    } = machine.io().io();
+    let mut failures = Vec::new();
+    match end {
+        end if end == expected_end => (),
+        End::Exit(code) => failures.push(format!(
+            "exited with {code} instead of {}",
+            describe(expected_end)
+        )),
+        End::Trap(tm) => failures.push(format!(
+            "ended with {tm:?} instead of {}",
+            describe(expected_end)
+        )),
+        End::Steps(n) => failures.push(format!("stopped after {n} steps")),
+        End::Timeout(t) => failures.push(format!("timed out after {t:?}")),
+    }
+    if *read > input.len() {
+        failures.push(format!(
+            "read {} bytes past the end of its input",
+            *read - input.len()
+        ));
+    }
+    if expected.is_some_and(|expected| expected != *output) {
+        failures.push("output differs from the .out file".to_owned());
+    }
+
+    Ok(Outcome {
+        end,
+        output: output.clone(),
+        steps,
+        cycles,
+        failures,
+    })
+}
+
+/// How the test was expected to end, for failures
+fn describe(end: End) -> String {
+    match end {
+        End::Exit(code) => format!("exiting with {code}"),
+        End::Trap(tm) => format!("{tm:?}"),
+        End::Steps(_) | End::Timeout(_) => unreachable!("tests are not expected to hit limits"),
+    }
+}
+
+fn main() -> ExitCode {
+    let Cli {
+        tests,
+        format,
+        timeout,
+        max_steps,
+    } = Cli::parse();
+
+    if format == Format::Tap {
+        println!("TAP version 13");
+        println!("1..{}", tests.len());
+    }
+    let mut all_passed = true;
+    for (n, test) in tests.iter().enumerate() {
+        let outcome = run_test(test, timeout, max_steps);
+        let passed = outcome.as_ref().is_ok_and(|o| o.failures.is_empty());
+        all_passed &= passed;
+        match format {
+            Format::Tap => report_tap(n + 1, test, passed, &outcome),
+            Format::Json => report_json(test, passed, &outcome),
+        }
+    }
+
+    if all_passed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn report_tap(n: usize, test: &Path, passed: bool, outcome: &Result<Outcome, String>) {
+    let ok = if passed { "ok" } else { "not ok" };
+    println!("{ok} {n} - {}", test.display());
+    match outcome {
+        Ok(o) if !passed => {
+            println!("  ---");
+            println!("  message: {}", json_string(&o.failures.join(", ")));
+            println!(
+                "  output: {}",
+                json_string(&String::from_utf8_lossy(&o.output))
+            );
+            println!("  steps: {}", o.steps);
+            println!("  ...");
+        }
+        Ok(_) => (),
+        Err(e) => {
+            println!("  ---");
+            println!("  message: {}", json_string(e));
+            println!("  ...");
+        }
+    }
+}
+
+fn report_json(test: &Path, passed: bool, outcome: &Result<Outcome, String>) {
+    let mut line = format!(
+        "{{\"test\":{},\"passed\":{passed}",
+        json_string(&test.display().to_string())
+    );
+    match outcome {
+        Ok(o) => {
+            let end = match o.end {
+                End::Exit(_) => "Halt".to_owned(),
+                End::Trap(tm) => format!("{tm:?}"),
+                End::Steps(_) => "max-steps".to_owned(),
+                End::Timeout(_) => "timeout".to_owned(),
+            };
+            let _ = write!(line, ",\"end\":{}", json_string(&end));
+            if let End::Exit(code) = o.end {
+                let _ = write!(line, ",\"exit_code\":{code}");
+            }
+            let failures: Vec<_> = o.failures.iter().map(|f| json_string(f)).collect();
+            let _ = write!(
+                line,
+                ",\"output\":{},\"steps\":{},\"cycles\":{},\"failures\":[{}]",
+                json_string(&String::from_utf8_lossy(&o.output)),
+                o.steps,
+                o.cycles,
+                failures.join(",")
+            );
+        }
+        Err(e) => {
+            let _ = write!(line, ",\"error\":{}", json_string(e));
+        }
+    }
+    line.push('}');
+    println!("{line}");
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,5 +1,5 @@
 use crate::{
-    isa::OP_HANDLERS,
+    isa::{execute, Instruction},
     mem::{Memory, IO_MAPPING_CUTOFF},
     U4,
 };
@@ -19,23 +19,99 @@ impl Cpu {
         }
     }
     pub fn run_instruction(&mut self, mem: &mut dyn Memory) -> Result<(), TrapMode> {
-        let opcode = mem.read(self.registers.program_counter);
-        self.registers.program_counter += 1;
-
-        OP_HANDLERS[opcode as usize](&mut self.registers, mem);
-
-        if self.registers.trap {
-            if self.registers.trap_handler == 0 {
-                return Err(self.registers.trap_mode);
-            } else {
-                Self::push_registers(&mut self.registers, mem);
-                self.registers.program_counter = self.registers.trap_handler;
-                self.registers
-                    .write_wide(R1, self.registers.trap_mode as u8 as u16);
+        self.step(mem).map(|_| ())
+    }
+    /// Runs one instruction, telling whether it went to the trap handler
+    ///
+    /// Errs with the trap mode if it trapped without a trap handler.
+    pub fn step(&mut self, mem: &mut dyn Memory) -> Result<Event, TrapMode> {
+        self.step_with(mem, &mut |_, _| ())
+    }
+    /// Like `step`, but shows each decoded instruction and its location to `on_instruction` before it is executed
+    pub fn step_with(
+        &mut self,
+        mem: &mut dyn Memory,
+        on_instruction: &mut dyn FnMut(u16, &Instruction),
+    ) -> Result<Event, TrapMode> {
+        let pc = self.registers.program_counter;
+        let (ins, size) = mem.fetch_instruction(pc);
+        self.registers.program_counter = pc.wrapping_add(size);
+        let handling = self.registers.trap;
+
+        if let Some(tm) = mem.take_fault() {
+            self.registers.trap(tm);
+        } else {
+            match ins {
+                Some(ins) => {
+                    on_instruction(pc, &ins);
+                    execute(ins, &mut self.registers, mem)
+                }
+                None => self.registers.trap(TrapMode::Invalid),
+            }
+            if let Some(tm) = mem.take_fault() {
+                self.registers.trap(tm);
             }
         }
 
-        Ok(())
+        if let Some(vector) = self.registers.trapped.take() {
+            let tm = self.registers.trap_mode;
+            if self.enter_vector(vector, mem) {
+                return Ok(Event::TrapHandled(tm));
+            }
+            // A trap in the trap handler cannot be handled either
+            if self.registers.trap_handler == 0 || handling {
+                return Err(tm);
+            }
+            self.enter_trap_handler(mem);
+            return Ok(Event::TrapHandled(tm));
+        }
+
+        Ok(Event::Executed)
+    }
+    /// Interrupts the program with the vector, as if the next instruction trapped with `TrapMode::Interrupt`
+    ///
+    /// Goes to the vector's entry in the vector table, or to the trap handler if it has none.
+    /// Does nothing and gives false if interrupts are disabled, or if it would go to the trap handler
+    /// while there is none or it is already handling a trap, so the interrupt has to stay pending.
+    pub fn interrupt(&mut self, mem: &mut dyn Memory, vector: u8) -> bool {
+        if !self.registers.interrupt_enable {
+            return false;
+        }
+        if self.enter_vector(vector, mem) {
+            return true;
+        }
+        if self.registers.trap_handler == 0 || self.registers.trap {
+            return false;
+        }
+        self.registers.trap_mode = TrapMode::Interrupt;
+        self.enter_trap_handler(mem);
+        true
+    }
+    /// Goes to the entry of the vector in the vector table if it has one
+    ///
+    /// Pushes the program counter and the flags, which `reti` pops again, and disables interrupts.
+    fn enter_vector(&mut self, vector: u8, mem: &mut dyn Memory) -> bool {
+        let table = self.registers.interrupt_vectors;
+        if table == 0 {
+            return false;
+        }
+        let entry = mem.read_wide(table.wrapping_add(2 * vector as u16));
+        if entry == 0 {
+            return false;
+        }
+        let r = &mut self.registers;
+        Self::pushw(r, r.program_counter, mem);
+        Self::pushw(r, r.flags(), mem);
+        r.interrupt_enable = false;
+        r.program_counter = entry;
+        true
+    }
+    fn enter_trap_handler(&mut self, mem: &mut dyn Memory) {
+        self.registers.trap = true;
+        Self::push_registers(&mut self.registers, mem);
+        self.registers.program_counter = self.registers.trap_handler;
+        self.registers
+            .write_wide(R1, self.registers.trap_mode as u8 as u16);
     }
     /// Until unhandled trap
     pub fn run_until_abort(&mut self, mem: &mut dyn Memory) -> TrapMode {
@@ -65,20 +141,10 @@ impl Cpu {
         registers.stack += 1;
         b
     }
+    /// Pushes the program counter, the flags and then `r1` to `rh`
     pub fn push_registers<M: ?Sized + Memory>(registers: &mut Registers, mem: &mut M) {
-        let Registers {
-            zero,
-            sign,
-            overflow,
-            carry,
-            ..
-        } = *registers;
-
-        let flags = ((zero as u16) << 7)
-            | ((overflow as u16) << 6)
-            | ((sign as u16) << 5)
-            | ((carry as u16) << 4);
-        Self::pushw(registers, flags, mem);
+        Self::pushw(registers, registers.program_counter, mem);
+        Self::pushw(registers, registers.flags(), mem);
         for r in 1..=15 {
             let w = registers.read_wide(WideRegister(U4::new(r)));
             Self::pushw(registers, w, mem);
@@ -90,14 +156,19 @@ impl Cpu {
             registers.write_wide(WideRegister(U4::new(r)), w);
         }
         let flags = Self::popw(registers, mem);
-
-        registers.zero = flags & 0b1000_0000 != 0;
-        registers.overflow = flags & 0b0100_0000 != 0;
-        registers.sign = flags & 0b0010_0000 != 0;
-        registers.carry = flags & 0b0001_0000 != 0;
+        registers.set_flags(flags);
+        registers.program_counter = Self::popw(registers, mem);
     }
 }
 
+/// What happened when an instruction ran without stopping the machine
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    Executed,
+    /// It trapped and went to the trap handler
+    TrapHandled(TrapMode),
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub enum TrapMode {
@@ -111,8 +182,30 @@ pub enum TrapMode {
     IllegalWrite = 0x12,
     IllegalExecute = 0x13,
     IllegalHandlerReturn = 0x1f,
+    /// A device raised its interrupt line, see `Io::interrupt_pending`
+    Interrupt = 0x20,
+}
+
+impl TrapMode {
+    /// The trap mode with the number, as the trap handler gets it in `r1`
+    pub const fn from_u8(n: u8) -> Option<Self> {
+        Some(match n {
+            0 => TrapMode::Invalid,
+            0x5 => TrapMode::SysCall,
+            0x8 => TrapMode::ZeroDiv,
+            0xa => TrapMode::Halt,
+            0x10 => TrapMode::IllegalOperation,
+            0x11 => TrapMode::IllegalRead,
+            0x12 => TrapMode::IllegalWrite,
+            0x13 => TrapMode::IllegalExecute,
+            0x1f => TrapMode::IllegalHandlerReturn,
+            0x20 => TrapMode::Interrupt,
+            _ => return None,
+        })
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Registers {
     general_purposes: [u8; 20],
 
@@ -124,11 +217,22 @@ pub struct Registers {
     /// Zero means no trap handler
     pub trap_handler: u16,
     pub trap_mode: TrapMode,
+    /// Set while the trap handler runs
     pub trap: bool,
+    /// The vector of the trap the instruction running caused, if it did
+    trapped: Option<u8>,
+    /// Where the vector table is, zero means there is none
+    ///
+    /// It has a wide for each vector, with the location to go to or zero to fall back to the trap handler.
+    pub interrupt_vectors: u16,
+    /// Whether devices can interrupt, cleared when entering a vector
+    pub interrupt_enable: bool,
     pub zero: bool,
     pub sign: bool,
     pub overflow: bool,
     pub carry: bool,
+    /// What the program exits with, set when it halts
+    pub exit_code: u8,
 }
 
 impl Registers {
@@ -143,12 +247,16 @@ impl Registers {
             frame: IO_MAPPING_CUTOFF,
             stack: IO_MAPPING_CUTOFF,
             trap: false,
+            trapped: None,
+            interrupt_vectors: 0,
+            interrupt_enable: false,
             trap_handler: 0,
             trap_mode: TrapMode::default(),
             zero: false,
             sign: false,
             overflow: false,
             carry: false,
+            exit_code: 0,
         }
     }
     pub fn read_byte(&self, r: ByteRegister) -> u8 {
@@ -218,8 +326,71 @@ impl Registers {
             _ => unimplemented!("no such register"),
         }
     }
+    /// Reads the 32 bits of the register pair with `r` as the upper half and the next register as the lower one
+    ///
+    /// `r0` is the zero pair instead of `r0:r1`.
+    pub fn read_long(&self, r: WideRegister) -> u32 {
+        if r == R0 {
+            return 0;
+        }
+        let lower = WideRegister(U4::new(u8::from(r.0) + 1));
+        (self.read_wide(r) as u32) << 16 | self.read_wide(lower) as u32
+    }
+    /// Writes the register pair `read_long` reads, writing nothing to the zero pair
+    pub fn write_long(&mut self, r: WideRegister, val: u32) {
+        if r == R0 {
+            return;
+        }
+        let lower = WideRegister(U4::new(u8::from(r.0) + 1));
+        self.write_wide(r, (val >> 16) as u16);
+        self.write_wide(lower, val as u16);
+    }
+    /// Traps with the trap mode as the vector
     pub fn trap(&mut self, trap_mode: TrapMode) {
-        self.trap = true;
+        self.trapped = Some(trap_mode as u8);
         self.trap_mode = trap_mode;
     }
+    /// Traps with the vector like `int` does, `TrapMode::SysCall` if it goes to the trap handler
+    pub fn software_interrupt(&mut self, vector: u8) {
+        self.trapped = Some(vector);
+        self.trap_mode = TrapMode::SysCall;
+    }
+
+    /// The flags as pushed by traps and interrupts: zero in bit 7, then overflow, sign, carry and interrupt enable
+    pub fn flags(&self) -> u16 {
+        ((self.zero as u16) << 7)
+            | ((self.overflow as u16) << 6)
+            | ((self.sign as u16) << 5)
+            | ((self.carry as u16) << 4)
+            | ((self.interrupt_enable as u16) << 3)
+    }
+    pub fn set_flags(&mut self, flags: u16) {
+        self.zero = flags & 0b1000_0000 != 0;
+        self.overflow = flags & 0b0100_0000 != 0;
+        self.sign = flags & 0b0010_0000 != 0;
+        self.carry = flags & 0b0001_0000 != 0;
+        self.interrupt_enable = flags & 0b0000_1000 != 0;
+    }
+}
+
+#[test]
+fn wrap_around() {
+    use crate::mem::{Area, AreaKind, Lazy, MemoryMap};
+
+    let mut mem = Lazy::new_panicking(alloc::vec![0; 0x10000]);
+    mem.map = MemoryMap::new(alloc::vec![Area {
+        start: 0,
+        end: 0x10000,
+        kind: AreaKind::Ram,
+    }])
+    .unwrap();
+    // ldi r1, 0x1234 straddling the end of memory, then halt
+    mem.mem[0xfffe..].copy_from_slice(&[0x40, 0x10]);
+    mem.mem[..3].copy_from_slice(&[0x34, 0x12, 0x0a]);
+
+    let mut cpu = Cpu::new(0xfffe);
+    cpu.run_instruction(&mut mem).unwrap();
+    assert_eq!(cpu.registers.read_wide(R1), 0x1234);
+    assert_eq!(cpu.registers.program_counter, 2);
+    assert_eq!(cpu.run_instruction(&mut mem), Err(TrapMode::Halt));
 }
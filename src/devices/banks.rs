@@ -0,0 +1,140 @@
+use std::io::{self, ErrorKind, Read};
+
+use crate::mem::Io;
+
+/// Where the selected bank shows up in memory
+pub const BANK_WINDOW: u16 = 0x8000;
+/// How big a bank, and so the window, is
+pub const BANK_SIZE: u16 = 0x4000;
+
+/// Banks of memory of which the selected one is in the window at `BANK_WINDOW`, for more memory than fits in the
+/// address space
+///
+/// Its ports are:
+///
+/// - 0: the selected bank, which is swapped into the window after the instruction that selected it.
+///   Selecting a bank there is none of does nothing
+/// - 1: reading it gives how many banks there are
+///
+/// Writes to the window go to the selected bank, they are still there when it is selected again.
+#[derive(Debug, Clone)]
+pub struct Banks {
+    banks: Vec<Vec<u8>>,
+    selected: u8,
+    /// The bank that is in the window
+    shown: Option<u8>,
+}
+
+impl Banks {
+    /// Banks starting with the contents, which are at most `BANK_SIZE` long
+    pub fn new(contents: Vec<Vec<u8>>) -> Self {
+        let banks = contents
+            .into_iter()
+            .map(|mut bank| {
+                bank.resize(BANK_SIZE as usize, 0);
+                bank
+            })
+            .collect();
+        Banks {
+            banks,
+            selected: 0,
+            shown: None,
+        }
+    }
+    pub fn selected(&self) -> u8 {
+        self.selected
+    }
+}
+
+impl Io for Banks {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.selected,
+            1 => self.banks.len() as u8,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        if addr == 0 && (val as usize) < self.banks.len() {
+            self.selected = val;
+        }
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        if self.shown == Some(self.selected) || self.banks.is_empty() {
+            return;
+        }
+        let window = BANK_WINDOW as usize..(BANK_WINDOW + BANK_SIZE) as usize;
+        if memory.len() < window.end {
+            memory.resize(window.end, 0);
+        }
+        if let Some(shown) = self.shown {
+            self.banks[shown as usize].copy_from_slice(&memory[window.clone()]);
+        }
+        memory[window].copy_from_slice(&self.banks[self.selected as usize]);
+        self.shown = Some(self.selected);
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        // The shown bank is saved with the memory
+        state.extend((self.banks.len() as u16).to_le_bytes());
+        state.push(self.selected);
+        state.push(self.shown.unwrap_or(0xff));
+        for (n, bank) in self.banks.iter().enumerate() {
+            if self.shown != Some(n as u8) {
+                state.extend(bank);
+            }
+        }
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 4];
+        state.read_exact(&mut buf)?;
+        let [c0, c1, selected, shown] = buf;
+        let count = u16::from_le_bytes([c0, c1]) as usize;
+        if count != self.banks.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "state is of {count} banks but there are {}",
+                    self.banks.len()
+                ),
+            ));
+        }
+        let shown = (shown != 0xff).then_some(shown);
+        if selected as usize >= count || shown.is_some_and(|s| s as usize >= count) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "selected bank does not exist",
+            ));
+        }
+        for (n, bank) in self.banks.iter_mut().enumerate() {
+            if shown != Some(n as u8) {
+                state.read_exact(bank)?;
+            }
+        }
+        self.selected = selected;
+        self.shown = shown;
+        Ok(())
+    }
+}
+
+#[test]
+fn banks() {
+    let mut banks = Banks::new(vec![vec![1, 2], vec![3]]);
+    let mut memory = Vec::new();
+    assert_eq!(banks.read(1), 2);
+
+    // Bank 0 is shown at first
+    banks.dma(&mut memory);
+    assert_eq!(memory.len(), (BANK_WINDOW + BANK_SIZE) as usize);
+    assert_eq!(memory[BANK_WINDOW as usize..][..3], [1, 2, 0]);
+
+    // Writes stay in their bank
+    memory[BANK_WINDOW as usize] = 9;
+    banks.write(0, 1);
+    banks.write(0, 2);
+    assert_eq!(banks.read(0), 1);
+    banks.dma(&mut memory);
+    assert_eq!(memory[BANK_WINDOW as usize], 3);
+    banks.write(0, 0);
+    banks.dma(&mut memory);
+    assert_eq!(memory[BANK_WINDOW as usize..][..2], [9, 2]);
+}
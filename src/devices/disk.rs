@@ -0,0 +1,165 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::mem::{Io, IO_MAPPING_CUTOFF};
+
+/// Storage in sectors of `SECTOR_SIZE` bytes, backed by a host file, copied to and from memory on command
+///
+/// Its ports are:
+///
+/// - 0 and 1: the sector, lower byte first
+/// - 2 and 3: the location of the sector's bytes in memory, lower byte first
+/// - 4: writing `1` reads the sector into memory and `2` writes memory to the sector, after the instruction.
+///   Reading it gives whether the last command failed in bit 0
+///
+/// Sectors past the end of the file read as zeroes, writing them grows the file.
+#[derive(Debug)]
+pub struct Disk<F> {
+    file: F,
+    sector: u16,
+    buffer: u16,
+    command: Option<Command>,
+    failed: bool,
+}
+
+const SECTOR_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    Read,
+    Write,
+}
+
+impl<F: Read + Write + Seek> Disk<F> {
+    pub const SECTOR_SIZE: usize = SECTOR_SIZE;
+
+    pub fn new(file: F) -> Self {
+        Disk {
+            file,
+            sector: 0,
+            buffer: 0,
+            command: None,
+            failed: false,
+        }
+    }
+
+    fn run(&mut self, command: Command, memory: &mut Vec<u8>) -> io::Result<()> {
+        let start = self.buffer as usize;
+        // Bytes that would be in the I/O mapping or past the end of memory are left out
+        let end = (start + Self::SECTOR_SIZE).min(IO_MAPPING_CUTOFF as usize);
+        let mut sector = [0; SECTOR_SIZE];
+        self.file.seek(SeekFrom::Start(
+            self.sector as u64 * Self::SECTOR_SIZE as u64,
+        ))?;
+        match command {
+            Command::Read => {
+                let mut read = 0;
+                while read < sector.len() {
+                    match self.file.read(&mut sector[read..])? {
+                        0 => break,
+                        n => read += n,
+                    }
+                }
+                if start < end {
+                    if memory.len() < end {
+                        memory.resize(end, 0);
+                    }
+                    memory[start..end].copy_from_slice(&sector[..end - start]);
+                }
+            }
+            Command::Write => {
+                for (i, addr) in (start..end).enumerate() {
+                    sector[i] = memory.get(addr).copied().unwrap_or(0);
+                }
+                self.file.write_all(&sector)?;
+                self.file.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F: Read + Write + Seek> Io for Disk<F> {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.sector.to_le_bytes()[0],
+            1 => self.sector.to_le_bytes()[1],
+            2 => self.buffer.to_le_bytes()[0],
+            3 => self.buffer.to_le_bytes()[1],
+            4 => self.failed as u8,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        let set = |word: &mut u16, higher: bool| {
+            let mut bytes = word.to_le_bytes();
+            bytes[higher as usize] = val;
+            *word = u16::from_le_bytes(bytes);
+        };
+        match addr {
+            0 | 1 => set(&mut self.sector, addr == 1),
+            2 | 3 => set(&mut self.buffer, addr == 3),
+            4 => match val {
+                1 => self.command = Some(Command::Read),
+                2 => self.command = Some(Command::Write),
+                _ => self.failed = true,
+            },
+            _ => (),
+        }
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        if let Some(command) = self.command.take() {
+            self.failed = self.run(command, memory).is_err();
+        }
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend(self.sector.to_le_bytes());
+        state.extend(self.buffer.to_le_bytes());
+        state.push(self.failed as u8);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 5];
+        state.read_exact(&mut buf)?;
+        let [s0, s1, b0, b1, failed] = buf;
+        self.sector = u16::from_le_bytes([s0, s1]);
+        self.buffer = u16::from_le_bytes([b0, b1]);
+        self.failed = failed != 0;
+        Ok(())
+    }
+}
+
+#[test]
+fn disk() {
+    use std::io::Cursor;
+
+    let mut disk = Disk::new(Cursor::new(Vec::new()));
+    let mut memory = vec![0; 0x20];
+    memory[0x10..0x14].copy_from_slice(b"data");
+
+    // Writing sector 2 from 0x10 grows the file
+    disk.write(0, 2);
+    disk.write(2, 0x10);
+    disk.write(4, 2);
+    disk.dma(&mut memory);
+    assert_eq!(disk.read(4), 0);
+    let file = disk.file.get_ref();
+    assert_eq!(file.len(), 3 * 256);
+    assert_eq!(&file[512..516], b"data");
+
+    // Reading it back to 0x1000 grows the memory
+    disk.write(2, 0x00);
+    disk.write(3, 0x10);
+    disk.write(4, 1);
+    disk.dma(&mut memory);
+    assert_eq!(memory.len(), 0x1100);
+    assert_eq!(&memory[0x1000..0x1004], b"data");
+
+    // Sectors past the end read as zeroes
+    disk.write(0, 9);
+    disk.write(4, 1);
+    disk.dma(&mut memory);
+    assert_eq!(disk.read(4), 0);
+    assert!(memory[0x1000..0x1100].iter().all(|&b| b == 0));
+
+    disk.write(4, 7);
+    assert_eq!(disk.read(4), 1);
+}
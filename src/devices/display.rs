@@ -0,0 +1,174 @@
+use std::io::{self, Read, Write};
+
+use crate::mem::Io;
+
+/// A text display of `COLUMNS` by `ROWS` characters, drawn from a buffer in memory when the program asks for it
+///
+/// Its ports are:
+///
+/// - 0 and 1: the location of the buffer, lower byte first. It has a byte for each character, row by row
+/// - 2: writing draws the buffer as it is after the instruction, with bit 1 of what is written letting it raise its
+///   interrupt line every time a frame ends. Reading gives how many frames ended since it was last read, up to 255,
+///   acknowledging the interrupt
+///
+/// It draws to a terminal, replacing the last frame. Frames end every [`FRAME_CYCLES`](Self::FRAME_CYCLES) cycles
+/// unless [`set_frame_cycles`](Self::set_frame_cycles) says otherwise, whether anything is drawn or not, so that game
+/// loops can wait for the next one to keep a steady pace.
+#[derive(Debug)]
+pub struct Display<W> {
+    out: W,
+    buffer: u16,
+    show: bool,
+    drawn: u64,
+    interrupt_enable: bool,
+    frame_cycles: u64,
+    /// Cycles until the frame ends
+    remaining: u64,
+    ended: u8,
+}
+
+const INTERRUPT_ENABLE: u8 = 0b10;
+
+impl<W: Write> Display<W> {
+    pub const COLUMNS: u16 = 40;
+    pub const ROWS: u16 = 25;
+    /// How long a frame is unless set otherwise, 60 frames a second of a 1 MHz machine
+    pub const FRAME_CYCLES: u64 = 1_000_000 / 60;
+
+    pub fn new(out: W) -> Self {
+        Display {
+            out,
+            buffer: 0,
+            show: false,
+            drawn: 0,
+            interrupt_enable: false,
+            frame_cycles: Self::FRAME_CYCLES,
+            remaining: Self::FRAME_CYCLES,
+            ended: 0,
+        }
+    }
+    /// Makes frames `cycles` long, like the clock speed over 60 for 60 frames a second
+    pub fn set_frame_cycles(&mut self, cycles: u64) {
+        self.frame_cycles = cycles.max(1);
+        self.remaining = self.frame_cycles;
+    }
+
+    /// The characters of the buffer at `buffer` in memory, a line for each row
+    ///
+    /// Bytes that are not printable ASCII are spaces, as is memory not yet used.
+    pub fn render(memory: &[u8], buffer: u16) -> String {
+        let mut text = String::new();
+        for row in 0..Self::ROWS {
+            for column in 0..Self::COLUMNS {
+                let addr = buffer as usize + (row * Self::COLUMNS + column) as usize;
+                let c = memory.get(addr).copied().unwrap_or(b' ');
+                text.push(if c.is_ascii_graphic() { c as char } else { ' ' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn draw(&mut self, memory: &[u8]) -> std::io::Result<()> {
+        if self.drawn == 0 {
+            // Clears the screen once, later frames draw over the last one
+            write!(self.out, "\x1b[2J")?;
+        }
+        self.drawn += 1;
+        write!(self.out, "\x1b[H{}", Self::render(memory, self.buffer))?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Io for Display<W> {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.buffer.to_le_bytes()[0],
+            1 => self.buffer.to_le_bytes()[1],
+            2 => std::mem::take(&mut self.ended),
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        let [mut lower, mut higher] = self.buffer.to_le_bytes();
+        match addr {
+            0 => lower = val,
+            1 => higher = val,
+            2 => {
+                self.show = true;
+                self.interrupt_enable = val & INTERRUPT_ENABLE != 0;
+            }
+            _ => (),
+        }
+        self.buffer = u16::from_le_bytes([lower, higher]);
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while cycles >= self.remaining {
+            cycles -= self.remaining;
+            self.remaining = self.frame_cycles;
+            self.ended = self.ended.saturating_add(1);
+        }
+        self.remaining -= cycles;
+    }
+    fn interrupt_pending(&self) -> bool {
+        self.interrupt_enable && self.ended > 0
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        if std::mem::take(&mut self.show) {
+            self.draw(memory).expect("display failed");
+        }
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend(self.buffer.to_le_bytes());
+        state.push(self.interrupt_enable as u8);
+        state.extend(self.remaining.to_le_bytes());
+        state.push(self.ended);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 12];
+        state.read_exact(&mut buf)?;
+        let [b0, b1, interrupt_enable, remaining @ .., ended] = buf;
+        self.buffer = u16::from_le_bytes([b0, b1]);
+        self.interrupt_enable = interrupt_enable != 0;
+        self.remaining = u64::from_le_bytes(remaining);
+        self.ended = ended;
+        Ok(())
+    }
+}
+
+#[test]
+fn display() {
+    let mut memory = vec![0; 0x100];
+    memory[0x10..0x15].copy_from_slice(b"hi\0yo");
+    memory[0x10 + 40] = b'!';
+
+    let mut display = Display::new(Vec::new());
+    display.write(0, 0x10);
+    display.write(2, 0);
+    display.dma(&mut memory);
+    display.dma(&mut memory);
+
+    let frame = String::from_utf8(display.out).unwrap();
+    let frame = frame.strip_prefix("\x1b[2J\x1b[H").unwrap();
+    let lines: Vec<_> = frame.lines().collect();
+    assert_eq!(lines.len(), 25);
+    assert_eq!(lines[0].trim_end(), "hi yo");
+    assert_eq!(lines[1].trim_end(), "!");
+    assert!(lines[2..]
+        .iter()
+        .all(|l| l.len() == 40 && l.trim().is_empty()));
+
+    let mut display = Display::new(io::sink());
+    display.set_frame_cycles(100);
+    display.tick(250);
+    assert!(!display.interrupt_pending());
+    assert_eq!(display.read(2), 2);
+    assert_eq!(display.read(2), 0);
+    display.write(2, INTERRUPT_ENABLE);
+    display.tick(49);
+    assert!(!display.interrupt_pending());
+    display.tick(1);
+    assert!(display.interrupt_pending());
+    assert_eq!(display.read(2), 1);
+    assert!(!display.interrupt_pending());
+}
@@ -0,0 +1,306 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::mem::{Io, IO_MAPPING_CUTOFF};
+
+/// Calls to the host for opening, reading, writing and closing files, copied to and from memory on command
+///
+/// Its ports are:
+///
+/// - 0: the handle of the file
+/// - 1 and 2: the location in memory, lower byte first. For opening, of the path ending with a zero byte
+/// - 3 and 4: how many bytes to read or write, lower byte first. After reading or writing, how many were.
+///   For opening, port 3 has the mode, see [`Files::READ`] and the others
+/// - 5: writing `1` opens the file, with its handle in port 0, `2` reads, `3` writes and `4` closes it,
+///   after the instruction. Reading it gives the error of the last call, 0 if it went fine (see [`Files::NOT_FOUND`]
+///   and the others)
+///
+/// Handles 0, 1 and 2 are the console's input, output and error. Reading 0 bytes means the file has ended.
+pub struct Files {
+    sandbox: Sandbox,
+    handles: Vec<Option<Handle>>,
+    handle: u8,
+    addr: u16,
+    len: u16,
+    call: Option<u8>,
+    error: u8,
+}
+
+/// Which host files programs may open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    /// Any, with relative paths from the working directory
+    None,
+    /// Only those in the directory, which relative paths are from
+    Directory(PathBuf),
+    /// None at all, only the console
+    Closed,
+}
+
+enum Handle {
+    File(File),
+    Input(Box<dyn Read>),
+    Output(Box<dyn Write>),
+}
+
+impl Files {
+    /// Opens the file for reading, the default if neither this nor `WRITE` is given
+    pub const READ: u8 = 1;
+    pub const WRITE: u8 = 2;
+    /// Creates the file if it does not exist
+    pub const CREATE: u8 = 4;
+    pub const TRUNCATE: u8 = 8;
+    pub const APPEND: u8 = 16;
+
+    pub const NOT_FOUND: u8 = 1;
+    /// The host or the sandbox does not allow it
+    pub const DENIED: u8 = 2;
+    /// No file is open with the handle, or it cannot be read or written
+    pub const BAD_HANDLE: u8 = 3;
+    pub const TOO_MANY_FILES: u8 = 4;
+    /// Any other error, like an unknown call or the path not ending before the I/O mapping
+    pub const FAILED: u8 = 5;
+
+    /// The most files open at once, including the console's
+    pub const MAX_HANDLES: usize = 16;
+
+    /// With the console as standard input, output and error
+    pub fn new(sandbox: Sandbox) -> Self {
+        Self::with_console(
+            sandbox,
+            Box::new(io::stdin()),
+            Box::new(io::stdout()),
+            Box::new(io::stderr()),
+        )
+    }
+    pub fn with_console(
+        sandbox: Sandbox,
+        input: Box<dyn Read>,
+        output: Box<dyn Write>,
+        error: Box<dyn Write>,
+    ) -> Self {
+        Files {
+            sandbox,
+            handles: vec![
+                Some(Handle::Input(input)),
+                Some(Handle::Output(output)),
+                Some(Handle::Output(error)),
+            ],
+            handle: 0,
+            addr: 0,
+            len: 0,
+            call: None,
+            error: 0,
+        }
+    }
+
+    /// Where the path is on the host, if the sandbox allows it
+    fn host_path(&self, path: &Path) -> Option<PathBuf> {
+        match &self.sandbox {
+            Sandbox::None => Some(path.to_owned()),
+            Sandbox::Directory(dir) => path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                .then(|| dir.join(path)),
+            Sandbox::Closed => None,
+        }
+    }
+
+    fn open(&mut self, memory: &[u8]) -> Result<(), u8> {
+        let start = self.addr as usize;
+        let path = memory
+            .get(start..memory.len().min(IO_MAPPING_CUTOFF as usize))
+            .and_then(|bytes| bytes.iter().position(|&b| b == 0).map(|end| &bytes[..end]))
+            .ok_or(Self::FAILED)?;
+        let path = std::str::from_utf8(path).map_err(|_| Self::NOT_FOUND)?;
+        let path = self.host_path(Path::new(path)).ok_or(Self::DENIED)?;
+
+        let mode = self.len.to_le_bytes()[0];
+        let write = mode & Self::WRITE != 0;
+        let file = OpenOptions::new()
+            .read(mode & Self::READ != 0 || !write)
+            .write(write)
+            .create(mode & Self::CREATE != 0)
+            .truncate(mode & Self::TRUNCATE != 0)
+            .append(mode & Self::APPEND != 0)
+            .open(path)
+            .map_err(|e| error_code(&e))?;
+
+        let free = self.handles.iter().position(Option::is_none);
+        let handle = match free {
+            Some(handle) => handle,
+            None if self.handles.len() < Self::MAX_HANDLES => {
+                self.handles.push(None);
+                self.handles.len() - 1
+            }
+            None => return Err(Self::TOO_MANY_FILES),
+        };
+        self.handles[handle] = Some(Handle::File(file));
+        self.handle = handle as u8;
+        Ok(())
+    }
+
+    fn run(&mut self, call: u8, memory: &mut Vec<u8>) -> Result<(), u8> {
+        if call == 1 {
+            return self.open(memory);
+        }
+        let handle = self
+            .handles
+            .get_mut(self.handle as usize)
+            .and_then(Option::as_mut)
+            .ok_or(Self::BAD_HANDLE)?;
+        let start = self.addr as usize;
+        // Bytes that would be in the I/O mapping are left out
+        let end = (start + self.len as usize).min(IO_MAPPING_CUTOFF as usize);
+        let len = end.saturating_sub(start);
+        match (call, handle) {
+            (2, Handle::File(file)) => self.len = read_into(file, memory, start, len)?,
+            (2, Handle::Input(input)) => self.len = read_into(input, memory, start, len)?,
+            (3, Handle::File(file)) => self.len = write_from(file, memory, start, len)?,
+            (3, Handle::Output(output)) => self.len = write_from(output, memory, start, len)?,
+            (2 | 3, _) => return Err(Self::BAD_HANDLE),
+            (4, _) => self.handles[self.handle as usize] = None,
+            _ => return Err(Self::FAILED),
+        }
+        Ok(())
+    }
+}
+
+fn error_code(e: &io::Error) -> u8 {
+    match e.kind() {
+        ErrorKind::NotFound => Files::NOT_FOUND,
+        ErrorKind::PermissionDenied => Files::DENIED,
+        _ => Files::FAILED,
+    }
+}
+
+fn read_into(
+    from: &mut impl Read,
+    memory: &mut Vec<u8>,
+    start: usize,
+    len: usize,
+) -> Result<u16, u8> {
+    let mut buf = vec![0; len];
+    let read = from.read(&mut buf).map_err(|e| error_code(&e))?;
+    if read > 0 {
+        if memory.len() < start + read {
+            memory.resize(start + read, 0);
+        }
+        memory[start..start + read].copy_from_slice(&buf[..read]);
+    }
+    Ok(read as u16)
+}
+fn write_from(to: &mut impl Write, memory: &[u8], start: usize, len: usize) -> Result<u16, u8> {
+    let buf: Vec<_> = (start..start + len)
+        .map(|addr| memory.get(addr).copied().unwrap_or(0))
+        .collect();
+    to.write_all(&buf)
+        .and_then(|()| to.flush())
+        .map_err(|e| error_code(&e))?;
+    Ok(len as u16)
+}
+
+impl Io for Files {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.handle,
+            1 => self.addr.to_le_bytes()[0],
+            2 => self.addr.to_le_bytes()[1],
+            3 => self.len.to_le_bytes()[0],
+            4 => self.len.to_le_bytes()[1],
+            5 => self.error,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        let set = |word: &mut u16, higher: bool| {
+            let mut bytes = word.to_le_bytes();
+            bytes[higher as usize] = val;
+            *word = u16::from_le_bytes(bytes);
+        };
+        match addr {
+            0 => self.handle = val,
+            1 | 2 => set(&mut self.addr, addr == 2),
+            3 | 4 => set(&mut self.len, addr == 4),
+            5 => self.call = Some(val),
+            _ => (),
+        }
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        if let Some(call) = self.call.take() {
+            self.error = self.run(call, memory).err().unwrap_or(0);
+        }
+    }
+    /// Saves the ports, files the program opened are closed when it is restored
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.handle);
+        state.extend(self.addr.to_le_bytes());
+        state.extend(self.len.to_le_bytes());
+        state.push(self.error);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 6];
+        state.read_exact(&mut buf)?;
+        let [handle, a0, a1, l0, l1, error] = buf;
+        self.handle = handle;
+        self.addr = u16::from_le_bytes([a0, a1]);
+        self.len = u16::from_le_bytes([l0, l1]);
+        self.error = error;
+        self.handles.truncate(3);
+        Ok(())
+    }
+}
+
+#[test]
+fn files() {
+    let dir = std::env::temp_dir().join(format!("telda-files-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut files = Files::with_console(
+        Sandbox::Directory(dir.clone()),
+        Box::new(io::empty()),
+        Box::new(io::sink()),
+        Box::new(io::sink()),
+    );
+    let mut memory = vec![0; 0x20];
+    memory[0x10..0x17].copy_from_slice(b"out.txt");
+    memory[0x00..0x02].copy_from_slice(b"hi");
+    let call = |files: &mut Files, memory: &mut Vec<u8>, call| {
+        files.write(5, call);
+        files.dma(memory);
+        files.read(5)
+    };
+
+    // Creating a file gives the first handle after the console's
+    files.write(1, 0x10);
+    files.write(3, Files::WRITE | Files::CREATE | Files::TRUNCATE);
+    assert_eq!(call(&mut files, &mut memory, 1), 0);
+    assert_eq!(files.read(0), 3);
+    files.write(1, 0x00);
+    files.write(3, 2);
+    assert_eq!(call(&mut files, &mut memory, 3), 0);
+    assert_eq!(call(&mut files, &mut memory, 4), 0);
+    assert_eq!(std::fs::read(dir.join("out.txt")).unwrap(), b"hi");
+    assert_eq!(call(&mut files, &mut memory, 4), Files::BAD_HANDLE);
+
+    // Reading it back to 0x1000 grows the memory
+    files.write(1, 0x10);
+    files.write(3, Files::READ);
+    assert_eq!(call(&mut files, &mut memory, 1), 0);
+    files.write(1, 0x00);
+    files.write(2, 0x10);
+    files.write(3, 0xff);
+    assert_eq!(call(&mut files, &mut memory, 2), 0);
+    assert_eq!(files.read(3), 2);
+    assert_eq!(&memory[0x1000..], b"hi");
+
+    // Nothing outside of the sandbox can be opened
+    memory[0x10..0x1a].copy_from_slice(b"../out.txt");
+    files.write(1, 0x10);
+    files.write(2, 0x00);
+    assert_eq!(call(&mut files, &mut memory, 1), Files::DENIED);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
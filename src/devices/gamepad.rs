@@ -0,0 +1,131 @@
+use std::{
+    io::{self, Read},
+    sync::mpsc::Receiver,
+};
+
+use crate::mem::Io;
+
+/// What a frontend tells a [`Gamepad`] about its buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buttons {
+    /// The buttons held now, from a frontend that sees them go down and up
+    Held(u8),
+    /// Buttons pressed once, from a frontend that only sees keys being typed, like a terminal. They count as held
+    /// for [`Gamepad::TAP_CYCLES`], which key repeat keeps extending while a key is held down
+    Tapped(u8),
+}
+
+/// The state of a few buttons, for games to poll instead of reading keys
+///
+/// Its ports are:
+///
+/// - 0: the buttons held now, a bit each
+/// - 1: the buttons pressed since this was last read, so that a short press is not missed between polls
+#[derive(Debug)]
+pub struct Gamepad {
+    buttons: Receiver<Buttons>,
+    held: u8,
+    tapped: u8,
+    /// Cycles until the tapped buttons are let go
+    tap_remaining: u64,
+    pressed: u8,
+}
+
+impl Gamepad {
+    pub const UP: u8 = 1 << 0;
+    pub const DOWN: u8 = 1 << 1;
+    pub const LEFT: u8 = 1 << 2;
+    pub const RIGHT: u8 = 1 << 3;
+    pub const A: u8 = 1 << 4;
+    pub const B: u8 = 1 << 5;
+    pub const START: u8 = 1 << 6;
+
+    /// How long tapped buttons stay held
+    pub const TAP_CYCLES: u64 = 100_000;
+
+    /// Gets the buttons from the channel, filled by the frontend
+    pub fn new(buttons: Receiver<Buttons>) -> Self {
+        Gamepad {
+            buttons,
+            held: 0,
+            tapped: 0,
+            tap_remaining: 0,
+            pressed: 0,
+        }
+    }
+
+    fn poll(&mut self) {
+        for buttons in self.buttons.try_iter() {
+            match buttons {
+                Buttons::Held(held) => {
+                    self.pressed |= held & !self.held;
+                    self.held = held;
+                }
+                Buttons::Tapped(tapped) => {
+                    self.pressed |= tapped;
+                    self.tapped |= tapped;
+                    self.tap_remaining = Self::TAP_CYCLES;
+                }
+            }
+        }
+    }
+}
+
+impl Io for Gamepad {
+    fn read(&mut self, addr: u8) -> u8 {
+        self.poll();
+        match addr {
+            0 => self.held | self.tapped,
+            1 => std::mem::take(&mut self.pressed),
+            _ => 0,
+        }
+    }
+    fn write(&mut self, _addr: u8, _val: u8) {}
+    fn tick(&mut self, cycles: u64) {
+        self.poll();
+        self.tap_remaining = self.tap_remaining.saturating_sub(cycles);
+        if self.tap_remaining == 0 {
+            self.tapped = 0;
+        }
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.held);
+        state.push(self.tapped);
+        state.extend(self.tap_remaining.to_le_bytes());
+        state.push(self.pressed);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 11];
+        state.read_exact(&mut buf)?;
+        let [held, tapped, remaining @ .., pressed] = buf;
+        self.held = held;
+        self.tapped = tapped;
+        self.tap_remaining = u64::from_le_bytes(remaining);
+        self.pressed = pressed;
+        Ok(())
+    }
+}
+
+#[test]
+fn gamepad() {
+    use std::sync::mpsc::channel;
+
+    let (send, buttons) = channel();
+    let mut gamepad = Gamepad::new(buttons);
+    assert_eq!(gamepad.read(0), 0);
+
+    send.send(Buttons::Held(Gamepad::LEFT | Gamepad::A))
+        .unwrap();
+    send.send(Buttons::Held(Gamepad::LEFT)).unwrap();
+    gamepad.tick(1);
+    assert_eq!(gamepad.read(0), Gamepad::LEFT);
+    assert_eq!(gamepad.read(1), Gamepad::LEFT | Gamepad::A);
+    assert_eq!(gamepad.read(1), 0);
+
+    send.send(Buttons::Tapped(Gamepad::START)).unwrap();
+    gamepad.tick(1);
+    assert_eq!(gamepad.read(0), Gamepad::LEFT | Gamepad::START);
+    gamepad.tick(Gamepad::TAP_CYCLES);
+    assert_eq!(gamepad.read(0), Gamepad::LEFT);
+    assert_eq!(gamepad.read(1), Gamepad::START);
+}
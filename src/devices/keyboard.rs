@@ -0,0 +1,114 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    sync::mpsc::Receiver,
+};
+
+use crate::mem::Io;
+
+/// Keys as they are typed, buffered so that reading them never blocks the machine
+///
+/// Its ports are:
+///
+/// - 0: status, bit 0 is set while a key is waiting and bit 1 lets it raise its interrupt line while one is
+/// - 1: data, reading it takes the next key or gives 0 if there is none
+#[derive(Debug)]
+pub struct Keyboard {
+    keys: Receiver<u8>,
+    buffer: VecDeque<u8>,
+    interrupt_enable: bool,
+}
+
+const KEY_WAITING: u8 = 0b01;
+const INTERRUPT_ENABLE: u8 = 0b10;
+
+impl Keyboard {
+    /// Gets the keys from the channel, usually filled by another thread reading a terminal
+    pub fn new(keys: Receiver<u8>) -> Self {
+        Keyboard {
+            keys,
+            buffer: VecDeque::new(),
+            interrupt_enable: false,
+        }
+    }
+    /// Waits for the next key, for when the program does want to block, or `None` if no more keys can come
+    pub fn wait(&mut self) -> Option<u8> {
+        self.buffer.pop_front().or_else(|| self.keys.recv().ok())
+    }
+
+    fn poll(&mut self) {
+        self.buffer.extend(self.keys.try_iter());
+    }
+}
+
+impl Io for Keyboard {
+    fn read(&mut self, addr: u8) -> u8 {
+        self.poll();
+        match addr {
+            0 => {
+                let waiting = if self.buffer.is_empty() {
+                    0
+                } else {
+                    KEY_WAITING
+                };
+                let interrupt = if self.interrupt_enable {
+                    INTERRUPT_ENABLE
+                } else {
+                    0
+                };
+                waiting | interrupt
+            }
+            1 => self.buffer.pop_front().unwrap_or(0),
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        if addr == 0 {
+            self.interrupt_enable = val & INTERRUPT_ENABLE != 0;
+        }
+    }
+    fn tick(&mut self, _cycles: u64) {
+        self.poll();
+    }
+    fn interrupt_pending(&self) -> bool {
+        self.interrupt_enable && !self.buffer.is_empty()
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.interrupt_enable as u8);
+        state.extend((self.buffer.len() as u16).to_le_bytes());
+        state.extend(&self.buffer);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 3];
+        state.read_exact(&mut buf)?;
+        let [interrupt_enable, len @ ..] = buf;
+        let mut keys = vec![0; u16::from_le_bytes(len) as usize];
+        state.read_exact(&mut keys)?;
+        self.interrupt_enable = interrupt_enable != 0;
+        self.buffer = keys.into();
+        Ok(())
+    }
+}
+
+#[test]
+fn keyboard() {
+    use std::sync::mpsc::channel;
+
+    let (send, keys) = channel();
+    let mut keyboard = Keyboard::new(keys);
+    assert_eq!(keyboard.read(0), 0);
+    assert_eq!(keyboard.read(1), 0);
+
+    keyboard.write(0, INTERRUPT_ENABLE);
+    send.send(b'h').unwrap();
+    send.send(b'i').unwrap();
+    keyboard.tick(1);
+    assert!(keyboard.interrupt_pending());
+    assert_eq!(keyboard.read(0), KEY_WAITING | INTERRUPT_ENABLE);
+    assert_eq!(keyboard.read(1), b'h');
+    assert_eq!(keyboard.wait(), Some(b'i'));
+    assert!(!keyboard.interrupt_pending());
+
+    drop(send);
+    assert_eq!(keyboard.wait(), None);
+}
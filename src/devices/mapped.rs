@@ -0,0 +1,177 @@
+use std::{
+    io::{self, ErrorKind, Read},
+    ops::Range,
+};
+
+use crate::mem::Io;
+
+/// A host file shown in a window of memory a page at a time, for big tables and assets the program only reads
+///
+/// The file is split into pages as long as the window, the last one filled up with zeroes. It has a single port:
+/// writing it selects the page, which is copied into the window after the instruction that selected it, and reading
+/// it gives how many pages there are. Selecting a page past the end of the file does nothing.
+///
+/// Writes to the window never reach the host file. They are lost when another page is selected, unless the file
+/// is mapped copy-on-write, where they stay with their page like with [`Banks`](super::Banks).
+#[derive(Debug, Clone)]
+pub struct MappedFile {
+    contents: Vec<u8>,
+    window: Range<u16>,
+    copy_on_write: bool,
+    selected: u8,
+    /// The page that is in the window
+    shown: Option<u8>,
+}
+
+impl MappedFile {
+    /// The contents of the file mapped into `window`, or `None` if they are more than 255 pages
+    pub fn new(mut contents: Vec<u8>, window: Range<u16>, copy_on_write: bool) -> Option<Self> {
+        let page = window.len().max(1);
+        let pages = contents.len().div_ceil(page).max(1);
+        if pages > u8::MAX as usize {
+            return None;
+        }
+        contents.resize(pages * page, 0);
+        Some(MappedFile {
+            contents,
+            window,
+            copy_on_write,
+            selected: 0,
+            shown: None,
+        })
+    }
+
+    fn pages(&self) -> usize {
+        self.contents.len() / self.window.len().max(1)
+    }
+    fn page(&self, n: u8) -> Range<usize> {
+        let len = self.window.len();
+        n as usize * len..(n as usize + 1) * len
+    }
+}
+
+impl Io for MappedFile {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.pages() as u8,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        if addr == 0 && (val as usize) < self.pages() {
+            self.selected = val;
+        }
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        if self.shown == Some(self.selected) {
+            return;
+        }
+        let window = self.window.start as usize..self.window.end as usize;
+        if memory.len() < window.end {
+            memory.resize(window.end, 0);
+        }
+        if let Some(shown) = self.shown.filter(|_| self.copy_on_write) {
+            let page = self.page(shown);
+            self.contents[page].copy_from_slice(&memory[window.clone()]);
+        }
+        memory[window].copy_from_slice(&self.contents[self.page(self.selected)]);
+        self.shown = Some(self.selected);
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        // The shown page is saved with the memory, and the others only change when copied on write
+        state.push(self.selected);
+        state.push(self.shown.unwrap_or(0xff));
+        if self.copy_on_write {
+            state.extend(&self.contents);
+        }
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 2];
+        state.read_exact(&mut buf)?;
+        let [selected, shown] = buf;
+        let shown = (shown != 0xff).then_some(shown);
+        let pages = self.pages();
+        if selected as usize >= pages || shown.is_some_and(|s| s as usize >= pages) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "selected page does not exist",
+            ));
+        }
+        if self.copy_on_write {
+            state.read_exact(&mut self.contents)?;
+        }
+        self.selected = selected;
+        self.shown = shown;
+        Ok(())
+    }
+}
+
+#[test]
+fn mapped_file() {
+    let mut memory = Vec::new();
+    let mut file = MappedFile::new(vec![1, 2, 3, 4, 5], 0x10..0x12, false).unwrap();
+    assert_eq!(file.read(0), 3);
+    file.dma(&mut memory);
+    assert_eq!(memory[0x10..], [1, 2]);
+
+    // Writes are lost without copy-on-write
+    memory[0x10] = 9;
+    file.write(0, 2);
+    file.dma(&mut memory);
+    assert_eq!(memory[0x10..], [5, 0]);
+    memory[0x11] = 9;
+    file.write(0, 3);
+    file.dma(&mut memory);
+    assert_eq!(memory[0x10..], [5, 9]);
+    file.write(0, 0);
+    file.dma(&mut memory);
+    assert_eq!(memory[0x10..], [1, 2]);
+
+    let mut file = MappedFile::new(vec![1, 2, 3], 0x10..0x12, true).unwrap();
+    file.dma(&mut memory);
+    memory[0x10] = 9;
+    file.write(0, 1);
+    file.dma(&mut memory);
+    file.write(0, 0);
+    file.dma(&mut memory);
+    assert_eq!(memory[0x10..], [9, 2]);
+
+    assert!(MappedFile::new(vec![0; 256], 0..1, false).is_none());
+}
+
+#[test]
+fn stdio_beside_mapped_file() {
+    use crate::{
+        aalv::obj::{Entry, Object, SegmentType},
+        cpu::TrapMode,
+        devices::{self, MAPPED_FILE_PORT},
+        machine::Machine,
+    };
+
+    /// Stdout kept in memory
+    struct Output(Vec<u8>);
+    impl Io for Output {
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+        fn write(&mut self, _addr: u8, val: u8) {
+            self.0.push(val);
+        }
+    }
+
+    let mut obj = Object::default();
+    // ldi r1, 0xffff; ldi r2, 'h'; store r1, 0, r2l; halt
+    let code = vec![
+        0x40, 0x10, 0xff, 0xff, 0x40, 0x20, b'h', 0x00, 0x27, 0x13, 0x00, 0x00, 0x0a,
+    ];
+    obj.segs.insert(SegmentType::Text, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Text, 0x10));
+
+    let mut bus = devices::standard(Output(Vec::new()));
+    let file = MappedFile::new(vec![1; 0x100], 0xc000..0xc100, false).unwrap();
+    bus.attach(MAPPED_FILE_PORT, 1, file);
+    let mut machine = Machine::with_io(obj, bus);
+    assert_eq!(machine.run(), TrapMode::Halt);
+    assert_eq!(machine.io().io().0, b"h");
+    assert_eq!(machine.peek(0xc000), 1);
+}
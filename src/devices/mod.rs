@@ -0,0 +1,245 @@
+//! Peripherals on the I/O mapping and the bus that routes ports to them
+
+use std::io::{self, ErrorKind, Read};
+
+use crate::mem::Io;
+
+mod banks;
+mod disk;
+mod display;
+mod files;
+mod gamepad;
+mod keyboard;
+mod mapped;
+mod pic;
+mod serial;
+mod timer;
+
+pub use self::{
+    banks::{Banks, BANK_SIZE, BANK_WINDOW},
+    disk::Disk,
+    display::Display,
+    files::{Files, Sandbox},
+    gamepad::{Buttons, Gamepad},
+    keyboard::Keyboard,
+    mapped::MappedFile,
+    pic::InterruptController,
+    serial::Serial,
+    timer::Timer,
+};
+
+/// The port the timer of [`standard`] is at, it takes 4 ports
+pub const TIMER_PORT: u8 = 0xf0;
+/// The port a [`Keyboard`] is at when there is one, it takes 2 ports
+pub const KEYBOARD_PORT: u8 = 0xf4;
+/// The port a [`Display`] is at when there is one, it takes 3 ports
+pub const DISPLAY_PORT: u8 = 0xf6;
+/// The port a [`Disk`] is at when there is one, it takes 5 ports
+pub const DISK_PORT: u8 = 0xf9;
+/// The port a [`Serial`] port is at when there is one, it takes 2 ports
+pub const SERIAL_PORT: u8 = 0xe0;
+/// The port the [`InterruptController`] of [`standard`] is at, it takes 3 ports
+pub const PIC_PORT: u8 = 0xe2;
+/// The port [`Banks`] are at when there are some, it takes 2 ports
+pub const BANK_PORT: u8 = 0xe5;
+/// The port [`Files`] is at when there is one, it takes 6 ports
+pub const FILES_PORT: u8 = 0xe7;
+/// The port a [`Gamepad`] is at when there is one, it takes 2 ports
+pub const GAMEPAD_PORT: u8 = 0xed;
+/// The port a [`MappedFile`] is at when there is one, it takes 1 port
+pub const MAPPED_FILE_PORT: u8 = 0xef;
+
+/// The interrupt line of the timer of [`standard`]
+pub const TIMER_LINE: u8 = 0;
+/// The interrupt line of a [`Serial`] port when there is one
+pub const SERIAL_LINE: u8 = 1;
+/// The interrupt line of a [`Display`] when there is one
+pub const DISPLAY_LINE: u8 = 2;
+/// The interrupt line of the I/O behind a bus, like a [`Keyboard`] in it
+pub const IO_LINE: u8 = 7;
+
+/// Devices attached to ranges of ports, with the ports no device is attached to going to `io`
+///
+/// Without an interrupt controller, any device raising its interrupt line interrupts.
+/// With one, devices on a line go through it and those without one interrupt regardless.
+pub struct Bus<I> {
+    io: I,
+    devices: Vec<Attached>,
+    controller: Option<(u8, InterruptController)>,
+}
+
+struct Attached {
+    port: u8,
+    len: u8,
+    line: Option<u8>,
+    device: Box<dyn Io>,
+}
+
+impl<I> Bus<I> {
+    pub fn new(io: I) -> Self {
+        Bus {
+            io,
+            devices: Vec::new(),
+            controller: None,
+        }
+    }
+    /// Attaches the device to `len` ports from `port`, it sees them numbered from 0
+    ///
+    /// Devices attached earlier win where they overlap.
+    pub fn attach(&mut self, port: u8, len: u8, device: impl Io + 'static) {
+        self.devices.push(Attached {
+            port,
+            len,
+            line: None,
+            device: Box::new(device),
+        });
+    }
+    /// Attaches the device like [`attach`](Self::attach), with its interrupts going through `line` of the controller
+    pub fn attach_on_line(&mut self, port: u8, len: u8, line: u8, device: impl Io + 'static) {
+        assert!(
+            line < InterruptController::LINES,
+            "no interrupt line {line}"
+        );
+        self.devices.push(Attached {
+            port,
+            len,
+            line: Some(line),
+            device: Box::new(device),
+        });
+    }
+    /// Adds an interrupt controller at 3 ports from `port`, with `io` on [`IO_LINE`]
+    ///
+    /// It takes priority over devices attached to the same ports.
+    pub fn add_controller(&mut self, port: u8) {
+        self.controller = Some((port, InterruptController::new()));
+    }
+    pub fn controller(&self) -> Option<&InterruptController> {
+        self.controller.as_ref().map(|(_, c)| c)
+    }
+    /// The I/O getting the ports no device is attached to
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    fn device(&mut self, port: u8) -> Option<(&mut (dyn Io + 'static), u8)> {
+        if let Some((at, controller)) = &mut self.controller {
+            if port.wrapping_sub(*at) < 3 {
+                return Some((controller, port - *at));
+            }
+        }
+        self.devices
+            .iter_mut()
+            .find(|d| port.wrapping_sub(d.port) < d.len)
+            .map(|d| (&mut *d.device, port - d.port))
+    }
+}
+
+impl<I: Io> Io for Bus<I> {
+    fn read(&mut self, addr: u8) -> u8 {
+        match self.device(addr) {
+            Some((device, port)) => device.read(port),
+            None => self.io.read(addr),
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        match self.device(addr) {
+            Some((device, port)) => device.write(port, val),
+            None => self.io.write(addr, val),
+        }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.io.tick(cycles);
+        for d in &mut self.devices {
+            d.device.tick(cycles);
+        }
+        if let Some((_, controller)) = &mut self.controller {
+            let mut lines = (self.io.interrupt_pending() as u8) << IO_LINE;
+            for d in &self.devices {
+                if let Some(line) = d.line {
+                    lines |= (d.device.interrupt_pending() as u8) << line;
+                }
+            }
+            controller.raise(lines);
+        }
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        self.io.dma(memory);
+        for d in &mut self.devices {
+            d.device.dma(memory);
+        }
+    }
+    fn interrupt_pending(&self) -> bool {
+        match &self.controller {
+            Some((_, controller)) => {
+                controller.interrupt_pending()
+                    || self
+                        .devices
+                        .iter()
+                        .any(|d| d.line.is_none() && d.device.interrupt_pending())
+            }
+            None => {
+                self.io.interrupt_pending()
+                    || self.devices.iter().any(|d| d.device.interrupt_pending())
+            }
+        }
+    }
+    /// Saves `io`, the controller and the devices in the order they were attached, each with its length
+    fn save_state(&self, state: &mut Vec<u8>) {
+        let mut parts: Vec<&dyn Io> = vec![&self.io];
+        parts.extend(self.controller.as_ref().map(|(_, c)| c as &dyn Io));
+        parts.extend(self.devices.iter().map(|d| &*d.device));
+        state.push(parts.len() as u8);
+        for part in parts {
+            let mut saved = Vec::new();
+            part.save_state(&mut saved);
+            state.extend((saved.len() as u32).to_le_bytes());
+            state.extend(saved);
+        }
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut parts: Vec<&mut dyn Io> = vec![&mut self.io];
+        parts.extend(self.controller.as_mut().map(|(_, c)| c as &mut dyn Io));
+        parts.extend(
+            self.devices
+                .iter_mut()
+                .map(|d| &mut *d.device as &mut dyn Io),
+        );
+
+        let mut count = [0];
+        state.read_exact(&mut count)?;
+        if count[0] as usize != parts.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "state is of {} devices but there are {}",
+                    count[0],
+                    parts.len()
+                ),
+            ));
+        }
+        for part in parts {
+            let mut len = [0; 4];
+            state.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as usize;
+            if state.len() < len {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            let (mut saved, rest) = state.split_at(len);
+            part.restore_state(&mut saved)?;
+            *state = rest;
+        }
+        Ok(())
+    }
+}
+
+/// The devices `t` runs programs with: an [`InterruptController`] at [`PIC_PORT`] and a [`Timer`] at [`TIMER_PORT`]
+/// on [`TIMER_LINE`], everything else goes to `io`
+pub fn standard<I: Io>(io: I) -> Bus<I> {
+    let mut bus = Bus::new(io);
+    bus.add_controller(PIC_PORT);
+    bus.attach_on_line(TIMER_PORT, 4, TIMER_LINE, Timer::new());
+    bus
+}
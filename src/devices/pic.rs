@@ -0,0 +1,121 @@
+use std::io::{self, Read};
+
+use crate::mem::Io;
+
+/// Gathers the interrupt lines of the devices on a [`Bus`](super::Bus) into one, with lines that can be masked and a
+/// lower numbered line taking priority over a higher one
+///
+/// Its ports are:
+///
+/// - 0: mask, lines with their bit set cannot interrupt. None are masked at first
+/// - 1: pending, the raised lines that are not masked
+/// - 2: the current interrupt, the pending line with the highest priority or `0xff` if there is none
+#[derive(Debug, Clone, Default)]
+pub struct InterruptController {
+    mask: u8,
+    /// The raised lines, as of the last instruction
+    lines: u8,
+}
+
+impl InterruptController {
+    pub const LINES: u8 = 8;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the lines raised by the devices
+    pub fn raise(&mut self, lines: u8) {
+        self.lines = lines;
+    }
+    pub fn pending(&self) -> u8 {
+        self.lines & !self.mask
+    }
+    /// The pending line with the highest priority
+    pub fn current(&self) -> Option<u8> {
+        match self.pending() {
+            0 => None,
+            pending => Some(pending.trailing_zeros() as u8),
+        }
+    }
+}
+
+impl Io for InterruptController {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.mask,
+            1 => self.pending(),
+            2 => self.current().unwrap_or(0xff),
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        if addr == 0 {
+            self.mask = val;
+        }
+    }
+    fn interrupt_pending(&self) -> bool {
+        self.pending() != 0
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.mask);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0];
+        state.read_exact(&mut buf)?;
+        self.mask = buf[0];
+        Ok(())
+    }
+}
+
+#[test]
+fn controller() {
+    use super::{Bus, IO_LINE};
+    use std::{cell::Cell, rc::Rc};
+
+    struct Line(Rc<Cell<bool>>);
+    impl Io for Line {
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+        fn write(&mut self, _addr: u8, _val: u8) {}
+        fn interrupt_pending(&self) -> bool {
+            self.0.get()
+        }
+    }
+    let line = || Rc::new(Cell::new(false));
+    let (io, low, high, unlined) = (line(), line(), line(), line());
+
+    let mut bus = Bus::new(Line(io.clone()));
+    bus.add_controller(0);
+    bus.attach_on_line(0x10, 1, 5, Line(low.clone()));
+    bus.attach_on_line(0x11, 1, 2, Line(high.clone()));
+    bus.attach(0x12, 1, Line(unlined.clone()));
+    bus.tick(1);
+    assert!(!bus.interrupt_pending());
+    assert_eq!(bus.read(2), 0xff);
+
+    low.set(true);
+    io.set(true);
+    bus.tick(1);
+    assert!(bus.interrupt_pending());
+    assert_eq!(bus.read(1), 1 << 5 | 1 << IO_LINE);
+    assert_eq!(bus.read(2), 5);
+
+    high.set(true);
+    bus.tick(1);
+    assert_eq!(bus.read(2), 2);
+
+    // Masking every line but the one of `io`
+    bus.write(0, !(1 << IO_LINE));
+    bus.tick(1);
+    assert_eq!(bus.read(0), !(1 << IO_LINE));
+    assert_eq!(bus.read(2), IO_LINE);
+    io.set(false);
+    bus.tick(1);
+    assert!(!bus.interrupt_pending());
+
+    // Devices without a line bypass it
+    unlined.set(true);
+    assert!(bus.interrupt_pending());
+    assert_eq!(bus.read(1), 0);
+}
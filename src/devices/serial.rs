@@ -0,0 +1,75 @@
+use std::{
+    io::{self, Write},
+    sync::mpsc::Receiver,
+};
+
+use super::Keyboard;
+use crate::mem::Io;
+
+/// A serial port, with bytes from the other end buffered like keys of a [`Keyboard`] and bytes to it written out
+///
+/// Its ports are:
+///
+/// - 0: status, bit 0 is set while a byte is waiting and bit 1 lets it raise its interrupt line while one is
+/// - 1: data, reading it takes the next byte or gives 0 if there is none, writing it sends the byte
+#[derive(Debug)]
+pub struct Serial<W> {
+    received: Keyboard,
+    out: W,
+}
+
+impl<W: Write> Serial<W> {
+    /// Gets the bytes from the other end from the channel and sends bytes to it through `out`
+    pub fn new(received: Receiver<u8>, out: W) -> Self {
+        Serial {
+            received: Keyboard::new(received),
+            out,
+        }
+    }
+}
+
+impl<W: Write> Io for Serial<W> {
+    fn read(&mut self, addr: u8) -> u8 {
+        self.received.read(addr)
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        match addr {
+            1 => {
+                // Like a wire with nothing at the other end, bytes that cannot be sent are lost
+                let _ = self.out.write_all(&[val]).and_then(|()| self.out.flush());
+            }
+            _ => self.received.write(addr, val),
+        }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.received.tick(cycles)
+    }
+    fn interrupt_pending(&self) -> bool {
+        self.received.interrupt_pending()
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.received.save_state(state)
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        self.received.restore_state(state)
+    }
+}
+
+#[test]
+fn serial() {
+    use std::sync::mpsc::channel;
+
+    let (send, received) = channel();
+    let mut serial = Serial::new(received, Vec::new());
+    serial.write(1, b'o');
+    serial.write(1, b'k');
+    assert_eq!(serial.out, b"ok");
+
+    send.send(b'!').unwrap();
+    serial.write(0, 0b10);
+    serial.tick(1);
+    assert!(serial.interrupt_pending());
+    assert_eq!(serial.read(0), 0b11);
+    assert_eq!(serial.read(1), b'!');
+    assert_eq!(serial.read(0), 0b10);
+}
@@ -0,0 +1,103 @@
+use std::io::{self, Read};
+
+use crate::mem::Io;
+
+/// Counts down a period in cycles and raises its interrupt line every time it runs out
+///
+/// Its ports are:
+///
+/// - 0 and 1: the period, lower byte first. Writing either starts the count over, a period of 0 stops it
+/// - 2: control, bit 0 makes it count and bit 1 lets it raise its interrupt line
+/// - 3: how many times it ran out since this was last read, up to 255. Reading it acknowledges the interrupt
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    period: u16,
+    control: u8,
+    /// Cycles until it runs out next
+    remaining: u64,
+    expired: u8,
+}
+
+const ENABLE: u8 = 0b01;
+const INTERRUPT_ENABLE: u8 = 0b10;
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Io for Timer {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => self.period.to_le_bytes()[0],
+            1 => self.period.to_le_bytes()[1],
+            2 => self.control,
+            3 => std::mem::take(&mut self.expired),
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        let [mut lower, mut higher] = self.period.to_le_bytes();
+        match addr {
+            0 => lower = val,
+            1 => higher = val,
+            2 => {
+                self.control = val & (ENABLE | INTERRUPT_ENABLE);
+                return;
+            }
+            _ => return,
+        }
+        self.period = u16::from_le_bytes([lower, higher]);
+        self.remaining = self.period as u64;
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        if self.control & ENABLE == 0 || self.period == 0 {
+            return;
+        }
+        while cycles >= self.remaining {
+            cycles -= self.remaining;
+            self.remaining = self.period as u64;
+            self.expired = self.expired.saturating_add(1);
+        }
+        self.remaining -= cycles;
+    }
+    fn interrupt_pending(&self) -> bool {
+        self.control & INTERRUPT_ENABLE != 0 && self.expired > 0
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend(self.period.to_le_bytes());
+        state.push(self.control);
+        state.extend(self.remaining.to_le_bytes());
+        state.push(self.expired);
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        let mut buf = [0; 12];
+        state.read_exact(&mut buf)?;
+        let [p0, p1, control, remaining @ .., expired] = buf;
+        self.period = u16::from_le_bytes([p0, p1]);
+        self.control = control;
+        self.remaining = u64::from_le_bytes(remaining);
+        self.expired = expired;
+        Ok(())
+    }
+}
+
+#[test]
+fn timer() {
+    let mut timer = Timer::new();
+    timer.write(0, 100);
+    timer.write(2, ENABLE);
+    timer.tick(250);
+    assert!(!timer.interrupt_pending());
+    assert_eq!(timer.read(3), 2);
+    assert_eq!(timer.read(3), 0);
+
+    timer.write(2, ENABLE | INTERRUPT_ENABLE);
+    timer.tick(49);
+    assert!(!timer.interrupt_pending());
+    timer.tick(1);
+    assert!(timer.interrupt_pending());
+    assert_eq!(timer.read(3), 1);
+    assert!(!timer.interrupt_pending());
+}
@@ -1,261 +1,360 @@
 use std::fmt::{self, Display, Write};
 
 use crate::{
-    cpu::{ByteRegister, Registers, WideRegister, R0},
-    isa::{arg_imm_wide, arg_pair},
-    mem::{Memory, IO_MAPPING_CUTOFF},
+    cpu::{WideRegister, R0},
+    isa::{decode, registers_in, Instruction},
     U4,
 };
 
-struct StrictMemory<'a> {
-    slice: &'a [u8],
-}
-
-impl Memory for StrictMemory<'_> {
-    fn read(&mut self, addr: u16) -> u8 {
-        if addr < IO_MAPPING_CUTOFF {
-            self.slice.get(addr as usize).copied().unwrap_or(0)
-        } else {
-            unimplemented!("no I/O for strict memory")
-        }
-    }
-    fn write(&mut self, _addr: u16, _val: u8) {
-        unimplemented!("no writing to strict memory")
-    }
+/// How an instruction passes on control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Continues with the next instruction
+    Next,
+    /// Either jumps to the target or continues with the next instruction
+    Branch(u16),
+    /// Always jumps to the target
+    Jump(u16),
+    /// Calls the target, continuing with the next instruction when it returns
+    Call(u16),
+    /// Jumps to an address in a register
+    IndirectJump,
+    /// Calls an address in a register, continuing with the next instruction when it returns
+    IndirectCall,
+    /// Returns or stops the program
+    Stop,
 }
 
 pub struct DisassembledInstruction {
     pub annotated_source: String,
+    /// Just the instruction, without address and bytes
+    pub text: String,
+    /// The decoded instruction, none if it could not be decoded
+    pub instruction: Option<Instruction>,
+    pub flow: Flow,
+    /// An immediate address that is loaded from, stored to or put in a register, which may point to data
+    pub data_reference: Option<u16>,
     pub ends_block: bool,
+    /// The opcode is not a known instruction
+    pub undecodable: bool,
     pub nesting_difference: i32,
     pub next_instruction_location: u16,
 }
 
-fn id<T>(x: T) -> T {
-    x
+type Symbolizer<'a> = Box<dyn Fn(u16) -> Option<String> + 'a>;
+
+/// Disassembles machine code, see [`Disassembler::decode`] and [`Disassembler::instructions`]
+///
+/// Addresses are indices into the code given, like memory.
+pub struct Disassembler<'a> {
+    code: &'a [u8],
+    symbolizer: Option<Symbolizer<'a>>,
+    nearest_symbolizer: Option<Symbolizer<'a>>,
+    show_bytes: bool,
+    follow_calls: bool,
+    linear: bool,
+    max_instructions: Option<usize>,
 }
 
-pub fn disassemble_instruction<'a, F: FnOnce(u16) -> Option<&'a str>>(
-    location: u16,
-    binary_code: &[u8],
-    label_lookup: F,
-) -> DisassembledInstruction {
-    use crate::isa::*;
-    let r = &mut Registers::new(location);
-    let m = &mut StrictMemory { slice: binary_code } as &mut dyn Memory;
+impl<'a> Disassembler<'a> {
+    pub fn new(code: &'a [u8]) -> Self {
+        Disassembler {
+            code,
+            symbolizer: None,
+            nearest_symbolizer: None,
+            show_bytes: true,
+            follow_calls: false,
+            linear: false,
+            max_instructions: None,
+        }
+    }
+    /// Gives names to addresses used as operands, which are shown as numbers otherwise
+    pub fn symbolizer(mut self, symbolizer: impl Fn(u16) -> Option<String> + 'a) -> Self {
+        self.symbolizer = Some(Box::new(symbolizer));
+        self
+    }
+    /// Gives names to the addresses jumped to, called, loaded from or stored to directly that the symbolizer has none
+    /// for, like `<buf+0x2>`
+    ///
+    /// Other immediates, like those of `ldi`, may just be numbers and only get names from the symbolizer.
+    pub fn nearest_symbolizer(mut self, symbolizer: impl Fn(u16) -> Option<String> + 'a) -> Self {
+        self.nearest_symbolizer = Some(Box::new(symbolizer));
+        self
+    }
+    /// Whether the annotated source shows the bytes of the instruction, on by default
+    pub fn show_bytes(mut self, show_bytes: bool) -> Self {
+        self.show_bytes = show_bytes;
+        self
+    }
+    /// Whether iterating goes on at the target of calls instead of after them
+    pub fn follow_calls(mut self, follow_calls: bool) -> Self {
+        self.follow_calls = follow_calls;
+        self
+    }
+    /// Whether iterating goes on past instructions that end a block, like for a whole segment
+    pub fn linear(mut self, linear: bool) -> Self {
+        self.linear = linear;
+        self
+    }
+    /// Stops iterating after this many instructions
+    pub fn max_instructions(mut self, max_instructions: usize) -> Self {
+        self.max_instructions = Some(max_instructions);
+        self
+    }
 
-    let addr = r.program_counter;
-    let opcode = m.read(addr);
-    r.program_counter += 1;
+    pub fn decode(&self, location: u16) -> DisassembledInstruction {
+        let mut name = String::new();
+        disassemble_instruction(location, self.code, self.show_bytes, |p, target| {
+            let exact = self.symbolizer.as_ref().and_then(|s| s(p));
+            name = match (exact, &self.nearest_symbolizer) {
+                (Some(exact), _) => exact,
+                (None, Some(nearest)) if target => nearest(p)?,
+                (None, _) => return None,
+            };
+            Some(&name)
+        })
+    }
+    /// Iterates over the instructions from `start` on, until a block ends unless disassembling linearly
+    pub fn instructions(&self, start: u16) -> Instructions<'_, 'a> {
+        Instructions {
+            disassembler: self,
+            next: Some(start),
+            count: 0,
+        }
+    }
+}
 
-    let mut op = String::with_capacity(32);
-    let f = &mut op;
-    let mut nesting_difference = 0;
-    let mut ends_block = false;
+pub struct Instructions<'d, 'a> {
+    disassembler: &'d Disassembler<'a>,
+    next: Option<u16>,
+    count: usize,
+}
 
-    match opcode {
-        NULL => {
-            write!(f, "null").unwrap();
-            ends_block = true;
-        }
-        HALT => {
-            write!(f, "halt").unwrap();
-            ends_block = true;
-        }
-        CTF => {
-            write!(f, "ctf").unwrap();
-        }
-        RETH => {
-            write!(f, "reth").unwrap();
-            ends_block = true;
-        }
-        NOP => write!(f, "nop").unwrap(),
-        PUSH_B => {
-            let (r1, _) = arg_pair(r, m, ByteRegister, id);
-            write!(f, "push {r1}").unwrap();
-        }
-        PUSH_W => {
-            let (r1, _) = arg_pair(r, m, WideRegister, id);
-            write!(f, "push {r1}").unwrap();
-        }
-        POP_B => {
-            let (r1, _r2) = arg_pair(r, m, ByteRegister, id);
-            write!(f, "pop {r1}").unwrap();
-        }
-        POP_W => {
-            let (r1, _r2) = arg_pair(r, m, WideRegister, id);
-            write!(f, "pop {r1}").unwrap();
-        }
-        CALL => {
-            let w = Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup);
-            write!(f, "call {w}").unwrap();
-            nesting_difference = 1;
-        }
-        RET => {
-            let b = arg_imm_byte(r, m);
-            write!(f, "ret {b}").unwrap();
-            nesting_difference = -1;
-            ends_block = true;
-        }
-        STORE_BI => {
-            let (r1, r2) = arg_pair(r, m, WideRegister, ByteRegister);
-            let offset = Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup);
-            write!(f, "store {r1}, {offset}, {r2}").unwrap();
-        }
-        STORE_WI => {
-            let (r1, r2) = arg_pair(r, m, WideRegister, WideRegister);
-            let offset = Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup);
-            write!(f, "store {r1}, {offset}, {r2}").unwrap();
-        }
-        STORE_BR => {
-            let (r1, r2) = arg_pair(r, m, WideRegister, WideRegister);
-            let (r3, _) = arg_pair(r, m, ByteRegister, id);
-            write!(f, "store {r1}, {r2}, {r3}").unwrap();
-        }
-        STORE_WR => {
-            let (r1, r2) = arg_pair(r, m, WideRegister, WideRegister);
-            let (r3, _) = arg_pair(r, m, WideRegister, id);
-            write!(f, "store {r1}, {r2}, {r3}").unwrap();
-        }
-        LOAD_BI => {
-            let (r1, r2) = arg_pair(r, m, ByteRegister, WideRegister);
-            let offset = Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup);
-            write!(f, "load {r1}, {r2}, {offset}").unwrap();
-        }
-        LOAD_WI => {
-            let (r1, r2) = arg_pair(r, m, WideRegister, WideRegister);
-            let offset = Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup);
-            write!(f, "load {r1}, {r2}, {offset}").unwrap();
-        }
-        LOAD_BR => {
-            let (r1, r2) = arg_pair(r, m, ByteRegister, WideRegister);
-            let (r3, _) = arg_pair(r, m, WideRegister, id);
-            write!(f, "load {r1}, {r2}, {r3}").unwrap();
-        }
-        LOAD_WR => {
-            let (r1, r2) = arg_pair(r, m, WideRegister, WideRegister);
-            let (r3, _) = arg_pair(r, m, WideRegister, id);
-            write!(f, "load {r1}, {r2}, {r3}").unwrap();
-        }
-        JEZ => cjmp("jez", r, m, label_lookup, f),
-        JLT => cjmp("jlt", r, m, label_lookup, f),
-        JLE => cjmp("jle", r, m, label_lookup, f),
-        JGT => cjmp("jgt", r, m, label_lookup, f),
-        JGE => cjmp("jge", r, m, label_lookup, f),
-        JNZ => cjmp("jnz", r, m, label_lookup, f),
-        JO => cjmp("jo", r, m, label_lookup, f),
-        JNO => cjmp("jno", r, m, label_lookup, f),
-        JB => cjmp("jb", r, m, label_lookup, f),
-        JAE => cjmp("jae", r, m, label_lookup, f),
-        JA => cjmp("ja", r, m, label_lookup, f),
-        JBE => cjmp("jbe", r, m, label_lookup, f),
-        LDI_B => {
-            let (r1, _o) = arg_pair(r, m, ByteRegister, id);
-            let b = arg_imm_byte(r, m);
+impl Iterator for Instructions<'_, '_> {
+    type Item = (u16, DisassembledInstruction);
 
-            write!(f, "ldi {r1}, {}", Operand::Byte(b)).unwrap();
+    fn next(&mut self) -> Option<Self::Item> {
+        let d = self.disassembler;
+        if d.max_instructions.is_some_and(|max| self.count >= max) {
+            return None;
         }
-        LDI_W => {
-            let (r1, o) = arg_pair(r, m, WideRegister, u8::from);
-            let w = Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup);
+        let location = self.next?;
+        let ins = d.decode(location);
+        self.count += 1;
 
-            match o {
-                // ldi
-                0 => write!(f, "ldi {r1}, {w}").unwrap(),
-                // jmp, jump
-                1 => {
-                    if r1 == R0 {
-                        // jmp imm
-                        write!(f, "jmp {w}").unwrap();
-                        ends_block = true;
-                    } else {
-                        // jmp r
-                        write!(f, "jmp {r1}").unwrap();
-                        ends_block = true;
-                    }
-                }
-                n => write!(f, "invalid ldi{n}, {r1}, {w}").unwrap(),
-            }
-        }
-        ADD_B => binop("add", ByteRegister, r, m, f),
-        ADD_W => binop("add", WideRegister, r, m, f),
-        SUB_B => binop("sub", ByteRegister, r, m, f),
-        SUB_W => binop("sub", WideRegister, r, m, f),
-        AND_B => binop("and", ByteRegister, r, m, f),
-        AND_W => binop("and", WideRegister, r, m, f),
-        OR_B => binop("or", ByteRegister, r, m, f),
-        OR_W => binop("or", WideRegister, r, m, f),
-        XOR_B => binop("xor", ByteRegister, r, m, f),
-        XOR_W => binop("xor", WideRegister, r, m, f),
-        SHL_B => binop("shl", ByteRegister, r, m, f),
-        SHL_W => binop("shl", WideRegister, r, m, f),
-        ASR_B => binop("asr", ByteRegister, r, m, f),
-        ASR_W => binop("asr", WideRegister, r, m, f),
-        LSR_B => binop("lsr", ByteRegister, r, m, f),
-        LSR_W => binop("lsr", WideRegister, r, m, f),
-        DIV_B => binop("div", ByteRegister, r, m, f),
-        DIV_W => binop("div", WideRegister, r, m, f),
-        MUL_B => binop("mul", ByteRegister, r, m, f),
-        MUL_W => binop("mul", WideRegister, r, m, f),
-        b => {
-            write!(f, "0x{b:02x}").unwrap();
-            ends_block = true;
-        }
+        self.next = match ins.flow {
+            Flow::Call(target) if d.follow_calls => Some(target),
+            _ if ins.ends_block && !d.linear => None,
+            // Wrapped around the end of memory
+            _ if ins.next_instruction_location <= location => None,
+            _ => Some(ins.next_instruction_location),
+        };
+
+        Some((location, ins))
     }
+}
+
+fn disassemble_instruction<'a, F: FnOnce(u16, bool) -> Option<&'a str>>(
+    addr: u16,
+    binary_code: &[u8],
+    show_bytes: bool,
+    label_lookup: F,
+) -> DisassembledInstruction {
+    let mut op = String::with_capacity(32);
 
-    let next_instruction_location = r.program_counter;
+    let (instruction, flow, next_instruction_location) = match decode(addr, binary_code) {
+        Ok((ins, size)) => {
+            write_instruction(&mut op, ins, label_lookup).unwrap();
+            (Some(ins), Flow::of(ins), addr.wrapping_add(size as u16))
+        }
+        Err(_) => {
+            let b = binary_code.get(addr as usize).copied().unwrap_or(0);
+            write!(op, "0x{b:02x}").unwrap();
+            (None, Flow::Stop, addr.wrapping_add(1))
+        }
+    };
+    let nesting_difference = match instruction {
+        Some(Instruction::Call(_) | Instruction::CallRegister(_)) => 1,
+        Some(Instruction::Ret(_)) => -1,
+        _ => 0,
+    };
+    let data_reference = match instruction {
+        Some(
+            Instruction::StoreBI(_, w, _)
+            | Instruction::StoreWI(_, w, _)
+            | Instruction::LoadBI(_, _, w)
+            | Instruction::LoadWI(_, _, w)
+            | Instruction::LoadAddress(_, _, w)
+            | Instruction::LdiW(_, w)
+            | Instruction::CompareW(_, _, w),
+        ) => Some(w),
+        _ => None,
+    };
 
     let mut annotated_source = String::with_capacity(op.len() + 21);
     write!(&mut annotated_source, "  {addr:04x}: ").unwrap();
 
-    if let Some(slice) = binary_code.get(addr as usize..next_instruction_location as usize) {
-        for b in slice {
-            write!(&mut annotated_source, " {b:02x}").unwrap();
-        }
-    } else {
-        for _ in addr as usize..next_instruction_location as usize {
-            write!(&mut annotated_source, " __").unwrap();
+    if show_bytes {
+        let size = next_instruction_location.wrapping_sub(addr);
+        if let Some(slice) = binary_code.get(addr as usize..addr as usize + size as usize) {
+            for b in slice {
+                write!(&mut annotated_source, " {b:02x}").unwrap();
+            }
+        } else {
+            for _ in 0..size {
+                write!(&mut annotated_source, " __").unwrap();
+            }
         }
-    }
 
-    for _ in 0..(4 - (next_instruction_location - addr)) {
+        for _ in size..4 {
+            write!(&mut annotated_source, "   ").unwrap();
+        }
         write!(&mut annotated_source, "   ").unwrap();
     }
-    write!(&mut annotated_source, "    {op}").unwrap();
+    write!(&mut annotated_source, " {op}").unwrap();
 
     DisassembledInstruction {
         annotated_source,
+        text: op,
+        instruction,
+        flow,
+        data_reference,
         next_instruction_location,
-        ends_block,
+        ends_block: matches!(flow, Flow::Stop | Flow::Jump(_) | Flow::IndirectJump),
+        undecodable: instruction.is_none(),
         nesting_difference,
     }
 }
 
-fn cjmp<'a, F: FnOnce(u16) -> Option<&'a str>>(
-    name: &str,
-    r: &mut Registers,
-    m: &mut dyn Memory,
+impl Flow {
+    pub fn of(ins: Instruction) -> Self {
+        use crate::isa::Instruction as I;
+        match ins {
+            I::Null | I::Halt | I::HaltB(_) | I::Reth | I::Reti | I::Ret(_) => Flow::Stop,
+            I::Call(target) => Flow::Call(target),
+            I::JumpIf(_, target) => Flow::Branch(target),
+            I::Jump(target) => Flow::Jump(target),
+            I::JumpRegister(_) => Flow::IndirectJump,
+            I::CallRegister(_) => Flow::IndirectCall,
+            _ => Flow::Next,
+        }
+    }
+}
+
+fn register_list(mask: u16) -> String {
+    let names: Vec<_> = registers_in(mask).map(|r| r.to_string()).collect();
+    names.join(", ")
+}
+
+/// Names the register pair with `r` as its upper half like `r1:r2`, or `r0` for the zero pair
+fn register_pair(r: WideRegister) -> String {
+    if r == R0 {
+        return r.to_string();
+    }
+    let lower = WideRegister(U4::new(u8::from(r.0) + 1));
+    format!("{r}:{lower}")
+}
+
+/// Writes the instruction like it is written in source, naming addresses with `label_lookup`
+///
+/// It is told whether the address is a target, jumped to, called or accessed with no base register.
+fn write_instruction<'a, F: FnOnce(u16, bool) -> Option<&'a str>>(
+    f: &mut dyn fmt::Write,
+    ins: Instruction,
     label_lookup: F,
-    f: &mut String,
-) {
-    write!(
-        f,
-        "{name} {}",
-        Operand::Wide(arg_imm_wide(r, m)).looked_up(label_lookup)
-    )
-    .unwrap();
+) -> fmt::Result {
+    use crate::isa::Instruction as I;
+    let wide = |w, target| Operand::Wide(w).looked_up(|w| label_lookup(w, target));
+
+    match ins {
+        I::Null => write!(f, "null"),
+        I::Halt => write!(f, "halt"),
+        I::HaltB(r1) => write!(f, "halt {r1}"),
+        I::Ctf => write!(f, "ctf"),
+        I::Reth => write!(f, "reth"),
+        I::Int(b) => write!(f, "int {}", Operand::Byte(b)),
+        I::Ei => write!(f, "ei"),
+        I::Di => write!(f, "di"),
+        I::Reti => write!(f, "reti"),
+        I::Ivt(r1) => write!(f, "ivt {r1}"),
+        I::Nop => write!(f, "nop"),
+        I::PushB(r1) => write!(f, "push {r1}"),
+        I::PushW(r1) => write!(f, "push {r1}"),
+        I::PopB(r1) => write!(f, "pop {r1}"),
+        I::PopW(r1) => write!(f, "pop {r1}"),
+        I::Enter(size) => write!(f, "enter {}", Operand::Wide(size)),
+        I::Leave => write!(f, "leave"),
+        I::PushMany(mask) => write!(f, "pushm {}", register_list(mask)),
+        I::PopMany(mask) => write!(f, "popm {}", register_list(mask)),
+        I::Call(w) => write!(f, "call {}", wide(w, true)),
+        I::CallRegister(r1) => write!(f, "call {r1}"),
+        I::Ret(b) => write!(f, "ret {b}"),
+        I::StoreBI(r1, offset, r2) => write!(f, "store {r1}, {}, {r2}", wide(offset, r1 == R0)),
+        I::StoreWI(r1, offset, r2) => write!(f, "store {r1}, {}, {r2}", wide(offset, r1 == R0)),
+        I::StoreBR(r1, r2, r3) => write!(f, "store {r1}, {r2}, {r3}"),
+        I::StoreWR(r1, r2, r3) => write!(f, "store {r1}, {r2}, {r3}"),
+        I::LoadBI(r1, r2, offset) => write!(f, "load {r1}, {r2}, {}", wide(offset, r2 == R0)),
+        I::LoadWI(r1, r2, offset) => write!(f, "load {r1}, {r2}, {}", wide(offset, r2 == R0)),
+        I::CopyMemory(r1, r2, r3) => write!(f, "mcopy {r1}, {r2}, {r3}"),
+        I::FillMemory(r1, r2, r3) => write!(f, "mfill {r1}, {r2}, {r3}"),
+        I::ExchangeAdd(r1, r2) => write!(f, "xadd {r1}, {r2}"),
+        I::CompareSwap(r1, r2, r3) => write!(f, "cas {r1}, {r2}, {r3}"),
+        I::LoadAddress(r1, r2, offset) => write!(f, "lea {r1}, {r2}, {}", wide(offset, r2 == R0)),
+        I::LoadBR(r1, r2, r3) => write!(f, "load {r1}, {r2}, {r3}"),
+        I::LoadWR(r1, r2, r3) => write!(f, "load {r1}, {r2}, {r3}"),
+        I::JumpIf(cond, w) => write!(f, "{} {}", cond.mnemonic(), wide(w, true)),
+        I::LdiB(r1, b) => write!(f, "ldi {r1}, {}", Operand::Byte(b)),
+        I::LdiW(r1, w) => write!(f, "ldi {r1}, {}", wide(w, false)),
+        I::Jump(w) => write!(f, "jmp {}", wide(w, true)),
+        I::JumpRegister(r1) => write!(f, "jmp {r1}"),
+        I::BinaryB(op, r1, r2, r3) => write!(f, "{} {r1}, {r2}, {r3}", op.mnemonic()),
+        I::BinaryW(op, r1, r2, r3) => write!(f, "{} {r1}, {r2}, {r3}", op.mnemonic()),
+        I::BinaryImmB(op, r1, r2, b) => write!(f, "{} {r1}, {r2}, {b}", op.mnemonic()),
+        I::BinaryImmW(op, r1, r2, b) => write!(f, "{} {r1}, {r2}, {b}", op.mnemonic()),
+        I::UnaryB(op, r1, r2) => write!(f, "{} {r1}, {r2}", op.mnemonic()),
+        I::BitB(op, r1, n) => write!(f, "{} {r1}, {n}", op.mnemonic()),
+        I::BitW(op, r1, n) => write!(f, "{} {r1}, {n}", op.mnemonic()),
+        I::UnaryW(op, r1, r2) => write!(f, "{} {r1}, {r2}", op.mnemonic()),
+        I::SignExtend(r1, r2) => write!(f, "movsx {r1}, {r2}"),
+        I::ZeroExtend(r1, r2) => write!(f, "movzx {r1}, {r2}"),
+        I::ExchangeB(r1, r2) => write!(f, "xchg {r1}, {r2}"),
+        I::ExchangeW(r1, r2) => write!(f, "xchg {r1}, {r2}"),
+        I::SwapBytes(r1, r2) => write!(f, "bswap {r1}, {r2}"),
+        I::CompareB(op, r1, b) => write!(f, "{} {r1}, {}", op.mnemonic(), Operand::Byte(b)),
+        I::CompareW(op, r1, w) => write!(f, "{} {r1}, {}", op.mnemonic(), wide(w, false)),
+        I::MulB(r1, r2, r3, r4) => write!(f, "mul {r1}, {r2}, {r3}, {r4}"),
+        I::MulW(r1, r2, r3, r4) => write!(f, "mul {r1}, {r2}, {r3}, {r4}"),
+        I::DivB(r1, r2, r3, r4) => write!(f, "div {r1}, {r2}, {r3}, {r4}"),
+        I::DivW(r1, r2, r3, r4) => write!(f, "div {r1}, {r2}, {r3}, {r4}"),
+        I::IMulB(r1, r2, r3, r4) => write!(f, "imul {r1}, {r2}, {r3}, {r4}"),
+        I::IMulW(r1, r2, r3, r4) => write!(f, "imul {r1}, {r2}, {r3}, {r4}"),
+        I::IDivB(r1, r2, r3, r4) => write!(f, "idiv {r1}, {r2}, {r3}, {r4}"),
+        I::IDivW(r1, r2, r3, r4) => write!(f, "idiv {r1}, {r2}, {r3}, {r4}"),
+        I::BinaryL(op, r1, r2, r3) => {
+            let (r1, r2, r3) = (register_pair(r1), register_pair(r2), register_pair(r3));
+            write!(f, "{} {r1}, {r2}, {r3}", op.mnemonic())
+        }
+        I::BinaryImmL(op, r1, r2, b) => {
+            let (r1, r2) = (register_pair(r1), register_pair(r2));
+            write!(f, "{} {r1}, {r2}, {b}", op.mnemonic())
+        }
+        I::MulL(r1, r2, r3, r4) | I::DivL(r1, r2, r3, r4) => {
+            let mnemonic = if let I::MulL(..) = ins { "mul" } else { "div" };
+            let [r1, r2, r3, r4] = [r1, r2, r3, r4].map(register_pair);
+            write!(f, "{mnemonic} {r1}, {r2}, {r3}, {r4}")
+        }
+        I::MulHigh(r1, r2, r3) => write!(f, "mulh {r1}, {r2}, {r3}"),
+        I::IMulHigh(r1, r2, r3) => write!(f, "imulh {r1}, {r2}, {r3}"),
+        I::FixedMul(r1, r2, r3, b) => write!(f, "fmul {r1}, {r2}, {r3}, {b}"),
+        I::SignExtendL(r1, r2) => write!(f, "movsx {}, {r2}", register_pair(r1)),
+        I::ZeroExtendL(r1, r2) => write!(f, "movzx {}, {r2}", register_pair(r1)),
+    }
 }
 
-fn binop<T: Display, RF: Fn(U4) -> T>(
-    name: &str,
-    rf: RF,
-    r: &mut Registers,
-    m: &mut dyn Memory,
-    f: &mut String,
-) {
-    let (r1, r2) = arg_pair(r, m, &rf, &rf);
-    let (r3, _o) = arg_pair(r, m, &rf, id);
-    write!(f, "{name} {r1}, {r2}, {r3}").unwrap();
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instruction(f, *self, |_, _| None)
+    }
 }
 
 enum Operand<'a> {
@@ -287,3 +386,49 @@ impl Display for Operand<'_> {
         }
     }
 }
+
+#[test]
+fn disassemble_block() {
+    // ldi r1, 0x010; call 0x0000; halt; nop
+    let code = [0x40, 0x10, 0x10, 0x00, 0x25, 0x00, 0x00, 0x0a, 0x20];
+    let disassembler = Disassembler::new(&code)
+        .show_bytes(false)
+        .symbolizer(|p| (p == 0).then(|| "start".to_owned()));
+
+    let texts: Vec<_> = disassembler
+        .instructions(0)
+        .map(|(_, ins)| ins.text)
+        .collect();
+    assert_eq!(texts, ["ldi r1, 0x010", "call start", "halt"]);
+    assert_eq!(disassembler.decode(7).annotated_source, "  0007:  halt");
+}
+
+#[test]
+fn nearest_symbols() {
+    // ldi r3, 0x005; call 0x0005; load r1, r2, 0x005; load r1, r0, 0x005
+    let code = [
+        0x40, 0x30, 0x05, 0x00, 0x25, 0x05, 0x00, 0x2c, 0x12, 0x05, 0x00, 0x2c, 0x10, 0x05, 0x00,
+    ];
+    let disassembler = Disassembler::new(&code)
+        .show_bytes(false)
+        .symbolizer(|p| (p == 0).then(|| "start".to_owned()))
+        .nearest_symbolizer(|p| Some(format!("<start+0x{p:x}>")));
+
+    let texts: Vec<_> = (0..4)
+        .scan(0, |location, _| {
+            let ins = disassembler.decode(*location);
+            *location = ins.next_instruction_location;
+            Some(ins.text)
+        })
+        .collect();
+    // Small constants stay numbers, only targets get the nearest symbol
+    assert_eq!(
+        texts,
+        [
+            "ldi r3, 0x005",
+            "call <start+0x5>",
+            "load r1, r2, 0x005",
+            "load r1, r0, <start+0x5>"
+        ]
+    );
+}
@@ -0,0 +1,855 @@
+use core::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use crate::{
+    cpu::{ByteRegister as Br, Registers, WideRegister as Wr, R0, RS},
+    U4,
+};
+
+use super::*;
+
+/// Condition of a conditional jump, on the flags of the last arithmetic
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Ez,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Nz,
+    O,
+    No,
+    A,
+    Ae,
+    B,
+    Be,
+}
+
+impl Condition {
+    pub fn holds(self, r: &Registers) -> bool {
+        use self::Condition::*;
+        match self {
+            Ez => r.zero,
+            Lt => r.sign != r.overflow,
+            Le => r.sign != r.overflow && r.zero,
+            Gt => r.sign == r.overflow && !r.zero,
+            Ge => r.sign == r.overflow,
+            Nz => !r.zero,
+            O => r.overflow,
+            No => !r.overflow,
+            A => !r.carry && !r.zero,
+            Ae => !r.carry,
+            B => r.carry,
+            Be => r.carry || r.zero,
+        }
+    }
+    pub fn mnemonic(self) -> &'static str {
+        use self::Condition::*;
+        match self {
+            Ez => "jez",
+            Lt => "jlt",
+            Le => "jle",
+            Gt => "jgt",
+            Ge => "jge",
+            Nz => "jnz",
+            O => "jo",
+            No => "jno",
+            A => "ja",
+            Ae => "jae",
+            B => "jb",
+            Be => "jbe",
+        }
+    }
+}
+
+/// Arithmetic taking two registers, storing the result in a third and setting the flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Asr,
+    Lsr,
+    Rol,
+    Ror,
+    /// Rotates through the carry flag
+    Rcl,
+    Rcr,
+    /// Adds the carry flag too
+    Adc,
+    /// Subtracts the carry flag too, as the borrow
+    Sbb,
+}
+
+impl BinaryOp {
+    pub fn mnemonic(self) -> &'static str {
+        use self::BinaryOp::*;
+        match self {
+            Add => "add",
+            Sub => "sub",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Shl => "shl",
+            Asr => "asr",
+            Lsr => "lsr",
+            Rol => "rol",
+            Ror => "ror",
+            Rcl => "rcl",
+            Rcr => "rcr",
+            Adc => "adc",
+            Sbb => "sbb",
+        }
+    }
+    /// Whether the result depends on the carry flag going in
+    pub fn uses_carry(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Rcl | BinaryOp::Rcr | BinaryOp::Adc | BinaryOp::Sbb
+        )
+    }
+}
+
+/// Arithmetic on one register, storing the result in another and setting the flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+impl UnaryOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            UnaryOp::Not => "not",
+            UnaryOp::Neg => "neg",
+        }
+    }
+}
+
+/// A binary operation with an immediate done only for the flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompareOp {
+    /// Subtracts
+    Cmp,
+    /// Ands
+    Test,
+}
+
+impl CompareOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            CompareOp::Cmp => "cmp",
+            CompareOp::Test => "test",
+        }
+    }
+    pub fn binary(self) -> BinaryOp {
+        match self {
+            CompareOp::Cmp => BinaryOp::Sub,
+            CompareOp::Test => BinaryOp::And,
+        }
+    }
+}
+
+/// What is done with a single bit of a register, which all set zero if it was clear before
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitOp {
+    Test,
+    Set,
+    Clear,
+    Toggle,
+}
+
+impl BitOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            BitOp::Test => "btst",
+            BitOp::Set => "bset",
+            BitOp::Clear => "bclr",
+            BitOp::Toggle => "btgl",
+        }
+    }
+    /// The value with the bits of the mask changed, none if it is only tested
+    pub fn apply(self, x: u16, mask: u16) -> Option<u16> {
+        match self {
+            BitOp::Test => None,
+            BitOp::Set => Some(x | mask),
+            BitOp::Clear => Some(x & !mask),
+            BitOp::Toggle => Some(x ^ mask),
+        }
+    }
+}
+
+/// A decoded instruction with its operands in the order they are written in source
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Null,
+    Halt,
+    /// Halts with the register as the exit code
+    HaltB(Br),
+    Ctf,
+    Reth,
+    /// Software interrupt with the vector
+    Int(u8),
+    Ei,
+    Di,
+    Reti,
+    /// Sets the location of the vector table
+    Ivt(Wr),
+    Nop,
+    PushB(Br),
+    PushW(Wr),
+    PopB(Br),
+    PopW(Wr),
+    /// Pushes the registers of the mask, the highest first so that the lowest ends up on top
+    PushMany(u16),
+    /// Pops the registers of the mask pushed like [`Instruction::PushMany`], the lowest first
+    PopMany(u16),
+    /// Pushes `rf`, points it at the stack and makes room for the locals below it
+    Enter(u16),
+    /// Gives back the stack of the frame and pops `rf`, undoing [`Instruction::Enter`]
+    Leave,
+    Call(u16),
+    Ret(u8),
+    /// Stores the last register at the first register plus the offset
+    StoreBI(Wr, u16, Br),
+    StoreWI(Wr, u16, Wr),
+    StoreBR(Wr, Wr, Br),
+    StoreWR(Wr, Wr, Wr),
+    /// Loads into the first register from the second register plus the offset
+    LoadBI(Br, Wr, u16),
+    LoadWI(Wr, Wr, u16),
+    LoadBR(Br, Wr, Wr),
+    LoadWR(Wr, Wr, Wr),
+    /// Puts the second register plus the offset in the first, the address [`Instruction::LoadWI`] would read
+    LoadAddress(Wr, Wr, u16),
+    /// Copies as many bytes as the last register says from where the second points to where the first does
+    CopyMemory(Wr, Wr, Wr),
+    /// Fills as many bytes as the last register says where the first points with the byte
+    FillMemory(Wr, Br, Wr),
+    /// Adds the second register to the wide at the address in the first, which the second gets
+    ExchangeAdd(Wr, Wr),
+    /// Compares the wide at the address in the first register with the second, storing the third if they are equal
+    /// and loading the wide into the second if not
+    CompareSwap(Wr, Wr, Wr),
+    JumpIf(Condition, u16),
+    LdiB(Br, u8),
+    LdiW(Wr, u16),
+    Jump(u16),
+    JumpRegister(Wr),
+    CallRegister(Wr),
+    BinaryB(BinaryOp, Br, Br, Br),
+    BinaryW(BinaryOp, Wr, Wr, Wr),
+    /// Like the binary operations with an immediate byte as the second operand, only for shifts and rotates
+    BinaryImmB(BinaryOp, Br, Br, u8),
+    BinaryImmW(BinaryOp, Wr, Wr, u8),
+    UnaryB(UnaryOp, Br, Br),
+    UnaryW(UnaryOp, Wr, Wr),
+    /// Copies the byte into the wide, filling the upper byte with its sign bit
+    SignExtend(Wr, Br),
+    /// Copies the byte into the wide, clearing the upper byte
+    ZeroExtend(Wr, Br),
+    ExchangeB(Br, Br),
+    ExchangeW(Wr, Wr),
+    SwapBytes(Wr, Wr),
+    CompareB(CompareOp, Br, u8),
+    CompareW(CompareOp, Wr, u16),
+    /// The index of the bit goes modulo the width of the register like shift amounts do
+    BitB(BitOp, Br, u8),
+    BitW(BitOp, Wr, u8),
+    /// Upper and lower half of the result, then the factors
+    MulB(Br, Br, Br, Br),
+    MulW(Wr, Wr, Wr, Wr),
+    /// Quotient and remainder, then the dividend and divisor
+    DivB(Br, Br, Br, Br),
+    DivW(Wr, Wr, Wr, Wr),
+    /// Like the unsigned ones, rounding the quotient towards zero
+    IMulB(Br, Br, Br, Br),
+    IMulW(Wr, Wr, Wr, Wr),
+    IDivB(Br, Br, Br, Br),
+    IDivW(Wr, Wr, Wr, Wr),
+    /// Like [`Instruction::BinaryW`] on the register pairs with these upper halves
+    BinaryL(BinaryOp, Wr, Wr, Wr),
+    BinaryImmL(BinaryOp, Wr, Wr, u8),
+    MulL(Wr, Wr, Wr, Wr),
+    DivL(Wr, Wr, Wr, Wr),
+    SignExtendL(Wr, Wr),
+    ZeroExtendL(Wr, Wr),
+    /// The upper wide of the product of the other two
+    MulHigh(Wr, Wr, Wr),
+    IMulHigh(Wr, Wr, Wr),
+    /// The signed product of the second and third shifted right by the amount modulo 32
+    FixedMul(Wr, Wr, Wr, u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode(u8),
+    /// Operands that the opcode does not allow, like a reserved nibble that is not zero
+    InvalidOperands(u8),
+    /// The code ends before the operands do
+    Truncated,
+}
+
+/// The wide registers with their bit set in the mask of `pushm` and `popm`, lowest first
+pub fn registers_in(mask: u16) -> impl DoubleEndedIterator<Item = Wr> {
+    (0..16)
+        .filter(move |n| mask & 1 << n != 0)
+        .map(|n| Wr(U4::new(n)))
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(op) => write!(f, "unknown opcode 0x{op:02x}"),
+            DecodeError::InvalidOperands(op) => {
+                write!(f, "invalid operands for opcode 0x{op:02x}")
+            }
+            DecodeError::Truncated => write!(f, "instruction is cut off"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// How many bytes of operands follow the opcode, if it is a known one
+pub const fn operand_size(opcode: u8) -> Option<u16> {
+    Some(match opcode {
+        NULL | HALT | CTF | RETH | NOP | EI | DI | RETI | LEAVE => 0,
+        PUSH_B | PUSH_W | POP_B | POP_W | RET | INT | IVT | HALT_B | CALL_R => 1,
+        CALL | JEZ | JLT | JLE | JGT | JGE | JNZ | JO | JNO | JA | JAE | JB | JBE => 2,
+        PUSHM | POPM | ENTER => 2,
+        JMP_S | JEZ_S | JLT_S | JLE_S | JGT_S | JGE_S | JNZ_S | JO_S | JNO_S | JA_S | JAE_S
+        | JB_S | JBE_S => 1,
+        STORE_BI | STORE_WI | LOAD_BI | LOAD_WI | LDI_W | LEA => 3,
+        STORE_BR | STORE_WR | LOAD_BR | LOAD_WR | LDI_B => 2,
+        ADD_B | ADD_W | SUB_B | SUB_W | AND_B | AND_W | OR_B | OR_W | XOR_B | XOR_W | SHL_B
+        | SHL_W | ASR_B | ASR_W | LSR_B | LSR_W | DIV_B | DIV_W | MUL_B | MUL_W => 2,
+        IMUL_B | IMUL_W | IDIV_B | IDIV_W => 2,
+        SHL_BI | SHL_WI | ASR_BI | ASR_WI | LSR_BI | LSR_WI => 2,
+        ROL_B | ROL_W | ROR_B | ROR_W | RCL_B | RCL_W | RCR_B | RCR_W => 2,
+        ROL_BI | ROL_WI | ROR_BI | ROR_WI | RCL_BI | RCL_WI | RCR_BI | RCR_WI => 2,
+        NOT_B | NOT_W | NEG_B | NEG_W => 1,
+        CMP_B | TEST_B => 2,
+        CMP_W | TEST_W => 3,
+        ADC_B | ADC_W | SBB_B | SBB_W => 2,
+        MOVSX | MOVZX => 1,
+        XCHG_B | XCHG_W | BSWAP => 1,
+        MCOPY | MFILL => 2,
+        BTST_B | BTST_W | BSET_B | BSET_W | BCLR_B | BCLR_W | BTGL_B | BTGL_W => 2,
+        ADD_L | SUB_L | AND_L | OR_L | XOR_L | SHL_L | ASR_L | LSR_L => 2,
+        SHL_LI | ASR_LI | LSR_LI | MUL_L | DIV_L => 2,
+        MOVSX_L | MOVZX_L => 1,
+        MULH | IMULH => 2,
+        FMUL => 3,
+        XADD => 1,
+        CAS => 2,
+        _ => return None,
+    })
+}
+
+/// How many cycles bytes of data read or written cost on top of the instruction, each
+pub const MEMORY_ACCESS_CYCLES: u64 = 2;
+
+/// How many cycles the opcode takes, not counting its memory accesses, if it is a known one
+///
+/// Instructions that read or write memory, including the stack and I/O, cost [`MEMORY_ACCESS_CYCLES`] more per byte.
+pub const fn cycles(opcode: u8) -> Option<u64> {
+    Some(match opcode {
+        NULL | HALT | HALT_B | CTF | NOP | LDI_B | LDI_W | LEA | EI | DI | IVT => 1,
+        ADD_B | ADD_W | SUB_B | SUB_W | AND_B | AND_W | OR_B | OR_W | XOR_B | XOR_W | SHL_B
+        | SHL_W | ASR_B | ASR_W | LSR_B | LSR_W => 1,
+        SHL_BI | SHL_WI | ASR_BI | ASR_WI | LSR_BI | LSR_WI => 1,
+        ROL_B | ROL_W | ROR_B | ROR_W | RCL_B | RCL_W | RCR_B | RCR_W => 1,
+        ROL_BI | ROL_WI | ROR_BI | ROR_WI | RCL_BI | RCL_WI | RCR_BI | RCR_WI => 1,
+        NOT_B | NOT_W | NEG_B | NEG_W | CMP_B | CMP_W | TEST_B | TEST_W => 1,
+        ADC_B | ADC_W | SBB_B | SBB_W | MOVSX | MOVZX => 1,
+        XCHG_B | XCHG_W | BSWAP => 1,
+        BTST_B | BTST_W | BSET_B | BSET_W | BCLR_B | BCLR_W | BTGL_B | BTGL_W => 1,
+        PUSH_B | PUSH_W | POP_B | POP_W | PUSHM | POPM | ENTER | LEAVE => 1,
+        STORE_BI | STORE_WI | STORE_BR | STORE_WR | LOAD_BI | LOAD_WI | LOAD_BR | LOAD_WR => 2,
+        MCOPY | MFILL => 2,
+        JEZ | JLT | JLE | JGT | JGE | JNZ | JO | JNO | JA | JAE | JB | JBE => 2,
+        JMP_S | JEZ_S | JLT_S | JLE_S | JGT_S | JGE_S | JNZ_S | JO_S | JNO_S | JA_S | JAE_S
+        | JB_S | JBE_S => 2,
+        CALL | CALL_R | RET | RETH | RETI | INT => 3,
+        XADD | CAS => 3,
+        MOVSX_L | MOVZX_L => 1,
+        ADD_L | SUB_L | AND_L | OR_L | XOR_L | SHL_L | ASR_L | LSR_L => 2,
+        SHL_LI | ASR_LI | LSR_LI => 2,
+        MUL_B | IMUL_B => 4,
+        MUL_W | IMUL_W | MULH | IMULH | FMUL => 8,
+        DIV_B | IDIV_B => 12,
+        DIV_W | IDIV_W => 24,
+        MUL_L => 16,
+        DIV_L => 48,
+        _ => return None,
+    })
+}
+
+/// Decodes the instruction at `addr` in the code, giving its size in bytes with it
+pub fn decode(addr: u16, code: &[u8]) -> Result<(Instruction, usize), DecodeError> {
+    let bytes = code.get(addr as usize..).ok_or(DecodeError::Truncated)?;
+    decode_at(addr, bytes)
+}
+
+/// Decodes the instruction at the start of `bytes`, which are at `location` for the jumps relative to it
+pub fn decode_at(location: u16, bytes: &[u8]) -> Result<(Instruction, usize), DecodeError> {
+    use self::Instruction as I;
+
+    let &opcode = bytes.first().ok_or(DecodeError::Truncated)?;
+    let size = operand_size(opcode).ok_or(DecodeError::UnknownOpcode(opcode))? as usize;
+    let operands = bytes.get(1..1 + size).ok_or(DecodeError::Truncated)?;
+
+    let invalid = DecodeError::InvalidOperands(opcode);
+    let pair = |i: usize| U4::paired(operands[i]);
+    // A pair whose second nibble is reserved and has to be zero
+    let single = |i: usize| {
+        let (a, z) = pair(i);
+        if z == U4::ZERO {
+            Ok(a)
+        } else {
+            Err(invalid)
+        }
+    };
+    let byte = |i: usize| operands[i];
+    let wide = |i: usize| u16::from_le_bytes([operands[i], operands[i + 1]]);
+    // Counted from the end of the instruction
+    let relative = |i: usize| {
+        let end = location.wrapping_add(1 + size as u16);
+        end.wrapping_add(operands[i] as i8 as u16)
+    };
+    let binary_b = |op| {
+        let (r1, r2) = pair(0);
+        Ok(I::BinaryB(op, Br(r1), Br(r2), Br(single(1)?)))
+    };
+    let binary_w = |op| {
+        let (r1, r2) = pair(0);
+        Ok(I::BinaryW(op, Wr(r1), Wr(r2), Wr(single(1)?)))
+    };
+    let binary_imm_b = |op| {
+        let (r1, r2) = pair(0);
+        I::BinaryImmB(op, Br(r1), Br(r2), byte(1))
+    };
+    let binary_imm_w = |op| {
+        let (r1, r2) = pair(0);
+        I::BinaryImmW(op, Wr(r1), Wr(r2), byte(1))
+    };
+    let unary_b = |op| {
+        let (r1, r2) = pair(0);
+        I::UnaryB(op, Br(r1), Br(r2))
+    };
+    let unary_w = |op| {
+        let (r1, r2) = pair(0);
+        I::UnaryW(op, Wr(r1), Wr(r2))
+    };
+    let four = || {
+        let ((r1, r2), (r3, r4)) = (pair(0), pair(1));
+        (r1, r2, r3, r4)
+    };
+    // The upper half of a register pair, whose lower half is the next general purpose register
+    let long = |r: U4| {
+        if u8::from(r) < 10 {
+            Ok(Wr(r))
+        } else {
+            Err(invalid)
+        }
+    };
+    let binary_l = |op| {
+        let (r1, r2) = pair(0);
+        Ok(I::BinaryL(op, long(r1)?, long(r2)?, long(single(1)?)?))
+    };
+    let binary_imm_l = |op| {
+        let (r1, r2) = pair(0);
+        Ok(I::BinaryImmL(op, long(r1)?, long(r2)?, byte(1)))
+    };
+    let four_l = || {
+        let (r1, r2, r3, r4) = four();
+        Ok((long(r1)?, long(r2)?, long(r3)?, long(r4)?))
+    };
+
+    let ins = match opcode {
+        NULL => I::Null,
+        HALT => I::Halt,
+        HALT_B => I::HaltB(Br(single(0)?)),
+        CTF => I::Ctf,
+        RETH => I::Reth,
+        INT => I::Int(byte(0)),
+        EI => I::Ei,
+        DI => I::Di,
+        RETI => I::Reti,
+        IVT => I::Ivt(Wr(single(0)?)),
+        NOP => I::Nop,
+        PUSH_B => I::PushB(Br(single(0)?)),
+        PUSH_W => I::PushW(Wr(single(0)?)),
+        POP_B => I::PopB(Br(single(0)?)),
+        POP_W => I::PopW(Wr(single(0)?)),
+        // The stack pointer moves as they go, so it cannot be one of them
+        PUSHM | POPM if wide(0) & 1 << u8::from(RS.0) != 0 => return Err(invalid),
+        PUSHM => I::PushMany(wide(0)),
+        POPM => I::PopMany(wide(0)),
+        ENTER => I::Enter(wide(0)),
+        LEAVE => I::Leave,
+        CALL => I::Call(wide(0)),
+        CALL_R => I::CallRegister(Wr(single(0)?)),
+        RET => I::Ret(byte(0)),
+        STORE_BI => {
+            let (r1, r2) = pair(0);
+            I::StoreBI(Wr(r1), wide(1), Br(r2))
+        }
+        STORE_WI => {
+            let (r1, r2) = pair(0);
+            I::StoreWI(Wr(r1), wide(1), Wr(r2))
+        }
+        STORE_BR => {
+            let (r1, r2) = pair(0);
+            I::StoreBR(Wr(r1), Wr(r2), Br(single(1)?))
+        }
+        STORE_WR => {
+            let (r1, r2) = pair(0);
+            I::StoreWR(Wr(r1), Wr(r2), Wr(single(1)?))
+        }
+        LOAD_BI => {
+            let (r1, r2) = pair(0);
+            I::LoadBI(Br(r1), Wr(r2), wide(1))
+        }
+        LOAD_WI => {
+            let (r1, r2) = pair(0);
+            I::LoadWI(Wr(r1), Wr(r2), wide(1))
+        }
+        LEA => {
+            let (r1, r2) = pair(0);
+            I::LoadAddress(Wr(r1), Wr(r2), wide(1))
+        }
+        MCOPY => {
+            let (r1, r2) = pair(0);
+            I::CopyMemory(Wr(r1), Wr(r2), Wr(single(1)?))
+        }
+        MFILL => {
+            let (r1, r3) = pair(0);
+            I::FillMemory(Wr(r1), Br(single(1)?), Wr(r3))
+        }
+        XADD => {
+            let (r1, r2) = pair(0);
+            I::ExchangeAdd(Wr(r1), Wr(r2))
+        }
+        CAS => {
+            let (r1, r2) = pair(0);
+            I::CompareSwap(Wr(r1), Wr(r2), Wr(single(1)?))
+        }
+        LOAD_BR => {
+            let (r1, r2) = pair(0);
+            I::LoadBR(Br(r1), Wr(r2), Wr(single(1)?))
+        }
+        LOAD_WR => {
+            let (r1, r2) = pair(0);
+            I::LoadWR(Wr(r1), Wr(r2), Wr(single(1)?))
+        }
+        JEZ => I::JumpIf(Condition::Ez, wide(0)),
+        JLT => I::JumpIf(Condition::Lt, wide(0)),
+        JLE => I::JumpIf(Condition::Le, wide(0)),
+        JGT => I::JumpIf(Condition::Gt, wide(0)),
+        JGE => I::JumpIf(Condition::Ge, wide(0)),
+        JNZ => I::JumpIf(Condition::Nz, wide(0)),
+        JO => I::JumpIf(Condition::O, wide(0)),
+        JNO => I::JumpIf(Condition::No, wide(0)),
+        JA => I::JumpIf(Condition::A, wide(0)),
+        JAE => I::JumpIf(Condition::Ae, wide(0)),
+        JB => I::JumpIf(Condition::B, wide(0)),
+        JBE => I::JumpIf(Condition::Be, wide(0)),
+        JMP_S => I::Jump(relative(0)),
+        JEZ_S => I::JumpIf(Condition::Ez, relative(0)),
+        JLT_S => I::JumpIf(Condition::Lt, relative(0)),
+        JLE_S => I::JumpIf(Condition::Le, relative(0)),
+        JGT_S => I::JumpIf(Condition::Gt, relative(0)),
+        JGE_S => I::JumpIf(Condition::Ge, relative(0)),
+        JNZ_S => I::JumpIf(Condition::Nz, relative(0)),
+        JO_S => I::JumpIf(Condition::O, relative(0)),
+        JNO_S => I::JumpIf(Condition::No, relative(0)),
+        JA_S => I::JumpIf(Condition::A, relative(0)),
+        JAE_S => I::JumpIf(Condition::Ae, relative(0)),
+        JB_S => I::JumpIf(Condition::B, relative(0)),
+        JBE_S => I::JumpIf(Condition::Be, relative(0)),
+        LDI_B => I::LdiB(Br(single(0)?), byte(1)),
+        LDI_W => {
+            let (r1, o) = pair(0);
+            match u8::from(o) {
+                0 => I::LdiW(Wr(r1), wide(1)),
+                1 if Wr(r1) == R0 => I::Jump(wide(1)),
+                1 => I::JumpRegister(Wr(r1)),
+                _ => return Err(invalid),
+            }
+        }
+        ADD_B => binary_b(BinaryOp::Add)?,
+        ADD_W => binary_w(BinaryOp::Add)?,
+        SUB_B => binary_b(BinaryOp::Sub)?,
+        SUB_W => binary_w(BinaryOp::Sub)?,
+        AND_B => binary_b(BinaryOp::And)?,
+        AND_W => binary_w(BinaryOp::And)?,
+        OR_B => binary_b(BinaryOp::Or)?,
+        OR_W => binary_w(BinaryOp::Or)?,
+        XOR_B => binary_b(BinaryOp::Xor)?,
+        XOR_W => binary_w(BinaryOp::Xor)?,
+        SHL_B => binary_b(BinaryOp::Shl)?,
+        SHL_W => binary_w(BinaryOp::Shl)?,
+        ASR_B => binary_b(BinaryOp::Asr)?,
+        ASR_W => binary_w(BinaryOp::Asr)?,
+        LSR_B => binary_b(BinaryOp::Lsr)?,
+        LSR_W => binary_w(BinaryOp::Lsr)?,
+        SHL_BI => binary_imm_b(BinaryOp::Shl),
+        SHL_WI => binary_imm_w(BinaryOp::Shl),
+        ASR_BI => binary_imm_b(BinaryOp::Asr),
+        ASR_WI => binary_imm_w(BinaryOp::Asr),
+        LSR_BI => binary_imm_b(BinaryOp::Lsr),
+        LSR_WI => binary_imm_w(BinaryOp::Lsr),
+        ROL_B => binary_b(BinaryOp::Rol)?,
+        ROL_W => binary_w(BinaryOp::Rol)?,
+        ROR_B => binary_b(BinaryOp::Ror)?,
+        ROR_W => binary_w(BinaryOp::Ror)?,
+        RCL_B => binary_b(BinaryOp::Rcl)?,
+        RCL_W => binary_w(BinaryOp::Rcl)?,
+        RCR_B => binary_b(BinaryOp::Rcr)?,
+        RCR_W => binary_w(BinaryOp::Rcr)?,
+        ROL_BI => binary_imm_b(BinaryOp::Rol),
+        ROL_WI => binary_imm_w(BinaryOp::Rol),
+        ROR_BI => binary_imm_b(BinaryOp::Ror),
+        ROR_WI => binary_imm_w(BinaryOp::Ror),
+        RCL_BI => binary_imm_b(BinaryOp::Rcl),
+        RCL_WI => binary_imm_w(BinaryOp::Rcl),
+        RCR_BI => binary_imm_b(BinaryOp::Rcr),
+        RCR_WI => binary_imm_w(BinaryOp::Rcr),
+        NOT_B => unary_b(UnaryOp::Not),
+        NOT_W => unary_w(UnaryOp::Not),
+        NEG_B => unary_b(UnaryOp::Neg),
+        NEG_W => unary_w(UnaryOp::Neg),
+        CMP_B => I::CompareB(CompareOp::Cmp, Br(single(0)?), byte(1)),
+        CMP_W => I::CompareW(CompareOp::Cmp, Wr(single(0)?), wide(1)),
+        TEST_B => I::CompareB(CompareOp::Test, Br(single(0)?), byte(1)),
+        TEST_W => I::CompareW(CompareOp::Test, Wr(single(0)?), wide(1)),
+        BTST_B => I::BitB(BitOp::Test, Br(single(0)?), byte(1)),
+        BTST_W => I::BitW(BitOp::Test, Wr(single(0)?), byte(1)),
+        BSET_B => I::BitB(BitOp::Set, Br(single(0)?), byte(1)),
+        BSET_W => I::BitW(BitOp::Set, Wr(single(0)?), byte(1)),
+        BCLR_B => I::BitB(BitOp::Clear, Br(single(0)?), byte(1)),
+        BCLR_W => I::BitW(BitOp::Clear, Wr(single(0)?), byte(1)),
+        BTGL_B => I::BitB(BitOp::Toggle, Br(single(0)?), byte(1)),
+        BTGL_W => I::BitW(BitOp::Toggle, Wr(single(0)?), byte(1)),
+        MOVSX => {
+            let (r1, r2) = pair(0);
+            I::SignExtend(Wr(r1), Br(r2))
+        }
+        MOVZX => {
+            let (r1, r2) = pair(0);
+            I::ZeroExtend(Wr(r1), Br(r2))
+        }
+        XCHG_B => {
+            let (r1, r2) = pair(0);
+            I::ExchangeB(Br(r1), Br(r2))
+        }
+        XCHG_W => {
+            let (r1, r2) = pair(0);
+            I::ExchangeW(Wr(r1), Wr(r2))
+        }
+        BSWAP => {
+            let (r1, r2) = pair(0);
+            I::SwapBytes(Wr(r1), Wr(r2))
+        }
+        ADC_B => binary_b(BinaryOp::Adc)?,
+        ADC_W => binary_w(BinaryOp::Adc)?,
+        SBB_B => binary_b(BinaryOp::Sbb)?,
+        SBB_W => binary_w(BinaryOp::Sbb)?,
+        MUL_B => {
+            let (r1, r2, r3, r4) = four();
+            I::MulB(Br(r1), Br(r2), Br(r3), Br(r4))
+        }
+        MUL_W => {
+            let (r1, r2, r3, r4) = four();
+            I::MulW(Wr(r1), Wr(r2), Wr(r3), Wr(r4))
+        }
+        DIV_B => {
+            let (r1, r2, r3, r4) = four();
+            I::DivB(Br(r1), Br(r2), Br(r3), Br(r4))
+        }
+        DIV_W => {
+            let (r1, r2, r3, r4) = four();
+            I::DivW(Wr(r1), Wr(r2), Wr(r3), Wr(r4))
+        }
+        IMUL_B => {
+            let (r1, r2, r3, r4) = four();
+            I::IMulB(Br(r1), Br(r2), Br(r3), Br(r4))
+        }
+        IMUL_W => {
+            let (r1, r2, r3, r4) = four();
+            I::IMulW(Wr(r1), Wr(r2), Wr(r3), Wr(r4))
+        }
+        IDIV_B => {
+            let (r1, r2, r3, r4) = four();
+            I::IDivB(Br(r1), Br(r2), Br(r3), Br(r4))
+        }
+        IDIV_W => {
+            let (r1, r2, r3, r4) = four();
+            I::IDivW(Wr(r1), Wr(r2), Wr(r3), Wr(r4))
+        }
+        ADD_L => binary_l(BinaryOp::Add)?,
+        SUB_L => binary_l(BinaryOp::Sub)?,
+        AND_L => binary_l(BinaryOp::And)?,
+        OR_L => binary_l(BinaryOp::Or)?,
+        XOR_L => binary_l(BinaryOp::Xor)?,
+        SHL_L => binary_l(BinaryOp::Shl)?,
+        ASR_L => binary_l(BinaryOp::Asr)?,
+        LSR_L => binary_l(BinaryOp::Lsr)?,
+        SHL_LI => binary_imm_l(BinaryOp::Shl)?,
+        ASR_LI => binary_imm_l(BinaryOp::Asr)?,
+        LSR_LI => binary_imm_l(BinaryOp::Lsr)?,
+        MUL_L => {
+            let (r1, r2, r3, r4) = four_l()?;
+            I::MulL(r1, r2, r3, r4)
+        }
+        DIV_L => {
+            let (r1, r2, r3, r4) = four_l()?;
+            I::DivL(r1, r2, r3, r4)
+        }
+        MOVSX_L => {
+            let (r1, r2) = pair(0);
+            I::SignExtendL(long(r1)?, Wr(r2))
+        }
+        MOVZX_L => {
+            let (r1, r2) = pair(0);
+            I::ZeroExtendL(long(r1)?, Wr(r2))
+        }
+        MULH => {
+            let (r1, r2) = pair(0);
+            I::MulHigh(Wr(r1), Wr(r2), Wr(single(1)?))
+        }
+        IMULH => {
+            let (r1, r2) = pair(0);
+            I::IMulHigh(Wr(r1), Wr(r2), Wr(single(1)?))
+        }
+        FMUL => {
+            let (r1, r2) = pair(0);
+            I::FixedMul(Wr(r1), Wr(r2), Wr(single(1)?), byte(2))
+        }
+        _ => unreachable!("opcode has an operand size so it is known"),
+    };
+
+    Ok((ins, 1 + size))
+}
+
+#[test]
+fn decode_instructions() {
+    use crate::cpu::{R1, R1H, R1L, R2, R3};
+
+    // ldi r1, 0x1234
+    assert_eq!(
+        decode(1, &[0, LDI_W, 0x10, 0x34, 0x12]),
+        Ok((Instruction::LdiW(R1, 0x1234), 4))
+    );
+    // add r1, r2, r1
+    assert_eq!(
+        decode(0, &[ADD_W, 0x12, 0x10]),
+        Ok((Instruction::BinaryW(BinaryOp::Add, R1, R2, R1), 3))
+    );
+    // push r1l with the reserved nibble set
+    assert_eq!(
+        decode(0, &[PUSH_B, 0x11]),
+        Err(DecodeError::InvalidOperands(PUSH_B))
+    );
+    assert_eq!(decode(0, &[PUSH_B]), Err(DecodeError::Truncated));
+    assert_eq!(decode(0, &[0xff]), Err(DecodeError::UnknownOpcode(0xff)));
+    assert_eq!(decode(0, &[PUSH_B, 0x10]), Ok((Instruction::PushB(R1L), 2)));
+    assert_eq!(decode(0, &[INT, 0x21]), Ok((Instruction::Int(0x21), 2)));
+    assert_eq!(decode(0, &[IVT, 0x10]), Ok((Instruction::Ivt(R1), 2)));
+    assert_eq!(decode(0, &[HALT_B, 0x10]), Ok((Instruction::HaltB(R1L), 2)));
+    // lsr r1, r2, 4
+    assert_eq!(
+        decode(0, &[LSR_WI, 0x12, 4]),
+        Ok((Instruction::BinaryImmW(BinaryOp::Lsr, R1, R2, 4), 3))
+    );
+    // Short jumps are decoded to where they go, here back to themselves
+    assert_eq!(
+        decode(1, &[0, JNZ_S, (-2i8) as u8]),
+        Ok((Instruction::JumpIf(Condition::Nz, 1), 2))
+    );
+    assert_eq!(
+        decode_at(0xfffe, &[JMP_S, 0x10]),
+        Ok((Instruction::Jump(0x0010), 2))
+    );
+    assert_eq!(
+        decode(0, &[CALL_R, 0x20]),
+        Ok((Instruction::CallRegister(R2), 2))
+    );
+    // pushm r6, rl
+    assert_eq!(
+        decode(0, &[PUSHM, 0x40, 0x10]),
+        Ok((Instruction::PushMany(0x1040), 3))
+    );
+    assert_eq!(
+        decode(0, &[POPM, 0x00, 0x08]),
+        Err(DecodeError::InvalidOperands(POPM))
+    );
+    // lea r1, r2, -4
+    assert_eq!(
+        decode(0, &[LEA, 0x12, 0xfc, 0xff]),
+        Ok((Instruction::LoadAddress(R1, R2, 0xfffc), 4))
+    );
+    // bset r1, 15
+    assert_eq!(
+        decode(0, &[BSET_W, 0x10, 15]),
+        Ok((Instruction::BitW(BitOp::Set, R1, 15), 3))
+    );
+    // mfill r1, r1l, r2
+    assert_eq!(
+        decode(0, &[MFILL, 0x12, 0x10]),
+        Ok((Instruction::FillMemory(R1, R1L, R2), 3))
+    );
+    // xchg r1l, r1h
+    assert_eq!(
+        decode(0, &[XCHG_B, 0x12]),
+        Ok((Instruction::ExchangeB(R1L, R1H), 2))
+    );
+    // movsx r1, r1l
+    assert_eq!(
+        decode(0, &[MOVSX, 0x11]),
+        Ok((Instruction::SignExtend(R1, R1L), 2))
+    );
+    // cmp r1, 0x1234
+    assert_eq!(
+        decode(0, &[CMP_W, 0x10, 0x34, 0x12]),
+        Ok((Instruction::CompareW(CompareOp::Cmp, R1, 0x1234), 4))
+    );
+    // neg r1l, r1l
+    assert_eq!(
+        decode(0, &[NEG_B, 0x11]),
+        Ok((Instruction::UnaryB(UnaryOp::Neg, R1L, R1L), 2))
+    );
+    // add r1:r2, r3:r4, r0
+    assert_eq!(
+        decode(0, &[ADD_L, 0x13, 0x00]),
+        Ok((Instruction::BinaryL(BinaryOp::Add, R1, R3, R0), 3))
+    );
+    // r10 has no register after it to pair with
+    assert_eq!(
+        decode(0, &[MOVZX_L, 0xa1]),
+        Err(DecodeError::InvalidOperands(MOVZX_L))
+    );
+    // fmul r1, r2, r3, 8
+    assert_eq!(
+        decode(0, &[FMUL, 0x12, 0x30, 0x08]),
+        Ok((Instruction::FixedMul(R1, R2, R3, 8), 4))
+    );
+    // cas r1, r2, r3
+    assert_eq!(
+        decode(0, &[CAS, 0x12, 0x30]),
+        Ok((Instruction::CompareSwap(R1, R2, R3), 3))
+    );
+    // enter 6
+    assert_eq!(
+        decode(0, &[ENTER, 0x06, 0x00]),
+        Ok((Instruction::Enter(6), 3))
+    );
+    assert_eq!(decode(0, &[LEAVE]), Ok((Instruction::Leave, 1)));
+}
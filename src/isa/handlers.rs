@@ -1,112 +1,243 @@
 use crate::{
-    cpu::{ByteRegister as Br, Cpu, Registers, TrapMode, WideRegister as Wr, R0},
+    cpu::{ByteRegister as Br, Cpu, Registers, TrapMode, WideRegister as Wr, R0, R0B},
     mem::Memory,
-    U4,
 };
 
-#[inline]
-pub fn arg_pair<T, U, F1: FnOnce(U4) -> T, F2: FnOnce(U4) -> U>(
-    r: &mut Registers,
-    m: &mut dyn Memory,
-    f1: F1,
-    f2: F2,
-) -> (T, U) {
-    let operand = m.read(r.program_counter);
-    r.program_counter += 1;
-    let (a, b) = U4::paired(operand);
-    (f1(a), f2(b))
-}
-
-#[inline]
-pub fn arg_imm_byte(r: &mut Registers, m: &mut dyn Memory) -> u8 {
-    let val = m.read(r.program_counter);
-    r.program_counter += 1;
-    val
-}
-#[inline]
-pub fn arg_imm_wide(r: &mut Registers, m: &mut dyn Memory) -> u16 {
-    let val = m.read_wide(r.program_counter);
-    r.program_counter += 2;
-    val
-}
-
-pub type OpHandler = fn(&mut Registers, &mut dyn Memory);
-
-pub static OP_HANDLERS: [OpHandler; 256] = {
-    let mut handlers: [OpHandler; 256] = [n; 256];
-
-    use super::*;
-
-    handlers[NULL as usize] = n;
-    handlers[HALT as usize] = halt;
-    handlers[CTF as usize] = ctf;
-    handlers[RETH as usize] = reth;
-    handlers[NOP as usize] = nop;
-    handlers[PUSH_B as usize] = push_b;
-    handlers[PUSH_W as usize] = push_w;
-    handlers[POP_B as usize] = pop_b;
-    handlers[POP_W as usize] = pop_w;
-    handlers[CALL as usize] = call;
-    handlers[RET as usize] = ret;
-    handlers[STORE_BI as usize] = store_bi;
-    handlers[STORE_WI as usize] = store_wi;
-    handlers[STORE_BR as usize] = store_br;
-    handlers[STORE_WR as usize] = store_wr;
-    handlers[LOAD_BI as usize] = load_bi;
-    handlers[LOAD_WI as usize] = load_wi;
-    handlers[LOAD_BR as usize] = load_br;
-    handlers[LOAD_WR as usize] = load_wr;
-    handlers[JEZ as usize] = jez;
-    handlers[JLT as usize] = jlt;
-    handlers[JLE as usize] = jle;
-    handlers[JGT as usize] = jgt;
-    handlers[JGE as usize] = jge;
-    handlers[JNZ as usize] = jnz;
-    handlers[JO as usize] = jo;
-    handlers[JNO as usize] = jno;
-    handlers[JB as usize] = jb;
-    handlers[JAE as usize] = jae;
-    handlers[JA as usize] = ja;
-    handlers[JBE as usize] = jbe;
-
-    handlers[LDI_B as usize] = ldi_b;
-    handlers[LDI_W as usize] = ldi_w;
-
-    handlers[ADD_B as usize] = add_b;
-    handlers[ADD_W as usize] = add_w;
-    handlers[SUB_B as usize] = sub_b;
-    handlers[SUB_W as usize] = sub_w;
-    handlers[AND_B as usize] = and_b;
-    handlers[AND_W as usize] = and_w;
-    handlers[OR_B as usize] = or_b;
-    handlers[OR_W as usize] = or_w;
-    handlers[XOR_B as usize] = xor_b;
-    handlers[XOR_W as usize] = xor_w;
-    handlers[SHL_B as usize] = shl_b;
-    handlers[SHL_W as usize] = shl_w;
-    handlers[ASR_B as usize] = asr_b;
-    handlers[ASR_W as usize] = asr_w;
-    handlers[LSR_B as usize] = lsr_b;
-    handlers[LSR_W as usize] = lsr_w;
-
-    handlers[DIV_B as usize] = div_b;
-    handlers[DIV_W as usize] = div_w;
-    handlers[MUL_B as usize] = mul_b;
-    handlers[MUL_W as usize] = mul_w;
-
-    handlers
-};
+use super::{registers_in, BinaryOp, Instruction, UnaryOp};
 
-fn n(r: &mut Registers, _: &mut dyn Memory) {
-    r.trap(TrapMode::Invalid);
-}
-fn halt(r: &mut Registers, _: &mut dyn Memory) {
-    r.trap(TrapMode::Halt);
+/// Carries out a decoded instruction, the program counter should already point past it
+pub fn execute(ins: Instruction, r: &mut Registers, m: &mut dyn Memory) {
+    use self::Instruction as I;
+    match ins {
+        I::Null => r.trap(TrapMode::Invalid),
+        I::Halt => {
+            r.exit_code = 0;
+            r.trap(TrapMode::Halt)
+        }
+        I::HaltB(r1) => {
+            r.exit_code = r.read_byte(r1);
+            r.trap(TrapMode::Halt)
+        }
+        I::Ctf => r.trap = false,
+        I::Reth => reth(r, m),
+        I::Int(vector) => r.software_interrupt(vector),
+        I::Ei => r.interrupt_enable = true,
+        I::Di => r.interrupt_enable = false,
+        I::Reti => {
+            let flags = Cpu::popw(r, m);
+            r.set_flags(flags);
+            r.program_counter = Cpu::popw(r, m);
+        }
+        I::Ivt(r1) => r.interrupt_vectors = r.read_wide(r1),
+        I::Nop => (),
+        I::PushB(r1) => {
+            let b = r.read_byte(r1);
+            r.stack -= 1;
+            m.write(r.stack, b);
+        }
+        I::PushW(r1) => {
+            let w = r.read_wide(r1);
+            r.stack -= 2;
+            m.write_wide(r.stack, w);
+        }
+        I::PopB(r1) => {
+            let n = m.read(r.stack);
+            r.stack += 1;
+            r.write_byte(r1, n);
+        }
+        I::PopW(r1) => {
+            let n = m.read_wide(r.stack);
+            r.stack += 2;
+            r.write_wide(r1, n);
+        }
+        I::Enter(size) => {
+            Cpu::pushw(r, r.frame, m);
+            r.frame = r.stack;
+            r.stack = r.stack.wrapping_sub(size);
+        }
+        I::Leave => {
+            r.stack = r.frame;
+            r.frame = Cpu::popw(r, m);
+        }
+        I::PushMany(mask) => {
+            for r1 in registers_in(mask).rev() {
+                let w = r.read_wide(r1);
+                r.stack -= 2;
+                m.write_wide(r.stack, w);
+            }
+        }
+        I::PopMany(mask) => {
+            for r1 in registers_in(mask) {
+                let n = m.read_wide(r.stack);
+                r.stack += 2;
+                r.write_wide(r1, n);
+            }
+        }
+        I::Call(w) => {
+            r.link = r.program_counter;
+            r.program_counter = w;
+        }
+        I::CallRegister(r1) => {
+            let to = r.read_wide(r1);
+            r.link = r.program_counter;
+            r.program_counter = to;
+        }
+        I::Ret(b) => {
+            r.stack += b as u16;
+            r.program_counter = r.link;
+        }
+        I::StoreBI(r1, offset, r2) => {
+            let addr = r.read_wide(r1).wrapping_add(offset);
+            m.write(addr, r.read_byte(r2));
+        }
+        I::StoreWI(r1, offset, r2) => {
+            let addr = r.read_wide(r1).wrapping_add(offset);
+            m.write_wide(addr, r.read_wide(r2));
+        }
+        I::StoreBR(r1, r2, r3) => {
+            let addr = r.read_wide(r1).wrapping_add(r.read_wide(r2));
+            m.write(addr, r.read_byte(r3));
+        }
+        I::StoreWR(r1, r2, r3) => {
+            let addr = r.read_wide(r1).wrapping_add(r.read_wide(r2));
+            m.write_wide(addr, r.read_wide(r3));
+        }
+        I::LoadBI(r1, r2, offset) => {
+            let addr = r.read_wide(r2).wrapping_add(offset);
+            r.write_byte(r1, m.read(addr));
+        }
+        I::LoadWI(r1, r2, offset) => {
+            let addr = r.read_wide(r2).wrapping_add(offset);
+            r.write_wide(r1, m.read_wide(addr));
+        }
+        I::LoadBR(r1, r2, r3) => {
+            let addr = r.read_wide(r2).wrapping_add(r.read_wide(r3));
+            r.write_byte(r1, m.read(addr));
+        }
+        I::LoadWR(r1, r2, r3) => {
+            let addr = r.read_wide(r2).wrapping_add(r.read_wide(r3));
+            r.write_wide(r1, m.read_wide(addr));
+        }
+        I::JumpIf(cond, location) => {
+            if cond.holds(r) {
+                r.program_counter = location;
+            }
+        }
+        I::LdiB(r1, b) => r.write_byte(r1, b),
+        I::LdiW(r1, w) => r.write_wide(r1, w),
+        I::Jump(location) => r.program_counter = location,
+        I::JumpRegister(r1) => r.program_counter = r.read_wide(r1),
+        I::BinaryB(op, r1, r2, r3) => {
+            let (x, y) = (r.read_byte(r2), r.read_byte(r3));
+            binop_b(r, op, r1, x, y)
+        }
+        I::BinaryW(op, r1, r2, r3) => {
+            let (x, y) = (r.read_wide(r2), r.read_wide(r3));
+            binop_w(r, op, r1, x, y)
+        }
+        I::BinaryImmB(op, r1, r2, b) => binop_b(r, op, r1, r.read_byte(r2), b),
+        I::BinaryImmW(op, r1, r2, b) => binop_w(r, op, r1, r.read_wide(r2), b as u16),
+        // Done as the binary operations they are short for, so the flags are the same
+        I::UnaryB(UnaryOp::Not, r1, r2) => binop_b(r, BinaryOp::Xor, r1, r.read_byte(r2), 0xff),
+        I::UnaryW(UnaryOp::Not, r1, r2) => binop_w(r, BinaryOp::Xor, r1, r.read_wide(r2), 0xffff),
+        I::UnaryB(UnaryOp::Neg, r1, r2) => binop_b(r, BinaryOp::Sub, r1, 0, r.read_byte(r2)),
+        I::UnaryW(UnaryOp::Neg, r1, r2) => binop_w(r, BinaryOp::Sub, r1, 0, r.read_wide(r2)),
+        I::SignExtend(r1, r2) => r.write_wide(r1, r.read_byte(r2) as i8 as u16),
+        I::ZeroExtend(r1, r2) => r.write_wide(r1, r.read_byte(r2) as u16),
+        I::CopyMemory(r1, r2, r3) => {
+            let (to, from) = (r.read_wide(r1), r.read_wide(r2));
+            for i in 0..r.read_wide(r3) {
+                let b = m.read(from.wrapping_add(i));
+                m.write(to.wrapping_add(i), b);
+            }
+        }
+        I::ExchangeAdd(r1, r2) => {
+            let (addr, x) = (r.read_wide(r1), r.read_wide(r2));
+            let old = m.read_wide(addr);
+            binop_w(r, BinaryOp::Add, R0, old, x);
+            m.write_wide(addr, old.wrapping_add(x));
+            r.write_wide(r2, old);
+        }
+        I::CompareSwap(r1, r2, r3) => {
+            let addr = r.read_wide(r1);
+            let old = m.read_wide(addr);
+            binop_w(r, BinaryOp::Sub, R0, old, r.read_wide(r2));
+            if r.zero {
+                m.write_wide(addr, r.read_wide(r3));
+            } else {
+                r.write_wide(r2, old);
+            }
+        }
+        I::FillMemory(r1, r2, r3) => {
+            let (to, b) = (r.read_wide(r1), r.read_byte(r2));
+            for i in 0..r.read_wide(r3) {
+                m.write(to.wrapping_add(i), b);
+            }
+        }
+        I::LoadAddress(r1, r2, offset) => r.write_wide(r1, r.read_wide(r2).wrapping_add(offset)),
+        I::ExchangeB(r1, r2) => {
+            let (b1, b2) = (r.read_byte(r1), r.read_byte(r2));
+            r.write_byte(r1, b2);
+            r.write_byte(r2, b1);
+        }
+        I::ExchangeW(r1, r2) => {
+            let (w1, w2) = (r.read_wide(r1), r.read_wide(r2));
+            r.write_wide(r1, w2);
+            r.write_wide(r2, w1);
+        }
+        I::SwapBytes(r1, r2) => r.write_wide(r1, r.read_wide(r2).swap_bytes()),
+        // The result goes to the zero register, which keeps none of it
+        I::CompareB(op, r1, b) => binop_b(r, op.binary(), R0B, r.read_byte(r1), b),
+        I::CompareW(op, r1, w) => binop_w(r, op.binary(), R0, r.read_wide(r1), w),
+        I::BitB(op, r1, n) => {
+            let x = r.read_byte(r1) as u16;
+            let mask = 1 << (n % 8);
+            r.zero = x & mask == 0;
+            if let Some(res) = op.apply(x, mask) {
+                r.write_byte(r1, res as u8);
+            }
+        }
+        I::BitW(op, r1, n) => {
+            let x = r.read_wide(r1);
+            let mask = 1 << (n % 16);
+            r.zero = x & mask == 0;
+            if let Some(res) = op.apply(x, mask) {
+                r.write_wide(r1, res);
+            }
+        }
+        I::MulB(r1, r2, r3, r4) => mul_b(r, r1, r2, r3, r4),
+        I::MulW(r1, r2, r3, r4) => mul_w(r, r1, r2, r3, r4),
+        I::DivB(r1, r2, r3, r4) => div_b(r, r1, r2, r3, r4),
+        I::DivW(r1, r2, r3, r4) => div_w(r, r1, r2, r3, r4),
+        I::IMulB(r1, r2, r3, r4) => imul_b(r, r1, r2, r3, r4),
+        I::IMulW(r1, r2, r3, r4) => imul_w(r, r1, r2, r3, r4),
+        I::IDivB(r1, r2, r3, r4) => idiv_b(r, r1, r2, r3, r4),
+        I::IDivW(r1, r2, r3, r4) => idiv_w(r, r1, r2, r3, r4),
+        I::BinaryL(op, r1, r2, r3) => {
+            let (x, y) = (r.read_long(r2), r.read_long(r3));
+            binop_l(r, op, r1, x, y)
+        }
+        I::BinaryImmL(op, r1, r2, b) => binop_l(r, op, r1, r.read_long(r2), b as u32),
+        I::MulL(r1, r2, r3, r4) => mul_l(r, r1, r2, r3, r4),
+        I::DivL(r1, r2, r3, r4) => div_l(r, r1, r2, r3, r4),
+        I::MulHigh(r1, r2, r3) => {
+            let res = r.read_wide(r2) as u32 * r.read_wide(r3) as u32;
+            fixed_result(r, r1, (res >> 16) as u16, false)
+        }
+        I::IMulHigh(r1, r2, r3) => {
+            let res = r.read_wide(r2) as i16 as i32 * r.read_wide(r3) as i16 as i32;
+            fixed_result(r, r1, (res >> 16) as u16, false)
+        }
+        I::FixedMul(r1, r2, r3, b) => {
+            let res = r.read_wide(r2) as i16 as i64 * r.read_wide(r3) as i16 as i64;
+            let res = res >> (b % 32);
+            fixed_result(r, r1, res as u16, res != res as i16 as i64)
+        }
+        I::SignExtendL(r1, r2) => r.write_long(r1, r.read_wide(r2) as i16 as u32),
+        I::ZeroExtendL(r1, r2) => r.write_long(r1, r.read_wide(r2) as u32),
+    }
 }
 
-fn ctf(r: &mut Registers, _: &mut dyn Memory) {
-    r.trap = false;
-}
 fn reth(r: &mut Registers, m: &mut dyn Memory) {
     if !r.trap {
         r.trap(TrapMode::IllegalHandlerReturn);
@@ -116,129 +247,217 @@ fn reth(r: &mut Registers, m: &mut dyn Memory) {
     r.trap = false;
 }
 
-#[inline]
-fn binop_b(
-    r: &mut Registers,
-    m: &mut dyn Memory,
-    binop: fn(u8, u8) -> (u8, bool),
-    ibinop: fn(i8, i8) -> (i8, bool),
-) {
-    let (r1, r2) = arg_pair(r, m, Br, Br);
-    let (r3, r4) = arg_pair(r, m, Br, u8::from);
-
-    let r2 = r.read_byte(r2);
-    let r3 = r.read_byte(r3);
-    if r4 != 0 {
-        return r.trap(TrapMode::Invalid);
-    }
+/// An operation that also tells whether it carried or overflowed
+type Flagged<T> = fn(T, T) -> (T, bool);
 
-    let (res, carry) = binop(r2, r3);
-    let (ires, overflowing) = ibinop(r2 as i8, r3 as i8);
+fn binop_b(r: &mut Registers, op: BinaryOp, r1: Br, r2: u8, r3: u8) {
+    let (res, carry, overflow) = match op {
+        BinaryOp::Rcl | BinaryOp::Rcr => {
+            let left = op == BinaryOp::Rcl;
+            let (res, carry) = through_carry(r2 as u32, r.carry, 8, r3 as u32, left);
+            (res as u8, carry, false)
+        }
+        // Done in 32 bits to see what does not fit
+        BinaryOp::Adc | BinaryOp::Sbb => {
+            let c = r.carry as i32;
+            let (unsigned, signed) = if op == BinaryOp::Adc {
+                (
+                    r2 as i32 + r3 as i32 + c,
+                    r2 as i8 as i32 + r3 as i8 as i32 + c,
+                )
+            } else {
+                (
+                    r2 as i32 - r3 as i32 - c,
+                    r2 as i8 as i32 - r3 as i8 as i32 - c,
+                )
+            };
+            let res = unsigned as u8;
+            (res, unsigned != res as i32, signed != res as i8 as i32)
+        }
+        op => {
+            let (binop, ibinop) = flagged_b(op);
+            let (res, carry) = binop(r2, r3);
+            let (_, overflow) = ibinop(r2 as i8, r3 as i8);
+            (res, carry, overflow)
+        }
+    };
     r.carry = carry;
-    r.overflow = overflowing;
-    r.sign = ires.is_negative();
+    r.overflow = overflow;
+    r.sign = (res as i8).is_negative();
     r.zero = res == 0;
 
     r.write_byte(r1, res);
 }
-#[inline]
-fn binop_w(
-    r: &mut Registers,
-    m: &mut dyn Memory,
-    binop: fn(u16, u16) -> (u16, bool),
-    ibinop: fn(i16, i16) -> (i16, bool),
-) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let (r3, r4) = arg_pair(r, m, Wr, u8::from);
-
-    let r2 = r.read_wide(r2);
-    let r3 = r.read_wide(r3);
-    if r4 != 0 {
-        return r.trap(TrapMode::Invalid);
+/// The operation with its carry and the signed one with its overflow
+fn flagged_b(op: BinaryOp) -> (Flagged<u8>, Flagged<i8>) {
+    match op {
+        BinaryOp::Add => (u8::overflowing_add, i8::overflowing_add),
+        BinaryOp::Sub => (u8::overflowing_sub, i8::overflowing_sub),
+        BinaryOp::And => (|x, y| (x & y, false), |x, y| (x & y, false)),
+        BinaryOp::Or => (|x, y| (x | y, false), |x, y| (x | y, false)),
+        BinaryOp::Xor => (|x, y| (x ^ y, false), |x, y| (x ^ y, false)),
+        // Only the low bits of the amount are used
+        BinaryOp::Shl => (
+            |x, y| (x.wrapping_shl(y as u32), false),
+            |x, y| (x.wrapping_shl(y as u32), false),
+        ),
+        BinaryOp::Asr => (
+            |x, y| (((x as i8).wrapping_shr(y as u32)) as u8, false),
+            |x, y| (x.wrapping_shr(y as u32), false),
+        ),
+        BinaryOp::Lsr => (
+            |x, y| (x.wrapping_shr(y as u32), false),
+            |x, y| (((x as u8).wrapping_shr(y as u32)) as i8, false),
+        ),
+        // The carry is the bit that went around
+        BinaryOp::Rol => (
+            |x, y| {
+                let res = x.rotate_left(y as u32);
+                (res, res & 1 != 0)
+            },
+            |x, y| (x.rotate_left(y as u32), false),
+        ),
+        BinaryOp::Ror => (
+            |x, y| {
+                let res = x.rotate_right(y as u32);
+                (res, res >> 7 != 0)
+            },
+            |x, y| (x.rotate_right(y as u32), false),
+        ),
+        BinaryOp::Rcl | BinaryOp::Rcr | BinaryOp::Adc | BinaryOp::Sbb => {
+            unreachable!("uses the carry going in")
+        }
     }
-
-    let (res, carry) = binop(r2, r3);
-    let (ires, overflowing) = ibinop(r2 as i16, r3 as i16);
+}
+fn binop_w(r: &mut Registers, op: BinaryOp, r1: Wr, r2: u16, r3: u16) {
+    let (res, carry, overflow) = match op {
+        BinaryOp::Rcl | BinaryOp::Rcr => {
+            let left = op == BinaryOp::Rcl;
+            let (res, carry) = through_carry(r2 as u32, r.carry, 16, r3 as u32, left);
+            (res as u16, carry, false)
+        }
+        // Done in 32 bits to see what does not fit
+        BinaryOp::Adc | BinaryOp::Sbb => {
+            let c = r.carry as i32;
+            let (unsigned, signed) = if op == BinaryOp::Adc {
+                (
+                    r2 as i32 + r3 as i32 + c,
+                    r2 as i16 as i32 + r3 as i16 as i32 + c,
+                )
+            } else {
+                (
+                    r2 as i32 - r3 as i32 - c,
+                    r2 as i16 as i32 - r3 as i16 as i32 - c,
+                )
+            };
+            let res = unsigned as u16;
+            (res, unsigned != res as i32, signed != res as i16 as i32)
+        }
+        op => {
+            let (binop, ibinop) = flagged_w(op);
+            let (res, carry) = binop(r2, r3);
+            let (_, overflow) = ibinop(r2 as i16, r3 as i16);
+            (res, carry, overflow)
+        }
+    };
     r.carry = carry;
-    r.overflow = overflowing;
-    r.sign = ires.is_negative();
+    r.overflow = overflow;
+    r.sign = (res as i16).is_negative();
     r.zero = res == 0;
 
     r.write_wide(r1, res);
 }
-
-fn add_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(r, m, u8::overflowing_add, i8::overflowing_add);
-}
-fn add_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(r, m, u16::overflowing_add, i16::overflowing_add);
-}
-fn sub_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(r, m, u8::overflowing_sub, i8::overflowing_sub);
-}
-fn sub_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(r, m, u16::overflowing_sub, i16::overflowing_sub);
-}
-fn and_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(r, m, |x, y| (x & y, false), |x, y| (x & y, false));
-}
-fn and_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(r, m, |x, y| (x & y, false), |x, y| (x & y, false));
-}
-fn or_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(r, m, |x, y| (x | y, false), |x, y| (x | y, false));
-}
-fn or_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(r, m, |x, y| (x | y, false), |x, y| (x | y, false));
-}
-fn xor_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(r, m, |x, y| (x ^ y, false), |x, y| (x ^ y, false));
-}
-fn xor_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(r, m, |x, y| (x ^ y, false), |x, y| (x ^ y, false));
-}
-fn shl_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(r, m, |x, y| (x << y, false), |x, y| (x << y, false));
-}
-fn shl_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(r, m, |x, y| (x << y, false), |x, y| (x << y, false));
-}
-fn asr_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(
-        r,
-        m,
-        |x, y| (((x as i8) >> y) as u8, false),
-        |x, y| (x >> y, false),
-    );
-}
-fn asr_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(
-        r,
-        m,
-        |x, y| (((x as i16) >> y) as u16, false),
-        |x, y| (x >> y, false),
-    );
+/// The operation with its carry and the signed one with its overflow
+fn flagged_w(op: BinaryOp) -> (Flagged<u16>, Flagged<i16>) {
+    match op {
+        BinaryOp::Add => (u16::overflowing_add, i16::overflowing_add),
+        BinaryOp::Sub => (u16::overflowing_sub, i16::overflowing_sub),
+        BinaryOp::And => (|x, y| (x & y, false), |x, y| (x & y, false)),
+        BinaryOp::Or => (|x, y| (x | y, false), |x, y| (x | y, false)),
+        BinaryOp::Xor => (|x, y| (x ^ y, false), |x, y| (x ^ y, false)),
+        // Only the low bits of the amount are used
+        BinaryOp::Shl => (
+            |x, y| (x.wrapping_shl(y as u32), false),
+            |x, y| (x.wrapping_shl(y as u32), false),
+        ),
+        BinaryOp::Asr => (
+            |x, y| (((x as i16).wrapping_shr(y as u32)) as u16, false),
+            |x, y| (x.wrapping_shr(y as u32), false),
+        ),
+        BinaryOp::Lsr => (
+            |x, y| (x.wrapping_shr(y as u32), false),
+            |x, y| (((x as u16).wrapping_shr(y as u32)) as i16, false),
+        ),
+        // The carry is the bit that went around
+        BinaryOp::Rol => (
+            |x, y| {
+                let res = x.rotate_left(y as u32);
+                (res, res & 1 != 0)
+            },
+            |x, y| (x.rotate_left(y as u32), false),
+        ),
+        BinaryOp::Ror => (
+            |x, y| {
+                let res = x.rotate_right(y as u32);
+                (res, res >> 15 != 0)
+            },
+            |x, y| (x.rotate_right(y as u32), false),
+        ),
+        BinaryOp::Rcl | BinaryOp::Rcr | BinaryOp::Adc | BinaryOp::Sbb => {
+            unreachable!("uses the carry going in")
+        }
+    }
 }
-fn lsr_b(r: &mut Registers, m: &mut dyn Memory) {
-    binop_b(
-        r,
-        m,
-        |x, y| (x >> y, false),
-        |x, y| (((x as u8) >> y) as i8, false),
-    );
+fn binop_l(r: &mut Registers, op: BinaryOp, r1: Wr, r2: u32, r3: u32) {
+    let (binop, ibinop) = flagged_l(op);
+    let (res, carry) = binop(r2, r3);
+    let (_, overflow) = ibinop(r2 as i32, r3 as i32);
+    r.carry = carry;
+    r.overflow = overflow;
+    r.sign = (res as i32).is_negative();
+    r.zero = res == 0;
+
+    r.write_long(r1, res);
+}
+/// The operation with its carry and the signed one with its overflow, for the ones register pairs have
+fn flagged_l(op: BinaryOp) -> (Flagged<u32>, Flagged<i32>) {
+    match op {
+        BinaryOp::Add => (u32::overflowing_add, i32::overflowing_add),
+        BinaryOp::Sub => (u32::overflowing_sub, i32::overflowing_sub),
+        BinaryOp::And => (|x, y| (x & y, false), |x, y| (x & y, false)),
+        BinaryOp::Or => (|x, y| (x | y, false), |x, y| (x | y, false)),
+        BinaryOp::Xor => (|x, y| (x ^ y, false), |x, y| (x ^ y, false)),
+        BinaryOp::Shl => (
+            |x, y| (x.wrapping_shl(y), false),
+            |x, y| (x.wrapping_shl(y as u32), false),
+        ),
+        BinaryOp::Asr => (
+            |x, y| (((x as i32).wrapping_shr(y)) as u32, false),
+            |x, y| (x.wrapping_shr(y as u32), false),
+        ),
+        BinaryOp::Lsr => (
+            |x, y| (x.wrapping_shr(y), false),
+            |x, y| (((x as u32).wrapping_shr(y as u32)) as i32, false),
+        ),
+        _ => unreachable!("register pairs do not have it"),
+    }
 }
-fn lsr_w(r: &mut Registers, m: &mut dyn Memory) {
-    binop_w(
-        r,
-        m,
-        |x, y| (x >> y, false),
-        |x, y| (((x as u16) >> y) as i16, false),
-    );
+
+/// Rotates the value with the carry as the bit above it, by the amount modulo the width plus one
+fn through_carry(x: u32, carry: bool, bits: u32, amount: u32, left: bool) -> (u32, bool) {
+    let width = bits + 1;
+    let val = x | (carry as u32) << bits;
+    let amount = amount % width;
+    let amount = if left {
+        amount
+    } else {
+        (width - amount) % width
+    };
+    let res = (val << amount | val >> (width - amount)) & ((1 << width) - 1);
+    (res & ((1 << bits) - 1), res >> bits != 0)
 }
-fn mul_b(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Br, Br);
-    let (r3, r4) = arg_pair(r, m, Br, Br);
 
+fn mul_b(r: &mut Registers, r1: Br, r2: Br, r3: Br, r4: Br) {
     let res = r.read_byte(r3) as u16 * r.read_byte(r4) as u16;
     let [lower, upper] = res.to_le_bytes();
 
@@ -250,10 +469,7 @@ fn mul_b(r: &mut Registers, m: &mut dyn Memory) {
     r.write_byte(r1, upper);
     r.write_byte(r2, lower);
 }
-fn mul_w(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let (r3, r4) = arg_pair(r, m, Wr, Wr);
-
+fn mul_w(r: &mut Registers, r1: Wr, r2: Wr, r3: Wr, r4: Wr) {
     let res = r.read_wide(r3) as u32 * r.read_wide(r4) as u32;
     let [lower1, lower2, upper1, upper2] = res.to_le_bytes();
     let lower = u16::from_le_bytes([lower1, lower2]);
@@ -267,10 +483,7 @@ fn mul_w(r: &mut Registers, m: &mut dyn Memory) {
     r.write_wide(r1, upper);
     r.write_wide(r2, lower);
 }
-fn div_b(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Br, Br);
-    let (r3, r4) = arg_pair(r, m, Br, Br);
-
+fn div_b(r: &mut Registers, r1: Br, r2: Br, r3: Br, r4: Br) {
     let n1 = r.read_byte(r3);
     let n2 = r.read_byte(r4);
     if n2 == 0 {
@@ -283,10 +496,7 @@ fn div_b(r: &mut Registers, m: &mut dyn Memory) {
     r.write_byte(r1, upper);
     r.write_byte(r2, lower);
 }
-fn div_w(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let (r3, r4) = arg_pair(r, m, Wr, Wr);
-
+fn div_w(r: &mut Registers, r1: Wr, r2: Wr, r3: Wr, r4: Wr) {
     let n1 = r.read_wide(r3);
     let n2 = r.read_wide(r4);
     if n2 == 0 {
@@ -299,202 +509,460 @@ fn div_w(r: &mut Registers, m: &mut dyn Memory) {
     r.write_wide(r1, upper);
     r.write_wide(r2, lower);
 }
+/// Overflows if the upper half is more than the sign of the lower half
+fn imul_b(r: &mut Registers, r1: Br, r2: Br, r3: Br, r4: Br) {
+    let res = r.read_byte(r3) as i8 as i16 * r.read_byte(r4) as i8 as i16;
+    let [lower, upper] = res.to_le_bytes();
 
-fn nop(_: &mut Registers, _: &mut dyn Memory) {}
-fn push_b(r: &mut Registers, m: &mut dyn Memory) {
-    let (b, z) = arg_pair(r, m, Br, u8::from);
-    let b = r.read_byte(b);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
-    }
-    r.stack -= 1;
-    m.write(r.stack, b);
-}
-fn push_w(r: &mut Registers, m: &mut dyn Memory) {
-    let (w, z) = arg_pair(r, m, Wr, u8::from);
-    let w = r.read_wide(w);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
-    }
-    r.stack -= 2;
-    m.write_wide(r.stack, w);
-}
-fn pop_b(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, z) = arg_pair(r, m, Br, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
-    }
-
-    let n = m.read(r.stack);
-    r.stack += 1;
+    r.carry = res != lower as i8 as i16;
+    r.overflow = r.carry;
+    r.zero = lower == 0;
+    r.sign = (lower as i8).is_negative();
 
-    r.write_byte(r1, n);
+    r.write_byte(r1, upper);
+    r.write_byte(r2, lower);
 }
-fn pop_w(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, z) = arg_pair(r, m, Wr, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
-    }
-
-    let n = m.read_wide(r.stack);
-    r.stack += 2;
+fn imul_w(r: &mut Registers, r1: Wr, r2: Wr, r3: Wr, r4: Wr) {
+    let res = r.read_wide(r3) as i16 as i32 * r.read_wide(r4) as i16 as i32;
+    let lower = res as u16;
+    let upper = (res >> 16) as u16;
 
-    r.write_wide(r1, n);
-}
-fn call(r: &mut Registers, m: &mut dyn Memory) {
-    let w = arg_imm_wide(r, m);
-    r.link = r.program_counter;
-    r.program_counter = w;
-}
-fn ret(r: &mut Registers, m: &mut dyn Memory) {
-    let b = arg_imm_byte(r, m);
-    r.stack += b as u16;
-    r.program_counter = r.link;
-}
-fn store_bi(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Br);
-    let offset = arg_imm_wide(r, m);
+    r.carry = res != lower as i16 as i32;
+    r.overflow = r.carry;
+    r.zero = lower == 0;
+    r.sign = (lower as i16).is_negative();
 
-    let addr = r.read_wide(r1) + offset;
-    m.write(addr, r.read_byte(r2));
+    r.write_wide(r1, upper);
+    r.write_wide(r2, lower);
 }
-fn store_br(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let (r3, z) = arg_pair(r, m, Br, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
+/// The remainder has the sign of the dividend, and dividing the lowest number by -1 wraps around to it
+fn idiv_b(r: &mut Registers, r1: Br, r2: Br, r3: Br, r4: Br) {
+    let n1 = r.read_byte(r3) as i8;
+    let n2 = r.read_byte(r4) as i8;
+    if n2 == 0 {
+        r.trap(TrapMode::ZeroDiv);
+        return;
     }
-    let offset = r.read_wide(r2);
-
-    let addr = r.read_wide(r1) + offset;
-    m.write(addr, r.read_byte(r3));
-}
-fn store_wi(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let offset = arg_imm_wide(r, m);
+    let upper = n1.wrapping_div(n2);
+    let lower = n1.wrapping_rem(n2);
 
-    let addr = r.read_wide(r1) + offset;
-    m.write_wide(addr, r.read_wide(r2));
+    r.write_byte(r1, upper as u8);
+    r.write_byte(r2, lower as u8);
 }
-fn store_wr(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let (r3, z) = arg_pair(r, m, Wr, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
+fn idiv_w(r: &mut Registers, r1: Wr, r2: Wr, r3: Wr, r4: Wr) {
+    let n1 = r.read_wide(r3) as i16;
+    let n2 = r.read_wide(r4) as i16;
+    if n2 == 0 {
+        r.trap(TrapMode::ZeroDiv);
+        return;
     }
-    let offset = r.read_wide(r2);
+    let upper = n1.wrapping_div(n2);
+    let lower = n1.wrapping_rem(n2);
 
-    let addr = r.read_wide(r1) + offset;
-    m.write_wide(addr, r.read_wide(r3));
+    r.write_wide(r1, upper as u16);
+    r.write_wide(r2, lower as u16);
 }
-fn load_bi(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Br, Wr);
-    let offset = arg_imm_wide(r, m);
+/// Carry and overflow are whether the result did not fit
+fn fixed_result(r: &mut Registers, r1: Wr, res: u16, overflow: bool) {
+    r.carry = overflow;
+    r.overflow = overflow;
+    r.zero = res == 0;
+    r.sign = (res as i16).is_negative();
 
-    let addr = r.read_wide(r2) + offset;
-    r.write_byte(r1, m.read(addr));
+    r.write_wide(r1, res);
 }
-fn load_br(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Br, Wr);
-    let (r3, z) = arg_pair(r, m, Wr, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
-    }
-    let offset = r.read_wide(r3);
+fn mul_l(r: &mut Registers, r1: Wr, r2: Wr, r3: Wr, r4: Wr) {
+    let res = r.read_long(r3) as u64 * r.read_long(r4) as u64;
+    let lower = res as u32;
+    let upper = (res >> 32) as u32;
 
-    let addr = r.read_wide(r2) + offset;
-    r.write_byte(r1, m.read(addr));
-}
-fn load_wi(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let offset = arg_imm_wide(r, m);
+    r.carry = upper != 0;
+    r.overflow = r.carry;
+    r.zero = lower == 0;
+    r.sign = (lower as i32).is_negative();
 
-    let addr = r.read_wide(r2) + offset;
-    r.write_wide(r1, m.read_wide(addr));
+    r.write_long(r1, upper);
+    r.write_long(r2, lower);
 }
-fn load_wr(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, r2) = arg_pair(r, m, Wr, Wr);
-    let (r3, z) = arg_pair(r, m, Wr, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
+fn div_l(r: &mut Registers, r1: Wr, r2: Wr, r3: Wr, r4: Wr) {
+    let n1 = r.read_long(r3);
+    let n2 = r.read_long(r4);
+    if n2 == 0 {
+        r.trap(TrapMode::ZeroDiv);
+        return;
     }
-    let offset = r.read_wide(r3);
 
-    let addr = r.read_wide(r2) + offset;
-    r.write_wide(r1, m.read_wide(addr));
+    r.write_long(r1, n1 / n2);
+    r.write_long(r2, n1 % n2);
 }
 
-fn jez(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.zero, r, m);
-}
-fn jlt(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.sign != r.overflow, r, m);
-}
-fn jle(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.sign != r.overflow && r.zero, r, m);
-}
-fn jgt(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.sign == r.overflow && !r.zero, r, m);
-}
-fn jge(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.sign == r.overflow, r, m);
-}
-fn jnz(r: &mut Registers, m: &mut dyn Memory) {
-    jif(!r.zero, r, m);
-}
-fn jo(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.overflow, r, m);
-}
-fn jno(r: &mut Registers, m: &mut dyn Memory) {
-    jif(!r.overflow, r, m);
-}
-fn ja(r: &mut Registers, m: &mut dyn Memory) {
-    jif(!r.carry && !r.zero, r, m);
-}
-fn jae(r: &mut Registers, m: &mut dyn Memory) {
-    jif(!r.carry, r, m);
-}
-fn jb(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.carry, r, m);
-}
-fn jbe(r: &mut Registers, m: &mut dyn Memory) {
-    jif(r.carry || r.zero, r, m);
-}
-fn jif(cond: bool, r: &mut Registers, m: &mut dyn Memory) {
-    let location = arg_imm_wide(r, m);
-    if cond {
-        r.program_counter = location;
+/// Registers with the wides set to the values and the carry given, the rest are left as they start out
+#[cfg(test)]
+fn regs(wides: &[(Wr, u16)], carry: bool) -> Registers {
+    let mut r = Registers::new(0);
+    for &(reg, val) in wides {
+        r.write_wide(reg, val);
     }
-}
-
-fn ldi_b(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, z) = arg_pair(r, m, Br, u8::from);
-    if z != 0 {
-        return r.trap(TrapMode::Invalid);
+    r.carry = carry;
+    r
+}
+
+/// Runs the instruction on the registers, with memory that panics on I/O
+#[cfg(test)]
+fn run(ins: Instruction, mut r: Registers) -> Registers {
+    let m = &mut crate::mem::Lazy::new_panicking(alloc::vec::Vec::new());
+    execute(ins, &mut r, m);
+    r
+}
+
+/// An instruction, the registers it runs on and the wides and flags it should leave them with
+#[cfg(test)]
+type Case<'a> = (Instruction, Registers, &'a [(Wr, u16)], &'a str);
+
+/// Runs each instruction on its registers and checks the wides and flags it leaves
+///
+/// Flags are named like `+c -s`, a plus for set and a minus for clear. The ones not named are not checked.
+#[cfg(test)]
+fn check(cases: &[Case]) {
+    for (ins, r, wides, flags) in cases {
+        let r = run(*ins, r.clone());
+        for &(reg, val) in *wides {
+            assert_eq!(r.read_wide(reg), val, "{reg:?} after {ins:?}");
+        }
+        for flag in flags.split_whitespace() {
+            let (set, name) = flag.split_at(1);
+            let value = match name {
+                "z" => r.zero,
+                "o" => r.overflow,
+                "s" => r.sign,
+                "c" => r.carry,
+                _ => panic!("no flag {name}"),
+            };
+            assert_eq!(value, set == "+", "{flag} after {ins:?}");
+        }
     }
-
-    let b = arg_imm_byte(r, m);
-
-    r.write_byte(r1, b);
 }
-fn ldi_w(r: &mut Registers, m: &mut dyn Memory) {
-    let (r1, o) = arg_pair(r, m, Wr, u8::from);
-
-    let w = arg_imm_wide(r, m);
-
-    match o {
-        // ldi
-        0 => r.write_wide(r1, w),
-        // jmp, jump
-        1 => {
-            if r1 == R0 {
-                // jmp imm
-                r.program_counter = w;
-            } else {
-                // jmp r
-                r.program_counter = r.read_wide(r1);
-            }
+
+#[test]
+fn signed_mul_div() {
+    use crate::cpu::{R1, R2, R3, R4};
+
+    let minus_300_by_7 = || regs(&[(R3, -300i16 as u16), (R4, 7)], false);
+    check(&[
+        (
+            Instruction::IMulW(R1, R2, R3, R4),
+            minus_300_by_7(),
+            &[(R1, 0xffff), (R2, -2100i16 as u16)],
+            "-c +s",
+        ),
+        (
+            Instruction::IDivW(R1, R2, R3, R4),
+            minus_300_by_7(),
+            &[(R1, -42i16 as u16), (R2, -6i16 as u16)],
+            "",
+        ),
+        // Unsigned, -300 is a big number
+        (
+            Instruction::DivW(R1, R2, R3, R4),
+            minus_300_by_7(),
+            &[(R1, 0xfed4 / 7)],
+            "",
+        ),
+        (
+            Instruction::IDivW(R1, R2, R3, R4),
+            regs(&[(R3, 0x8000), (R4, 0xffff)], false),
+            &[(R1, 0x8000), (R2, 0)],
+            "",
+        ),
+    ]);
+
+    let r = run(
+        Instruction::IDivW(R1, R2, R3, R4),
+        regs(&[(R3, 0x8000), (R4, 0)], false),
+    );
+    assert_eq!(r.trap_mode, TrapMode::ZeroDiv);
+}
+
+#[test]
+fn shifts() {
+    use crate::cpu::{R1, R1L, R2, R3};
+
+    let r1 = || regs(&[(R1, 0x8421), (R3, 17)], false);
+    check(&[
+        (
+            Instruction::BinaryImmW(BinaryOp::Shl, R2, R1, 4),
+            r1(),
+            &[(R2, 0x4210)],
+            "",
+        ),
+        (
+            Instruction::BinaryImmW(BinaryOp::Asr, R2, R1, 4),
+            r1(),
+            &[(R2, 0xf842)],
+            "+s",
+        ),
+        // The amount wraps around the width instead of clearing the register
+        (
+            Instruction::BinaryW(BinaryOp::Lsr, R2, R1, R3),
+            r1(),
+            &[(R2, 0x4210)],
+            "",
+        ),
+        // r1l is the lower byte of r1
+        (
+            Instruction::BinaryImmB(BinaryOp::Lsr, R1L, R1L, 5),
+            r1(),
+            &[(R1, 0x8401)],
+            "",
+        ),
+    ]);
+}
+
+#[test]
+fn rotates() {
+    use crate::cpu::{R1, R1L, R2};
+
+    check(&[
+        (
+            Instruction::BinaryImmW(BinaryOp::Rol, R2, R1, 4),
+            regs(&[(R1, 0x8421)], false),
+            &[(R2, 0x4218)],
+            "-c",
+        ),
+        (
+            Instruction::BinaryImmW(BinaryOp::Ror, R2, R1, 1),
+            regs(&[(R1, 0x8421)], false),
+            &[(R2, 0xc210)],
+            "+c",
+        ),
+        // Shifting a wide left by one across two registers, the carry taking the bit from the lower to the upper
+        (
+            Instruction::BinaryImmW(BinaryOp::Rcl, R1, R1, 1),
+            regs(&[(R1, 0x8001)], false),
+            &[(R1, 0x0002)],
+            "+c",
+        ),
+        (
+            Instruction::BinaryImmW(BinaryOp::Rcl, R2, R2, 1),
+            regs(&[(R2, 0x0001)], true),
+            &[(R2, 0x0003)],
+            "-c",
+        ),
+        (
+            Instruction::BinaryImmB(BinaryOp::Rcr, R1L, R1L, 1),
+            regs(&[(R1, 0x0001)], true),
+            &[(R1, 0x0080)],
+            "+c",
+        ),
+        // Nine bits make a whole turn for a byte
+        (
+            Instruction::BinaryImmB(BinaryOp::Rcr, R1L, R1L, 9),
+            regs(&[(R1, 0x0080)], true),
+            &[(R1, 0x0080)],
+            "+c",
+        ),
+    ]);
+}
+
+#[test]
+fn not_and_neg() {
+    use crate::cpu::{R1, R1L, R2};
+
+    check(&[
+        (
+            Instruction::UnaryW(UnaryOp::Not, R2, R1),
+            regs(&[(R1, 0x00ff)], false),
+            &[(R2, 0xff00)],
+            "+s -c",
+        ),
+        (
+            Instruction::UnaryW(UnaryOp::Neg, R2, R1),
+            regs(&[(R1, 0x00ff)], false),
+            &[(R2, 0xff01)],
+            "+c",
+        ),
+        // Only the most negative byte overflows, as it has no positive counterpart
+        (
+            Instruction::UnaryB(UnaryOp::Neg, R1L, R1L),
+            regs(&[(R1, 0x0080)], false),
+            &[(R1, 0x0080)],
+            "+o",
+        ),
+    ]);
+}
+
+#[test]
+fn with_carry() {
+    use crate::cpu::{R1, R2, R3, R4};
+
+    // 0x0001ffff + 0x00020001 in r1:r2 and r3:r4, the lower halves first and back again
+    check(&[
+        (
+            Instruction::BinaryW(BinaryOp::Add, R2, R2, R4),
+            regs(&[(R2, 0xffff), (R4, 0x0001)], false),
+            &[(R2, 0x0000)],
+            "+c",
+        ),
+        (
+            Instruction::BinaryW(BinaryOp::Adc, R1, R1, R3),
+            regs(&[(R1, 0x0001), (R3, 0x0002)], true),
+            &[(R1, 0x0004)],
+            "-c",
+        ),
+        (
+            Instruction::BinaryW(BinaryOp::Sub, R2, R2, R4),
+            regs(&[(R2, 0x0000), (R4, 0x0001)], false),
+            &[(R2, 0xffff)],
+            "+c",
+        ),
+        (
+            Instruction::BinaryW(BinaryOp::Sbb, R1, R1, R3),
+            regs(&[(R1, 0x0004), (R3, 0x0002)], true),
+            &[(R1, 0x0001)],
+            "-c",
+        ),
+        (
+            Instruction::BinaryW(BinaryOp::Sbb, R1, R1, R3),
+            regs(&[(R1, 0x0001), (R3, 0x0002)], false),
+            &[(R1, 0xffff)],
+            "+c +s",
+        ),
+    ]);
+}
+
+#[test]
+fn long_arithmetic() {
+    use crate::cpu::{R1, R2, R3, R4, R5, R6, R7, R8};
+
+    // Longs are in register pairs like r1:r2, the upper half first
+    check(&[
+        (
+            Instruction::BinaryL(BinaryOp::Add, R5, R1, R3),
+            regs(&[(R1, 0x0001), (R2, 0xffff), (R3, 0), (R4, 1)], false),
+            &[(R5, 0x0002), (R6, 0x0000)],
+            "-c -z",
+        ),
+        // The zero pair only keeps the flags
+        (
+            Instruction::BinaryL(BinaryOp::Sub, R0, R3, R1),
+            regs(&[(R1, 0x0001), (R2, 0xffff), (R3, 0), (R4, 1)], false),
+            &[],
+            "+c +s",
+        ),
+        (
+            Instruction::BinaryImmL(BinaryOp::Asr, R5, R5, 33),
+            regs(&[(R5, 0x0002), (R6, 0x0000)], false),
+            &[(R5, 0x0001), (R6, 0x0000)],
+            "",
+        ),
+        (
+            Instruction::MulL(R5, R7, R5, R1),
+            regs(
+                &[(R5, 0x0001), (R6, 0x0000), (R1, 0x0001), (R2, 0xffff)],
+                false,
+            ),
+            &[(R5, 0), (R6, 1), (R7, 0xffff), (R8, 0x0000)],
+            "+c",
+        ),
+        (
+            Instruction::DivL(R5, R7, R7, R1),
+            regs(
+                &[(R7, 0xffff), (R8, 0x0000), (R1, 0x0001), (R2, 0xffff)],
+                false,
+            ),
+            &[(R5, 0), (R6, 0x7fff), (R7, 0x0001), (R8, 0x7fff)],
+            "",
+        ),
+        (
+            Instruction::SignExtendL(R1, R3),
+            regs(&[(R3, 0xfffe)], false),
+            &[(R1, 0xffff), (R2, 0xfffe)],
+            "",
+        ),
+    ]);
+}
+
+#[test]
+fn fixed_point() {
+    use crate::cpu::{R1, R2, R3};
+
+    // 1.5 * -2.25 in 8.8
+    let factors = || regs(&[(R2, 0x0180), (R3, -0x0240i16 as u16)], false);
+    check(&[
+        (
+            Instruction::FixedMul(R1, R2, R3, 8),
+            factors(),
+            &[(R1, -0x0360i16 as u16)],
+            "+s -o",
+        ),
+        (
+            Instruction::IMulHigh(R1, R2, R3),
+            factors(),
+            &[(R1, 0xfffc)],
+            "",
+        ),
+        (
+            Instruction::MulHigh(R1, R2, R3),
+            factors(),
+            &[(R1, 0x017c)],
+            "",
+        ),
+        // 128.0 does not fit
+        (
+            Instruction::FixedMul(R1, R2, R3, 8),
+            regs(&[(R2, 0x1000), (R3, 0x0800)], false),
+            &[],
+            "+o +c",
+        ),
+    ]);
+}
+
+#[test]
+fn atomics() {
+    use crate::{
+        cpu::{R1, R2, R3},
+        mem::Lazy,
+    };
+
+    // The instruction, the wide at 4 before and after, and the registers before and after
+    let cases: [(_, _, _, &[_], &[_], _); 3] = [
+        (
+            Instruction::ExchangeAdd(R1, R2),
+            10,
+            15,
+            &[(R1, 4), (R2, 5)],
+            &[(R2, 10)],
+            None,
+        ),
+        // Another writer got there first, so it is not swapped
+        (
+            Instruction::CompareSwap(R1, R2, R3),
+            15,
+            15,
+            &[(R1, 4), (R2, 10), (R3, 11)],
+            &[(R2, 15)],
+            Some(false),
+        ),
+        (
+            Instruction::CompareSwap(R1, R2, R3),
+            15,
+            11,
+            &[(R1, 4), (R2, 15), (R3, 11)],
+            &[],
+            Some(true),
+        ),
+    ];
+    for (ins, before, after, input, output, zero) in cases {
+        let m = &mut Lazy::new_panicking(alloc::vec![0; 16]);
+        m.write_wide(4, before);
+        let mut r = regs(input, false);
+        execute(ins, &mut r, m);
+        assert_eq!(m.read_wide(4), after, "{ins:?}");
+        for &(reg, val) in output {
+            assert_eq!(r.read_wide(reg), val, "{reg:?} after {ins:?}");
+        }
+        if let Some(zero) = zero {
+            assert_eq!(r.zero, zero, "{ins:?}");
         }
-        _ => r.trap(TrapMode::Invalid),
     }
 }
@@ -1,7 +1,16 @@
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+
 pub const NULL: u8 = 0x00;
+pub const INT: u8 = 0x05;
+pub const EI: u8 = 0x06;
+pub const DI: u8 = 0x07;
 pub const HALT: u8 = 0x0a;
 pub const CTF: u8 = 0x0b;
+/// Halt with the byte register as the exit code
+pub const HALT_B: u8 = 0x0c;
 pub const RETH: u8 = 0x0d;
+pub const RETI: u8 = 0x0e;
+pub const IVT: u8 = 0x0f;
 
 pub const NOP: u8 = 0x20;
 pub const PUSH_B: u8 = 0x21;
@@ -56,6 +65,227 @@ pub const DIV_B: u8 = 0x51;
 pub const DIV_W: u8 = 0x52;
 pub const MUL_B: u8 = 0x53;
 pub const MUL_W: u8 = 0x54;
+/// Shifts by an immediate amount instead of a register
+pub const SHL_BI: u8 = 0x55;
+pub const SHL_WI: u8 = 0x56;
+pub const ASR_BI: u8 = 0x57;
+pub const ASR_WI: u8 = 0x58;
+pub const LSR_BI: u8 = 0x59;
+pub const LSR_WI: u8 = 0x5a;
+pub const ROL_B: u8 = 0x5b;
+pub const ROL_W: u8 = 0x5c;
+pub const ROR_B: u8 = 0x5d;
+pub const ROR_W: u8 = 0x5e;
+/// Rotates through the carry flag, as if it were one more bit above the register
+pub const RCL_B: u8 = 0x5f;
+pub const RCL_W: u8 = 0x60;
+pub const RCR_B: u8 = 0x61;
+pub const RCR_W: u8 = 0x62;
+pub const ROL_BI: u8 = 0x63;
+pub const ROL_WI: u8 = 0x64;
+pub const ROR_BI: u8 = 0x65;
+pub const ROR_WI: u8 = 0x66;
+pub const RCL_BI: u8 = 0x67;
+pub const RCL_WI: u8 = 0x68;
+pub const RCR_BI: u8 = 0x69;
+pub const RCR_WI: u8 = 0x6a;
+pub const NOT_B: u8 = 0x6b;
+pub const NOT_W: u8 = 0x6c;
+pub const NEG_B: u8 = 0x6d;
+pub const NEG_W: u8 = 0x6e;
+/// Only set the flags of subtracting or and-ing the immediate from the register
+pub const CMP_B: u8 = 0x6f;
+pub const CMP_W: u8 = 0x70;
+pub const TEST_B: u8 = 0x71;
+pub const TEST_W: u8 = 0x72;
+/// Add and subtract the carry flag too, for numbers bigger than a register
+pub const ADC_B: u8 = 0x73;
+pub const ADC_W: u8 = 0x74;
+pub const SBB_B: u8 = 0x75;
+pub const SBB_W: u8 = 0x76;
+/// Like `MUL` and `DIV` taking the registers as two's complement
+pub const IMUL_B: u8 = 0x77;
+pub const IMUL_W: u8 = 0x78;
+pub const IDIV_B: u8 = 0x79;
+pub const IDIV_W: u8 = 0x7a;
+/// Widens the byte register into the wide one with copies of its sign bit or zeroes
+pub const MOVSX: u8 = 0x7b;
+pub const MOVZX: u8 = 0x7c;
+/// Jumps by a signed byte from the end of the instruction, instead of to a wide address
+pub const JMP_S: u8 = 0x7d;
+pub const JEZ_S: u8 = 0x7e;
+pub const JLT_S: u8 = 0x7f;
+pub const JLE_S: u8 = 0x80;
+pub const JGT_S: u8 = 0x81;
+pub const JGE_S: u8 = 0x82;
+pub const JNZ_S: u8 = 0x83;
+pub const JO_S: u8 = 0x84;
+pub const JNO_S: u8 = 0x85;
+pub const JA_S: u8 = 0x86;
+pub const JAE_S: u8 = 0x87;
+pub const JB_S: u8 = 0x88;
+pub const JBE_S: u8 = 0x89;
+/// Calls the address in a wide register, like `jmp wr` does not
+pub const CALL_R: u8 = 0x8a;
+/// Pushes or pops the wide registers of a mask with a bit for each, by number
+pub const PUSHM: u8 = 0x8b;
+pub const POPM: u8 = 0x8c;
+pub const XCHG_B: u8 = 0x8d;
+pub const XCHG_W: u8 = 0x8e;
+/// Swaps the bytes of the second wide register into the first
+pub const BSWAP: u8 = 0x8f;
+/// Computes the address a load or store with the same operands would access
+pub const LEA: u8 = 0x90;
+/// Copies or fills as many bytes as the last register says, from the lowest address up
+pub const MCOPY: u8 = 0x91;
+/// Encoded with the destination and length first and the byte register with the value last
+pub const MFILL: u8 = 0x92;
+/// Tests, sets, clears or toggles the bit of a register with the index, setting zero if it was clear
+pub const BTST_B: u8 = 0x93;
+pub const BTST_W: u8 = 0x94;
+pub const BSET_B: u8 = 0x95;
+pub const BSET_W: u8 = 0x96;
+pub const BCLR_B: u8 = 0x97;
+pub const BCLR_W: u8 = 0x98;
+pub const BTGL_B: u8 = 0x99;
+pub const BTGL_W: u8 = 0x9a;
+/// Sets up and tears down the stack frame of a function with `rf` as the frame pointer
+pub const ENTER: u8 = 0x9b;
+pub const LEAVE: u8 = 0x9c;
+/// 32-bit arithmetic on register pairs, encoded by the register of the upper half with `r0` as the zero pair
+pub const ADD_L: u8 = 0x9d;
+pub const SUB_L: u8 = 0x9e;
+pub const AND_L: u8 = 0x9f;
+pub const OR_L: u8 = 0xa0;
+pub const XOR_L: u8 = 0xa1;
+pub const SHL_L: u8 = 0xa2;
+pub const ASR_L: u8 = 0xa3;
+pub const LSR_L: u8 = 0xa4;
+pub const SHL_LI: u8 = 0xa5;
+pub const ASR_LI: u8 = 0xa6;
+pub const LSR_LI: u8 = 0xa7;
+pub const MUL_L: u8 = 0xa8;
+pub const DIV_L: u8 = 0xa9;
+/// Widens the wide register into the register pair
+pub const MOVSX_L: u8 = 0xaa;
+pub const MOVZX_L: u8 = 0xab;
+/// Only the upper half of the product, which is the whole product of fixed-point numbers with 16 fraction bits
+pub const MULH: u8 = 0xac;
+pub const IMULH: u8 = 0xad;
+/// Multiplies two's complement fixed-point numbers, shifting the product right by the number of fraction bits
+pub const FMUL: u8 = 0xae;
+/// Read, change and write a wide in memory in one instruction, so no interrupt comes in between
+pub const XADD: u8 = 0xaf;
+pub const CAS: u8 = 0xb0;
+
+/// Vectored interrupts: `int`, `ei`, `di`, `reti` and `ivt`
+pub const FEATURE_INTERRUPTS: u32 = 0x0000_0001;
+/// Shifts by an immediate amount and rotates
+pub const FEATURE_ROTATES: u32 = 0x0000_0002;
+/// `not`, `neg`, `cmp`, `test`, `adc`, `sbb`, `imul`, `idiv`, `movsx` and `movzx`
+pub const FEATURE_ARITHMETIC: u32 = 0x0000_0004;
+/// Jumps by a signed byte
+pub const FEATURE_SHORT_JUMPS: u32 = 0x0000_0008;
+/// `call wr`, `pushm`, `popm`, `enter` and `leave`
+pub const FEATURE_STACK: u32 = 0x0000_0010;
+/// `xchg`, `bswap` and the bit instructions
+pub const FEATURE_BITS: u32 = 0x0000_0020;
+/// `lea`, `mcopy` and `mfill`
+pub const FEATURE_MEMORY: u32 = 0x0000_0040;
+/// 32-bit arithmetic on register pairs
+pub const FEATURE_LONG: u32 = 0x0000_0080;
+/// `mulh`, `imulh` and `fmul` for fixed-point numbers
+pub const FEATURE_FIXED: u32 = 0x0000_0100;
+/// `xadd` and `cas`
+pub const FEATURE_ATOMIC: u32 = 0x0000_0200;
+/// Every feature this version can emulate
+pub const ALL_FEATURES: u32 = 0x0000_03ff;
+/// Names of the features, as `tc` and `t --cpu` know them
+pub const FEATURE_NAMES: &[(&str, u32)] = &[
+    ("interrupts", FEATURE_INTERRUPTS),
+    ("rotates", FEATURE_ROTATES),
+    ("arithmetic", FEATURE_ARITHMETIC),
+    ("short-jumps", FEATURE_SHORT_JUMPS),
+    ("stack", FEATURE_STACK),
+    ("bits", FEATURE_BITS),
+    ("memory", FEATURE_MEMORY),
+    ("long", FEATURE_LONG),
+    ("fixed", FEATURE_FIXED),
+    ("atomic", FEATURE_ATOMIC),
+];
+
+/// The feature a CPU needs to have the instruction, with no bits set for the ones every CPU has
+pub const fn feature(opcode: u8) -> u32 {
+    match opcode {
+        INT | EI | DI | RETI | IVT => FEATURE_INTERRUPTS,
+        SHL_BI..=RCR_WI => FEATURE_ROTATES,
+        NOT_B..=MOVZX => FEATURE_ARITHMETIC,
+        JMP_S..=JBE_S => FEATURE_SHORT_JUMPS,
+        CALL_R | PUSHM | POPM | ENTER | LEAVE => FEATURE_STACK,
+        XCHG_B | XCHG_W | BSWAP | BTST_B..=BTGL_W => FEATURE_BITS,
+        LEA | MCOPY | MFILL => FEATURE_MEMORY,
+        ADD_L..=MOVZX_L => FEATURE_LONG,
+        MULH | IMULH | FMUL => FEATURE_FIXED,
+        XADD | CAS => FEATURE_ATOMIC,
+        _ => 0,
+    }
+}
+
+/// The names of the features, and the bits of the ones this version does not know in hex
+pub fn feature_names(features: u32) -> Vec<String> {
+    let mut names: Vec<String> = FEATURE_NAMES
+        .iter()
+        .filter(|&&(_, bit)| features & bit != 0)
+        .map(|&(name, _)| name.to_owned())
+        .collect();
+    if features & !ALL_FEATURES != 0 {
+        names.push(format!("{:#x}", features & !ALL_FEATURES));
+    }
+    names
+}
 
+/// Parses a list of features like `stack,bits`, where `base` is none of them and `all` is all of them
+pub fn parse_features(s: &str) -> Result<u32, String> {
+    s.split(',').map(str::trim).try_fold(0, |features, name| {
+        let bit = match name {
+            "base" => 0,
+            "all" => ALL_FEATURES,
+            _ => FEATURE_NAMES
+                .iter()
+                .find(|&&(n, _)| n == name)
+                .map(|&(_, bit)| bit)
+                .ok_or_else(|| format!("unknown cpu feature {name}"))?,
+        };
+        Ok(features | bit)
+    })
+}
+
+/// The short form of a conditional jump, as the assembler and linker choose when the target is close enough
+pub const fn short_jump(opcode: u8) -> Option<u8> {
+    Some(match opcode {
+        JEZ..=JBE => opcode - JEZ + JEZ_S,
+        _ => return None,
+    })
+}
+
+mod decode;
 mod handlers;
+pub use self::decode::*;
 pub use handlers::*;
+
+#[test]
+fn cpu_features() {
+    assert_eq!(feature(ADD_W), 0);
+    assert_eq!(feature(JNZ_S), FEATURE_SHORT_JUMPS);
+    assert_eq!(feature(LEAVE), FEATURE_STACK);
+    assert_eq!(
+        parse_features("base, stack,bits"),
+        Ok(FEATURE_STACK | FEATURE_BITS)
+    );
+    assert_eq!(parse_features("all"), Ok(ALL_FEATURES));
+    assert!(parse_features("stack,float").is_err());
+    assert_eq!(
+        feature_names(FEATURE_ROTATES | 0x1000),
+        ["rotates".to_owned(), "0x1000".to_owned()]
+    );
+}
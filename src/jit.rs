@@ -0,0 +1,721 @@
+//! Dynamic translation of hot code to native code, used by `Machine::step_native`
+//!
+//! Blocks of arithmetic, immediate loads and jumps are compiled with cranelift once they have been run often enough.
+//! Everything else (memory accesses, I/O, traps and interrupts) is left to the interpreter,
+//! which stays the reference for how instructions behave.
+
+use std::collections::HashMap;
+
+use cranelift_codegen::{
+    ir::{self, condcodes::IntCC, types, AbiParam, Endianness, InstBuilder, MemFlags, Type, Value},
+    settings::{self, Configurable},
+    Context,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::{
+    cpu::{ByteRegister as Br, Registers, WideRegister as Wr},
+    isa::{cycles, decode, BinaryOp, BitOp, Condition, Instruction, UnaryOp},
+    U4,
+};
+
+/// How many times a location is run by the interpreter before the block there is compiled
+const HOT: u8 = 16;
+/// Heat of locations where no block could be compiled
+const UNCOMPILABLE: u8 = u8::MAX;
+/// The most instructions in a block
+const MAX_BLOCK: usize = 64;
+/// The most times a block loops in compiled code in one go, so devices and interrupts are not held off for long
+const MAX_LOOPS: u64 = 256;
+
+/// The registers as compiled code reads and writes them
+#[repr(C)]
+#[derive(Debug, Default)]
+struct State {
+    /// `r1` to `r10` as little endian wides, also the byte registers
+    general_purposes: [u8; 20],
+    /// `rs`, `rl`, `rf`, `rp` and `rh`
+    specials: [u16; 5],
+    program_counter: u16,
+    zero: u8,
+    sign: u8,
+    overflow: u8,
+    carry: u8,
+}
+
+const GENERAL_PURPOSES: i32 = 0;
+const SPECIALS: i32 = 20;
+const PROGRAM_COUNTER: i32 = 30;
+const ZERO: i32 = 32;
+const SIGN: i32 = 33;
+const OVERFLOW: i32 = 34;
+const CARRY: i32 = 35;
+
+impl State {
+    fn new(r: &Registers) -> Self {
+        let mut state = State {
+            program_counter: r.program_counter,
+            zero: r.zero as u8,
+            sign: r.sign as u8,
+            overflow: r.overflow as u8,
+            carry: r.carry as u8,
+            ..State::default()
+        };
+        for n in 1..=10 {
+            let [l, h] = r.read_wide(Wr(U4::new(n))).to_le_bytes();
+            state.general_purposes[2 * (n as usize - 1)] = l;
+            state.general_purposes[2 * (n as usize - 1) + 1] = h;
+        }
+        for n in 11..=15 {
+            state.specials[n as usize - 11] = r.read_wide(Wr(U4::new(n)));
+        }
+        state
+    }
+    fn store(&self, r: &mut Registers) {
+        for n in 1..=10 {
+            let i = 2 * (n as usize - 1);
+            let w = u16::from_le_bytes([self.general_purposes[i], self.general_purposes[i + 1]]);
+            r.write_wide(Wr(U4::new(n)), w);
+        }
+        for n in 11..=15 {
+            r.write_wide(Wr(U4::new(n)), self.specials[n as usize - 11]);
+        }
+        r.program_counter = self.program_counter;
+        r.zero = self.zero != 0;
+        r.sign = self.sign != 0;
+        r.overflow = self.overflow != 0;
+        r.carry = self.carry != 0;
+    }
+}
+
+/// Runs the block with the registers at most the given times in a row, giving how many times it ran
+type Compiled = unsafe extern "C" fn(*mut State, i64) -> i64;
+
+struct Block {
+    /// The code it was compiled from, it has to be compiled again if it changes
+    bytes: Vec<u8>,
+    instructions: u64,
+    cycles: u64,
+    code: Compiled,
+}
+
+/// What running a compiled block did
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ran {
+    /// The instructions run, as many as the interpreter would have stepped
+    pub steps: u64,
+    pub cycles: u64,
+}
+
+/// Compiled blocks by location and how often the interpreter ran the locations without one
+pub struct Jit {
+    module: Option<JITModule>,
+    ctx: Context,
+    builder_ctx: FunctionBuilderContext,
+    blocks: HashMap<u16, Block>,
+    heat: Vec<u8>,
+}
+
+impl Jit {
+    /// Sets up compiling for the host, which cranelift has to support
+    pub fn new() -> Result<Self, String> {
+        let mut flags = settings::builder();
+        for (name, value) in [
+            ("opt_level", "speed"),
+            ("use_colocated_libcalls", "false"),
+            ("is_pic", "true"),
+        ] {
+            flags.set(name, value).map_err(|e| e.to_string())?;
+        }
+        let isa = cranelift_native::builder()
+            .map_err(|e| format!("host machine is not supported: {e}"))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| e.to_string())?;
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        Ok(Jit {
+            ctx: module.make_context(),
+            module: Some(module),
+            builder_ctx: FunctionBuilderContext::new(),
+            blocks: HashMap::new(),
+            heat: vec![0; 0x10000],
+        })
+    }
+    /// How many blocks have been compiled and are still valid
+    pub fn compiled(&self) -> usize {
+        self.blocks.len()
+    }
+    /// Runs the block at the program counter if it is hot, or gives `None` so the interpreter runs the instruction
+    ///
+    /// `memory` is the memory below I/O mapping and `executable` tells whether an address can be run from.
+    /// A block that loops to itself runs again in compiled code, as long as it stays within `max_steps`.
+    pub(crate) fn run(
+        &mut self,
+        registers: &mut Registers,
+        memory: &[u8],
+        executable: impl Fn(u16) -> bool,
+        max_steps: u64,
+    ) -> Option<Ran> {
+        let pc = registers.program_counter;
+        let start = pc as usize;
+        if let Some(block) = self.blocks.get(&pc) {
+            if memory.get(start..start + block.bytes.len()) != Some(&block.bytes[..]) {
+                // Changed since it was compiled
+                self.blocks.remove(&pc);
+                self.heat[start] = 0;
+                return None;
+            }
+        } else {
+            let heat = &mut self.heat[start];
+            if *heat >= HOT {
+                return None;
+            }
+            *heat += 1;
+            if *heat < HOT {
+                return None;
+            }
+            match self.compile(pc, memory, executable) {
+                Some(block) => {
+                    self.blocks.insert(pc, block);
+                }
+                None => {
+                    self.heat[start] = UNCOMPILABLE;
+                    return None;
+                }
+            }
+        }
+
+        let block = &self.blocks[&pc];
+        let times = (max_steps / block.instructions).min(MAX_LOOPS);
+        if times == 0 {
+            return None;
+        }
+        let mut state = State::new(registers);
+        // Safety: the code was compiled for `State` and the module it is in is alive
+        let ran = unsafe { (block.code)(&mut state, times as i64) } as u64;
+        state.store(registers);
+
+        Some(Ran {
+            steps: ran * block.instructions,
+            cycles: ran * block.cycles,
+        })
+    }
+
+    /// Compiles the instructions from `start` up to and including the first jump, if any can be
+    fn compile(
+        &mut self,
+        start: u16,
+        memory: &[u8],
+        executable: impl Fn(u16) -> bool,
+    ) -> Option<Block> {
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        while instructions.len() < MAX_BLOCK {
+            let Ok((ins, size)) = decode(addr, memory) else {
+                break;
+            };
+            let Some(next) = addr.checked_add(size as u16) else {
+                break;
+            };
+            if !compilable(ins) || !(addr..next).all(&executable) {
+                break;
+            }
+            instructions.push((ins, next, cycles(memory[addr as usize]).unwrap_or(1)));
+            addr = next;
+            if ends_block(ins) {
+                break;
+            }
+        }
+        if instructions.is_empty() {
+            return None;
+        }
+
+        let module = self.module.as_mut()?;
+        let pointer = module.target_config().pointer_type();
+        self.ctx.func.signature = module.make_signature();
+        let signature = &mut self.ctx.func.signature;
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I64));
+        signature.returns.push(AbiParam::new(types::I64));
+
+        let mut b = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_ctx);
+        let entry = b.create_block();
+        let body = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.append_block_param(body, types::I64);
+        b.switch_to_block(entry);
+        let state = b.block_params(entry)[0];
+        let max_times = b.block_params(entry)[1];
+        let once = b.ins().iconst(types::I64, 1);
+        b.ins().jump(body, &[once]);
+
+        b.switch_to_block(body);
+        let times = b.block_params(body)[0];
+        let mut t = Translator { b, state };
+        for &(ins, next, _) in &instructions {
+            t.instruction(ins, start, next, body, times, max_times);
+        }
+        if !ends_block(instructions.last()?.0) {
+            // Stopped before something the interpreter has to run
+            let pc = t.b.ins().iconst(types::I16, addr as i64);
+            t.store(pc, PROGRAM_COUNTER);
+            t.b.ins().return_(&[times]);
+        }
+        t.b.seal_all_blocks();
+        t.b.finalize();
+
+        let id = module
+            .declare_anonymous_function(&self.ctx.func.signature)
+            .ok()?;
+        let defined = module.define_function(id, &mut self.ctx);
+        module.clear_context(&mut self.ctx);
+        defined.ok()?;
+        module.finalize_definitions().ok()?;
+        // Safety: it was built with the signature of `Compiled` in the host's calling convention
+        let code = unsafe {
+            std::mem::transmute::<*const u8, Compiled>(module.get_finalized_function(id))
+        };
+
+        Some(Block {
+            bytes: memory[start as usize..addr as usize].to_vec(),
+            instructions: instructions.len() as u64,
+            cycles: instructions.iter().map(|&(_, _, c)| c).sum(),
+            code,
+        })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        self.blocks.clear();
+        if let Some(module) = self.module.take() {
+            // Safety: the blocks pointing into it are gone
+            unsafe { module.free_memory() }
+        }
+    }
+}
+
+/// Whether the instruction only uses registers and cannot trap
+fn compilable(ins: Instruction) -> bool {
+    use self::Instruction as I;
+    // Operations using the carry going in are left to the interpreter
+    if let I::BinaryB(op, ..) | I::BinaryW(op, ..) | I::BinaryImmB(op, ..) | I::BinaryImmW(op, ..) =
+        ins
+    {
+        return !op.uses_carry();
+    }
+    matches!(
+        ins,
+        I::Nop
+            | I::LdiB(..)
+            | I::LdiW(..)
+            | I::LoadAddress(..)
+            | I::UnaryB(..)
+            | I::BitB(..)
+            | I::BitW(..)
+            | I::UnaryW(..)
+            | I::SignExtend(..)
+            | I::ZeroExtend(..)
+            | I::ExchangeB(..)
+            | I::ExchangeW(..)
+            | I::SwapBytes(..)
+            | I::CompareB(..)
+            | I::CompareW(..)
+            | I::MulB(..)
+            | I::MulW(..)
+            | I::IMulB(..)
+            | I::IMulW(..)
+            | I::MulHigh(..)
+            | I::IMulHigh(..)
+            | I::FixedMul(..)
+            | I::Jump(_)
+            | I::JumpIf(..)
+            | I::JumpRegister(_)
+    )
+}
+fn ends_block(ins: Instruction) -> bool {
+    use self::Instruction as I;
+    matches!(ins, I::Jump(_) | I::JumpIf(..) | I::JumpRegister(_))
+}
+
+/// Builds the code of a block, reading and writing the registers in the state
+struct Translator<'a> {
+    b: FunctionBuilder<'a>,
+    state: Value,
+}
+
+impl Translator<'_> {
+    fn flags(&self) -> MemFlags {
+        MemFlags::trusted().with_endianness(Endianness::Little)
+    }
+    fn load(&mut self, ty: Type, offset: i32) -> Value {
+        let flags = self.flags();
+        self.b.ins().load(ty, flags, self.state, offset)
+    }
+    fn store(&mut self, val: Value, offset: i32) {
+        let flags = self.flags();
+        self.b.ins().store(flags, val, self.state, offset);
+    }
+
+    fn read_byte(&mut self, r: Br) -> Value {
+        match u8::from(r.0) {
+            0 => self.b.ins().iconst(types::I8, 0),
+            n @ 1..=10 => self.load(types::I8, GENERAL_PURPOSES + n as i32 - 1),
+            // The lower byte of r6 to r10
+            n => self.load(types::I8, GENERAL_PURPOSES + 10 + 2 * (n as i32 - 11)),
+        }
+    }
+    fn write_byte(&mut self, r: Br, val: Value) {
+        match u8::from(r.0) {
+            0 => (),
+            n @ 1..=10 => self.store(val, GENERAL_PURPOSES + n as i32 - 1),
+            n => {
+                let wide = self.b.ins().uextend(types::I16, val);
+                self.store(wide, GENERAL_PURPOSES + 10 + 2 * (n as i32 - 11));
+            }
+        }
+    }
+    fn wide_offset(r: Wr) -> Option<i32> {
+        match u8::from(r.0) {
+            0 => None,
+            n @ 1..=10 => Some(GENERAL_PURPOSES + 2 * (n as i32 - 1)),
+            n => Some(SPECIALS + 2 * (n as i32 - 11)),
+        }
+    }
+    fn read_wide(&mut self, r: Wr) -> Value {
+        match Self::wide_offset(r) {
+            Some(offset) => self.load(types::I16, offset),
+            None => self.b.ins().iconst(types::I16, 0),
+        }
+    }
+    fn write_wide(&mut self, r: Wr, val: Value) {
+        if let Some(offset) = Self::wide_offset(r) {
+            self.store(val, offset);
+        }
+    }
+    /// Sets the flags like the interpreter does, with the sign and zero flags from the result
+    fn set_flags(&mut self, res: Value, carry: Value, overflow: Value) {
+        let zero = self.b.ins().icmp_imm(IntCC::Equal, res, 0);
+        let sign = self.b.ins().icmp_imm(IntCC::SignedLessThan, res, 0);
+        self.store(zero, ZERO);
+        self.store(sign, SIGN);
+        self.store(overflow, OVERFLOW);
+        self.store(carry, CARRY);
+    }
+
+    fn binop(&mut self, ty: Type, op: BinaryOp, x: Value, y: Value) -> Value {
+        let no = self.b.ins().iconst(types::I8, 0);
+        let (res, carry, overflow) = match op {
+            BinaryOp::Add | BinaryOp::Sub => {
+                // Done in 32 bits to see what does not fit
+                let (ux, uy) = (
+                    self.b.ins().uextend(types::I32, x),
+                    self.b.ins().uextend(types::I32, y),
+                );
+                let (sx, sy) = (
+                    self.b.ins().sextend(types::I32, x),
+                    self.b.ins().sextend(types::I32, y),
+                );
+                let (unsigned, signed) = if op == BinaryOp::Add {
+                    (self.b.ins().iadd(ux, uy), self.b.ins().iadd(sx, sy))
+                } else {
+                    (self.b.ins().isub(ux, uy), self.b.ins().isub(sx, sy))
+                };
+                let res = self.b.ins().ireduce(ty, unsigned);
+                let back = self.b.ins().uextend(types::I32, res);
+                let carry = self.b.ins().icmp(IntCC::NotEqual, back, unsigned);
+                let back = self.b.ins().sextend(types::I32, res);
+                let overflow = self.b.ins().icmp(IntCC::NotEqual, back, signed);
+                (res, carry, overflow)
+            }
+            BinaryOp::And => (self.b.ins().band(x, y), no, no),
+            BinaryOp::Or => (self.b.ins().bor(x, y), no, no),
+            BinaryOp::Xor => (self.b.ins().bxor(x, y), no, no),
+            // Shifts only use the low bits of the amount, like the interpreter's wrapping shifts
+            BinaryOp::Shl => (self.b.ins().ishl(x, y), no, no),
+            BinaryOp::Asr => (self.b.ins().sshr(x, y), no, no),
+            BinaryOp::Lsr => (self.b.ins().ushr(x, y), no, no),
+            BinaryOp::Rol => {
+                let res = self.b.ins().rotl(x, y);
+                let low = self.b.ins().band_imm(res, 1);
+                let carry = self.b.ins().icmp_imm(IntCC::NotEqual, low, 0);
+                (res, carry, no)
+            }
+            BinaryOp::Ror => {
+                let res = self.b.ins().rotr(x, y);
+                let carry = self.b.ins().icmp_imm(IntCC::SignedLessThan, res, 0);
+                (res, carry, no)
+            }
+            BinaryOp::Rcl | BinaryOp::Rcr | BinaryOp::Adc | BinaryOp::Sbb => {
+                unreachable!("not compilable")
+            }
+        };
+        self.set_flags(res, carry, overflow);
+        res
+    }
+    /// Done as the binary operation it is short for, like the interpreter
+    fn unop(&mut self, ty: Type, op: UnaryOp, x: Value) -> Value {
+        match op {
+            UnaryOp::Not => {
+                let ones = self.b.ins().iconst(ty, -1);
+                self.binop(ty, BinaryOp::Xor, x, ones)
+            }
+            UnaryOp::Neg => {
+                let zero = self.b.ins().iconst(ty, 0);
+                self.binop(ty, BinaryOp::Sub, zero, x)
+            }
+        }
+    }
+    /// Sets zero if the bit is clear, giving the value with it changed unless it is only tested
+    fn bit(&mut self, op: BitOp, x: Value, n: u8) -> Option<Value> {
+        let mask = 1i64 << n;
+        let bit = self.b.ins().band_imm(x, mask);
+        let zero = self.b.ins().icmp_imm(IntCC::Equal, bit, 0);
+        self.store(zero, ZERO);
+        Some(match op {
+            BitOp::Test => return None,
+            BitOp::Set => self.b.ins().bor_imm(x, mask),
+            BitOp::Clear => self.b.ins().band_imm(x, !mask),
+            BitOp::Toggle => self.b.ins().bxor_imm(x, mask),
+        })
+    }
+    /// Multiplies into the upper and lower halves, setting the flags from the lower one
+    ///
+    /// Signed, it carries if the upper half is more than the sign of the lower one.
+    fn mul(&mut self, ty: Type, wider: Type, signed: bool, x: Value, y: Value) -> (Value, Value) {
+        let (x, y) = if signed {
+            (
+                self.b.ins().sextend(wider, x),
+                self.b.ins().sextend(wider, y),
+            )
+        } else {
+            (
+                self.b.ins().uextend(wider, x),
+                self.b.ins().uextend(wider, y),
+            )
+        };
+        let res = self.b.ins().imul(x, y);
+        let lower = self.b.ins().ireduce(ty, res);
+        let upper = self.b.ins().ushr_imm(res, ty.bits() as i64);
+        let upper = self.b.ins().ireduce(ty, upper);
+        let carry = if signed {
+            let back = self.b.ins().sextend(wider, lower);
+            self.b.ins().icmp(IntCC::NotEqual, back, res)
+        } else {
+            self.b.ins().icmp_imm(IntCC::NotEqual, upper, 0)
+        };
+        self.set_flags(lower, carry, carry);
+        (upper, lower)
+    }
+    fn condition(&mut self, cond: Condition) -> Value {
+        use self::Condition::*;
+        let z = self.load(types::I8, ZERO);
+        let s = self.load(types::I8, SIGN);
+        let o = self.load(types::I8, OVERFLOW);
+        let c = self.load(types::I8, CARRY);
+        let b = &mut self.b;
+        match cond {
+            Ez => z,
+            Lt => b.ins().bxor(s, o),
+            Le => {
+                let lt = b.ins().bxor(s, o);
+                b.ins().band(lt, z)
+            }
+            Gt => {
+                let ge = b.ins().icmp(IntCC::Equal, s, o);
+                let nz = b.ins().icmp_imm(IntCC::Equal, z, 0);
+                b.ins().band(ge, nz)
+            }
+            Ge => b.ins().icmp(IntCC::Equal, s, o),
+            Nz => b.ins().icmp_imm(IntCC::Equal, z, 0),
+            O => o,
+            No => b.ins().icmp_imm(IntCC::Equal, o, 0),
+            A => {
+                let c_or_z = b.ins().bor(c, z);
+                b.ins().icmp_imm(IntCC::Equal, c_or_z, 0)
+            }
+            Ae => b.ins().icmp_imm(IntCC::Equal, c, 0),
+            B => c,
+            Be => b.ins().bor(c, z),
+        }
+    }
+    /// Jumps to the location, looping in compiled code if it is the start of the block and it may run again
+    fn jump(&mut self, to: Value, start: u16, body: ir::Block, times: Value, max_times: Value) {
+        let again = self.b.create_block();
+        let exit = self.b.create_block();
+        let to_start = self.b.ins().icmp_imm(IntCC::Equal, to, start as i64);
+        let more = self.b.ins().icmp(IntCC::UnsignedLessThan, times, max_times);
+        let looping = self.b.ins().band(to_start, more);
+        self.b.ins().brif(looping, again, &[], exit, &[]);
+
+        self.b.switch_to_block(again);
+        let next = self.b.ins().iadd_imm(times, 1);
+        self.b.ins().jump(body, &[next]);
+
+        self.b.switch_to_block(exit);
+        self.store(to, PROGRAM_COUNTER);
+        self.b.ins().return_(&[times]);
+    }
+    fn instruction(
+        &mut self,
+        ins: Instruction,
+        start: u16,
+        next: u16,
+        body: ir::Block,
+        times: Value,
+        max_times: Value,
+    ) {
+        use self::Instruction as I;
+        match ins {
+            I::Nop => (),
+            I::LdiB(r1, b) => {
+                let b = self.b.ins().iconst(types::I8, b as i64);
+                self.write_byte(r1, b);
+            }
+            I::LdiW(r1, w) => {
+                let w = self.b.ins().iconst(types::I16, w as i64);
+                self.write_wide(r1, w);
+            }
+            I::LoadAddress(r1, r2, offset) => {
+                let base = self.read_wide(r2);
+                let w = self.b.ins().iadd_imm(base, offset as i64);
+                self.write_wide(r1, w);
+            }
+            I::BinaryB(op, r1, r2, r3) => {
+                let (x, y) = (self.read_byte(r2), self.read_byte(r3));
+                let res = self.binop(types::I8, op, x, y);
+                self.write_byte(r1, res);
+            }
+            I::BinaryW(op, r1, r2, r3) => {
+                let (x, y) = (self.read_wide(r2), self.read_wide(r3));
+                let res = self.binop(types::I16, op, x, y);
+                self.write_wide(r1, res);
+            }
+            I::BinaryImmB(op, r1, r2, b) => {
+                let x = self.read_byte(r2);
+                let y = self.b.ins().iconst(types::I8, b as i64);
+                let res = self.binop(types::I8, op, x, y);
+                self.write_byte(r1, res);
+            }
+            I::BinaryImmW(op, r1, r2, b) => {
+                let x = self.read_wide(r2);
+                let y = self.b.ins().iconst(types::I16, b as i64);
+                let res = self.binop(types::I16, op, x, y);
+                self.write_wide(r1, res);
+            }
+            I::BitB(op, r1, n) => {
+                let x = self.read_byte(r1);
+                if let Some(res) = self.bit(op, x, n % 8) {
+                    self.write_byte(r1, res);
+                }
+            }
+            I::BitW(op, r1, n) => {
+                let x = self.read_wide(r1);
+                if let Some(res) = self.bit(op, x, n % 16) {
+                    self.write_wide(r1, res);
+                }
+            }
+            I::UnaryB(op, r1, r2) => {
+                let x = self.read_byte(r2);
+                let res = self.unop(types::I8, op, x);
+                self.write_byte(r1, res);
+            }
+            I::UnaryW(op, r1, r2) => {
+                let x = self.read_wide(r2);
+                let res = self.unop(types::I16, op, x);
+                self.write_wide(r1, res);
+            }
+            I::SignExtend(r1, r2) => {
+                let b = self.read_byte(r2);
+                let w = self.b.ins().sextend(types::I16, b);
+                self.write_wide(r1, w);
+            }
+            I::ZeroExtend(r1, r2) => {
+                let b = self.read_byte(r2);
+                let w = self.b.ins().uextend(types::I16, b);
+                self.write_wide(r1, w);
+            }
+            I::ExchangeB(r1, r2) => {
+                let (b1, b2) = (self.read_byte(r1), self.read_byte(r2));
+                self.write_byte(r1, b2);
+                self.write_byte(r2, b1);
+            }
+            I::ExchangeW(r1, r2) => {
+                let (w1, w2) = (self.read_wide(r1), self.read_wide(r2));
+                self.write_wide(r1, w2);
+                self.write_wide(r2, w1);
+            }
+            I::SwapBytes(r1, r2) => {
+                let w = self.read_wide(r2);
+                let swapped = self.b.ins().bswap(w);
+                self.write_wide(r1, swapped);
+            }
+            I::CompareB(op, r1, b) => {
+                let x = self.read_byte(r1);
+                let y = self.b.ins().iconst(types::I8, b as i64);
+                self.binop(types::I8, op.binary(), x, y);
+            }
+            I::CompareW(op, r1, w) => {
+                let x = self.read_wide(r1);
+                let y = self.b.ins().iconst(types::I16, w as i64);
+                self.binop(types::I16, op.binary(), x, y);
+            }
+            I::MulB(r1, r2, r3, r4) | I::IMulB(r1, r2, r3, r4) => {
+                let signed = matches!(ins, I::IMulB(..));
+                let (x, y) = (self.read_byte(r3), self.read_byte(r4));
+                let (upper, lower) = self.mul(types::I8, types::I16, signed, x, y);
+                self.write_byte(r1, upper);
+                self.write_byte(r2, lower);
+            }
+            I::MulW(r1, r2, r3, r4) | I::IMulW(r1, r2, r3, r4) => {
+                let signed = matches!(ins, I::IMulW(..));
+                let (x, y) = (self.read_wide(r3), self.read_wide(r4));
+                let (upper, lower) = self.mul(types::I16, types::I32, signed, x, y);
+                self.write_wide(r1, upper);
+                self.write_wide(r2, lower);
+            }
+            I::MulHigh(r1, r2, r3) | I::IMulHigh(r1, r2, r3) => {
+                let (x, y) = (self.read_wide(r2), self.read_wide(r3));
+                let res = if let I::IMulHigh(..) = ins {
+                    self.b.ins().smulhi(x, y)
+                } else {
+                    self.b.ins().umulhi(x, y)
+                };
+                let no = self.b.ins().iconst(types::I8, 0);
+                self.set_flags(res, no, no);
+                self.write_wide(r1, res);
+            }
+            I::FixedMul(r1, r2, r3, b) => {
+                let x = self.read_wide(r2);
+                let x = self.b.ins().sextend(types::I64, x);
+                let y = self.read_wide(r3);
+                let y = self.b.ins().sextend(types::I64, y);
+                let product = self.b.ins().imul(x, y);
+                let res = self.b.ins().sshr_imm(product, (b % 32) as i64);
+                let lower = self.b.ins().ireduce(types::I16, res);
+                let back = self.b.ins().sextend(types::I64, lower);
+                let overflow = self.b.ins().icmp(IntCC::NotEqual, back, res);
+                self.set_flags(lower, overflow, overflow);
+                self.write_wide(r1, lower);
+            }
+            I::Jump(location) => {
+                let to = self.b.ins().iconst(types::I16, location as i64);
+                self.jump(to, start, body, times, max_times);
+            }
+            I::JumpRegister(r1) => {
+                let to = self.read_wide(r1);
+                self.jump(to, start, body, times, max_times);
+            }
+            I::JumpIf(cond, location) => {
+                let holds = self.condition(cond);
+                let taken = self.b.ins().iconst(types::I16, location as i64);
+                let not_taken = self.b.ins().iconst(types::I16, next as i64);
+                let to = self.b.ins().select(holds, taken, not_taken);
+                self.jump(to, start, body, times, max_times);
+            }
+            _ => unreachable!("{ins:?} is not compiled"),
+        }
+    }
+}
@@ -1,10 +1,35 @@
+//! The ISA, decoder and [`machine::Machine`] only need `alloc`, building without the default `std` feature
+//! leaves out objects, the assembler, linker and devices, which need files and the host.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod aalv;
 pub mod cpu;
+#[cfg(feature = "std")]
+pub mod devices;
+#[cfg(feature = "std")]
 pub mod disassemble;
 pub mod isa;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "std")]
+pub mod link;
+pub mod machine;
 pub mod mem;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod rsp;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
 pub mod source;
 pub mod u4;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub use self::u4::U4;
 
@@ -24,11 +49,11 @@ pub const fn align(addr: u16, alignment: u16) -> u16 {
 
 #[test]
 fn test_align() {
-    assert_eq!(align(0x124, SEGMENT_ALIGNMENT), 0x130);
+    assert_eq!(align(0x124, SEGMENT_ALIGNMENT), 0x124);
     assert_eq!(align(0x120, SEGMENT_ALIGNMENT), 0x120);
     assert_eq!(align(0x000, SEGMENT_ALIGNMENT), 0x000);
-    assert_eq!(align(0x411, SEGMENT_ALIGNMENT), 0x420);
-    assert_eq!(align(0x456, SEGMENT_ALIGNMENT), 0x460);
+    assert_eq!(align(0x411, SEGMENT_ALIGNMENT), 0x414);
+    assert_eq!(align(0x456, SEGMENT_ALIGNMENT), 0x458);
     assert_eq!(align(0x63f, SEGMENT_ALIGNMENT), 0x640);
     assert_eq!(align(0x630, SEGMENT_ALIGNMENT), 0x630);
 }
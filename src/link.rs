@@ -0,0 +1,668 @@
+//! Linking objects together into one, like `tl` does
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, Seek, Write},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    aalv::obj::{
+        read_archive_file, Entry, LineEntry, LineTable, Note, Notes, Object, RelocationEntry,
+        RelocationKind, RelocationTable, SegmentFlags, SegmentFlagsTable, SegmentType,
+        SymbolDefinition, SymbolTable, AALV_ARCHIVE_EXT,
+    },
+    align,
+    cpu::{R0, R1},
+    devices::{BANK_SIZE, BANK_WINDOW},
+    isa::{short_jump, FEATURE_SHORT_JUMPS, JMP_S, LDI_W},
+    SEGMENT_ALIGNMENT,
+};
+
+/// Name of the standard library that gets linked in unless `no_std` is set
+pub const STD_LIB: &str = "tstd";
+/// Environment variable with the path of the standard library archive
+///
+/// Without it the standard library is looked for in the library search path like `-l tstd`
+pub const STD_LIB_VAR: &str = "TELDA_STD_LIB";
+/// Environment variable with extra library search directories seperated by colons
+///
+/// These are searched after the directories given with -L
+pub const LIBRARY_PATH_VAR: &str = "TELDA_LIBRARY_PATH";
+
+#[derive(Debug, Default, Clone)]
+pub struct LinkOptions {
+    /// A hexadecimal address prefixed by 0x or a global symbol to use as the entry point
+    pub set_entry: Option<String>,
+    /// Erase internal symbols
+    pub strip_internal: bool,
+    /// Disallows undefined references
+    pub executable: bool,
+    /// Directories to search for libraries in before the ones in `TELDA_LIBRARY_PATH`
+    pub library_dirs: Vec<PathBuf>,
+    /// Names of library archives lib<NAME>.ta to link with
+    pub libraries: Vec<String>,
+    /// Do not link with the standard library
+    pub no_std: bool,
+    /// Objects to put in banks instead of main memory, with the number of the bank
+    ///
+    /// The objects of a bank are laid out from the bank window, see [`crate::devices::Banks`].
+    pub banks: Vec<(u8, PathBuf)>,
+    /// Makes the jumps short that reach their target with a signed byte, see [`relax`]
+    pub relax: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidEntryPointFormat(ParseIntError),
+    /// The problems have already been printed
+    ObjectFailure,
+    NoEntryPoint,
+    ReferenceToNonExistantSegment,
+    LibraryNotFound(String),
+    /// The bank does not fit in the bank window
+    BankTooLarge(u8),
+    /// The segments outside of banks reach into the bank window
+    BankWindowInUse,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {e}"),
+            Error::InvalidEntryPointFormat(e) => write!(f, "invalid entry point format: {e}"),
+            Error::NoEntryPoint => write!(
+                f,
+                "No entry point was defined, cannot make executable. Perhaps use -E to set one?"
+            ),
+            Error::ReferenceToNonExistantSegment => {
+                write!(f, "reference to a segment that was not defined")
+            }
+            Error::LibraryNotFound(name) => write!(
+                f,
+                "could not find library lib{name}.{AALV_ARCHIVE_EXT} in library search path"
+            ),
+            Error::ObjectFailure => write!(f, "linking failed"),
+            Error::BankTooLarge(n) => write!(
+                f,
+                "bank {n} is larger than the bank window of 0x{BANK_SIZE:04x} bytes"
+            ),
+            Error::BankWindowInUse => write!(
+                f,
+                "segments outside of banks reach into the bank window at 0x{BANK_WINDOW:04x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn find_library(name: &str, search_dirs: &[PathBuf]) -> Option<PathBuf> {
+    let file_name = format!("lib{name}.{AALV_ARCHIVE_EXT}");
+    search_dirs
+        .iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|p| p.is_file())
+}
+
+/// Adds the members of an archive that define a global symbol which is
+/// referenced but not yet defined by the objects linked so far
+///
+/// Members are added until no more references can be resolved by the archive,
+/// so that members can depend on each other.
+fn link_archive(objects: &mut Vec<(PathBuf, Object)>, archive: &Path) -> io::Result<()> {
+    let mut members = read_archive_file(archive)?;
+
+    fn add_symbols(
+        obj: &Object,
+        defined: &mut HashSet<Box<str>>,
+        referenced: &mut HashSet<Box<str>>,
+    ) {
+        for sym in obj.symbols.0.iter().filter(|s| s.is_global) {
+            if let SegmentType::Unknown = sym.segment_type {
+                referenced.insert(sym.name.clone());
+            } else {
+                defined.insert(sym.name.clone());
+            }
+        }
+    }
+
+    let mut defined = HashSet::new();
+    let mut referenced = HashSet::new();
+    for (_, obj) in objects.iter() {
+        add_symbols(obj, &mut defined, &mut referenced);
+    }
+
+    loop {
+        let needed = members.iter().position(|member| {
+            member.symbols.0.iter().any(|s| {
+                s.is_global
+                    && s.segment_type != SegmentType::Unknown
+                    && referenced.contains(&s.name)
+                    && !defined.contains(&s.name)
+            })
+        });
+        let Some(needed) = needed else {
+            break;
+        };
+
+        let member = members.swap_remove(needed);
+        add_symbols(&member, &mut defined, &mut referenced);
+        objects.push((archive.to_owned(), member));
+    }
+
+    Ok(())
+}
+
+/// Links the objects, with the libraries from the options, into one object
+///
+/// `producer` and `flags` go in the note recording what made the output.
+pub fn link(
+    mut objects: Vec<(PathBuf, Object)>,
+    options: &LinkOptions,
+    producer: &str,
+    flags: &str,
+) -> Result<Object, Error> {
+    let LinkOptions {
+        set_entry,
+        strip_internal,
+        executable,
+        library_dirs,
+        libraries,
+        no_std,
+        banks,
+        relax: relax_jumps,
+    } = options;
+
+    let mut library_dirs = library_dirs.clone();
+    if let Some(paths) = env::var_os(LIBRARY_PATH_VAR) {
+        library_dirs.extend(env::split_paths(&paths));
+    }
+    for name in libraries {
+        let archive = find_library(name, &library_dirs)
+            .ok_or_else(|| Error::LibraryNotFound(name.clone()))?;
+        link_archive(&mut objects, &archive).map_err(Error::Io)?;
+    }
+    if !no_std {
+        match env::var_os(STD_LIB_VAR).map(PathBuf::from) {
+            Some(archive) if archive.is_file() => {
+                link_archive(&mut objects, &archive).map_err(Error::Io)?
+            }
+            Some(archive) => eprintln!(
+                "warning: standard library {} from {STD_LIB_VAR} not found, use --no-std to not link it",
+                archive.display()
+            ),
+            None => match find_library(STD_LIB, &library_dirs) {
+                Some(archive) => link_archive(&mut objects, &archive).map_err(Error::Io)?,
+                None if library_dirs.is_empty() => eprintln!(
+                    "warning: standard library lib{STD_LIB}.{AALV_ARCHIVE_EXT} not found, the library search path is empty, \
+                    add to it with -L or {LIBRARY_PATH_VAR}, set {STD_LIB_VAR} or use --no-std to not link it"
+                ),
+                None => eprintln!(
+                    "warning: standard library lib{STD_LIB}.{AALV_ARCHIVE_EXT} not found in {}, use --no-std to not link it",
+                    library_dirs.iter().map(|d| d.display().to_string()).collect::<Vec<_>>().join(", ")
+                ),
+            },
+        }
+    }
+
+    // Group 0 is main memory, the others are the banks
+    let mut bank_numbers = Vec::new();
+    let mut objects: Vec<_> = objects.into_iter().map(|(p, o)| (0, p, o)).collect();
+    for (number, path) in banks {
+        let group = match bank_numbers.iter().position(|n| n == number) {
+            Some(i) => i + 1,
+            None => {
+                bank_numbers.push(*number);
+                bank_numbers.len()
+            }
+        };
+        let obj = Object::from_file(path).map_err(Error::Io)?;
+        objects.push((group, path.clone(), obj));
+    }
+
+    let mut layouts = vec![BTreeMap::new(); bank_numbers.len() + 1];
+    let mut seg_flags_out = BTreeMap::new();
+
+    for (group, layout) in layouts.iter_mut().enumerate() {
+        let mut lengths = BTreeMap::new();
+
+        for (_, _, obj) in objects.iter().filter(|o| o.0 == group) {
+            for (&stype, &(_start, ref v)) in &obj.segs {
+                *lengths.entry(stype).or_insert(0u32) += v.len() as u32;
+
+                if group == 0 {
+                    let flags = seg_flags_out.entry(stype).or_insert(SegmentFlags::NONE);
+                    *flags = flags.union(obj.segment_flags(stype));
+                }
+            }
+        }
+        let mut last_end = if group == 0 {
+            (lengths.remove(&SegmentType::Zero).unwrap_or(0) as u16).max(SEGMENT_ALIGNMENT)
+        } else {
+            BANK_WINDOW
+        };
+
+        for (st, size) in lengths {
+            let start = align(last_end, SEGMENT_ALIGNMENT);
+            layout.insert(st, (start, Vec::with_capacity(size as usize)));
+            last_end = start + size as u16;
+        }
+        let end = last_end as u32;
+        if group == 0 && !bank_numbers.is_empty() && end > BANK_WINDOW as u32 {
+            return Err(Error::BankWindowInUse);
+        }
+        if group != 0 && end > (BANK_WINDOW + BANK_SIZE) as u32 {
+            return Err(Error::BankTooLarge(bank_numbers[group - 1]));
+        }
+    }
+
+    let mut segs: Vec<BTreeMap<_, _>> = layouts
+        .iter_mut()
+        .map(|layout| {
+            layout
+                .iter_mut()
+                .map(|(&st, &mut (start, ref mut bytes))| (st, (start, bytes)))
+                .collect()
+        })
+        .collect();
+
+    let mut global_symbols = HashMap::new();
+    let mut symbols_out = Vec::new();
+    let mut reloc_out = Vec::new();
+    let mut lines_out = LineTable::default();
+    let mut notes_out = Vec::new();
+    let mut cpu_features = 0;
+    {
+        let mut inputs: Vec<String> = Vec::new();
+        for (_, p, _) in &objects {
+            let p = p.to_string_lossy().into_owned();
+            if !inputs.contains(&p) {
+                inputs.push(p);
+            }
+        }
+        notes_out.push(Note::new(producer, flags, &inputs));
+    }
+    let mut undefined_references = Vec::new();
+
+    let mut entry_point = None;
+
+    let mut failure = false;
+
+    for (group, input_file, mut obj) in objects {
+        let segs = &mut segs[group];
+        entry_point = entry_point.or_else(|| {
+            obj.entry
+                .map(|Entry(st, ep)| Entry(st, ep - obj.segs[&st].0 + segs[&st].0))
+        });
+
+        let mut file_symbol_to_out_symbol = Vec::new();
+        let reloc;
+        {
+            for mut symdef in obj.symbols.into_iter() {
+                let next_id = symbols_out.len();
+                let mut id_in_fstos = None;
+
+                symdef.location -= obj.segs.get(&symdef.segment_type).map(|s| s.0).unwrap_or(0);
+                symdef.location += segs.get(&symdef.segment_type).map(|s| s.0).unwrap_or(0);
+
+                if symdef.is_global {
+                    match global_symbols.get(&symdef.name) {
+                        None => {
+                            global_symbols.insert(symdef.name.clone(), next_id);
+                        }
+                        Some(&id) => {
+                            let cur_symdef: &mut SymbolDefinition = &mut symbols_out[id];
+
+                            if let SegmentType::Unknown = symdef.segment_type {
+                            } else if let SegmentType::Unknown = cur_symdef.segment_type {
+                                *cur_symdef = symdef.clone();
+                            } else {
+                                let provenance = |def: &SymbolDefinition| {
+                                    def.source_description()
+                                        .map(|s| format!(" (defined in {s})"))
+                                        .unwrap_or_default()
+                                };
+                                eprintln!("global symbol {} defined in {}{} but was already defined in a previous file at location 0x{:02x} in {}{}",
+                                    symdef.name,
+                                    input_file.display(),
+                                    provenance(&symdef),
+                                    cur_symdef.location,
+                                    cur_symdef.segment_type,
+                                    provenance(cur_symdef),
+                                );
+                                failure = true;
+                            }
+
+                            id_in_fstos = Some(id);
+                        }
+                    }
+                } else if *strip_internal {
+                    symdef.name = "".into();
+                }
+
+                let id;
+                if let Some(id_in_fstos) = id_in_fstos {
+                    id = id_in_fstos;
+                } else {
+                    symbols_out.push(symdef);
+                    id = next_id;
+                }
+                file_symbol_to_out_symbol.push(id);
+            }
+            reloc = obj.relocation_table.0;
+        }
+
+        for RelocationEntry {
+            reference_location,
+            reference_segment,
+            symbol_index,
+            kind,
+        } in reloc
+        {
+            let symbol_index = file_symbol_to_out_symbol[symbol_index as usize];
+
+            let location_in_file = reference_location - obj.segs[&reference_segment].0;
+            let reference_location = location_in_file + segs[&reference_segment].0;
+
+            let bytes = &mut obj
+                .segs
+                .get_mut(&reference_segment)
+                .ok_or(Error::ReferenceToNonExistantSegment)?
+                .1;
+
+            let symdef = &symbols_out[symbol_index];
+            let undefined = matches!(symdef.segment_type, SegmentType::Unknown);
+
+            kind.apply(
+                &mut bytes[location_in_file as usize..],
+                symdef.location,
+                reference_location,
+            );
+
+            let entry = RelocationEntry {
+                reference_location,
+                reference_segment,
+                symbol_index: symbol_index as u16,
+                kind,
+            };
+
+            // References in banks are not kept, as they could not be told apart from those in main memory
+            if group == 0 {
+                reloc_out.push(entry);
+            }
+            if undefined {
+                undefined_references.push((group, entry));
+            }
+        }
+
+        cpu_features |= obj.cpu_features;
+        for note in obj.notes.0 {
+            if !notes_out.contains(&note) {
+                notes_out.push(note);
+            }
+        }
+
+        let lines = if group == 0 {
+            obj.lines.entries
+        } else {
+            Vec::new()
+        };
+        for LineEntry {
+            segment,
+            location,
+            file,
+            line,
+        } in lines
+        {
+            let (Some(&(start_in_file, _)), Some(&(start, _))) =
+                (obj.segs.get(&segment), segs.get(&segment))
+            else {
+                continue;
+            };
+
+            let file = lines_out.file_id(&obj.lines.files[file as usize]);
+            lines_out.entries.push(LineEntry {
+                segment,
+                location: location - start_in_file + start,
+                file,
+                line,
+            });
+        }
+
+        for (t, (_, bytes)) in obj.segs {
+            let seg = segs.get_mut(&t).expect("segment guaranteed to exist");
+            seg.0 += bytes.len() as u16;
+            seg.1.extend(bytes);
+        }
+    }
+    drop(segs);
+
+    for (
+        group,
+        RelocationEntry {
+            reference_segment,
+            reference_location,
+            symbol_index,
+            kind,
+        },
+    ) in undefined_references
+    {
+        let symdef = &symbols_out[symbol_index as usize];
+        if let SegmentType::Unknown = symdef.segment_type {
+            if *executable {
+                eprintln!(
+                    "undefined reference to {} at 0x{:02x}",
+                    symdef.name, symdef.location
+                );
+                failure = true;
+            }
+            continue;
+        };
+
+        let seg = layouts[group]
+            .get_mut(&reference_segment)
+            .expect("would have been caught earlier");
+        let index = (reference_location - seg.0) as usize;
+        kind.apply(&mut seg.1[index..], symdef.location, reference_location);
+    }
+
+    if *relax_jumps
+        && !failure
+        && relax(
+            &mut layouts[0],
+            &mut symbols_out,
+            &mut reloc_out,
+            &mut lines_out,
+            &mut entry_point,
+        )
+    {
+        cpu_features |= FEATURE_SHORT_JUMPS;
+    }
+
+    if let Some(entry) = set_entry {
+        entry_point = Some({
+            if let Some(entry) = entry.strip_prefix("0x") {
+                Entry(
+                    SegmentType::Zero,
+                    u16::from_str_radix(entry, 16).map_err(Error::InvalidEntryPointFormat)?,
+                )
+            } else if let Some(&pos) = global_symbols.get(entry.as_str()) {
+                let sym = &symbols_out[pos];
+                Entry(sym.segment_type, sym.location)
+            } else {
+                eprintln!("Start symbol {entry} was not found. Perhaps it is not global?");
+                eprintln!("Aborting linking");
+                failure = true;
+                Entry(SegmentType::Unknown, 0xffff)
+            }
+        });
+    };
+
+    if failure {
+        return Err(Error::ObjectFailure);
+    }
+
+    lines_out.sort();
+
+    // Only keep the flags that differ from the default
+    seg_flags_out.retain(|&st, &mut flags| flags != SegmentFlags::default_for(st));
+
+    let segs_out = layouts.remove(0);
+    let banks_out = bank_numbers
+        .into_iter()
+        .zip(layouts)
+        .map(|(number, layout)| {
+            let mut bytes = Vec::new();
+            for (start, seg) in layout.into_values() {
+                let offset = (start - BANK_WINDOW) as usize;
+                bytes.resize(offset, 0);
+                bytes.extend(seg);
+            }
+            (number, bytes)
+        })
+        .collect();
+
+    Ok(Object {
+        segs: segs_out,
+        banks: banks_out,
+        seg_flags: SegmentFlagsTable(seg_flags_out),
+        entry: entry_point,
+        symbols: SymbolTable(symbols_out),
+        relocation_table: RelocationTable(reloc_out),
+        lines: lines_out,
+        notes: Notes(notes_out),
+        cpu_features,
+        ..Object::default()
+    })
+}
+
+/// Where a jump is that could be made short, with its short opcode and how long it is
+fn short_jump_at(
+    segs: &BTreeMap<SegmentType, (u16, Vec<u8>)>,
+    symbols: &[SymbolDefinition],
+    reloc: &RelocationEntry,
+) -> Option<(u16, u8, u16)> {
+    if reloc.kind != RelocationKind::Jump {
+        return None;
+    }
+    let target = &symbols[reloc.symbol_index as usize];
+    if target.segment_type == SegmentType::Unknown {
+        return None;
+    }
+    let (start, bytes) = segs.get(&reloc.reference_segment)?;
+    let at = reloc.reference_location.checked_sub(*start)? as usize;
+
+    let (ins, op, size) = match *bytes.get(at.checked_sub(2)?..at)? {
+        [LDI_W, pair] if pair == R0.0.pair(R1.0) => (at - 2, JMP_S, 4),
+        [_, op] => (at - 1, short_jump(op)?, 3),
+        _ => unreachable!(),
+    };
+    let location = start + ins as u16;
+    let offset = target.location as i32 - (location as i32 + 2);
+    i8::try_from(offset).ok()?;
+    Some((location, op, size))
+}
+
+/// Makes the jumps that reach their target with a signed byte short, for `tl --relax`
+///
+/// What comes after a jump made short in its segment moves closer to it, leaving the space at the end of the
+/// segment unused so the other segments stay where they are. Every reference is applied again after.
+/// Tells whether any jump was made short.
+fn relax(
+    segs: &mut BTreeMap<SegmentType, (u16, Vec<u8>)>,
+    symbols: &mut [SymbolDefinition],
+    relocs: &mut [RelocationEntry],
+    lines: &mut LineTable,
+    entry: &mut Option<Entry>,
+) -> bool {
+    let mut relaxed = false;
+    while let Some((i, (location, op, size))) = relocs
+        .iter()
+        .enumerate()
+        .find_map(|(i, r)| Some((i, short_jump_at(segs, symbols, r)?)))
+    {
+        let st = relocs[i].reference_segment;
+        let (start, bytes) = segs.get_mut(&st).expect("the jump is in it");
+        let seg_end = *start + bytes.len() as u16;
+        let index = (location - *start) as usize;
+        bytes.splice(index..index + size as usize, [op, 0]);
+        relaxed = true;
+
+        let end = location + size;
+        let by = size - 2;
+        let moves = |s: SegmentType, at: u16| s == st && (end..=seg_end).contains(&at);
+        for symdef in symbols.iter_mut() {
+            if moves(symdef.segment_type, symdef.location) {
+                symdef.location -= by;
+            } else if let Some(size) = &mut symdef.size {
+                if symdef.segment_type == st
+                    && symdef.location <= location
+                    && symdef.location + *size >= end
+                {
+                    *size -= by;
+                }
+            }
+        }
+        for r in relocs.iter_mut() {
+            if moves(r.reference_segment, r.reference_location) {
+                r.reference_location -= by;
+            }
+        }
+        relocs[i].reference_location = location + 1;
+        relocs[i].kind = RelocationKind::Relative;
+        for line in &mut lines.entries {
+            if moves(line.segment, line.location) {
+                line.location -= by;
+            }
+        }
+        if let Some(Entry(s, at)) = entry {
+            if moves(*s, *at) {
+                *at -= by;
+            }
+        }
+    }
+
+    for r in relocs.iter() {
+        let symdef = &symbols[r.symbol_index as usize];
+        if symdef.segment_type == SegmentType::Unknown {
+            continue;
+        }
+        let (start, bytes) = segs
+            .get_mut(&r.reference_segment)
+            .expect("would have been caught earlier");
+        let index = (r.reference_location - *start) as usize;
+        r.kind
+            .apply(&mut bytes[index..], symdef.location, r.reference_location);
+    }
+    relaxed
+}
+
+/// Writes the object as an executable starting with a `#!` line to run it with `t`
+///
+/// Fails if it has no entry point.
+pub fn write_executable(mut obj: Object, out: &Path, compress: bool) -> Result<(), Error> {
+    if obj.entry.is_none() {
+        return Err(Error::NoEntryPoint);
+    }
+
+    {
+        let mut file = File::create(out).map_err(Error::Io)?;
+        writeln!(file, "#!/bin/env t").map_err(Error::Io)?;
+
+        obj.file_offset = file.stream_position().map_err(Error::Io)?;
+    }
+
+    obj.write_to_file_with(out, compress).map_err(Error::Io)?;
+
+    let perms = fs::metadata(out).map_err(Error::Io)?.permissions();
+    // perms.set_mode(perms.mode() | 0o111);
+    fs::set_permissions(out, perms).map_err(Error::Io)?;
+
+    Ok(())
+}
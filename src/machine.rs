@@ -0,0 +1,1030 @@
+//! The emulator as a whole: a CPU running an object in protected memory
+
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
+#[cfg(feature = "std")]
+use crate::{aalv::obj::Object, snapshot::Snapshot};
+use crate::{
+    cpu::{Cpu, Event, Registers, TrapMode, R1, R2, R3},
+    isa::{self, Instruction, MEMORY_ACCESS_CYCLES},
+    mem::{AreaKind, Io, Lazy, Memory, MemoryMap, Protected, Region, SegmentFlags, StdIo},
+};
+
+/// Whether memory was read from or written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which accesses a watchpoint catches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    /// Reads and writes
+    Any,
+}
+
+impl Watch {
+    fn catches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Watch::Any, _) | (Watch::Read, Access::Read) | (Watch::Write, Access::Write)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Watchpoint {
+    start: u16,
+    len: u16,
+    watch: Watch,
+}
+
+/// An access that memory protection stopped, see [`Protected`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub trap: TrapMode,
+    pub addr: u16,
+    /// The location of the instruction that made the access
+    pub pc: u16,
+}
+
+/// An access to a watched address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub access: Access,
+    /// The value read or written
+    pub value: u8,
+    /// The value before it was written, the same as `value` for reads
+    pub previous: u8,
+}
+
+type MemoryHook = Box<dyn FnMut(u16, u8)>;
+type IoHook = Box<dyn FnMut(Access, u8, u8)>;
+type InstructionHook = Box<dyn FnMut(u16, &Instruction)>;
+
+/// An instruction decoded before
+#[derive(Debug, Clone, Copy)]
+struct Decoded {
+    ins: Instruction,
+    size: u16,
+    /// Of the cache when it was decoded, it is stale once that has changed
+    generation: u64,
+}
+
+/// Memory that tells the hooks about the accesses instructions make and notices watched addresses
+///
+/// Fetching instructions does not count as reading them.
+/// It keeps the instructions it decoded, dropping them when their bytes are written.
+struct Hooked<I> {
+    inner: Lazy<I>,
+    /// By location, empty until the first instruction is decoded
+    decoded: Vec<Option<Decoded>>,
+    /// Bumped to drop all decoded instructions at once, when devices may have changed memory
+    generation: u64,
+    /// Whether the program wrote to a device since the last DMA, which is when devices change memory
+    wrote_io: bool,
+    on_read: Vec<MemoryHook>,
+    on_write: Vec<MemoryHook>,
+    on_io: Vec<IoHook>,
+    watchpoints: Vec<Watchpoint>,
+    /// The first watched access since it was last taken
+    watch_hit: Option<WatchHit>,
+    /// Bytes read or written by the instruction running
+    accesses: u64,
+}
+
+impl<I> Hooked<I> {
+    /// Drops the decoded instructions the byte at `addr` is part of
+    fn invalidate(&mut self, addr: u16) {
+        if self.decoded.is_empty() {
+            return;
+        }
+        for back in 0..4 {
+            let start = addr.wrapping_sub(back) as usize;
+            if matches!(self.decoded[start], Some(d) if d.size > back) {
+                self.decoded[start] = None;
+            }
+        }
+    }
+    fn watched(&mut self, addr: u16, access: Access, value: u8, previous: u8) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let caught = self
+            .watchpoints
+            .iter()
+            .any(|w| w.watch.catches(access) && addr.wrapping_sub(w.start) < w.len);
+        if caught {
+            self.watch_hit = Some(WatchHit {
+                addr,
+                access,
+                value,
+                previous,
+            });
+        }
+    }
+}
+
+impl<I: Io> Memory for Hooked<I> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.inner.read(addr);
+        self.accesses += 1;
+        if !self.watchpoints.is_empty() {
+            self.watched(addr, Access::Read, val, val);
+        }
+        if !self.inner.map.is_io(addr) {
+            self.on_read.iter_mut().for_each(|hook| hook(addr, val));
+        } else {
+            let port = addr as u8;
+            self.on_io
+                .iter_mut()
+                .for_each(|hook| hook(Access::Read, port, val));
+        }
+        val
+    }
+    fn write(&mut self, addr: u16, val: u8) {
+        self.accesses += 1;
+        if !self.watchpoints.is_empty() {
+            let previous = self.inner.mem.get(addr as usize).copied().unwrap_or(0);
+            self.watched(addr, Access::Write, val, previous);
+        }
+        self.inner.write(addr, val);
+        if !self.inner.map.is_io(addr) {
+            self.invalidate(addr);
+            self.on_write.iter_mut().for_each(|hook| hook(addr, val));
+        } else {
+            self.wrote_io = true;
+            let port = addr as u8;
+            self.on_io
+                .iter_mut()
+                .for_each(|hook| hook(Access::Write, port, val));
+        }
+    }
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.inner.fetch(addr)
+    }
+    fn fetch_instruction(&mut self, addr: u16) -> (Option<Instruction>, u16) {
+        let start = addr as usize;
+        if let Some(&Some(d)) = self.decoded.get(start) {
+            if d.generation == self.generation {
+                return (Some(d.ins), d.size);
+            }
+        }
+        let (ins, size) = self.inner.fetch_instruction(addr);
+        let end = start + size as usize;
+        let in_memory = end <= self.inner.mem.len()
+            && (addr..=addr + (size - 1)).all(|a| !self.inner.map.is_io(a));
+        if let (Some(ins), true) = (ins, in_memory) {
+            if self.decoded.is_empty() {
+                self.decoded = vec![None; 0x10000];
+            }
+            self.decoded[start] = Some(Decoded {
+                ins,
+                size,
+                generation: self.generation,
+            });
+        }
+        (ins, size)
+    }
+}
+
+/// A CPU with the memory of an object loaded, doing I/O with `I`
+pub struct Machine<I = StdIo> {
+    cpu: Cpu,
+    mem: Protected<Hooked<I>>,
+    on_instruction: Vec<InstructionHook>,
+    cycles: u64,
+    fault: Option<Fault>,
+    /// The location of the instruction `step` ran last
+    last_instruction: u16,
+}
+
+#[cfg(feature = "std")]
+impl Machine<StdIo> {
+    /// Loads the object with I/O going to stdin and stdout
+    pub fn new(obj: Object) -> Self {
+        Self::with_io(obj, StdIo)
+    }
+}
+
+impl<I: Io> Machine<I> {
+    /// Loads the object and starts at its entry point, or 0 if it has none
+    ///
+    /// Accesses the flags of its segments do not allow trap.
+    #[cfg(feature = "std")]
+    pub fn with_io(obj: Object, io: I) -> Self {
+        Self::with_map(obj, io, MemoryMap::default())
+    }
+    /// Loads the object into memory laid out like the map, with the ROMs on top of it
+    ///
+    /// The stack starts at the end of the last RAM area and accesses the map does not allow trap as well.
+    /// Devices get to set up memory with [`Io::dma`] before the first instruction.
+    #[cfg(feature = "std")]
+    pub fn with_map(obj: Object, io: I, map: MemoryMap) -> Self {
+        let entry = obj.entry.map_or(0, |e| e.1);
+        Self::from_image(
+            obj.get_flattened_memory(),
+            entry,
+            obj.memory_regions(),
+            io,
+            map,
+        )
+    }
+    /// Starts at `entry` with the memory image, which only the regions may be accessed of besides what the map allows
+    ///
+    /// This is what [`Machine::with_map`] does after flattening the object, for loading binaries without `std`.
+    pub fn from_image(
+        mut mem: Vec<u8>,
+        entry: u16,
+        regions: Vec<Region>,
+        mut io: I,
+        map: MemoryMap,
+    ) -> Self {
+        for area in map.areas() {
+            if let AreaKind::Rom(contents) = &area.kind {
+                let start = area.start as usize;
+                if mem.len() < start + contents.len() {
+                    mem.resize(start + contents.len(), 0);
+                }
+                mem[start..start + contents.len()].copy_from_slice(contents);
+            }
+        }
+        io.dma(&mut mem);
+        let mut cpu = Cpu::new(entry);
+        cpu.registers.stack = map.stack_top();
+        cpu.registers.frame = map.stack_top();
+        let mem = Hooked {
+            inner: Lazy {
+                mem,
+                io,
+                map: map.clone(),
+            },
+            decoded: Vec::new(),
+            generation: 0,
+            wrote_io: false,
+            on_read: Vec::new(),
+            on_write: Vec::new(),
+            on_io: Vec::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            accesses: 0,
+        };
+
+        Machine {
+            cpu,
+            mem: Protected::with_map(mem, regions, &map),
+            on_instruction: Vec::new(),
+            cycles: 0,
+            fault: None,
+            last_instruction: entry,
+        }
+    }
+
+    /// Calls `hook` with the location of every instruction and the instruction before it is executed
+    pub fn on_instruction(&mut self, hook: impl FnMut(u16, &Instruction) + 'static) {
+        self.on_instruction.push(Box::new(hook));
+    }
+    /// Calls `hook` with the address and value of every byte instructions read from memory below the I/O mapping
+    pub fn on_mem_read(&mut self, hook: impl FnMut(u16, u8) + 'static) {
+        self.mem.inner.on_read.push(Box::new(hook));
+    }
+    /// Calls `hook` with the address and value of every byte instructions write to memory below the I/O mapping
+    pub fn on_mem_write(&mut self, hook: impl FnMut(u16, u8) + 'static) {
+        self.mem.inner.on_write.push(Box::new(hook));
+    }
+    /// Calls `hook` with the lower byte of the address, as `Io` gets it, and the value of every I/O access
+    pub fn on_io(&mut self, hook: impl FnMut(Access, u8, u8) + 'static) {
+        self.mem.inner.on_io.push(Box::new(hook));
+    }
+
+    /// Watches `len` bytes from `start` for the accesses instructions make to them, see [`Machine::watch_hit`]
+    pub fn watch(&mut self, start: u16, len: u16, watch: Watch) {
+        self.mem
+            .inner
+            .watchpoints
+            .push(Watchpoint { start, len, watch });
+    }
+    pub fn clear_watchpoints(&mut self) {
+        self.mem.inner.watchpoints.clear();
+        self.mem.inner.watch_hit = None;
+    }
+    /// Takes the first access to a watched address since the last time
+    ///
+    /// Checking this after every step stops right after the instruction that made the access.
+    pub fn watch_hit(&mut self) -> Option<WatchHit> {
+        self.mem.inner.watch_hit.take()
+    }
+
+    /// Runs one instruction
+    ///
+    /// Then interrupts the program with vector `0x20` (`TrapMode::Interrupt`) if the I/O has an interrupt pending,
+    /// see [`Cpu::interrupt`].
+    ///
+    /// Errs with the trap mode when it trapped without a trap handler, after which it should not be run further.
+    pub fn step(&mut self) -> Result<Event, TrapMode> {
+        let location = self.pc();
+        self.last_instruction = location;
+        let opcode = self.peek(location);
+        let hooks = &mut self.on_instruction;
+        let result = self.cpu.step_with(&mut self.mem, &mut |addr, ins| {
+            hooks.iter_mut().for_each(|hook| hook(addr, ins))
+        });
+        if let Some((trap, addr)) = self.mem.take_faulted() {
+            self.fault = Some(Fault {
+                trap,
+                addr,
+                pc: location,
+            });
+        }
+        let accesses = core::mem::take(&mut self.mem.inner.accesses);
+        let cycles = isa::cycles(opcode).unwrap_or(1) + accesses * MEMORY_ACCESS_CYCLES;
+        self.pass(cycles);
+        self.interrupt(result)
+    }
+    /// Like `step`, but runs the block compiled to native code at the program counter instead if there is one
+    ///
+    /// Gives how many instructions ran, at most `max_steps` which has to be at least one.
+    /// Devices only see the time the block took once it is done and interrupts come after it.
+    /// Instruction hooks see every instruction, so nothing is compiled while there are any.
+    #[cfg(feature = "jit")]
+    pub fn step_native(&mut self, jit: &mut Jit, max_steps: u64) -> (u64, Result<Event, TrapMode>) {
+        if self.on_instruction.is_empty() {
+            let mem = &self.mem;
+            let Lazy {
+                mem: memory, map, ..
+            } = &mem.inner.inner;
+            let executable = |addr| !map.is_io(addr) && mem.permits(addr, SegmentFlags::EXECUTE);
+            if let Some(ran) = jit.run(&mut self.cpu.registers, memory, executable, max_steps) {
+                self.pass(ran.cycles);
+                return (ran.steps, self.interrupt(Ok(Event::Executed)));
+            }
+        }
+        (1, self.step())
+    }
+    /// Lets a device interrupt after an instruction ran
+    fn interrupt(&mut self, result: Result<Event, TrapMode>) -> Result<Event, TrapMode> {
+        let interrupted = result.is_ok()
+            && self.io().interrupt_pending()
+            && self.cpu.interrupt(&mut self.mem, TrapMode::Interrupt as u8);
+        if interrupted {
+            // Pushing the registers takes time as well
+            let accesses = core::mem::take(&mut self.mem.inner.accesses);
+            self.pass(accesses * MEMORY_ACCESS_CYCLES);
+            return Ok(Event::TrapHandled(TrapMode::Interrupt));
+        }
+        result
+    }
+    fn pass(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        let hooked = &mut self.mem.inner;
+        let Lazy { mem, io, .. } = &mut hooked.inner;
+        io.tick(cycles);
+        io.dma(mem);
+        if core::mem::take(&mut hooked.wrote_io) {
+            hooked.generation += 1;
+        }
+    }
+    /// Runs until a trap without a trap handler, like `halt`
+    pub fn run(&mut self) -> TrapMode {
+        loop {
+            if let Err(tm) = self.step() {
+                break tm;
+            }
+        }
+    }
+
+    /// The last access memory protection stopped, which trapped with an illegal access
+    pub fn last_fault(&self) -> Option<Fault> {
+        self.fault
+    }
+    /// The location of the instruction that ran last, where the program was when a step trapped
+    pub fn last_instruction(&self) -> u16 {
+        self.last_instruction
+    }
+    /// Where the code the program is in was called from, innermost first, at most `max` of them
+    ///
+    /// This is a best guess: there are no frame records, so `rl` and the words on the stack up to its top count when
+    /// they are right after a `call` in executable memory. Calls that have returned may have left some behind.
+    pub fn return_addresses(&self, max: usize) -> Vec<u16> {
+        let is_call = |at: Option<u16>, size| {
+            at.is_some_and(|at| {
+                self.mem.permits(at, SegmentFlags::EXECUTE)
+                    && matches!(
+                        isa::decode(at, self.memory()),
+                        Ok((Instruction::Call(_) | Instruction::CallRegister(_), s)) if s == size
+                    )
+            })
+        };
+        let is_return =
+            |addr: u16| is_call(addr.checked_sub(3), 3) || is_call(addr.checked_sub(2), 2);
+        let registers = &self.cpu.registers;
+        let mut found = Vec::new();
+        if is_return(registers.link) {
+            found.push(registers.link);
+        }
+        let top = self.mem.inner.inner.map.stack_top();
+        // Bytes can be pushed as well, so return addresses can be at odd places
+        let mut addr = registers.stack;
+        while found.len() < max && addr < top.saturating_sub(1) {
+            let word = self.peek_wide(addr);
+            if is_return(word) && found.last() != Some(&word) {
+                found.push(word);
+                addr += 2;
+            } else {
+                addr += 1;
+            }
+        }
+        found.truncate(max);
+        found
+    }
+
+    /// How many cycles the instructions run so far took, see [`isa::cycles`]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The registers, memory and device state as they are between instructions
+    #[cfg(feature = "std")]
+    pub fn snapshot(&self) -> Snapshot {
+        let mut devices = Vec::new();
+        self.io().save_state(&mut devices);
+        Snapshot {
+            registers: self.cpu.registers.clone(),
+            cycles: self.cycles,
+            memory: self.memory().to_vec(),
+            devices,
+        }
+    }
+    /// Goes back to the state of the snapshot, which should be of a machine running the same object with the same I/O
+    ///
+    /// Errs if the device state does not fit the I/O, which is left as it was up to the device that did not fit.
+    #[cfg(feature = "std")]
+    pub fn restore(&mut self, snapshot: &Snapshot) -> std::io::Result<()> {
+        self.io_mut().restore_state(&mut &*snapshot.devices)?;
+        self.cpu.registers = snapshot.registers.clone();
+        self.cycles = snapshot.cycles;
+        self.mem.inner.inner.mem = snapshot.memory.clone();
+        self.mem.inner.generation += 1;
+        self.fault = None;
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.cpu.registers
+    }
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.cpu.registers
+    }
+    pub fn pc(&self) -> u16 {
+        self.cpu.registers.program_counter
+    }
+    pub fn set_pc(&mut self, pc: u16) {
+        self.cpu.registers.program_counter = pc;
+    }
+
+    /// The memory below the I/O mapping, only as long as it has been used
+    pub fn memory(&self) -> &[u8] {
+        &self.mem.inner.inner.mem
+    }
+    /// Reads memory without I/O or checking the segment flags, addresses not yet used are 0
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.memory().get(addr as usize).copied().unwrap_or(0)
+    }
+    pub fn peek_wide(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
+    /// Writes memory without I/O or checking the segment flags
+    ///
+    /// Writes to the I/O mapping are ignored.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        if !self.mem.inner.inner.map.is_io(addr) {
+            self.mem.inner.inner.write(addr, val);
+            self.mem.inner.invalidate(addr);
+        }
+    }
+    pub fn poke_wide(&mut self, addr: u16, val: u16) {
+        let [lower, higher] = val.to_le_bytes();
+        self.poke(addr, lower);
+        self.poke(addr.wrapping_add(1), higher);
+    }
+
+    /// Copies the arguments and environment to the top of the stack, for a program to start with
+    ///
+    /// The strings end with a zero byte and the program gets the number of arguments in `r1`, the location of a list of
+    /// the arguments' locations in `r2` and the same for the environment in `r3`, with both lists ending with `0`.
+    /// The stack starts below them. Errs if they do not fit above the program.
+    pub fn pass_arguments<A: AsRef<[u8]>, E: AsRef<[u8]>>(
+        &mut self,
+        args: &[A],
+        env: &[E],
+    ) -> Result<(), String> {
+        let args: Vec<&[u8]> = args.iter().map(AsRef::as_ref).collect();
+        let env: Vec<&[u8]> = env.iter().map(AsRef::as_ref).collect();
+        // Each string with its zero byte and location, and the ends of the lists
+        let size = args.iter().chain(&env).map(|s| s.len() + 3).sum::<usize>() + 4;
+        let top = self.cpu.registers.stack as usize;
+        let bottom = top
+            .checked_sub(size)
+            .filter(|&bottom| bottom >= self.memory().len())
+            .ok_or_else(|| format!("{size} bytes of arguments do not fit on the stack"))?;
+
+        let argv = bottom as u16;
+        let envp = (bottom + 2 * (args.len() + 1)) as u16;
+        let mut string_at = top;
+        let mut list_at = bottom;
+        for list in [&args, &env] {
+            for s in list {
+                string_at -= s.len() + 1;
+                for (i, &b) in s.iter().chain(&[0]).enumerate() {
+                    self.poke((string_at + i) as u16, b);
+                }
+                self.poke_wide(list_at as u16, string_at as u16);
+                list_at += 2;
+            }
+            self.poke_wide(list_at as u16, 0);
+            list_at += 2;
+        }
+
+        let r = &mut self.cpu.registers;
+        r.write_wide(R1, args.len() as u16);
+        r.write_wide(R2, argv);
+        r.write_wide(R3, envp);
+        r.stack = argv;
+        r.frame = argv;
+        Ok(())
+    }
+
+    pub fn io(&self) -> &I {
+        &self.mem.inner.inner.io
+    }
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.mem.inner.inner.io
+    }
+}
+
+/// A machine with a program at 0x10 that stores 0x105 at 0x100 and halts
+#[cfg(all(test, feature = "std"))]
+fn test_machine<I: Io>(io: I) -> Machine<I> {
+    use crate::aalv::obj::{Entry, SegmentType};
+
+    let code = vec![
+        0x40, 0x10, 0x05, 0x01, // 0x10: ldi r1, 0x105
+        0x28, 0x01, 0x00, 0x01, // 0x14: store r0, 0x100, r1
+        0x0a, // 0x18: halt
+    ];
+    let mut obj = Object::default();
+    obj.segs.insert(SegmentType::Text, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Text, 0x10));
+    Machine::with_io(obj, io)
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn run_machine() {
+    use crate::{cpu::R1, mem::PanickingIO};
+
+    let mut machine = test_machine(PanickingIO);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.registers().read_wide(R1), 0x105);
+    assert_eq!(machine.run(), TrapMode::Halt);
+    assert_eq!(machine.peek_wide(0x100), 0x105);
+    assert_eq!(machine.pc(), 0x19);
+    // ldi, then store with two bytes written, then halt
+    assert_eq!(machine.cycles(), 1 + 2 + 2 * MEMORY_ACCESS_CYCLES + 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn hooks() {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::mem::PanickingIO;
+
+    let mut machine = test_machine(PanickingIO);
+    let executed = Rc::new(RefCell::new(Vec::new()));
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let e = executed.clone();
+    machine.on_instruction(move |addr, ins| e.borrow_mut().push((addr, *ins)));
+    let w = written.clone();
+    machine.on_mem_write(move |addr, val| w.borrow_mut().push((addr, val)));
+    machine.on_mem_read(|addr, _| panic!("read 0x{addr:04x}"));
+
+    assert_eq!(machine.run(), TrapMode::Halt);
+    let locations: Vec<_> = executed.borrow().iter().map(|&(a, _)| a).collect();
+    assert_eq!(locations, [0x10, 0x14, 0x18]);
+    assert_eq!(executed.borrow()[2].1, Instruction::Halt);
+    assert_eq!(*written.borrow(), [(0x100, 0x05), (0x101, 0x01)]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn watchpoints() {
+    use crate::mem::PanickingIO;
+
+    let mut machine = test_machine(PanickingIO);
+    machine.watch(0x101, 1, Watch::Write);
+    machine.watch(0x10, 4, Watch::Any);
+    machine.poke(0x101, 0x22);
+
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.watch_hit(), None);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(
+        machine.watch_hit(),
+        Some(WatchHit {
+            addr: 0x101,
+            access: Access::Write,
+            value: 0x01,
+            previous: 0x22,
+        })
+    );
+    assert_eq!(machine.pc(), 0x18);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn interrupts() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        cpu::R1,
+    };
+
+    /// An interrupt line that reading any port acknowledges
+    struct Line(bool);
+    impl Io for Line {
+        fn read(&mut self, _addr: u8) -> u8 {
+            self.0 = false;
+            0
+        }
+        fn write(&mut self, _addr: u8, _val: u8) {}
+        fn interrupt_pending(&self) -> bool {
+            self.0
+        }
+    }
+
+    let mut obj = Object::default();
+    // ldi rh, 0x20; ei; nop; halt
+    let mut code = vec![0x40, 0xf0, 0x20, 0x00, 0x06, 0x20, 0x0a];
+    // At 0x20: load r2, r0, 0xffe0; reth; reti
+    code.resize(0x10, 0);
+    code.extend([0x2c, 0x20, 0xe0, 0xff, 0x0d, 0x0e]);
+    obj.segs.insert(SegmentType::Text, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Text, 0x10));
+
+    let mut machine = Machine::with_io(obj, Line(false));
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    machine.io_mut().0 = true;
+    // Interrupts are disabled until `ei`
+    assert_eq!(machine.step(), Ok(Event::TrapHandled(TrapMode::Interrupt)));
+    assert_eq!(machine.pc(), 0x20);
+    assert_eq!(
+        machine.registers().read_wide(R1),
+        TrapMode::Interrupt as u16
+    );
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert!(!machine.io().0);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.pc(), 0x15);
+    assert!(!machine.registers().trap);
+
+    // With a vector table, the interrupt goes to its vector
+    machine.registers_mut().interrupt_vectors = 0x100;
+    machine.poke_wide(0x100 + 2 * 0x20, 0x25);
+    machine.io_mut().0 = true;
+    assert_eq!(machine.step(), Ok(Event::TrapHandled(TrapMode::Interrupt)));
+    assert_eq!(machine.pc(), 0x25);
+    assert!(!machine.registers().interrupt_enable);
+    machine.io_mut().0 = false;
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.pc(), 0x16);
+    assert!(machine.registers().interrupt_enable);
+
+    // Halting has no vector, so it goes to the trap handler
+    assert_eq!(machine.step(), Ok(Event::TrapHandled(TrapMode::Halt)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn software_interrupts() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        cpu::R1,
+        mem::PanickingIO,
+    };
+
+    let mut obj = Object::default();
+    // int 0x30; int 0x31; int 0x31
+    let mut code = vec![0x05, 0x30, 0x05, 0x31, 0x05, 0x31];
+    // At 0x20: reti; reth
+    code.resize(0x10, 0);
+    code.extend([0x0e, 0x0d]);
+    obj.segs.insert(SegmentType::Text, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Text, 0x10));
+
+    let mut machine = Machine::with_io(obj, PanickingIO);
+    machine.registers_mut().interrupt_vectors = 0x100;
+    machine.poke_wide(0x100 + 2 * 0x30, 0x20);
+    machine.registers_mut().trap_handler = 0x21;
+    machine.registers_mut().carry = true;
+
+    assert_eq!(machine.step(), Ok(Event::TrapHandled(TrapMode::SysCall)));
+    assert_eq!(machine.pc(), 0x20);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.pc(), 0x12);
+    assert!(machine.registers().carry);
+
+    // Without a vector it is a syscall for the trap handler
+    assert_eq!(machine.step(), Ok(Event::TrapHandled(TrapMode::SysCall)));
+    assert_eq!(machine.pc(), 0x21);
+    assert_eq!(machine.registers().read_wide(R1), TrapMode::SysCall as u16);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.pc(), 0x14);
+
+    // Or ends the program without one
+    machine.registers_mut().trap_handler = 0;
+    assert_eq!(machine.step(), Err(TrapMode::SysCall));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn snapshots() {
+    use std::io::Cursor;
+
+    use crate::{cpu::R1, devices, mem::PanickingIO};
+
+    let mut machine = test_machine(devices::standard(PanickingIO));
+    machine.step().unwrap();
+    // Sets the period of the timer
+    machine.io_mut().write(devices::TIMER_PORT, 0x34);
+    let snapshot = machine.snapshot();
+    let mut file = Cursor::new(Vec::new());
+    snapshot.write(&mut file).unwrap();
+    file.set_position(0);
+    assert_eq!(Snapshot::read(file).unwrap(), snapshot);
+
+    let mut restored = test_machine(devices::standard(PanickingIO));
+    restored.restore(&snapshot).unwrap();
+    assert_eq!(restored.registers(), machine.registers());
+    assert_eq!(restored.io_mut().read(devices::TIMER_PORT), 0x34);
+    assert_eq!(restored.run(), machine.run());
+    assert_eq!(restored.registers().read_wide(R1), 0x105);
+    assert_eq!(restored.memory(), machine.memory());
+    assert_eq!(restored.cycles(), machine.cycles());
+
+    // Without the timer the device state does not fit
+    let mut timerless = test_machine(devices::Bus::new(PanickingIO));
+    assert!(timerless.restore(&snapshot).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn protection() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        mem::PanickingIO,
+    };
+
+    let loading = |addr: u16| {
+        let mut obj = Object::default();
+        // load r2, r0, addr; halt
+        let [lo, hi] = addr.to_le_bytes();
+        let code = vec![0x2c, 0x20, lo, hi, 0x0a];
+        obj.segs.insert(SegmentType::Text, (0x10, code));
+        obj.segs.insert(SegmentType::Data, (0x40, vec![0]));
+        obj.entry = Some(Entry(SegmentType::Text, 0x10));
+        Machine::with_io(obj, PanickingIO)
+    };
+
+    // 0x30 is in the gap between the segments
+    let mut machine = loading(0x30);
+    assert_eq!(machine.run(), TrapMode::IllegalRead);
+    assert_eq!(
+        machine.last_fault(),
+        Some(Fault {
+            trap: TrapMode::IllegalRead,
+            addr: 0x30,
+            pc: 0x10,
+        })
+    );
+
+    // Past the last segment is not checked
+    let mut machine = loading(0x50);
+    assert_eq!(machine.run(), TrapMode::Halt);
+    assert_eq!(machine.last_fault(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn memory_map() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        cpu::R2,
+        mem::PanickingIO,
+    };
+
+    let map = MemoryMap::parse(
+        "ram 0x0..0x100 # for the code\nrom 0x100..0x180 boot.bin\n",
+        |path| {
+            assert_eq!(path, "boot.bin");
+            Ok::<_, std::io::Error>(vec![0x34, 0x12])
+        },
+    )
+    .unwrap();
+    assert_eq!(map.stack_top(), 0x100);
+    assert!(MemoryMap::parse("ram 0x0..0x100\nrom 0xff..0x180", |_| std::fs::read("")).is_err());
+
+    let mut obj = Object::default();
+    // load r2, r0, 0x100; store r0, 0x100, r2
+    let code = vec![0x2c, 0x20, 0x00, 0x01, 0x28, 0x02, 0x00, 0x01];
+    obj.segs.insert(SegmentType::Text, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Text, 0x10));
+
+    let mut machine = Machine::with_map(obj, PanickingIO, map);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.registers().read_wide(R2), 0x1234);
+    // The ROM cannot be written and there is no I/O mapping
+    assert_eq!(machine.run(), TrapMode::IllegalWrite);
+    assert_eq!(machine.last_fault().map(|f| f.addr), Some(0x100));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn self_modifying() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        mem::PanickingIO,
+    };
+
+    let mut obj = Object::default();
+    // The zero segment can be both written and executed
+    // ldi r1, 0x0a0a; store r0, 0x10, r1; jmp 0x10
+    let code = vec![
+        0x40, 0x10, 0x0a, 0x0a, 0x28, 0x01, 0x10, 0x00, 0x40, 0x01, 0x10, 0x00,
+    ];
+    obj.segs.insert(SegmentType::Zero, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Zero, 0x10));
+
+    let mut machine = Machine::with_io(obj, PanickingIO);
+    for _ in 0..3 {
+        assert_eq!(machine.step(), Ok(Event::Executed));
+    }
+    // The ldi decoded the first time round was overwritten with halt
+    assert_eq!(machine.step(), Err(TrapMode::Halt));
+    assert_eq!(machine.pc(), 0x11);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn changed_code_is_decoded_again() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        mem::PanickingIO,
+    };
+
+    let mut machine = test_machine(PanickingIO);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    machine.set_pc(0x10);
+    machine.poke(0x11, 0x20);
+    assert_eq!(machine.step(), Ok(Event::Executed));
+    assert_eq!(machine.registers().read_wide(R2), 0x105);
+
+    /// Writes halt over the start of the program when told to
+    struct Overwrite(bool);
+    impl Io for Overwrite {
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+        fn write(&mut self, _addr: u8, _val: u8) {
+            self.0 = true;
+        }
+        fn dma(&mut self, memory: &mut Vec<u8>) {
+            if core::mem::take(&mut self.0) {
+                memory[0x10] = 0x0a;
+            }
+        }
+    }
+
+    let code = vec![
+        0x40, 0x10, 0x0a, 0x0a, // 0x10: ldi r1, 0x0a0a
+        0x28, 0x01, 0xe0, 0xff, // 0x14: store r0, 0xffe0, r1
+        0x40, 0x01, 0x10, 0x00, // 0x18: jmp 0x10
+    ];
+    let mut obj = Object::default();
+    obj.segs.insert(SegmentType::Text, (0x10, code));
+    obj.entry = Some(Entry(SegmentType::Text, 0x10));
+
+    let mut machine = Machine::with_io(obj, Overwrite(false));
+    for _ in 0..3 {
+        assert_eq!(machine.step(), Ok(Event::Executed));
+    }
+    assert_eq!(machine.step(), Err(TrapMode::Halt));
+    assert_eq!(machine.pc(), 0x11);
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn native_steps_match_interpreter() {
+    use crate::{
+        aalv::obj::{Entry, SegmentType},
+        mem::PanickingIO,
+    };
+
+    // ldi r1, 0; ldi r2, 1; ldi r3, 100; ldi r5, 7
+    // loop: add r1, r1, r2; mul r6, r4, r1, r5; xor r4, r4, r1; sub r0, r1, r3; jlt loop
+    // halt
+    #[rustfmt::skip]
+    let code = vec![
+        0x40, 0x10, 0x00, 0x00, 0x40, 0x20, 0x01, 0x00, 0x40, 0x30, 0x64, 0x00, 0x40, 0x50, 0x07, 0x00,
+        0x42, 0x11, 0x20, 0x54, 0x64, 0x15, 0x4a, 0x44, 0x10, 0x44, 0x01, 0x30, 0x30, 0x20, 0x00,
+        0x0a,
+    ];
+    let machine = || {
+        let mut obj = Object::default();
+        obj.segs.insert(SegmentType::Text, (0x10, code.clone()));
+        obj.entry = Some(Entry(SegmentType::Text, 0x10));
+        Machine::with_io(obj, PanickingIO)
+    };
+
+    let mut interpreted = machine();
+    assert_eq!(interpreted.run(), TrapMode::Halt);
+
+    let mut native = machine();
+    let mut jit = Jit::new().unwrap();
+    let mut steps = 0;
+    let tm = loop {
+        let (ran, result) = native.step_native(&mut jit, 7);
+        assert!((1..=7).contains(&ran));
+        steps += ran;
+        if let Err(tm) = result {
+            break tm;
+        }
+    };
+    assert_eq!(tm, TrapMode::Halt);
+    assert_eq!(jit.compiled(), 1);
+    assert_eq!(steps, 4 + 100 * 5 + 1);
+    assert_eq!(native.registers().read_wide(crate::cpu::R1), 100);
+    assert_eq!(
+        format!("{:?}", native.registers()),
+        format!("{:?}", interpreted.registers())
+    );
+    assert_eq!(native.cycles(), interpreted.cycles());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn arguments() {
+    use crate::{
+        cpu::{R1, R2, R3, RS},
+        mem::PanickingIO,
+    };
+
+    let mut machine = Machine::with_io(Object::default(), PanickingIO);
+    machine.pass_arguments(&["prog", "-v"], &["A=b"]).unwrap();
+    let r = machine.registers();
+    assert_eq!(r.read_wide(R1), 2);
+    let (argv, envp) = (r.read_wide(R2), r.read_wide(R3));
+    let string = |at: u16| -> Vec<u8> {
+        (at..)
+            .map(|addr| machine.peek(addr))
+            .take_while(|&b| b != 0)
+            .collect()
+    };
+    assert_eq!(string(machine.peek_wide(argv)), b"prog");
+    assert_eq!(string(machine.peek_wide(argv + 2)), b"-v");
+    assert_eq!(machine.peek_wide(argv + 4), 0);
+    assert_eq!(envp, argv + 6);
+    assert_eq!(string(machine.peek_wide(envp)), b"A=b");
+    assert_eq!(machine.peek_wide(envp + 2), 0);
+    assert_eq!(machine.registers().read_wide(RS), argv);
+}
+
+#[test]
+fn return_addresses() {
+    use crate::{
+        cpu::{RL, RS},
+        mem::PanickingIO,
+    };
+
+    // call 0x0008; call 0x0008; 0x0011
+    let code = vec![0x25, 0x08, 0x00, 0x25, 0x08, 0x00, 0x11, 0x00];
+    let mut machine = Machine::from_image(code, 8, Vec::new(), PanickingIO, MemoryMap::default());
+    let r = machine.registers_mut();
+    r.write_wide(RL, 0x06);
+    r.write_wide(RS, r.read_wide(RS) - 7);
+    let stack = machine.registers().read_wide(RS);
+    // A pushed byte, rl pushed twice and the first return address
+    for (n, &b) in [0x01, 0x06, 0x00, 0x06, 0x00, 0x03, 0x00]
+        .iter()
+        .enumerate()
+    {
+        machine.poke(stack + n as u16, b);
+    }
+    assert_eq!(machine.return_addresses(16), [0x06, 0x03]);
+    assert_eq!(machine.return_addresses(1), [0x06]);
+}
@@ -1,4 +1,47 @@
-use std::io::{stdin, stdout, Read, Write};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec::Vec};
+use core::fmt::Display;
+#[cfg(feature = "std")]
+use std::io::{self, stdin, stdout, Read, Write};
+
+use crate::{
+    cpu::TrapMode,
+    isa::{decode_at, operand_size, Instruction},
+};
+
+/// Access permissions of a segment
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentFlags(pub u8);
+
+impl SegmentFlags {
+    pub const NONE: Self = SegmentFlags(0);
+    pub const READ: Self = SegmentFlags(0b001);
+    pub const WRITE: Self = SegmentFlags(0b010);
+    pub const EXECUTE: Self = SegmentFlags(0b100);
+
+    pub const fn union(self, other: Self) -> Self {
+        SegmentFlags(self.0 | other.0)
+    }
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// An address range of memory from a segment
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    pub start: u16,
+    /// Exclusive, so up to `0x10000`
+    pub end: u32,
+    pub flags: SegmentFlags,
+}
+
+impl Region {
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && (addr as u32) < self.end
+    }
+}
 
 /// Memory below this address is used for IO mapping
 pub const IO_MAPPING_CUTOFF: u16 = 0xffe0;
@@ -14,7 +57,7 @@ pub trait Memory {
 
     fn read_wide(&mut self, addr: u16) -> u16 {
         let lower = self.read(addr);
-        let higher = self.read(addr + 1);
+        let higher = self.read(addr.wrapping_add(1));
 
         u16::from_le_bytes([lower, higher])
     }
@@ -22,13 +65,86 @@ pub trait Memory {
         let [lower, higher] = val.to_le_bytes();
 
         self.write(addr, lower);
-        self.write(addr + 1, higher);
+        self.write(addr.wrapping_add(1), higher);
+    }
+
+    /// Reads the opcode of an instruction that is about to be executed
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+    /// Fetches and decodes the instruction about to be executed, giving it (or `None` if it does not decode) with its size
+    ///
+    /// Memory that knows the bytes there did not change can give an instruction it decoded before.
+    fn fetch_instruction(&mut self, addr: u16) -> (Option<Instruction>, u16) {
+        let mut bytes = [self.fetch(addr), 0, 0, 0];
+        // Unknown opcodes do not decode anyway
+        let size = operand_size(bytes[0]).unwrap_or(0);
+        for i in 1..=size {
+            bytes[i as usize] = self.fetch(addr.wrapping_add(i));
+        }
+        let ins = decode_at(addr, &bytes[..1 + size as usize])
+            .ok()
+            .map(|(ins, _)| ins);
+        (ins, 1 + size)
+    }
+    /// Returns (and clears) the trap caused by an access since last time this was called
+    fn take_fault(&mut self) -> Option<TrapMode> {
+        None
     }
 }
 
 pub trait Io {
     fn read(&mut self, addr: u8) -> u8;
     fn write(&mut self, addr: u8, val: u8);
+
+    /// Lets time pass for the devices behind this, by the cycles the last instruction took
+    fn tick(&mut self, _cycles: u64) {}
+    /// Lets devices read and write the memory below the I/O mapping directly, after every instruction
+    ///
+    /// The memory is only as long as it has been used, devices writing past it should grow it.
+    /// Devices are expected to only change memory after the program wrote to one of them, and before the first
+    /// instruction, as the emulator keeps the instructions it decoded until then.
+    fn dma(&mut self, _memory: &mut Vec<u8>) {}
+    /// Whether a device has its interrupt line raised
+    ///
+    /// The line stays raised until the program acknowledges it through the device.
+    fn interrupt_pending(&self) -> bool {
+        false
+    }
+    /// Writes the state of the devices behind this for a snapshot of the machine
+    #[cfg(feature = "std")]
+    fn save_state(&self, _state: &mut Vec<u8>) {}
+    /// Takes back the state [`Io::save_state`] wrote from the front of `state`
+    #[cfg(feature = "std")]
+    fn restore_state(&mut self, _state: &mut &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<I: Io + ?Sized> Io for Box<I> {
+    fn read(&mut self, addr: u8) -> u8 {
+        (**self).read(addr)
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        (**self).write(addr, val)
+    }
+    fn tick(&mut self, cycles: u64) {
+        (**self).tick(cycles)
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        (**self).dma(memory)
+    }
+    fn interrupt_pending(&self) -> bool {
+        (**self).interrupt_pending()
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self, state: &mut Vec<u8>) {
+        (**self).save_state(state)
+    }
+    #[cfg(feature = "std")]
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        (**self).restore_state(state)
+    }
 }
 
 pub struct PanickingIO;
@@ -41,6 +157,7 @@ impl Io for PanickingIO {
     }
 }
 pub struct StdIo;
+#[cfg(feature = "std")]
 impl Io for StdIo {
     fn read(&mut self, _addr: u8) -> u8 {
         // TODO: use the address
@@ -53,10 +170,244 @@ impl Io for StdIo {
     }
 }
 
+/// What a part of the address space is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AreaKind {
+    Ram,
+    /// Read-only memory, starting with these contents
+    Rom(Vec<u8>),
+    /// Goes to the I/O with the lower byte of the address, like the I/O mapping does
+    Device,
+}
+
+impl AreaKind {
+    /// What programs may do in the area
+    pub fn flags(&self) -> SegmentFlags {
+        match self {
+            AreaKind::Ram => SegmentFlags::READ
+                .union(SegmentFlags::WRITE)
+                .union(SegmentFlags::EXECUTE),
+            AreaKind::Rom(_) => SegmentFlags::READ.union(SegmentFlags::EXECUTE),
+            AreaKind::Device => SegmentFlags::READ.union(SegmentFlags::WRITE),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Area {
+    pub start: u16,
+    /// Exclusive, so up to `0x10000`
+    pub end: u32,
+    pub kind: AreaKind,
+}
+
+impl Area {
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && (addr as u32) < self.end
+    }
+}
+
+/// Which parts of the address space are RAM, ROM or devices, accessing the rest traps
+///
+/// By default everything below the I/O mapping is RAM and the I/O mapping is a device window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    areas: Vec<Area>,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::with_size(IO_MAPPING_CUTOFF).expect("all of the memory fits")
+    }
+}
+
+impl MemoryMap {
+    /// Errs if areas overlap or a ROM has more contents than fit in it
+    pub fn new(mut areas: Vec<Area>) -> Result<Self, String> {
+        areas.sort_by_key(|a| a.start);
+        for a in &areas {
+            if a.end <= a.start as u32 || a.end > 0x10000 {
+                return Err(format!("{} is not a valid area", describe_area(a)));
+            }
+            if let AreaKind::Rom(contents) = &a.kind {
+                if contents.len() as u32 > a.end - a.start as u32 {
+                    return Err(format!(
+                        "{} cannot hold its {} bytes",
+                        describe_area(a),
+                        contents.len()
+                    ));
+                }
+            }
+        }
+        for pair in areas.windows(2) {
+            if (pair[1].start as u32) < pair[0].end {
+                return Err(format!(
+                    "{} overlaps {}",
+                    describe_area(&pair[0]),
+                    describe_area(&pair[1])
+                ));
+            }
+        }
+        Ok(MemoryMap { areas })
+    }
+    /// RAM up to `size` and the I/O mapping
+    pub fn with_size(size: u16) -> Result<Self, String> {
+        if size > IO_MAPPING_CUTOFF {
+            return Err(format!(
+                "0x{size:04x} bytes of memory overlap the I/O mapping at 0x{IO_MAPPING_CUTOFF:04x}"
+            ));
+        }
+        let mut areas = Vec::new();
+        if size > 0 {
+            areas.push(Area {
+                start: 0,
+                end: size as u32,
+                kind: AreaKind::Ram,
+            });
+        }
+        areas.push(Area {
+            start: IO_MAPPING_CUTOFF,
+            end: 0x10000,
+            kind: AreaKind::Device,
+        });
+        Self::new(areas)
+    }
+    /// Parses a map with an area on each line, like `ram 0x0..0x8000`, `rom 0x8000..0xa000 boot.bin` or
+    /// `device 0xffe0..0x10000`
+    ///
+    /// The file of a ROM is optional and read with `load`. Everything after a `#` is a comment.
+    pub fn parse<E: Display>(
+        text: &str,
+        mut load: impl FnMut(&str) -> Result<Vec<u8>, E>,
+    ) -> Result<Self, String> {
+        let mut areas = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(kind) = words.next() else {
+                continue;
+            };
+            let err = |e: String| format!("line {}: {e}", n + 1);
+            let range = words
+                .next()
+                .ok_or_else(|| err(format!("{kind} has no address range")))?;
+            let (start, end) = range
+                .split_once("..")
+                .and_then(|(start, end)| Some((parse_number(start)?, parse_number(end)?)))
+                .filter(|&(start, _)| start <= 0xffff)
+                .ok_or_else(|| err(format!("{range} is not an address range like 0x0..0x8000")))?;
+            let kind = match (kind, words.next()) {
+                ("ram", None) => AreaKind::Ram,
+                ("device", None) => AreaKind::Device,
+                ("rom", None) => AreaKind::Rom(Vec::new()),
+                ("rom", Some(path)) => {
+                    AreaKind::Rom(load(path).map_err(|e| err(format!("cannot read {path}: {e}")))?)
+                }
+                ("ram" | "device", Some(_)) => {
+                    return Err(err(format!("{kind} does not take a file")))
+                }
+                _ => return Err(err(format!("unknown kind of area {kind}"))),
+            };
+            if words.next().is_some() {
+                return Err(err("too many words".to_owned()));
+            }
+            areas.push(Area {
+                start: start as u16,
+                end,
+                kind,
+            });
+        }
+        Self::new(areas)
+    }
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+    pub fn area(&self, addr: u16) -> Option<&Area> {
+        self.areas.iter().find(|a| a.contains(addr))
+    }
+    pub fn is_io(&self, addr: u16) -> bool {
+        matches!(
+            self.area(addr),
+            Some(Area {
+                kind: AreaKind::Device,
+                ..
+            })
+        )
+    }
+    /// Whether the addresses from `start` up to `end` are all in one RAM or ROM area
+    pub fn holds(&self, start: u16, end: u32) -> bool {
+        self.area(start)
+            .is_some_and(|a| a.kind != AreaKind::Device && end <= a.end)
+    }
+    /// Makes the addresses from `start` up to `end` read-only, splitting the RAM area they are in
+    ///
+    /// Errs if they are not all in one RAM or ROM area.
+    pub fn protect(&mut self, start: u16, end: u32) -> Result<(), String> {
+        let Some(n) = self
+            .areas
+            .iter()
+            .position(|a| a.contains(start) && end <= a.end)
+        else {
+            return Err(format!("0x{start:04x}..0x{end:04x} is not in one area"));
+        };
+        let area = self.areas[n].clone();
+        match area.kind {
+            AreaKind::Rom(_) => Ok(()),
+            AreaKind::Device => Err(format!("{} cannot be read-only", describe_area(&area))),
+            AreaKind::Ram => {
+                let split = [
+                    (area.start as u32, start as u32, AreaKind::Ram),
+                    (start as u32, end, AreaKind::Rom(Vec::new())),
+                    (end, area.end, AreaKind::Ram),
+                ];
+                self.areas.splice(
+                    n..=n,
+                    split
+                        .into_iter()
+                        .filter(|&(start, end, _)| start < end)
+                        .map(|(start, end, kind)| Area {
+                            start: start as u16,
+                            end,
+                            kind,
+                        }),
+                );
+                Ok(())
+            }
+        }
+    }
+    /// Where the stack starts, the end of the last RAM area
+    pub fn stack_top(&self) -> u16 {
+        self.areas
+            .iter()
+            .rev()
+            .find(|a| a.kind == AreaKind::Ram)
+            .map_or(0, |a| a.end.min(0xfffe) as u16)
+    }
+}
+
+fn describe_area(a: &Area) -> String {
+    let kind = match a.kind {
+        AreaKind::Ram => "ram",
+        AreaKind::Rom(_) => "rom",
+        AreaKind::Device => "device",
+    };
+    format!("{kind} 0x{:04x}..0x{:04x}", a.start, a.end)
+}
+
+/// Parses decimal or `0x` hexadecimal
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Memory that grows as it is used, with the device windows of its map going to the I/O
 #[derive(Debug, Clone)]
 pub struct Lazy<I> {
     pub mem: Vec<u8>,
     pub io: I,
+    pub map: MemoryMap,
 }
 
 impl Lazy<PanickingIO> {
@@ -64,31 +415,36 @@ impl Lazy<PanickingIO> {
         Self {
             mem,
             io: PanickingIO,
+            map: MemoryMap::default(),
         }
     }
 }
 impl Lazy<StdIo> {
     pub fn new_stdio(mem: Vec<u8>) -> Self {
-        Self { mem, io: StdIo }
+        Self {
+            mem,
+            io: StdIo,
+            map: MemoryMap::default(),
+        }
     }
 }
 
 impl<I: Io> Memory for Lazy<I> {
     fn read(&mut self, addr: u16) -> u8 {
-        if addr < IO_MAPPING_CUTOFF {
-            self.mem.get(addr as usize).copied().unwrap_or(0)
-        } else {
+        if self.map.is_io(addr) {
             self.io.read(addr as u8)
+        } else {
+            self.mem.get(addr as usize).copied().unwrap_or(0)
         }
     }
     fn write(&mut self, addr: u16, val: u8) {
-        if addr < IO_MAPPING_CUTOFF {
+        if self.map.is_io(addr) {
+            self.io.write(addr as u8, val);
+        } else {
             if self.mem.len() <= addr as usize {
                 self.mem.resize(addr as usize + 1, 0);
             }
             self.mem[addr as usize] = val;
-        } else {
-            self.io.write(addr as u8, val);
         }
     }
 }
@@ -103,3 +459,107 @@ impl Memory for [u8] {
         self[addr as usize] = val;
     }
 }
+
+/// Memory which traps on accesses that the flags of the region they are in do not allow
+///
+/// Nothing is allowed in the gaps between regions. Addresses before the first and after the last region, like the stack,
+/// are only checked against the area of the memory map they are in.
+#[derive(Debug, Clone)]
+pub struct Protected<M> {
+    pub inner: M,
+    regions: Vec<Region>,
+    /// The areas of the memory map with what they allow
+    areas: Vec<(u16, u32, SegmentFlags)>,
+    /// From the start of the first region to the end of the last
+    mapped: (u16, u32),
+    fault: Option<TrapMode>,
+    /// The first access that faulted since it was last taken
+    faulted: Option<(TrapMode, u16)>,
+}
+
+impl<M> Protected<M> {
+    pub fn new(inner: M, regions: Vec<Region>) -> Self {
+        Self::with_map(inner, regions, &MemoryMap::default())
+    }
+    pub fn with_map(inner: M, regions: Vec<Region>, map: &MemoryMap) -> Self {
+        let areas = map
+            .areas()
+            .iter()
+            .map(|a| (a.start, a.end, a.kind.flags()))
+            .collect();
+        let start = regions.iter().map(|r| r.start).min().unwrap_or(0);
+        let end = regions.iter().map(|r| r.end).max().unwrap_or(0);
+        Self {
+            inner,
+            regions,
+            areas,
+            mapped: (start, end),
+            fault: None,
+            faulted: None,
+        }
+    }
+    /// Takes the trap and address of the first access that faulted since the last time
+    pub fn take_faulted(&mut self) -> Option<(TrapMode, u16)> {
+        self.faulted.take()
+    }
+    /// Whether the access would be allowed, without faulting if not
+    pub fn permits(&self, addr: u16, flag: SegmentFlags) -> bool {
+        let region = self.regions.iter().find(|r| r.contains(addr));
+        let area = self
+            .areas
+            .iter()
+            .find(|&&(start, end, _)| start <= addr && (addr as u32) < end);
+        area.is_some_and(|&(_, _, flags)| flags.contains(flag))
+            && match region {
+                Some(r) => r.flags.contains(flag),
+                None => !(self.mapped.0 <= addr && (addr as u32) < self.mapped.1),
+            }
+    }
+    fn allowed(&mut self, addr: u16, flag: SegmentFlags, trap: TrapMode) -> bool {
+        let allowed = self.permits(addr, flag);
+        if !allowed {
+            self.fault.get_or_insert(trap);
+            self.faulted.get_or_insert((trap, addr));
+        }
+        allowed
+    }
+}
+
+impl<M: Memory> Memory for Protected<M> {
+    fn read(&mut self, addr: u16) -> u8 {
+        if self.allowed(addr, SegmentFlags::READ, TrapMode::IllegalRead) {
+            self.inner.read(addr)
+        } else {
+            0
+        }
+    }
+    fn write(&mut self, addr: u16, val: u8) {
+        if self.allowed(addr, SegmentFlags::WRITE, TrapMode::IllegalWrite) {
+            self.inner.write(addr, val)
+        }
+    }
+    fn fetch(&mut self, addr: u16) -> u8 {
+        if self.allowed(addr, SegmentFlags::EXECUTE, TrapMode::IllegalExecute) {
+            self.inner.fetch(addr)
+        } else {
+            0
+        }
+    }
+    fn fetch_instruction(&mut self, addr: u16) -> (Option<Instruction>, u16) {
+        if !self.allowed(addr, SegmentFlags::EXECUTE, TrapMode::IllegalExecute) {
+            return (None, 1);
+        }
+        let (ins, size) = self.inner.fetch_instruction(addr);
+        for i in 1..size {
+            self.allowed(
+                addr.wrapping_add(i),
+                SegmentFlags::EXECUTE,
+                TrapMode::IllegalExecute,
+            );
+        }
+        (ins, size)
+    }
+    fn take_fault(&mut self) -> Option<TrapMode> {
+        self.fault.take().or_else(|| self.inner.take_fault())
+    }
+}
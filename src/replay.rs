@@ -0,0 +1,437 @@
+//! Recording what a program got from its I/O to replay the run exactly
+//!
+//! Everything else a machine does follows from the object and the cycle model, so the values of I/O reads, when
+//! interrupts were pending and what devices wrote to memory are enough. Each is recorded with the cycle it happened at.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    aalv::{AalvReader, AalvWriter, Section},
+    mem::Io,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Reading the port gave the value
+    Read { at: u64, port: u8, value: u8 },
+    /// The I/O had an interrupt pending when the machine asked
+    Interrupt { at: u64 },
+    /// A device wrote the bytes to memory from `addr`
+    Memory { at: u64, addr: u16, bytes: Vec<u8> },
+}
+
+impl Event {
+    fn at(&self) -> u64 {
+        match *self {
+            Event::Read { at, .. } | Event::Interrupt { at } | Event::Memory { at, .. } => at,
+        }
+    }
+}
+
+/// Events are written in sections of about this many bytes
+const CHUNK_SIZE: usize = 0x8000;
+/// Longer runs of changed memory are split, so that a section stays within its size limit
+const MAX_RUN: usize = 0x4000;
+
+/// Some events, in a section of a recording file
+struct Chunk(Vec<Event>);
+
+impl Section for Chunk {
+    const NAME: &'static str = "_replay";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut events = Vec::new();
+        let mut tag = [0];
+        while reader.read(&mut tag)? == 1 {
+            let mut at = [0; 8];
+            reader.read_exact(&mut at)?;
+            let at = u64::from_le_bytes(at);
+            events.push(match tag[0] {
+                0 => {
+                    let mut buf = [0; 2];
+                    reader.read_exact(&mut buf)?;
+                    Event::Read {
+                        at,
+                        port: buf[0],
+                        value: buf[1],
+                    }
+                }
+                1 => Event::Interrupt { at },
+                2 => {
+                    let mut buf = [0; 4];
+                    reader.read_exact(&mut buf)?;
+                    let [a0, a1, l0, l1] = buf;
+                    let mut bytes = vec![0; u16::from_le_bytes([l0, l1]) as usize];
+                    reader.read_exact(&mut bytes)?;
+                    Event::Memory {
+                        at,
+                        addr: u16::from_le_bytes([a0, a1]),
+                        bytes,
+                    }
+                }
+                t => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown replay event {t}"),
+                    ))
+                }
+            });
+        }
+        Ok(Chunk(events))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for event in &self.0 {
+            let tag = match event {
+                Event::Read { .. } => 0,
+                Event::Interrupt { .. } => 1,
+                Event::Memory { .. } => 2,
+            };
+            writer.write_all(&[tag])?;
+            writer.write_all(&event.at().to_le_bytes())?;
+            match event {
+                &Event::Read { port, value, .. } => writer.write_all(&[port, value])?,
+                Event::Interrupt { .. } => (),
+                Event::Memory { addr, bytes, .. } => {
+                    writer.write_all(&addr.to_le_bytes())?;
+                    writer.write_all(&(bytes.len() as u16).to_le_bytes())?;
+                    writer.write_all(bytes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encoded_len(event: &Event) -> usize {
+    match event {
+        Event::Read { .. } => 11,
+        Event::Interrupt { .. } => 9,
+        Event::Memory { bytes, .. } => 13 + bytes.len(),
+    }
+}
+
+/// Reads all events of a recording file
+pub fn read_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<Event>> {
+    read_events(BufReader::new(File::open(path)?))
+}
+pub fn read_events<R: io::BufRead + Seek>(file: R) -> io::Result<Vec<Event>> {
+    let mut aalvur = AalvReader::new(file)?;
+    let mut events = Vec::new();
+    while let Some(chunk) = aalvur.read_section::<Chunk>() {
+        events.extend(chunk?.0);
+    }
+    Ok(events)
+}
+
+/// Passes everything on to `io` while recording the events to a file, which is finished when this is dropped
+pub struct Recorder<I, W: Write + Seek> {
+    io: I,
+    log: RefCell<Log<W>>,
+    cycles: u64,
+    /// Whether the program wrote to a device since devices last got to access memory
+    wrote: bool,
+}
+
+struct Log<W: Write + Seek> {
+    out: AalvWriter<W>,
+    chunk: Vec<Event>,
+    chunk_len: usize,
+}
+
+impl<W: Write + Seek> Log<W> {
+    fn record(&mut self, event: Event) {
+        self.chunk_len += encoded_len(&event);
+        self.chunk.push(event);
+        if self.chunk_len >= CHUNK_SIZE {
+            self.write_chunk();
+        }
+    }
+    fn write_chunk(&mut self) {
+        let chunk = Chunk(std::mem::take(&mut self.chunk));
+        self.chunk_len = 0;
+        self.out
+            .write_section(&chunk)
+            .expect("writing recording failed");
+    }
+}
+
+impl<W: Write + Seek> Drop for Log<W> {
+    fn drop(&mut self) {
+        if !self.chunk.is_empty() {
+            self.write_chunk();
+        }
+    }
+}
+
+impl<I: Io, W: Write + Seek> Recorder<I, W> {
+    pub fn new(io: I, out: W) -> io::Result<Self> {
+        let mut out = AalvWriter::new(out, 0)?;
+        out.set_compression(true);
+        Ok(Recorder {
+            io,
+            log: RefCell::new(Log {
+                out,
+                chunk: Vec::new(),
+                chunk_len: 0,
+            }),
+            cycles: 0,
+            wrote: false,
+        })
+    }
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+
+    fn record(&self, event: Event) {
+        self.log.borrow_mut().record(event);
+    }
+}
+
+impl<I: Io, W: Write + Seek> Io for Recorder<I, W> {
+    fn read(&mut self, addr: u8) -> u8 {
+        let value = self.io.read(addr);
+        self.record(Event::Read {
+            at: self.cycles,
+            port: addr,
+            value,
+        });
+        value
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        self.wrote = true;
+        self.io.write(addr, val)
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.io.tick(cycles)
+    }
+    /// Only looks for what devices changed in memory after the program wrote to one, devices are expected to only
+    /// access memory when told to
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        if !std::mem::take(&mut self.wrote) {
+            return self.io.dma(memory);
+        }
+        let before = memory.clone();
+        self.io.dma(memory);
+
+        let mut addr = 0;
+        while addr < memory.len() {
+            let changed = |a: usize| before.get(a).copied().unwrap_or(0) != memory[a];
+            if !changed(addr) {
+                addr += 1;
+                continue;
+            }
+            let start = addr;
+            while addr < memory.len() && addr - start < MAX_RUN && changed(addr) {
+                addr += 1;
+            }
+            self.record(Event::Memory {
+                at: self.cycles,
+                addr: start as u16,
+                bytes: memory[start..addr].to_vec(),
+            });
+        }
+    }
+    fn interrupt_pending(&self) -> bool {
+        let pending = self.io.interrupt_pending();
+        if pending {
+            self.record(Event::Interrupt { at: self.cycles });
+        }
+        pending
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.io.save_state(state)
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        self.io.restore_state(state)
+    }
+}
+
+/// Takes I/O reads, interrupts and what devices wrote to memory from a recording instead of `io`
+///
+/// What the program writes still goes to `io`, with its devices getting time and memory access as usual, so its
+/// output shows again. Once the program does a read the recording does not have, it has diverged from it and
+/// everything goes to `io`.
+pub struct Replayer<I> {
+    io: I,
+    events: Vec<Event>,
+    next: Cell<usize>,
+    cycles: u64,
+    diverged: Option<u64>,
+}
+
+impl<I: Io> Replayer<I> {
+    pub fn new(io: I, events: Vec<Event>) -> Self {
+        Replayer {
+            io,
+            events,
+            next: Cell::new(0),
+            cycles: 0,
+            diverged: None,
+        }
+    }
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+    /// The cycle the program stopped following the recording at, if it did
+    pub fn diverged(&self) -> Option<u64> {
+        self.diverged
+    }
+    /// Whether all of the recording was replayed
+    pub fn finished(&self) -> bool {
+        self.next.get() == self.events.len()
+    }
+
+    fn peek(&self) -> Option<&Event> {
+        self.events.get(self.next.get())
+    }
+    fn advance(&self) {
+        self.next.set(self.next.get() + 1);
+    }
+}
+
+impl<I: Io> Io for Replayer<I> {
+    fn read(&mut self, addr: u8) -> u8 {
+        if self.diverged.is_none() {
+            match *self.peek().unwrap_or(&Event::Interrupt { at: u64::MAX }) {
+                Event::Read { at, port, value } if at == self.cycles && port == addr => {
+                    self.advance();
+                    return value;
+                }
+                _ => self.diverged = Some(self.cycles),
+            }
+        }
+        self.io.read(addr)
+    }
+    fn write(&mut self, addr: u8, val: u8) {
+        self.io.write(addr, val)
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.io.tick(cycles)
+    }
+    fn dma(&mut self, memory: &mut Vec<u8>) {
+        self.io.dma(memory);
+        if self.diverged.is_some() {
+            return;
+        }
+        while let Some(Event::Memory { at, addr, bytes }) = self.peek() {
+            if *at != self.cycles {
+                break;
+            }
+            let (start, end) = (*addr as usize, *addr as usize + bytes.len());
+            if memory.len() < end {
+                memory.resize(end, 0);
+            }
+            memory[start..end].copy_from_slice(bytes);
+            self.advance();
+        }
+    }
+    fn interrupt_pending(&self) -> bool {
+        if self.diverged.is_some() {
+            return self.io.interrupt_pending();
+        }
+        match self.peek() {
+            Some(&Event::Interrupt { at }) if at == self.cycles => {
+                self.advance();
+                true
+            }
+            _ => false,
+        }
+    }
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.io.save_state(state)
+    }
+    fn restore_state(&mut self, state: &mut &[u8]) -> io::Result<()> {
+        self.io.restore_state(state)
+    }
+}
+
+#[test]
+fn record_and_replay() {
+    use std::io::Cursor;
+
+    /// Gives the next input on every read and has an interrupt pending after a write
+    struct Input(u8, bool);
+    impl Io for Input {
+        fn read(&mut self, _addr: u8) -> u8 {
+            self.0 += 1;
+            self.0
+        }
+        fn write(&mut self, _addr: u8, _val: u8) {
+            self.1 = true;
+        }
+        fn dma(&mut self, memory: &mut Vec<u8>) {
+            if std::mem::take(&mut self.1) {
+                memory.resize(0x12, 0);
+                memory[0x10..].copy_from_slice(b"hi");
+            }
+        }
+        fn interrupt_pending(&self) -> bool {
+            self.1
+        }
+    }
+
+    let mut file = Cursor::new(Vec::new());
+    let mut recorder = Recorder::new(Input(0, false), &mut file).unwrap();
+    let mut memory = Vec::new();
+    assert_eq!(recorder.read(0), 1);
+    recorder.tick(3);
+    recorder.write(0, 0);
+    assert!(recorder.interrupt_pending());
+    recorder.dma(&mut memory);
+    recorder.tick(2);
+    assert_eq!(recorder.read(1), 2);
+    drop(recorder);
+
+    file.set_position(0);
+    let events = read_events(file).unwrap();
+    assert_eq!(
+        events,
+        [
+            Event::Read {
+                at: 0,
+                port: 0,
+                value: 1
+            },
+            Event::Interrupt { at: 3 },
+            Event::Memory {
+                at: 3,
+                addr: 0x10,
+                bytes: b"hi".to_vec()
+            },
+            Event::Read {
+                at: 5,
+                port: 1,
+                value: 2
+            },
+        ]
+    );
+
+    // The I/O of the replay gives other inputs and never interrupts
+    struct Other;
+    impl Io for Other {
+        fn read(&mut self, _addr: u8) -> u8 {
+            0xff
+        }
+        fn write(&mut self, _addr: u8, _val: u8) {}
+    }
+    let mut replayer = Replayer::new(Other, events);
+    let mut memory = Vec::new();
+    assert_eq!(replayer.read(0), 1);
+    assert!(!replayer.interrupt_pending());
+    replayer.tick(3);
+    assert!(replayer.interrupt_pending());
+    replayer.dma(&mut memory);
+    assert_eq!(&memory[0x10..], b"hi");
+    replayer.tick(2);
+    assert_eq!(replayer.read(1), 2);
+    assert!(replayer.finished());
+    assert_eq!(replayer.read(1), 0xff);
+    assert_eq!(replayer.diverged(), Some(5));
+}
@@ -0,0 +1,142 @@
+//! Packets of GDB's remote serial protocol, which `t --gdb` and `t --debug-listen` serve and `tdb --attach` sends
+//!
+//! The registers are r0 to r10, rs, rl, rf, rp, rh, the program counter and the flags,
+//! each 16 bits and sent little-endian like memory. The flags have carry in bit 0, then overflow, sign, zero, trap
+//! and interrupt enable.
+
+use std::{
+    fmt::Write as _,
+    io::{self, ErrorKind, Read, Write},
+};
+
+use crate::cpu::Registers;
+
+pub const REGISTERS: usize = 18;
+pub const PC: usize = 16;
+pub const FLAGS: usize = 17;
+
+/// Reads the next packet, acknowledging it, none if the connection closed
+///
+/// An interrupt outside of a packet is taken as asking why it stopped.
+pub fn read_packet<R: Read, W: Write>(r: &mut R, w: &mut W) -> io::Result<Option<String>> {
+    let mut byte = [0];
+    loop {
+        match r.read_exact(&mut byte) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        match byte[0] {
+            b'$' => break,
+            0x03 => return Ok(Some("?".to_owned())),
+            // Acknowledgements of our packets, or noise
+            _ => continue,
+        }
+    }
+
+    let mut data = Vec::new();
+    loop {
+        r.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' => break,
+            b'}' => {
+                r.read_exact(&mut byte)?;
+                data.push(byte[0] ^ 0x20);
+            }
+            b => data.push(b),
+        }
+    }
+    let mut checksum = [0; 2];
+    r.read_exact(&mut checksum)?;
+    let expected = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+    let sum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+    if expected != Some(sum) {
+        w.write_all(b"-")?;
+        return read_packet(r, w);
+    }
+    w.write_all(b"+")?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+pub fn write_packet<W: Write>(w: &mut W, data: &str) -> io::Result<()> {
+    let mut packet = String::with_capacity(data.len() + 4);
+    packet.push('$');
+    for c in data.chars() {
+        if matches!(c, '$' | '#' | '}' | '*') {
+            packet.push('}');
+            packet.push((c as u8 ^ 0x20) as char);
+        } else {
+            packet.push(c);
+        }
+    }
+    let sum = packet[1..].bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+    write!(packet, "#{sum:02x}").unwrap();
+    w.write_all(packet.as_bytes())?;
+    w.flush()
+}
+
+/// Sends a packet and waits for the reply, for the debugger's side
+pub fn request<R: Read, W: Write>(r: &mut R, w: &mut W, packet: &str) -> io::Result<String> {
+    write_packet(w, packet)?;
+    read_packet(r, w)?.ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "disconnected"))
+}
+
+pub fn flags(r: &Registers) -> u16 {
+    r.carry as u16
+        | (r.overflow as u16) << 1
+        | (r.sign as u16) << 2
+        | (r.zero as u16) << 3
+        | (r.trap as u16) << 4
+        | (r.interrupt_enable as u16) << 5
+}
+pub fn set_flags(r: &mut Registers, val: u16) {
+    r.carry = val & 1 != 0;
+    r.overflow = val & 2 != 0;
+    r.sign = val & 4 != 0;
+    r.zero = val & 8 != 0;
+    r.trap = val & 16 != 0;
+    r.interrupt_enable = val & 32 != 0;
+}
+
+pub fn wide_hex(val: u16) -> String {
+    let [l, h] = val.to_le_bytes();
+    format!("{l:02x}{h:02x}")
+}
+
+pub fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn parse_wides(hex: &str) -> Option<Vec<u16>> {
+    let bytes = parse_bytes(hex)?;
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect(),
+    )
+}
+
+#[test]
+fn packets() {
+    let mut out = Vec::new();
+    write_packet(&mut out, "OK").unwrap();
+    assert_eq!(out, b"$OK#9a");
+
+    let mut acks = Vec::new();
+    let packet = read_packet(&mut &b"+$m10,4#2e"[..], &mut acks).unwrap();
+    assert_eq!(packet.as_deref(), Some("m10,4"));
+    assert_eq!(acks, b"+");
+}
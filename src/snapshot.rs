@@ -0,0 +1,160 @@
+//! The state of a machine at some point, saved in an álvur file to restore it later
+//!
+//! The object the machine runs is not part of it, a snapshot is restored into a machine loaded from the same object.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    aalv::{AalvReader, AalvWriter, Section},
+    cpu::{Registers, TrapMode, WideRegister},
+    U4,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: Registers,
+    pub cycles: u64,
+    /// The memory below the I/O mapping, only as long as it had been used
+    pub memory: Vec<u8>,
+    /// What the I/O wrote with [`Io::save_state`](crate::mem::Io::save_state)
+    pub devices: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn read<R: io::BufRead + Seek>(file: R) -> io::Result<Self> {
+        let mut aalvur = AalvReader::new(file)?;
+        let missing =
+            |name| io::Error::new(ErrorKind::InvalidData, format!("snapshot has no {name}"));
+        let SnapshotRegisters(registers) = aalvur
+            .read_section()
+            .ok_or_else(|| missing("registers"))??;
+        let Cycles(cycles) = aalvur.read_section().ok_or_else(|| missing("cycles"))??;
+        let MemoryImage(memory) = aalvur.read_section().ok_or_else(|| missing("memory"))??;
+        let DeviceState(mut devices) = aalvur
+            .read_section()
+            .ok_or_else(|| missing("device state"))??;
+        while let Some(more) = aalvur.read_section::<DeviceState>() {
+            devices.extend(more?.0);
+        }
+        Ok(Snapshot {
+            registers,
+            cycles,
+            memory,
+            devices,
+        })
+    }
+    pub fn write<W: Write + Seek>(&self, file: W) -> io::Result<()> {
+        let mut aalvur = AalvWriter::new(file, 0)?;
+        aalvur.set_compression(true);
+        aalvur.write_section(&SnapshotRegisters(self.registers.clone()))?;
+        aalvur.write_section(&Cycles(self.cycles))?;
+        aalvur.write_section(&MemoryImage(self.memory.clone()))?;
+        // In as many sections as it takes to stay within their size limit
+        for chunk in self.devices.chunks(DEVICE_STATE_CHUNK).chain(
+            // There is always one, even if there is no state
+            self.devices.is_empty().then_some(&[][..]),
+        ) {
+            aalvur.write_section(&DeviceState(chunk.to_vec()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The wide registers from `r1` to `rh`, the program counter, trap mode, whether the trap handler runs,
+/// the vector table and the flags
+struct SnapshotRegisters(Registers);
+
+impl Section for SnapshotRegisters {
+    const NAME: &'static str = "_regs";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0; 2 * 15 + 2 + 1 + 1 + 2 + 2];
+        reader.read_exact(&mut buf)?;
+        let wide = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+
+        let mut registers = Registers::new(0);
+        for n in 1..16 {
+            registers.write_wide(WideRegister(U4::new(n)), wide(2 * (n as usize - 1)));
+        }
+        registers.program_counter = wide(30);
+        registers.trap_mode = TrapMode::from_u8(buf[32]).ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid trap mode {:#x}", buf[32]),
+            )
+        })?;
+        registers.trap = buf[33] != 0;
+        registers.interrupt_vectors = wide(34);
+        registers.set_flags(wide(36));
+        Ok(SnapshotRegisters(registers))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let r = &self.0;
+        for n in 1..16 {
+            writer.write_all(&r.read_wide(WideRegister(U4::new(n))).to_le_bytes())?;
+        }
+        writer.write_all(&r.program_counter.to_le_bytes())?;
+        writer.write_all(&[r.trap_mode as u8, r.trap as u8])?;
+        writer.write_all(&r.interrupt_vectors.to_le_bytes())?;
+        writer.write_all(&r.flags().to_le_bytes())
+    }
+}
+
+struct Cycles(u64);
+
+impl Section for Cycles {
+    const NAME: &'static str = "_cycles";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        Ok(Cycles(u64::from_le_bytes(buf)))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.0.to_le_bytes())
+    }
+}
+
+struct MemoryImage(Vec<u8>);
+
+impl Section for MemoryImage {
+    const NAME: &'static str = "_mem";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut memory = Vec::new();
+        reader.read_to_end(&mut memory)?;
+        Ok(MemoryImage(memory))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.0)
+    }
+}
+
+/// The device state is split into sections of this many bytes
+const DEVICE_STATE_CHUNK: usize = 0x8000;
+
+struct DeviceState(Vec<u8>);
+
+impl Section for DeviceState {
+    const NAME: &'static str = "_devstate";
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut state = Vec::new();
+        reader.read_to_end(&mut state)?;
+        Ok(DeviceState(state))
+    }
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.0)
+    }
+}
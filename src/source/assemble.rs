@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    aalv::obj::{
+        Note, Notes, Object, RelocationEntry, RelocationKind, RelocationTable, SegmentFlagsTable,
+        SegmentType, SymbolDefinition, SymbolTable,
+    },
+    isa::feature,
+};
+
+use super::{
+    process, write_data_operand, Byte, DataLine, LabelAttributes, LabelRead, ProcessedSource,
+    Result, SourceLines, SymbolType, Wide,
+};
+
+/// Assembles a source file into an object, with a line table if `debug_info` is set
+pub fn assemble(path: &Path, debug_info: bool) -> Result<Object> {
+    let ProcessedSource {
+        labels,
+        dls,
+        seg_flags,
+        entry,
+        lines: line_table,
+        sources,
+    } = process(SourceLines::new(path)?)?;
+
+    let mut label_reads: Vec<Vec<LabelRead>> = Vec::new();
+    label_reads.resize_with(labels.len(), Vec::new);
+
+    let mut segs = BTreeMap::new();
+    let mut cpu_features = 0;
+    let mut lines = Vec::with_capacity(dls.len());
+
+    for (stype, dls) in dls {
+        segs.insert(stype, (dls.start, Vec::with_capacity(dls.size as usize)));
+        lines.push(dls.lines);
+    }
+
+    for ((&st, &mut (_, ref mut mem)), lines) in segs.iter_mut().zip(lines) {
+        for data_line in lines {
+            match data_line {
+                DataLine::Raw(mut bytes) => {
+                    mem.append(&mut bytes);
+                }
+                DataLine::Wide(Wide::Number(w)) => mem.extend_from_slice(&w.to_le_bytes()),
+                DataLine::Wide(Wide::Label(id)) => {
+                    let lr = LabelRead {
+                        segment: st,
+                        position: mem.len() as u16,
+                        kind: RelocationKind::Wide,
+                    };
+                    label_reads[id].push(lr);
+                    let w = labels[id].3;
+                    mem.extend_from_slice(&w.to_le_bytes());
+                }
+                DataLine::Byte(Byte::Number(b)) => mem.push(b),
+                DataLine::Byte(Byte::Label(id, kind)) => {
+                    let position = mem.len() as u16;
+                    let lr = LabelRead {
+                        segment: st,
+                        position,
+                        kind,
+                    };
+                    label_reads[id].push(lr);
+                    let mut bytes = [0; 2];
+                    kind.apply(&mut bytes, labels[id].3, position);
+                    mem.push(bytes[0]);
+                }
+                DataLine::Ins(opcode, dat_op) => {
+                    mem.push(opcode);
+                    cpu_features |= feature(opcode);
+
+                    let read_label = |id: usize, lr| {
+                        label_reads[id].push(lr);
+                        labels[id].3
+                    };
+
+                    write_data_operand(st, mem, read_label, dat_op);
+                }
+            }
+        }
+    }
+
+    let mut aalvur = Object {
+        segs,
+        seg_flags: SegmentFlagsTable(seg_flags),
+        entry,
+        cpu_features,
+        ..Object::default()
+    };
+    if debug_info {
+        aalvur.lines = line_table;
+    }
+
+    let mut symbol_table = Vec::new();
+    {
+        for (lbl, st, segment_type, location, attrs) in labels {
+            let LabelAttributes {
+                kind,
+                size,
+                defined_in,
+            } = attrs;
+            let is_global = match st {
+                SymbolType::Global => true,
+                SymbolType::Internal => false,
+                SymbolType::Reference => {
+                    assert_eq!(
+                        segment_type,
+                        SegmentType::Unknown,
+                        "reference symbols should have unknown segment type"
+                    );
+                    true
+                }
+            };
+
+            symbol_table.push(SymbolDefinition {
+                name: lbl,
+                is_global,
+                segment_type,
+                location,
+                kind,
+                size,
+                defined_in,
+            })
+        }
+    }
+    aalvur.symbols = SymbolTable(symbol_table);
+
+    let reloc_table;
+    {
+        let mut reloc_t = Vec::new();
+
+        for (i, label_reads) in label_reads.into_iter().enumerate() {
+            let symbol_index = i as u16;
+
+            for LabelRead {
+                segment,
+                position,
+                kind,
+            } in label_reads
+            {
+                let entry = RelocationEntry {
+                    reference_location: aalvur.segs[&segment].0 + position,
+                    reference_segment: segment,
+                    symbol_index,
+                    kind,
+                };
+
+                reloc_t.push(entry);
+            }
+        }
+        reloc_table = RelocationTable(reloc_t);
+    }
+    aalvur.relocation_table = reloc_table;
+    aalvur.notes = Notes(vec![Note::new(
+        "tc",
+        if debug_info { "-g" } else { "" },
+        &sources,
+    )]);
+
+    Ok(aalvur)
+}
@@ -7,16 +7,23 @@ use std::{
 };
 
 use crate::{
-    aalv::obj::Entry,
+    aalv::obj::{Entry, LineEntry, LineTable},
     cpu::{ByteRegister as BReg, WideRegister as WReg},
 };
-use crate::{aalv::obj::SegmentType, align, cpu::*, isa, SEGMENT_ALIGNMENT, U4};
+use crate::{
+    aalv::obj::{RelocationKind, SegmentFlags, SegmentType, SymbolKind},
+    align,
+    cpu::*,
+    isa, SEGMENT_ALIGNMENT, U4,
+};
 
+mod assemble;
 mod err;
+pub use self::assemble::assemble;
 pub use self::err::*;
 mod symbols;
 use self::symbols::*;
-pub use self::symbols::{LabelRead, SymbolType};
+pub use self::symbols::{LabelAttributes, LabelRead, SymbolType};
 
 type Opcode = u8;
 
@@ -27,7 +34,11 @@ pub enum SourceOperand {
     Number(i32),
     ByteReg(BReg),
     WideReg(WReg),
+    /// `r1:r2`, by the register of its upper half
+    RegisterPair(WReg),
     Label(String),
+    /// `lo(label)` or `hi(label)`
+    LabelByte(String, RelocationKind),
 }
 
 #[derive(Debug, Clone)]
@@ -37,12 +48,15 @@ pub enum SourceLine {
     Comment,
     DirInclude(String),
     DirString(Vec<u8>),
-    DirByte(u8),
+    DirByte(StdResult<u8, (String, RelocationKind)>),
     DirWide(StdResult<u16, String>),
     DirGlobal(String),
     DirReference(String),
     DirSeg(String),
     DirEntry,
+    /// Without a size, the size is from the label to the current location
+    DirSize(String, Option<u16>),
+    DirType(String, SymbolKind),
 }
 
 pub struct SourceLines<B> {
@@ -82,6 +96,21 @@ impl SourceLines<BufReader<File>> {
     }
 }
 
+/// Two general purpose registers in a row like `r1:r2`, the first one being the upper half
+fn parse_register_pair(arg: &str) -> StdResult<SourceOperand, ErrorType> {
+    let number = |r: &str| r.strip_prefix('r')?.parse::<u8>().ok();
+    let (upper, lower) = arg.split_once(':').expect("it has a colon");
+    match (number(upper), number(lower)) {
+        (Some(n @ 1..=9), Some(m)) if m == n + 1 => {
+            Ok(SourceOperand::RegisterPair(WReg(U4::new(n))))
+        }
+        _ => Err(ErrorType::Other(
+            format!("`{arg}' is not a register pair, which are two registers in a row like r1:r2")
+                .into_boxed_str(),
+        )),
+    }
+}
+
 fn parse_number(arg: &str) -> StdResult<SourceOperand, ErrorType> {
     let so;
     let mut radix = 10;
@@ -122,6 +151,10 @@ fn parse_number(arg: &str) -> StdResult<SourceOperand, ErrorType> {
 
     Ok(if let Some(so) = so {
         so
+    } else if let Some(lbl) = arg.strip_prefix("lo(").and_then(|a| a.strip_suffix(')')) {
+        SourceOperand::LabelByte(lbl.trim().to_owned(), RelocationKind::ByteLow)
+    } else if let Some(lbl) = arg.strip_prefix("hi(").and_then(|a| a.strip_suffix(')')) {
+        SourceOperand::LabelByte(lbl.trim().to_owned(), RelocationKind::ByteHigh)
     } else {
         SourceOperand::Label(arg.to_owned())
     })
@@ -182,7 +215,7 @@ impl<B: BufRead> SourceLines<B> {
                         match parse_number(arg)
                             .map_err(|et| Error::new(self.source.clone(), self.ln, et))?
                         {
-                            SourceOperand::Byte(n) => b = n,
+                            SourceOperand::Byte(n) => b = Ok(n),
                             SourceOperand::Number(n) => {
                                 if n > u8::MAX as i32 {
                                     eprintln!("warning: byte literal overflow");
@@ -190,8 +223,9 @@ impl<B: BufRead> SourceLines<B> {
                                     eprintln!("warning: byte literal underflow");
                                 }
 
-                                b = n as u8
+                                b = Ok(n as u8)
                             }
+                            SourceOperand::LabelByte(l, kind) => b = Err((l, kind)),
                             _ => {
                                 return Err(Error::new(
                                     self.source.clone(),
@@ -235,8 +269,61 @@ impl<B: BufRead> SourceLines<B> {
                     "include" => SourceLine::DirInclude(arg.to_string()),
                     "global" | "globl" => SourceLine::DirGlobal(arg.to_string()),
                     "ref" | "reference" => SourceLine::DirReference(arg.to_string()),
-                    "seg" => SourceLine::DirSeg(arg.to_string()),
+                    "seg" | "section" => SourceLine::DirSeg(arg.to_string()),
                     "entry" => SourceLine::DirEntry,
+                    "size" => {
+                        let (lbl, size) = match arg.split_once(',') {
+                            Some((lbl, size)) => (lbl, Some(size.trim())),
+                            None => (arg, None),
+                        };
+                        let size = match size.map(parse_number).transpose() {
+                            Ok(None) => None,
+                            Ok(Some(SourceOperand::Number(n)))
+                                if (0..=u16::MAX as i32).contains(&n) =>
+                            {
+                                Some(n as u16)
+                            }
+                            Ok(Some(SourceOperand::Wide(n))) => Some(n),
+                            Ok(Some(_)) => {
+                                return Err(Error::new(
+                                    self.source.clone(),
+                                    self.ln,
+                                    ErrorType::Other(
+                                        format!("invalid size `{}'", size.unwrap_or_default())
+                                            .into_boxed_str(),
+                                    ),
+                                ))
+                            }
+                            Err(et) => return Err(Error::new(self.source.clone(), self.ln, et)),
+                        };
+                        SourceLine::DirSize(lbl.trim().to_string(), size)
+                    }
+                    "type" => {
+                        let Some((lbl, kind)) = arg.split_once(',') else {
+                            return Err(Error::new(
+                                self.source.clone(),
+                                self.ln,
+                                ErrorType::Other("expected a symbol and a type".into()),
+                            ));
+                        };
+                        let kind = match kind.trim().trim_start_matches('@') {
+                            "function" | "func" => SymbolKind::Function,
+                            "object" => SymbolKind::Object,
+                            "section" => SymbolKind::Section,
+                            "notype" => SymbolKind::NoType,
+                            kind => {
+                                return Err(Error::new(
+                                    self.source.clone(),
+                                    self.ln,
+                                    ErrorType::Other(
+                                        format!("unknown symbol type `{kind}', expected function, object, section or notype")
+                                            .into_boxed_str(),
+                                    ),
+                                ))
+                            }
+                        };
+                        SourceLine::DirType(lbl.trim().to_string(), kind)
+                    }
                     s => {
                         return Err(Error::new(
                             self.source.clone(),
@@ -287,6 +374,10 @@ impl<B: BufRead> SourceLines<B> {
                         "rf" => SourceOperand::WideReg(RF),
                         "rp" => SourceOperand::WideReg(RP),
                         "rh" => SourceOperand::WideReg(RH),
+                        arg if arg.starts_with('r') && arg.contains(':') => {
+                            parse_register_pair(arg)
+                                .map_err(|et| Error::new(self.source.clone(), self.ln, et))?
+                        }
                         arg => parse_number(arg)
                             .map_err(|et| Error::new(self.source.clone(), self.ln, et))?,
                     });
@@ -342,14 +433,33 @@ impl SourceLocation {
 pub enum DataLine {
     Ins(Opcode, DataOperand),
     Wide(Wide),
+    Byte(Byte),
     Raw(Vec<u8>),
 }
 
+impl DataLine {
+    fn size(&self) -> u16 {
+        match self {
+            DataLine::Ins(_, dat_op) => 1 + dat_op.size(),
+            DataLine::Wide(_) => 2,
+            DataLine::Byte(_) => 1,
+            DataLine::Raw(bytes) => bytes.len() as u16,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProcessedSource {
-    pub labels: Vec<(Box<str>, SymbolType, SegmentType, u16)>,
+    #[allow(clippy::type_complexity)]
+    pub labels: Vec<(Box<str>, SymbolType, SegmentType, u16, LabelAttributes)>,
     pub dls: BTreeMap<SegmentType, DataLineSegment>,
+    /// Flags explicitly given to segments
+    pub seg_flags: BTreeMap<SegmentType, SegmentFlags>,
     pub entry: Option<Entry>,
+    /// Which source line every line of data came from
+    pub lines: LineTable,
+    /// The source file and all files it included
+    pub sources: Vec<Box<str>>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -361,20 +471,43 @@ pub struct DataLineSegment {
 
 struct ProcessState {
     dls: BTreeMap<SegmentType, DataLineSegment>,
+    seg_flags: BTreeMap<SegmentType, SegmentFlags>,
     pub entry: Option<Address>,
+    /// Locations are relative to the start of their segment
+    lines: LineTable,
+    sources: Vec<Box<str>>,
+    /// The file being processed followed by the files that included it, outermost last
+    include_stack: Vec<Box<str>>,
+    /// Labels sized up to a location with `.size`, measured again after [`relax`]
+    measured: Vec<(usize, Address)>,
 }
 
 impl ProcessState {
     fn new() -> Self {
         Self {
             dls: BTreeMap::new(),
+            seg_flags: BTreeMap::new(),
             entry: None,
+            lines: LineTable::default(),
+            sources: Vec::new(),
+            include_stack: Vec::new(),
+            measured: Vec::new(),
         }
     }
     fn get_size(&self, st: SegmentType) -> u16 {
         self.dls.get(&st).map(|dls| dls.size).unwrap_or(0)
     }
-    fn add_line(&mut self, st: SegmentType, line: DataLine, size: u16) {
+    fn add_line(&mut self, st: SegmentType, line: DataLine, size: u16, src: &str, ln: LineNumber) {
+        if size != 0 {
+            let file = self.lines.file_id(src);
+            self.lines.entries.push(LineEntry {
+                segment: st,
+                location: self.get_size(st),
+                file,
+                line: ln,
+            });
+        }
+
         let dls = self.dls.entry(st).or_default();
         dls.lines.push(line);
         dls.size += size;
@@ -384,6 +517,81 @@ impl ProcessState {
     }
 }
 
+/// Makes the jumps to labels in the same segment short where they reach
+///
+/// Every jump made short brings what comes after it closer, so this goes on until no more can be. The offsets of
+/// the short jumps are only filled in once nothing moves anymore.
+fn relax(
+    dls: &mut BTreeMap<SegmentType, DataLineSegment>,
+    symbols: &mut Symbols,
+    entry: &mut Option<Address>,
+    lines: &mut LineTable,
+    measured: &mut [(usize, Address)],
+) {
+    let short = |line: &DataLine| match *line {
+        DataLine::Ins(op, DataOperand::ImmediateJump(id)) => Some((isa::short_jump(op)?, id)),
+        DataLine::Ins(isa::LDI_W, DataOperand::TwoWideJump(R0, R1, id)) => Some((isa::JMP_S, id)),
+        _ => None,
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (&st, dls) in dls.iter_mut() {
+            let mut position = 0u16;
+            for line in &mut dls.lines {
+                let size = line.size();
+                let end = position + size;
+                position += match short(line) {
+                    Some((op, id)) => match symbols.get_address(id) {
+                        Some(Address(s, target)) if s == st => {
+                            let offset = target as i32 - (position as i32 + 2);
+                            if i8::try_from(offset).is_err() {
+                                size
+                            } else {
+                                *line = DataLine::Ins(op, DataOperand::ShortJump(id, 0));
+                                let by = size - 2;
+                                symbols.shift(st, end, by);
+                                let moved = |Address(s, pos): &mut Address| {
+                                    if *s == st && *pos >= end {
+                                        *pos -= by;
+                                    }
+                                };
+                                entry.as_mut().map(moved);
+                                measured.iter_mut().for_each(|(_, a)| moved(a));
+                                for e in &mut lines.entries {
+                                    if e.segment == st && e.location >= end {
+                                        e.location -= by;
+                                    }
+                                }
+                                dls.size -= by;
+                                changed = true;
+                                2
+                            }
+                        }
+                        _ => size,
+                    },
+                    None => size,
+                };
+            }
+        }
+    }
+
+    for (&st, dls) in dls.iter_mut() {
+        let mut position = 0u16;
+        for line in &mut dls.lines {
+            if let DataLine::Ins(_, DataOperand::ShortJump(id, offset)) = line {
+                let Some(Address(_, target)) = symbols.get_address(*id) else {
+                    unreachable!("short jumps go to defined labels")
+                };
+                *offset = target.wrapping_sub(position + 2) as u8;
+            }
+            position += line.size();
+        }
+        debug_assert_eq!(position, dls.size, "relaxing {st} lost track of its size");
+    }
+}
+
 pub fn process<B: BufRead>(lines: SourceLines<B>) -> Result<ProcessedSource> {
     let mut symbols = Symbols::new();
     let mut state = ProcessState::new();
@@ -392,17 +600,43 @@ pub fn process<B: BufRead>(lines: SourceLines<B>) -> Result<ProcessedSource> {
 
     let mut errors = inner_process(lines, &mut state, &mut symbols);
 
-    let ProcessState { mut dls, entry } = state;
+    let ProcessState {
+        mut dls,
+        seg_flags,
+        mut entry,
+        mut lines,
+        sources,
+        include_stack: _,
+        mut measured,
+    } = state;
+
+    relax(
+        &mut dls,
+        &mut symbols,
+        &mut entry,
+        &mut lines,
+        &mut measured,
+    );
+    for (id, Address(_, end)) in measured {
+        let Address(_, start) = symbols
+            .get_address(id)
+            .expect("measured from a defined label");
+        symbols.set_size(id, end - start);
+    }
 
     let mut last_end = SEGMENT_ALIGNMENT;
     for s in dls.values_mut() {
         s.start = align(last_end, SEGMENT_ALIGNMENT);
         last_end = s.start + s.size;
     }
+    for entry in &mut lines.entries {
+        entry.location += dls.get(&entry.segment).map(|dl| dl.start).unwrap_or(0);
+    }
+    lines.sort();
 
     let mut labels = Vec::with_capacity(symbols.size());
 
-    for (l, st, r) in symbols.into_iter() {
+    for (l, st, r, attrs) in symbols.into_iter() {
         let element;
         use self::SymbolType::*;
 
@@ -432,7 +666,7 @@ pub fn process<B: BufRead>(lines: SourceLines<B>) -> Result<ProcessedSource> {
                 let offset = dls.get(&stype).map(|dl| dl.start).unwrap_or(0);
                 let pos = addr.1 + offset;
 
-                element = (l, st, stype, pos)
+                element = (l, st, stype, pos, attrs)
             }
             Err(e) => {
                 match st {
@@ -450,7 +684,7 @@ pub fn process<B: BufRead>(lines: SourceLines<B>) -> Result<ProcessedSource> {
                         continue;
                     }
                     Reference | Global => {
-                        element = (l, Reference, SegmentType::Unknown, 0xfaff);
+                        element = (l, Reference, SegmentType::Unknown, 0xfaff, attrs);
                     }
                 }
             }
@@ -467,7 +701,14 @@ pub fn process<B: BufRead>(lines: SourceLines<B>) -> Result<ProcessedSource> {
             Entry(addr.0, addr.1 + offset)
         });
 
-        Ok(ProcessedSource { labels, dls, entry })
+        Ok(ProcessedSource {
+            labels,
+            dls,
+            seg_flags,
+            entry,
+            lines,
+            sources,
+        })
     }
 }
 fn inner_process<B: BufRead>(
@@ -476,7 +717,7 @@ fn inner_process<B: BufRead>(
     symbols: &mut Symbols,
 ) -> Option<Error> {
     fn inner_process_line(
-        src: &Box<str>,
+        src: &str,
         ln: u32,
         line: SourceLine,
         current_segment: &mut SegmentType,
@@ -485,57 +726,87 @@ fn inner_process<B: BufRead>(
     ) -> Result<()> {
         match line {
             SourceLine::DirSeg(seg) => {
-                let new_seg = match &*seg {
-                    "data" => SegmentType::Data,
-                    "rodata" => SegmentType::RoData,
-                    "text" => SegmentType::Text,
-                    "heap" => SegmentType::Heap,
-                    seg => {
+                let (seg, flags) = match seg.split_once(' ') {
+                    Some((seg, flags)) => (seg, Some(flags.trim())),
+                    None => (&*seg, None),
+                };
+                let Ok(new_seg) = seg.parse() else {
+                    return Err(Error::new(src, ln, ErrorType::UnknownSegment(seg.into())));
+                };
+
+                if let Some(flags) = flags {
+                    let Some(flags) = SegmentFlags::parse(flags) else {
                         return Err(Error::new(
-                            src.clone(),
+                            src,
                             ln,
-                            ErrorType::UnknownSegment(seg.into()),
-                        ))
+                            ErrorType::Other(
+                                format!("invalid segment flags `{flags}', expected a combination of r, w and x")
+                                    .into_boxed_str(),
+                            ),
+                        ));
+                    };
+                    match state.seg_flags.insert(new_seg, flags) {
+                        Some(old_flags) if old_flags != flags => {
+                            return Err(Error::new(
+                                src,
+                                ln,
+                                ErrorType::Other(
+                                    format!(
+                                        "segment {new_seg} was already given flags {old_flags}"
+                                    )
+                                    .into_boxed_str(),
+                                ),
+                            ));
+                        }
+                        _ => (),
                     }
-                };
+                }
 
                 *current_segment = new_seg;
             }
             SourceLine::DirEntry => {
                 if state.entry.is_some() {
-                    return Err(Error::new(src.clone(), ln, ErrorType::DoubleEntry));
+                    return Err(Error::new(src, ln, ErrorType::DoubleEntry));
                 }
                 state.entry = Some(Address(*current_segment, state.get_size(*current_segment)));
             }
             SourceLine::Label(s) => {
                 let addr = Address(*current_segment, state.get_size(*current_segment));
-                symbols.set_label(&s, addr, SourceLocation::new(&src, ln))?;
+                let id = symbols.set_label(&s, addr, SourceLocation::new(src, ln))?;
+                symbols.set_defined_in(id, state.include_stack.iter().rev().cloned().collect());
             }
             SourceLine::Ins(s, ops) => {
-                let Some((opcode, dat_op)) = parse_ins(&s, ops, symbols, SourceLocation::new(&src, ln))
-                    .map_err(|e| Error::new(src.clone(), ln, ErrorType::IncorrectOperands(e)))?
+                let Some((opcode, dat_op)) = parse_ins(&s, ops, symbols, SourceLocation::new(src, ln))
+                    .map_err(|e| Error::new(src, ln, ErrorType::IncorrectOperands(e)))?
                 else {
-                    return Err(Error::new(src.clone(), ln, ErrorType::UnknownInstruction(s.into_boxed_str())));
+                    return Err(Error::new(src, ln, ErrorType::UnknownInstruction(s.into_boxed_str())));
                 };
                 state.add_line(
                     *current_segment,
                     DataLine::Ins(opcode, dat_op),
                     1 + dat_op.size(),
+                    src,
+                    ln,
                 );
             }
-            SourceLine::DirByte(b) => {
-                state.add_line(*current_segment, DataLine::Raw(vec![b]), 1);
+            SourceLine::DirByte(Ok(b)) => {
+                state.add_line(*current_segment, DataLine::Raw(vec![b]), 1, src, ln);
+            }
+            SourceLine::DirByte(Err((l, kind))) => {
+                let id = symbols.get_label(&l, SourceLocation::new(src, ln));
+                let byte = Byte::Label(id, kind);
+                state.add_line(*current_segment, DataLine::Byte(byte), 1, src, ln);
             }
             SourceLine::DirWide(w) => {
                 let wide = match w {
                     Ok(w) => Wide::Number(w),
-                    Err(l) => Wide::Label(symbols.get_label(&l, SourceLocation::new(&src, ln))),
+                    Err(l) => Wide::Label(symbols.get_label(&l, SourceLocation::new(src, ln))),
                 };
-                state.add_line(*current_segment, DataLine::Wide(wide), 2);
+                state.add_line(*current_segment, DataLine::Wide(wide), 2, src, ln);
             }
             SourceLine::DirString(s) => {
                 let size = s.len() as u16;
-                state.add_line(*current_segment, DataLine::Raw(s), size);
+                state.add_line(*current_segment, DataLine::Raw(s), size, src, ln);
             }
             SourceLine::DirInclude(path) => {
                 let pth_buf;
@@ -543,7 +814,7 @@ fn inner_process<B: BufRead>(
                 let path = if let Some(path) = path.strip_prefix('/') {
                     Path::new(path)
                 } else {
-                    pth_buf = Path::new(&**src).with_file_name("").join(&path);
+                    pth_buf = Path::new(src).with_file_name("").join(&path);
                     &pth_buf
                 };
 
@@ -553,19 +824,47 @@ fn inner_process<B: BufRead>(
                 }
             }
             SourceLine::DirGlobal(l) => {
-                let id = symbols.get_label(&l, SourceLocation::new(&src, ln));
+                let id = symbols.get_label(&l, SourceLocation::new(src, ln));
                 symbols.set_global(id);
             }
             SourceLine::DirReference(l) => {
-                let id = symbols.get_label(&l, SourceLocation::new(&src, ln));
+                let id = symbols.get_label(&l, SourceLocation::new(src, ln));
                 symbols.set_reference(id);
             }
+            SourceLine::DirSize(l, size) => {
+                let id = symbols.get_label(&l, SourceLocation::new(src, ln));
+                let size = match size {
+                    Some(size) => size,
+                    None => match symbols.get_address(id) {
+                        Some(Address(st, pos)) if st == *current_segment => {
+                            let end = state.get_size(st);
+                            state.measured.push((id, Address(st, end)));
+                            end - pos
+                        }
+                        _ => {
+                            return Err(Error::new(
+                                src,
+                                ln,
+                                ErrorType::Other(
+                                    format!("label `{l}' has to be defined earlier in the current segment to get its size")
+                                        .into_boxed_str(),
+                                ),
+                            ))
+                        }
+                    },
+                };
+                symbols.set_size(id, size);
+            }
+            SourceLine::DirType(l, kind) => {
+                let id = symbols.get_label(&l, SourceLocation::new(src, ln));
+                symbols.set_kind(id, kind);
+            }
             SourceLine::Comment => (),
         }
 
         if state.unknown_defined() {
             return Err(Error::new(
-                src.clone(),
+                src,
                 ln,
                 ErrorType::Other("no segment was started".to_string().into_boxed_str()),
             ));
@@ -574,6 +873,8 @@ fn inner_process<B: BufRead>(
         Ok(())
     }
 
+    state.sources.push(lines.source.clone());
+    state.include_stack.push(lines.source.clone());
     let mut current_segment = SegmentType::Unknown;
 
     while let Some((ln, line)) = lines.parse_next_line() {
@@ -589,6 +890,7 @@ fn inner_process<B: BufRead>(
             Err(e) => lines.add_error(e),
         }
     }
+    state.include_stack.pop();
 
     lines.errors
 }
@@ -604,9 +906,22 @@ fn parse_ins(
     let ops = ops.iter();
     Ok(Some(match s {
         "null" => (NULL, O::parse_nothing(ops).ok_or("no operands")?),
-        "halt" => (HALT, O::parse_nothing(ops).ok_or("no operands")?),
+        "halt" => {
+            if let Some(dat_op) = O::parse_nothing(ops.clone()) {
+                (HALT, dat_op)
+            } else if let Some(dat_op) = O::parse_breg(ops) {
+                (HALT_B, dat_op)
+            } else {
+                return Err("no operands or a byte register for the exit code");
+            }
+        }
         "ctf" => (CTF, O::parse_nothing(ops).ok_or("no operands")?),
         "reth" => (RETH, O::parse_nothing(ops).ok_or("no operands")?),
+        "int" => (INT, O::parse_imm_byte(ops).ok_or("a byte for the vector")?),
+        "ei" => (EI, O::parse_nothing(ops).ok_or("no operands")?),
+        "di" => (DI, O::parse_nothing(ops).ok_or("no operands")?),
+        "reti" => (RETI, O::parse_nothing(ops).ok_or("no operands")?),
+        "ivt" => (IVT, O::parse_wreg(ops).ok_or("one wide register")?),
         "nop" => (NOP, O::parse_nothing(ops).ok_or("no operands")?),
         "push" => {
             if let Some(dat_op) = O::parse_breg(ops.clone()) {
@@ -626,10 +941,28 @@ fn parse_ins(
                 return Err("one register");
             }
         }
-        "call" => (
-            CALL,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+        "enter" => (
+            ENTER,
+            O::parse_imm_wide(ops, sym, sl).ok_or("a wide for the size of the locals")?,
         ),
+        "leave" => (LEAVE, O::parse_nothing(ops).ok_or("no operands")?),
+        "pushm" => (
+            PUSHM,
+            O::parse_reg_mask(ops).ok_or("wide registers other than rs")?,
+        ),
+        "popm" => (
+            POPM,
+            O::parse_reg_mask(ops).ok_or("wide registers other than rs")?,
+        ),
+        "call" => {
+            if let Some(dat_op) = O::parse_imm_wide(ops.clone(), sym, sl) {
+                (CALL, dat_op)
+            } else if let Some(dat_op) = O::parse_wreg(ops) {
+                (CALL_R, dat_op)
+            } else {
+                return Err("address or wide register");
+            }
+        }
         "ret" => (
             RET,
             O::parse_nothing(ops.clone())
@@ -650,6 +983,23 @@ fn parse_ins(
                 return Err("a wide and another wide or immediate for destination and a source register (any size)");
             }
         }
+        "btst" => parse_bit([BTST_B, BTST_W], ops)?,
+        "bset" => parse_bit([BSET_B, BSET_W], ops)?,
+        "bclr" => parse_bit([BCLR_B, BCLR_W], ops)?,
+        "btgl" => parse_bit([BTGL_B, BTGL_W], ops)?,
+        "mcopy" => (
+            MCOPY,
+            O::parse_three_wide(ops).ok_or("three wide registers")?,
+        ),
+        "mfill" => (
+            MFILL,
+            O::parse_fill(ops).ok_or("a wide, a byte and a wide register")?,
+        ),
+        "lea" => (
+            LEA,
+            O::parse_two_wide_imm(ops, sym, sl)
+                .ok_or("a destination and a wide register and then a wide")?,
+        ),
         "load" => {
             if let Some(dat_op) = O::parse_byte_wide_imm(ops.clone(), sym, sl.clone()) {
                 (LOAD_BI, dat_op)
@@ -665,55 +1015,55 @@ fn parse_ins(
         }
         "jez" => (
             JEZ,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jlt" => (
             JLT,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jle" => (
             JLE,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jgt" => (
             JGT,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jge" => (
             JGE,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jnz" | "jne" => (
             JNZ,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jo" => (
             JO,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jno" => (
             JNO,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jb" | "jc" => (
             JB,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jae" | "jnc" => (
             JAE,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "ja" => (
             JA,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
         "jbe" => (
             JBE,
-            O::parse_imm_wide(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
+            O::parse_jump(ops, sym, sl).ok_or("a wide (addr like a label or just a number)")?,
         ),
 
         "ldi" => {
-            if let Some(dat_op) = O::parse_byte_imm(ops.clone()) {
+            if let Some(dat_op) = O::parse_byte_imm(ops.clone(), sym, sl.clone()) {
                 (LDI_B, dat_op)
             } else if let Some(dat_op) = O::parse_wide_imm(ops.clone(), sym, sl) {
                 let DataOperand::WideImm(r, w) = dat_op else { unreachable!() };
@@ -724,10 +1074,12 @@ fn parse_ins(
             }
         }
         "jmp" | "jump" => {
-            if let Some(dat_op) = O::parse_imm_wide(ops.clone(), sym, sl) {
-                let DataOperand::ImmediateWide(w) = dat_op else { unreachable!() };
-
-                (LDI_W, DataOperand::TwoWideImm(R0, R1, w))
+            if let Some(dat_op) = O::parse_jump(ops.clone(), sym, sl) {
+                match dat_op {
+                    DataOperand::ImmediateWide(w) => (LDI_W, DataOperand::TwoWideImm(R0, R1, w)),
+                    DataOperand::ImmediateJump(id) => (LDI_W, DataOperand::TwoWideJump(R0, R1, id)),
+                    _ => unreachable!(),
+                }
             } else if let Some(dat_op) = O::parse_wreg(ops) {
                 let DataOperand::WideRegister(wr) = dat_op else { unreachable!() };
                 if wr == R0 {
@@ -739,14 +1091,46 @@ fn parse_ins(
             }
         }
 
+        "add" | "sub" | "and" | "or" | "xor" | "shl" | "asr" | "sar" | "lsr" | "shr" | "cmp"
+        | "test" | "mul" | "div" | "movsx" | "movzx"
+            if ops
+                .clone()
+                .any(|op| matches!(op, SourceOperand::RegisterPair(_))) =>
+        {
+            parse_long(s, ops)?
+        }
         "add" => parse_binop(ADD_B, ADD_W, ops)?,
         "sub" => parse_binop(SUB_B, SUB_W, ops)?,
+        "adc" => parse_binop(ADC_B, ADC_W, ops)?,
+        "sbb" => parse_binop(SBB_B, SBB_W, ops)?,
         "and" => parse_binop(AND_B, AND_W, ops)?,
         "or" => parse_binop(OR_B, OR_W, ops)?,
         "xor" => parse_binop(XOR_B, XOR_W, ops)?,
-        "shl" => parse_binop(SHL_B, SHL_W, ops)?,
-        "asr" => parse_binop(ASR_B, ASR_W, ops)?,
-        "lsr" => parse_binop(LSR_B, LSR_W, ops)?,
+        "shl" => parse_shift([SHL_B, SHL_W, SHL_BI, SHL_WI], ops)?,
+        "asr" | "sar" => parse_shift([ASR_B, ASR_W, ASR_BI, ASR_WI], ops)?,
+        "lsr" | "shr" => parse_shift([LSR_B, LSR_W, LSR_BI, LSR_WI], ops)?,
+        "rol" => parse_shift([ROL_B, ROL_W, ROL_BI, ROL_WI], ops)?,
+        "ror" => parse_shift([ROR_B, ROR_W, ROR_BI, ROR_WI], ops)?,
+        "rcl" => parse_shift([RCL_B, RCL_W, RCL_BI, RCL_WI], ops)?,
+        "rcr" => parse_shift([RCR_B, RCR_W, RCR_BI, RCR_WI], ops)?,
+        "cmp" => parse_compare([CMP_B, CMP_W, SUB_B, SUB_W], ops, sym, sl)?,
+        "test" => parse_compare([TEST_B, TEST_W, AND_B, AND_W], ops, sym, sl)?,
+        "movsx" => (
+            MOVSX,
+            O::parse_wide_byte(ops).ok_or("a wide and a byte register")?,
+        ),
+        "movzx" => (
+            MOVZX,
+            O::parse_wide_byte(ops).ok_or("a wide and a byte register")?,
+        ),
+        "xchg" if ops.len() == 2 => parse_unary(XCHG_B, XCHG_W, ops)?,
+        "xchg" => return Err("two regs of same size"),
+        "bswap" => (
+            BSWAP,
+            O::parse_two_wide(ops).ok_or("one or two wide registers")?,
+        ),
+        "not" => parse_unary(NOT_B, NOT_W, ops)?,
+        "neg" => parse_unary(NEG_B, NEG_W, ops)?,
         "mul" => {
             if let Some(dat_op) = O::parse_four_byte(ops.clone()) {
                 (MUL_B, dat_op)
@@ -765,6 +1149,40 @@ fn parse_ins(
                 return Err("four registers");
             }
         }
+        "xadd" if ops.len() == 2 => (XADD, O::parse_two_wide(ops).ok_or("two wide registers")?),
+        "xadd" => return Err("two wide registers"),
+        "cas" => (CAS, O::parse_three_wide(ops).ok_or("three wide registers")?),
+        "mulh" => (
+            MULH,
+            O::parse_three_wide(ops).ok_or("three wide registers")?,
+        ),
+        "imulh" => (
+            IMULH,
+            O::parse_three_wide(ops).ok_or("three wide registers")?,
+        ),
+        "fmul" => (
+            FMUL,
+            O::parse_three_wide_imm_byte(ops)
+                .ok_or("three wide registers and the number of fraction bits")?,
+        ),
+        "imul" => {
+            if let Some(dat_op) = O::parse_four_byte(ops.clone()) {
+                (IMUL_B, dat_op)
+            } else if let Some(dat_op) = O::parse_four_wide(ops) {
+                (IMUL_W, dat_op)
+            } else {
+                return Err("four registers");
+            }
+        }
+        "idiv" => {
+            if let Some(dat_op) = O::parse_four_byte(ops.clone()) {
+                (IDIV_B, dat_op)
+            } else if let Some(dat_op) = O::parse_four_wide(ops) {
+                (IDIV_W, dat_op)
+            } else {
+                return Err("four registers");
+            }
+        }
         // TODO: BAD
         _ => {
             return Ok(None);
@@ -786,6 +1204,116 @@ fn parse_binop(
     }
 }
 
+/// An operation on register pairs, where `r0` is the zero pair
+fn parse_long(s: &str, ops: Iter<SourceOperand>) -> StdResult<(u8, DataOperand), &'static str> {
+    use self::isa::*;
+    use self::DataOperand as O;
+    let (op, imm_op) = match s {
+        "add" => (ADD_L, None),
+        "sub" => (SUB_L, None),
+        "and" => (AND_L, None),
+        "or" => (OR_L, None),
+        "xor" => (XOR_L, None),
+        "shl" => (SHL_L, Some(SHL_LI)),
+        "asr" | "sar" => (ASR_L, Some(ASR_LI)),
+        "lsr" | "shr" => (LSR_L, Some(LSR_LI)),
+        "cmp" | "test" => {
+            let op = if s == "cmp" { SUB_L } else { AND_L };
+            return match O::parse_two_long(ops) {
+                Some(O::TwoWide(r1, r2)) => Ok((op, O::ThreeWide(R0, r1, r2))),
+                _ => Err("two register pairs"),
+            };
+        }
+        "mul" => return Ok((MUL_L, O::parse_four_long(ops).ok_or("four register pairs")?)),
+        "div" => return Ok((DIV_L, O::parse_four_long(ops).ok_or("four register pairs")?)),
+        "movsx" | "movzx" => {
+            let op = if s == "movsx" { MOVSX_L } else { MOVZX_L };
+            let dat_op = O::parse_long_wide(ops).ok_or("a register pair and a wide register")?;
+            return Ok((op, dat_op));
+        }
+        _ => unreachable!("only called for the operations register pairs have"),
+    };
+    if let Some(dat_op) = O::parse_three_long(ops.clone()) {
+        Ok((op, dat_op))
+    } else if let (Some(imm_op), Some(dat_op)) = (imm_op, O::parse_two_long_imm_byte(ops)) {
+        Ok((imm_op, dat_op))
+    } else if imm_op.is_some() {
+        Err("three register pairs, or two and a byte")
+    } else {
+        Err("three register pairs")
+    }
+}
+
+/// A register and an immediate of its size, or two registers for the binary operation into the zero register
+fn parse_compare(
+    [bop, wop, binop_b, binop_w]: [u8; 4],
+    ops: Iter<SourceOperand>,
+    sym: &mut Symbols,
+    sl: SourceLocation,
+) -> StdResult<(u8, DataOperand), &'static str> {
+    use self::DataOperand as O;
+    let two = ops.len() == 2;
+    if let (true, Some(O::TwoByte(r1, r2))) = (two, O::parse_two_byte(ops.clone())) {
+        Ok((binop_b, O::ThreeByte(R0B, r1, r2)))
+    } else if let (true, Some(O::TwoWide(r1, r2))) = (two, O::parse_two_wide(ops.clone())) {
+        Ok((binop_w, O::ThreeWide(R0, r1, r2)))
+    } else if let Some(dat_op) = O::parse_byte_imm(ops.clone(), sym, sl.clone()) {
+        Ok((bop, dat_op))
+    } else if let Some(dat_op) = O::parse_wide_imm(ops, sym, sl) {
+        Ok((wop, dat_op))
+    } else {
+        Err("one register and one immediate, or two registers")
+    }
+}
+
+/// A register and the index of one of its bits, encoded like a register with a zero one and a byte
+fn parse_bit(
+    [bop, wop]: [u8; 2],
+    ops: Iter<SourceOperand>,
+) -> StdResult<(u8, DataOperand), &'static str> {
+    use self::DataOperand as O;
+    let err = "a register and the index of a bit";
+    let mut it = ops.clone();
+    let (Some(reg), Some(n), None) = (it.next(), it.next(), it.next()) else {
+        return Err(err);
+    };
+    let n = O::imm_byte(n).ok_or(err)?;
+    match *reg {
+        SourceOperand::ByteReg(r) => Ok((bop, O::TwoByteImmByte(r, R0B, n))),
+        SourceOperand::WideReg(r) => Ok((wop, O::TwoWideImmByte(r, R0, n))),
+        _ => Err(err),
+    }
+}
+
+/// An operation with a destination and a source register, or one register that is both
+fn parse_unary(
+    bop: u8,
+    wop: u8,
+    ops: Iter<SourceOperand>,
+) -> StdResult<(u8, DataOperand), &'static str> {
+    if let Some(dat_op) = DataOperand::parse_two_byte(ops.clone()) {
+        Ok((bop, dat_op))
+    } else if let Some(dat_op) = DataOperand::parse_two_wide(ops) {
+        Ok((wop, dat_op))
+    } else {
+        Err("one or two regs of same size")
+    }
+}
+
+/// A binary operation with three registers, or two and an immediate byte like a shift or rotate amount
+fn parse_shift(
+    [bop, wop, biop, wiop]: [u8; 4],
+    ops: Iter<SourceOperand>,
+) -> StdResult<(u8, DataOperand), &'static str> {
+    if let Some(dat_op) = DataOperand::parse_two_byte_imm_byte(ops.clone()) {
+        Ok((biop, dat_op))
+    } else if let Some(dat_op) = DataOperand::parse_two_wide_imm_byte(ops.clone()) {
+        Ok((wiop, dat_op))
+    } else {
+        parse_binop(bop, wop, ops).map_err(|_| "three regs of same size, or two and a byte")
+    }
+}
+
 fn parse_wide<F: FnOnce(usize, LabelRead) -> u16>(
     w: Wide,
     read_label: F,
@@ -793,11 +1321,59 @@ fn parse_wide<F: FnOnce(usize, LabelRead) -> u16>(
     position: u16,
 ) -> u16 {
     match w {
-        Wide::Label(l) => read_label(l, LabelRead { segment, position }),
+        Wide::Label(l) => read_label(
+            l,
+            LabelRead {
+                segment,
+                position,
+                kind: RelocationKind::Wide,
+            },
+        ),
         Wide::Number(n) => n,
     }
 }
 
+/// The address of the label a long jump goes to, read so the linker may make it short
+fn jump_target<F: FnOnce(usize, LabelRead) -> u16>(
+    id: usize,
+    read_label: F,
+    segment: SegmentType,
+    position: u16,
+) -> u16 {
+    read_label(
+        id,
+        LabelRead {
+            segment,
+            position,
+            kind: RelocationKind::Jump,
+        },
+    )
+}
+
+fn parse_byte<F: FnOnce(usize, LabelRead) -> u16>(
+    b: Byte,
+    read_label: F,
+    segment: SegmentType,
+    position: u16,
+) -> u8 {
+    match b {
+        Byte::Label(l, kind) => {
+            let address = read_label(
+                l,
+                LabelRead {
+                    segment,
+                    position,
+                    kind,
+                },
+            );
+            let mut bytes = [0; 2];
+            kind.apply(&mut bytes, address, position);
+            bytes[0]
+        }
+        Byte::Number(n) => n,
+    }
+}
+
 pub fn write_data_operand<F: FnOnce(usize, LabelRead) -> u16>(
     st: SegmentType,
     mem: &mut Vec<u8>,
@@ -819,7 +1395,8 @@ pub fn write_data_operand<F: FnOnce(usize, LabelRead) -> u16>(
         }
         ByteImm(r, b) => {
             mem.push(r.0.pair(U4::ZERO));
-            mem.push(b);
+            let position = mem.len() as u16;
+            mem.push(parse_byte(b, read_label, st, position));
         }
         WideImm(r, w) => {
             mem.push(r.0.pair(U4::ZERO));
@@ -854,10 +1431,21 @@ pub fn write_data_operand<F: FnOnce(usize, LabelRead) -> u16>(
             mem.push(r1.0.pair(r2.0));
             mem.push(r3.0.pair(U4::ZERO));
         }
+        WideByte(r1, r2) => mem.push(r1.0.pair(r2.0)),
+        TwoByte(r1, r2) => mem.push(r1.0.pair(r2.0)),
+        TwoWide(r1, r2) => mem.push(r1.0.pair(r2.0)),
         ThreeByte(r1, r2, r3) => {
             mem.push(r1.0.pair(r2.0));
             mem.push(r3.0.pair(U4::ZERO));
         }
+        TwoByteImmByte(r1, r2, b) => {
+            mem.push(r1.0.pair(r2.0));
+            mem.push(b);
+        }
+        TwoWideImmByte(r1, r2, b) => {
+            mem.push(r1.0.pair(r2.0));
+            mem.push(b);
+        }
         ThreeWide(r1, r2, r3) => {
             mem.push(r1.0.pair(r2.0));
             mem.push(r3.0.pair(U4::ZERO));
@@ -870,6 +1458,32 @@ pub fn write_data_operand<F: FnOnce(usize, LabelRead) -> u16>(
             mem.push(r1.0.pair(r2.0));
             mem.push(r3.0.pair(r4.0));
         }
+        ThreeWideImmByte(r1, r2, r3, b) => {
+            mem.push(r1.0.pair(r2.0));
+            mem.push(r3.0.pair(U4::ZERO));
+            mem.push(b);
+        }
+        ImmediateJump(id) => {
+            let position = mem.len() as u16;
+            mem.extend_from_slice(&jump_target(id, read_label, st, position).to_le_bytes());
+        }
+        TwoWideJump(r1, r2, id) => {
+            mem.push(r1.0.pair(r2.0));
+            let position = mem.len() as u16;
+            mem.extend_from_slice(&jump_target(id, read_label, st, position).to_le_bytes());
+        }
+        ShortJump(id, offset) => {
+            let position = mem.len() as u16;
+            read_label(
+                id,
+                LabelRead {
+                    segment: st,
+                    position,
+                    kind: RelocationKind::Relative,
+                },
+            );
+            mem.push(offset);
+        }
     }
 }
 
@@ -879,6 +1493,13 @@ pub enum Wide {
     Label(usize),
 }
 
+/// A byte operand which can be part of the address of a label
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Byte {
+    Number(u8),
+    Label(usize, RelocationKind),
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DataOperand {
     Nothing,
@@ -886,7 +1507,7 @@ pub enum DataOperand {
     WideRegister(WReg),
     ImmediateByte(u8),
     ImmediateWide(Wide),
-    ByteImm(BReg, u8),
+    ByteImm(BReg, Byte),
     WideImm(WReg, Wide),
     WideImmByte(WReg, Wide, BReg),
     WideImmWide(WReg, Wide, WReg),
@@ -894,10 +1515,22 @@ pub enum DataOperand {
     ByteWideImm(BReg, WReg, Wide),
     TwoWideImm(WReg, WReg, Wide),
     ByteTwoWide(BReg, WReg, WReg),
+    WideByte(WReg, BReg),
+    TwoByte(BReg, BReg),
+    TwoWide(WReg, WReg),
     ThreeByte(BReg, BReg, BReg),
     ThreeWide(WReg, WReg, WReg),
+    TwoByteImmByte(BReg, BReg, u8),
+    TwoWideImmByte(WReg, WReg, u8),
     FourByte(BReg, BReg, BReg, BReg),
     FourWide(WReg, WReg, WReg, WReg),
+    ThreeWideImmByte(WReg, WReg, WReg, u8),
+    /// The label a jump goes to, which [`relax`] makes short if it is close enough
+    ImmediateJump(usize),
+    /// The label `jmp` loads into the program counter
+    TwoWideJump(WReg, WReg, usize),
+    /// The label a short jump goes to and how far that is from the end of the jump
+    ShortJump(usize, u8),
 }
 
 impl DataOperand {
@@ -917,10 +1550,19 @@ impl DataOperand {
             ByteWideImm(_, _, _) => 3,
             TwoWideImm(_, _, _) => 3,
             ByteTwoWide(_, _, _) => 2,
+            WideByte(_, _) => 1,
+            TwoByte(_, _) => 1,
+            TwoWide(_, _) => 1,
             ThreeByte(_, _, _) => 2,
             ThreeWide(_, _, _) => 2,
+            TwoByteImmByte(_, _, _) => 2,
+            TwoWideImmByte(_, _, _) => 2,
             FourByte(_, _, _, _) => 2,
             FourWide(_, _, _, _) => 2,
+            ThreeWideImmByte(_, _, _, _) => 3,
+            ImmediateJump(_) => 2,
+            TwoWideJump(_, _, _) => 3,
+            ShortJump(_, _) => 1,
         }
     }
     fn parse_nothing<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
@@ -940,6 +1582,17 @@ impl DataOperand {
         Self::parse_nothing(ops)?;
         Some(DataOperand::WideRegister(wreg))
     }
+    /// The wide registers as the mask of `pushm` and `popm`
+    fn parse_reg_mask<'a>(ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
+        let mut mask = 0u16;
+        for op in ops {
+            match Self::wide(op)? {
+                RS => return None,
+                r => mask |= 1 << u8::from(r.0),
+            }
+        }
+        (mask != 0).then_some(DataOperand::ImmediateWide(Wide::Number(mask)))
+    }
     fn parse_imm_byte<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
         let ret = Some(DataOperand::ImmediateByte(Self::imm_byte(ops.next()?)?));
         Self::parse_nothing(ops)?;
@@ -958,12 +1611,27 @@ impl DataOperand {
         Self::parse_nothing(ops)?;
         ret
     }
-    fn parse_byte_imm<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
+    /// Like [`Self::parse_imm_wide`], but a label is kept apart for [`relax`]
+    fn parse_jump<'a>(
+        ops: impl Iterator<Item = &'a SourceOperand>,
+        sym: &mut Symbols,
+        sl: SourceLocation,
+    ) -> Option<DataOperand> {
+        match Self::parse_imm_wide(ops, sym, sl)? {
+            DataOperand::ImmediateWide(Wide::Label(id)) => Some(DataOperand::ImmediateJump(id)),
+            dat_op => Some(dat_op),
+        }
+    }
+    fn parse_byte_imm<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+        sym: &mut Symbols,
+        sl: SourceLocation,
+    ) -> Option<DataOperand> {
         let reg1 = ops.next()?;
         let imm = ops.next()?;
         Some(DataOperand::ByteImm(
             Self::byte(reg1)?,
-            Self::imm_byte(imm)?,
+            Self::imm_byte_label(imm, sym, sl)?,
         ))
     }
     fn parse_wide_imm<'a>(
@@ -978,6 +1646,33 @@ impl DataOperand {
             Self::imm_wide(imm, sym, sl)?,
         ))
     }
+    fn parse_wide_byte<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::wide(ops.next()?)?;
+        let reg2 = Self::byte(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::WideByte(reg1, reg2))
+    }
+    /// Two registers, or one that is both
+    fn parse_two_byte<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
+        let reg1 = Self::byte(ops.next()?)?;
+        let reg2 = match ops.next() {
+            Some(op) => Self::byte(op)?,
+            None => reg1,
+        };
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoByte(reg1, reg2))
+    }
+    fn parse_two_wide<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
+        let reg1 = Self::wide(ops.next()?)?;
+        let reg2 = match ops.next() {
+            Some(op) => Self::wide(op)?,
+            None => reg1,
+        };
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoWide(reg1, reg2))
+    }
     fn parse_three_byte<'a>(
         mut ops: impl Iterator<Item = &'a SourceOperand>,
     ) -> Option<DataOperand> {
@@ -1002,6 +1697,32 @@ impl DataOperand {
             Self::wide(reg3)?,
         ))
     }
+    /// The destination, value and length of `mfill`, with the value encoded last
+    fn parse_fill<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
+        let dst = Self::wide(ops.next()?)?;
+        let val = Self::byte(ops.next()?)?;
+        let len = Self::wide(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoWideOneByte(dst, len, val))
+    }
+    fn parse_two_byte_imm_byte<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::byte(ops.next()?)?;
+        let reg2 = Self::byte(ops.next()?)?;
+        let imm = Self::imm_byte(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoByteImmByte(reg1, reg2, imm))
+    }
+    fn parse_two_wide_imm_byte<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::wide(ops.next()?)?;
+        let reg2 = Self::wide(ops.next()?)?;
+        let imm = Self::imm_byte(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoWideImmByte(reg1, reg2, imm))
+    }
     fn parse_wide_imm_byte<'a>(
         mut ops: impl Iterator<Item = &'a SourceOperand>,
         sym: &mut Symbols,
@@ -1062,6 +1783,16 @@ impl DataOperand {
             Self::wide(ops.next()?)?,
         ))
     }
+    fn parse_three_wide_imm_byte<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::wide(ops.next()?)?;
+        let reg2 = Self::wide(ops.next()?)?;
+        let reg3 = Self::wide(ops.next()?)?;
+        let imm = Self::imm_byte(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::ThreeWideImmByte(reg1, reg2, reg3, imm))
+    }
     fn parse_four_byte<'a>(
         mut ops: impl Iterator<Item = &'a SourceOperand>,
     ) -> Option<DataOperand> {
@@ -1093,6 +1824,50 @@ impl DataOperand {
         ))
     }
 
+    /// Register pairs are encoded like wide registers by their upper half
+    fn parse_two_long<'a>(mut ops: impl Iterator<Item = &'a SourceOperand>) -> Option<DataOperand> {
+        let reg1 = Self::long(ops.next()?)?;
+        let reg2 = Self::long(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoWide(reg1, reg2))
+    }
+    fn parse_three_long<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::long(ops.next()?)?;
+        let reg2 = Self::long(ops.next()?)?;
+        let reg3 = Self::long(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::ThreeWide(reg1, reg2, reg3))
+    }
+    fn parse_two_long_imm_byte<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::long(ops.next()?)?;
+        let reg2 = Self::long(ops.next()?)?;
+        let imm = Self::imm_byte(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoWideImmByte(reg1, reg2, imm))
+    }
+    fn parse_four_long<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::long(ops.next()?)?;
+        let reg2 = Self::long(ops.next()?)?;
+        let reg3 = Self::long(ops.next()?)?;
+        let reg4 = Self::long(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::FourWide(reg1, reg2, reg3, reg4))
+    }
+    fn parse_long_wide<'a>(
+        mut ops: impl Iterator<Item = &'a SourceOperand>,
+    ) -> Option<DataOperand> {
+        let reg1 = Self::long(ops.next()?)?;
+        let reg2 = Self::wide(ops.next()?)?;
+        Self::parse_nothing(ops)?;
+        Some(DataOperand::TwoWide(reg1, reg2))
+    }
+
     fn byte(op: &SourceOperand) -> Option<BReg> {
         match op {
             &SourceOperand::ByteReg(r) => Some(r),
@@ -1105,6 +1880,14 @@ impl DataOperand {
             _ => None,
         }
     }
+    /// The upper half of a register pair, or `r0` for the zero pair
+    fn long(op: &SourceOperand) -> Option<WReg> {
+        match *op {
+            SourceOperand::RegisterPair(r) => Some(r),
+            SourceOperand::WideReg(R0) => Some(R0),
+            _ => None,
+        }
+    }
     fn imm_byte(op: &SourceOperand) -> Option<u8> {
         match *op {
             SourceOperand::Number(n) => Some(n as u8),
@@ -1112,6 +1895,12 @@ impl DataOperand {
             _ => None,
         }
     }
+    fn imm_byte_label(op: &SourceOperand, sym: &mut Symbols, sl: SourceLocation) -> Option<Byte> {
+        match op {
+            SourceOperand::LabelByte(lbl, kind) => Some(Byte::Label(sym.get_label(lbl, sl), *kind)),
+            op => Self::imm_byte(op).map(Byte::Number),
+        }
+    }
     fn imm_wide(op: &SourceOperand, sym: &mut Symbols, sl: SourceLocation) -> Option<Wide> {
         match op {
             &SourceOperand::Number(n) => Some(Wide::Number(n as u16)),
@@ -3,7 +3,7 @@ use std::{
     iter, mem,
 };
 
-use crate::aalv::obj::SegmentType;
+use crate::aalv::obj::{RelocationKind, SegmentType, SymbolKind};
 
 use super::{Error, ErrorType, Result as SourceResult, SourceLocation};
 
@@ -19,6 +19,7 @@ impl Display for Address {
 pub struct LabelRead {
     pub segment: SegmentType,
     pub position: u16,
+    pub kind: RelocationKind,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -48,10 +49,20 @@ impl SymbolType {
     }
 }
 
+/// Extra information about a label that ends up in the symbol table
+#[derive(Debug, Clone, Default)]
+pub struct LabelAttributes {
+    pub kind: SymbolKind,
+    pub size: Option<u16>,
+    /// The file the label was defined in followed by the files that included it
+    pub defined_in: Vec<Box<str>>,
+}
+
 pub(super) struct Symbols {
     labels: Vec<Box<str>>,
     id_to_pos: Vec<Result<Address, Vec<SourceLocation>>>,
     symbol_types: Vec<SymbolType>,
+    attributes: Vec<LabelAttributes>,
 }
 
 impl Symbols {
@@ -60,6 +71,7 @@ impl Symbols {
             labels: Vec::new(),
             symbol_types: Vec::new(),
             id_to_pos: Vec::new(),
+            attributes: Vec::new(),
         }
     }
     fn find_id(&mut self, lbl: &str) -> usize {
@@ -72,7 +84,12 @@ impl Symbols {
             i
         }
     }
-    pub fn set_label(&mut self, lbl: &str, addr: Address, loc: SourceLocation) -> SourceResult<()> {
+    pub fn set_label(
+        &mut self,
+        lbl: &str,
+        addr: Address,
+        loc: SourceLocation,
+    ) -> SourceResult<usize> {
         let id = self.find_id(lbl);
 
         match mem::replace(&mut self.id_to_pos[id], Ok(addr)) {
@@ -84,7 +101,7 @@ impl Symbols {
                         .into_boxed_str(),
                 ),
             )),
-            Err(_) => Ok(()),
+            Err(_) => Ok(id),
         }
     }
     pub fn get_label(&mut self, lbl: &str, loc: SourceLocation) -> usize {
@@ -108,12 +125,46 @@ impl Symbols {
         }
         self.symbol_types[id].set_reference();
     }
+    pub fn get_address(&self, id: usize) -> Option<Address> {
+        self.id_to_pos[id].as_ref().ok().copied()
+    }
+    /// Moves the labels at or after `from` in the segment `by` bytes closer to its start
+    pub fn shift(&mut self, segment: SegmentType, from: u16, by: u16) {
+        for Address(st, pos) in self.id_to_pos.iter_mut().flatten() {
+            if *st == segment && *pos >= from {
+                *pos -= by;
+            }
+        }
+    }
+    fn attributes_mut(&mut self, id: usize) -> &mut LabelAttributes {
+        if id >= self.attributes.len() {
+            self.attributes.resize(id + 1, Default::default());
+        }
+        &mut self.attributes[id]
+    }
+    pub fn set_kind(&mut self, id: usize, kind: SymbolKind) {
+        self.attributes_mut(id).kind = kind;
+    }
+    pub fn set_size(&mut self, id: usize, size: u16) {
+        self.attributes_mut(id).size = Some(size);
+    }
+    pub fn set_defined_in(&mut self, id: usize, defined_in: Vec<Box<str>>) {
+        self.attributes_mut(id).defined_in = defined_in;
+    }
     pub fn size(&self) -> usize {
         self.labels.len()
     }
+    #[allow(clippy::type_complexity)]
     pub fn into_iter(
         self,
-    ) -> impl Iterator<Item = (Box<str>, SymbolType, Result<Address, Vec<SourceLocation>>)> {
+    ) -> impl Iterator<
+        Item = (
+            Box<str>,
+            SymbolType,
+            Result<Address, Vec<SourceLocation>>,
+            LabelAttributes,
+        ),
+    > {
         self.labels
             .into_iter()
             .zip(
@@ -122,6 +173,11 @@ impl Symbols {
                     .chain(iter::repeat(SymbolType::default()))
                     .zip(self.id_to_pos),
             )
-            .map(|(a, (b, c))| (a, b, c))
+            .zip(
+                self.attributes
+                    .into_iter()
+                    .chain(iter::repeat(Default::default())),
+            )
+            .map(|((a, (b, c)), d)| (a, b, c, d))
     }
 }
@@ -0,0 +1,150 @@
+//! Bindings for running binaries from JavaScript, for a playground in the browser
+//!
+//! The machine gets the standard devices with a keyboard, a gamepad and a display, stdin is fed with [`Emulator::input`] and
+//! stdout is collected for [`Emulator::take_output`]. There are no host files.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Cursor},
+    sync::mpsc::{channel, Sender},
+};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    aalv::obj::Object,
+    cpu::{TrapMode, WideRegister},
+    devices::{self, Bus, Buttons, Display, Gamepad, Keyboard},
+    machine::Machine,
+    mem::Io,
+    U4,
+};
+
+/// Stdin and stdout kept in memory, reading gives 0 when there is no input since a page cannot wait for it
+#[derive(Debug, Default)]
+struct Buffered {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Io for Buffered {
+    fn read(&mut self, _addr: u8) -> u8 {
+        self.input.pop_front().unwrap_or(0)
+    }
+    fn write(&mut self, _addr: u8, val: u8) {
+        self.output.push(val);
+    }
+}
+
+fn register(n: u8) -> Result<WideRegister, JsError> {
+    (n < 16)
+        .then(|| WideRegister(U4::new(n)))
+        .ok_or_else(|| JsError::new("registers go from 0 to 15"))
+}
+
+#[wasm_bindgen]
+pub struct Emulator {
+    machine: Machine<Bus<Buffered>>,
+    keys: Sender<u8>,
+    buttons: Sender<Buttons>,
+    ended: Option<TrapMode>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Loads the bytes of a binary, like from a `.to` file
+    #[wasm_bindgen(constructor)]
+    pub fn new(binary: &[u8]) -> Result<Emulator, JsError> {
+        let obj = Object::from_reader(Cursor::new(binary))?;
+        if obj.entry.is_none() {
+            return Err(JsError::new("no entry point in binary"));
+        }
+        let (keys, received) = channel();
+        let (buttons, pressed) = channel();
+        let mut bus = devices::standard(Buffered::default());
+        bus.attach(devices::KEYBOARD_PORT, 2, Keyboard::new(received));
+        bus.attach(devices::GAMEPAD_PORT, 2, Gamepad::new(pressed));
+        bus.attach_on_line(
+            devices::DISPLAY_PORT,
+            3,
+            devices::DISPLAY_LINE,
+            Display::new(io::sink()),
+        );
+        Ok(Emulator {
+            machine: Machine::with_io(obj, bus),
+            keys,
+            buttons,
+            ended: None,
+        })
+    }
+
+    /// Runs one instruction, giving the name of the trap the program ended with if it did, like `Halt`
+    pub fn step(&mut self) -> Option<String> {
+        if self.ended.is_none() {
+            self.ended = self.machine.step().err();
+        }
+        self.ended.map(|tm| format!("{tm:?}"))
+    }
+    /// Runs at most `max_steps` instructions, so that the page can draw in between
+    pub fn run(&mut self, max_steps: u32) -> Option<String> {
+        for _ in 0..max_steps {
+            if let Some(end) = self.step() {
+                return Some(end);
+            }
+        }
+        None
+    }
+    /// What the program exited with if it halted
+    pub fn exit_code(&self) -> Option<u8> {
+        (self.ended == Some(TrapMode::Halt)).then(|| self.machine.registers().exit_code)
+    }
+
+    /// The wide register from 0 (`r0`) to 15 (`rh`)
+    pub fn register(&self, n: u8) -> Result<u16, JsError> {
+        Ok(self.machine.registers().read_wide(register(n)?))
+    }
+    pub fn set_register(&mut self, n: u8, val: u16) -> Result<(), JsError> {
+        self.machine.registers_mut().write_wide(register(n)?, val);
+        Ok(())
+    }
+    pub fn pc(&self) -> u16 {
+        self.machine.pc()
+    }
+    /// As a number, which is exact up to 2^53 cycles
+    pub fn cycles(&self) -> f64 {
+        self.machine.cycles() as f64
+    }
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.machine.peek(addr)
+    }
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.machine.poke(addr, val)
+    }
+
+    /// The text on the display, a line of 40 characters for each of its 25 rows
+    pub fn framebuffer(&mut self) -> String {
+        let bus = self.machine.io_mut();
+        let buffer = u16::from_le_bytes([
+            bus.read(devices::DISPLAY_PORT),
+            bus.read(devices::DISPLAY_PORT + 1),
+        ]);
+        Display::<io::Sink>::render(self.machine.memory(), buffer)
+    }
+    /// Types a key on the keyboard
+    pub fn key(&self, key: u8) {
+        // The keyboard lives as long as the machine
+        let _ = self.keys.send(key);
+    }
+    /// Sets the gamepad buttons held now, a bit each like the device gives them, for `keydown` and `keyup` to call
+    pub fn buttons(&self, held: u8) {
+        let _ = self.buttons.send(Buttons::Held(held));
+    }
+    /// Adds to what the program reads from stdin
+    pub fn input(&mut self, bytes: &[u8]) {
+        self.machine.io_mut().io_mut().input.extend(bytes);
+    }
+    /// Takes what the program wrote to stdout since the last time
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.machine.io_mut().io_mut().output)
+    }
+}